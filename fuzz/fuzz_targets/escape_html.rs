@@ -0,0 +1,41 @@
+#![no_main]
+
+use app::{escape_html, render_error_page, StatusCode};
+use libfuzzer_sys::fuzz_target;
+
+// Characters that must never reach the page unescaped
+const SPECIAL: [char; 4] = ['<', '>', '"', '\''];
+
+// Treats the input as a file name that ends up in a server-generated page
+fuzz_target!(|data: &[u8]| {
+    let name = String::from_utf8_lossy(data);
+
+    let escaped = escape_html(&name);
+    assert!(
+        !escaped.contains(SPECIAL),
+        "unescaped character in {escaped:?}"
+    );
+    for (index, _) in escaped.match_indices('&') {
+        let entity = &escaped[index..];
+        assert!(
+            ["&lt;", "&gt;", "&amp;", "&quot;", "&#x27;"]
+                .iter()
+                .any(|reference| entity.starts_with(reference)),
+            "bare ampersand in {escaped:?}"
+        );
+    }
+
+    // The message must not add any markup to the page around it
+    let count = |body: &[u8]| {
+        body.iter()
+            .filter(|&&b| SPECIAL.contains(&(b as char)))
+            .count()
+    };
+    let empty = render_error_page(StatusCode::NOT_FOUND, "");
+    let page = render_error_page(StatusCode::NOT_FOUND, &format!("No file named {name}"));
+    assert_eq!(
+        count(&page.body),
+        count(&empty.body),
+        "markup injected by {name:?}"
+    );
+});
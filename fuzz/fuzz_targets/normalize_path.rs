@@ -0,0 +1,36 @@
+#![no_main]
+
+use app::normalize_path;
+use libfuzzer_sys::fuzz_target;
+
+// Treats the input as a request target and checks that a normalized path
+// can never climb above the root, however its dots are spelled:
+// cargo fuzz run normalize_path corpus/normalize_path seeds/normalize_path
+fuzz_target!(|data: &[u8]| {
+    let target = String::from_utf8_lossy(data);
+    let Ok(normalized) = normalize_path(&target) else {
+        return;
+    };
+    if !target.starts_with('/') {
+        assert_eq!(normalized, target, "changed a target that is not a path");
+        return;
+    }
+
+    let path = normalized.split('?').next().unwrap_or_default();
+    let segments = path.strip_prefix('/').expect("lost the leading slash");
+    let segments: Vec<&str> = segments.split('/').collect();
+    for (index, segment) in segments.iter().enumerate() {
+        let dots = segment.replace("%2E", ".").replace("%2e", ".");
+        assert!(dots != "." && dots != "..", "dot segment left in {normalized:?}");
+        assert!(
+            !segment.is_empty() || index == segments.len() - 1,
+            "empty segment left in {normalized:?}"
+        );
+    }
+
+    assert_eq!(
+        normalize_path(&normalized).ok().as_deref(),
+        Some(normalized.as_str()),
+        "normalizing {normalized:?} again changed it"
+    );
+});
@@ -0,0 +1,50 @@
+#![no_main]
+
+use app::Request;
+use libfuzzer_sys::fuzz_target;
+
+// Treats the input as the bytes a client sent and checks that a request
+// whose body could be framed two ways, or not at all, is never accepted. Start from the
+// smuggling attempts in seeds/:
+// cargo fuzz run parse_request corpus/parse_request seeds/parse_request
+fuzz_target!(|data: &[u8]| {
+    let Ok(request) = Request::new(data) else {
+        return;
+    };
+    let headers = &request.headers;
+
+    assert!(
+        !(headers.contains("Content-Length") && headers.contains("Transfer-Encoding")),
+        "accepted both Content-Length and Transfer-Encoding"
+    );
+    let lengths: Vec<u64> = headers
+        .get_all("Content-Length")
+        .flat_map(|value| value.split(','))
+        .map(str::trim)
+        .inspect(|value| {
+            assert!(
+                value.bytes().all(|byte| byte.is_ascii_digit()),
+                "accepted Content-Length {value:?}"
+            )
+        })
+        .map(|value| value.parse().expect("accepted an invalid Content-Length"))
+        .collect();
+    assert!(
+        lengths.windows(2).all(|pair| pair[0] == pair[1]),
+        "accepted conflicting Content-Length values {lengths:?}"
+    );
+
+    let codings: Vec<&str> = headers
+        .get_all("Transfer-Encoding")
+        .flat_map(|value| value.split(','))
+        .map(str::trim)
+        .filter(|coding| !coding.is_empty())
+        .collect();
+    if headers.contains("Transfer-Encoding") {
+        let chunked = codings.iter().filter(|coding| coding.eq_ignore_ascii_case("chunked"));
+        assert!(
+            chunked.count() == 1 && codings.last().unwrap().eq_ignore_ascii_case("chunked"),
+            "accepted Transfer-Encoding {codings:?}"
+        );
+    }
+});
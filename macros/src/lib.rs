@@ -0,0 +1,366 @@
+use proc_macro::{Delimiter, Group, Ident, Literal, Punct, Spacing, Span, TokenStream, TokenTree};
+
+/// Defines a `dispatch` function that routes requests with a fixed route table.
+///
+/// Each route is a method, a path pattern and a handler function taking a
+/// `Request` and returning a `Response`. Patterns use the same syntax as
+/// `Router`: `:name` captures a non-empty segment into `req.params` and a
+/// `*` segment captures the rest of the path under `*`.
+///
+/// ```ignore
+/// use app::{define_routes, Request, Response, StatusCode};
+///
+/// fn root_handler(_req: Request) -> Response {
+///     Response::new(StatusCode::OK)
+/// }
+///
+/// fn user_handler(req: Request) -> Response {
+///     let id = req.params["id"].clone();
+///     Response::with_body(StatusCode::OK, "text/plain", id.into_bytes())
+/// }
+///
+/// define_routes! {
+///     GET "/" => root_handler,
+///     GET "/user/:id" => user_handler,
+/// }
+///
+/// let req = Request::new(&b"GET /user/7 HTTP/1.1\r\n\r\n"[..]).unwrap();
+/// assert_eq!(dispatch(req).body, b"7");
+/// ```
+///
+/// The table becomes one `match` on the method and the path's segments,
+/// e.g. `("GET", ["", "user", id]) if !id.is_empty()`, so the compiler
+/// checks the whole table at once and no pattern is parsed at run time.
+/// Routes are tried in the order they are listed. A path that matches only
+/// routes for other methods gets `405 Method Not Allowed` with an `Allow`
+/// header, and any other path `404 Not Found`.
+///
+/// Methods must be written in upper case, as sent on the wire, and a route
+/// listed twice is a compile error. Handlers are paths or closures; wrap
+/// any other expression containing a comma in parentheses. Unlike
+/// `Router`, there is no middleware, `HEAD` is not answered from `GET`
+/// routes and `OPTIONS` is not answered automatically.
+#[proc_macro]
+pub fn define_routes(input: TokenStream) -> TokenStream {
+    match parse_routes(input) {
+        Ok(routes) => expand(&routes),
+        Err((span, message)) => compile_error(span, &message),
+    }
+}
+
+// One `METHOD "pattern" => handler` entry of the table
+struct Route {
+    method: String,       // The method, e.g. `GET`
+    pattern: Pattern,     // The path pattern it matches
+    handler: TokenStream, // The handler expression, as written
+    handler_span: Span,   // Where the handler starts, for type errors in the call
+}
+
+// The pieces of a path pattern, split on `/` like `Router` splits them
+#[derive(Debug, PartialEq, Eq)]
+enum Segment {
+    Literal(String), // Must equal the path segment
+    Param(String),   // `:name`, captures a non-empty segment
+    Rest,            // `*`, captures every remaining segment
+}
+
+// A path pattern ready to be turned into a slice pattern
+#[derive(Debug, PartialEq, Eq)]
+struct Pattern {
+    segments: Vec<Segment>,
+}
+
+impl Pattern {
+    // Splits `pattern` into segments; anything after a `*` segment is ignored,
+    // as `Router` ignores it
+    fn parse(pattern: &str) -> Pattern {
+        let mut segments = Vec::new();
+        for segment in pattern.split('/') {
+            if segment == "*" {
+                segments.push(Segment::Rest);
+                break;
+            }
+            match segment.strip_prefix(':') {
+                Some(name) => segments.push(Segment::Param(name.to_string())),
+                None => segments.push(Segment::Literal(segment.to_string())),
+            }
+        }
+        Pattern { segments }
+    }
+
+    // The slice pattern matching the path's segments, e.g. `["", "user", __p1]`.
+    // Bindings start with `__` so unused ones do not warn in the caller's crate.
+    fn slice_pattern(&self) -> String {
+        let elements: Vec<String> = self
+            .segments
+            .iter()
+            .enumerate()
+            .map(|(index, segment)| match segment {
+                Segment::Literal(text) => format!("{text:?}"),
+                Segment::Param(_) => format!("__p{index}"),
+                Segment::Rest => "__rest @ ..".to_string(),
+            })
+            .collect();
+        format!("[{}]", elements.join(", "))
+    }
+
+    // The match guard rejecting empty parameter segments, with its `if`
+    fn guard(&self) -> String {
+        let checks: Vec<String> = self
+            .segments
+            .iter()
+            .enumerate()
+            .filter(|(_, segment)| matches!(segment, Segment::Param(_)))
+            .map(|(index, _)| format!("!__p{index}.is_empty()"))
+            .collect();
+        if checks.is_empty() {
+            return String::new();
+        }
+        format!(" if {}", checks.join(" && "))
+    }
+
+    // The statements copying the captured segments into `req.params`
+    fn captures(&self) -> String {
+        self.segments
+            .iter()
+            .enumerate()
+            .filter_map(|(index, segment)| match segment {
+                Segment::Literal(_) => None,
+                Segment::Param(name) => Some(format!(
+                    "req.params.insert({name:?}.to_string(), __p{index}.to_string());"
+                )),
+                Segment::Rest => {
+                    Some("req.params.insert(\"*\".to_string(), __rest.join(\"/\"));".to_string())
+                }
+            })
+            .collect()
+    }
+}
+
+// Reads the routes, failing with the span and message of the first mistake
+fn parse_routes(input: TokenStream) -> Result<Vec<Route>, (Span, String)> {
+    let mut routes: Vec<Route> = Vec::new();
+    let mut tokens = input.into_iter().peekable();
+
+    while let Some(token) = tokens.next() {
+        let TokenTree::Ident(method) = token else {
+            return Err((token.span(), "expected a method, e.g. `GET`".to_string()));
+        };
+        let method_name = method.to_string();
+        if !method_name.bytes().all(|byte| byte.is_ascii_uppercase()) {
+            let message = format!("`{method_name}` is not an upper-case method name");
+            return Err((method.span(), message));
+        }
+
+        let literal = match tokens.next() {
+            Some(TokenTree::Literal(literal)) => literal,
+            other => return Err((span_of(other.as_ref(), &method), expected_pattern())),
+        };
+        let pattern = string_value(&literal.to_string())
+            .ok_or_else(|| (literal.span(), expected_pattern()))?;
+
+        for expected in ['=', '>'] {
+            match tokens.next() {
+                Some(TokenTree::Punct(punct)) if punct.as_char() == expected => {}
+                other => {
+                    let span = other.map_or(literal.span(), |token| token.span());
+                    return Err((span, "expected `=>` after the pattern".to_string()));
+                }
+            }
+        }
+
+        let mut handler = TokenStream::new();
+        while let Some(token) = tokens.next_if(|token| !is_comma(token)) {
+            handler.extend([token]);
+        }
+        let Some(handler_span) = handler.clone().into_iter().next().map(|token| token.span())
+        else {
+            return Err((literal.span(), "expected a handler after `=>`".to_string()));
+        };
+        tokens.next();
+
+        let pattern = Pattern::parse(&pattern);
+        let duplicate = routes.iter().any(|route| {
+            route.method == method_name && route.pattern.slice_pattern() == pattern.slice_pattern()
+        });
+        if duplicate {
+            let message = format!("the route {method_name} {literal} is already defined");
+            return Err((literal.span(), message));
+        }
+        routes.push(Route {
+            method: method_name,
+            pattern,
+            handler,
+            handler_span,
+        });
+    }
+    Ok(routes)
+}
+
+// The span to report a problem with `token` at, or after `previous` if the input ended
+fn span_of(token: Option<&TokenTree>, previous: &Ident) -> Span {
+    token.map_or(previous.span(), TokenTree::span)
+}
+
+fn expected_pattern() -> String {
+    "expected a path pattern string, e.g. \"/user/:id\"".to_string()
+}
+
+fn is_comma(token: &TokenTree) -> bool {
+    matches!(token, TokenTree::Punct(punct) if punct.as_char() == ',')
+}
+
+// The contents of a plain or raw string literal without escapes, e.g. `"/"` or `r#"/"#`
+fn string_value(literal: &str) -> Option<String> {
+    let quoted = match literal.strip_prefix('r') {
+        Some(raw) => raw.trim_matches('#'),
+        None if literal.contains('\\') => return None,
+        None => literal,
+    };
+    let contents = quoted.strip_prefix('"')?.strip_suffix('"')?;
+    Some(contents.to_string())
+}
+
+// Generates `dispatch` for `routes`
+fn expand(routes: &[Route]) -> TokenStream {
+    if routes.is_empty() {
+        return code(
+            "fn dispatch(_req: ::app::Request) -> ::app::Response {
+                ::app::Response::new(::app::StatusCode::NOT_FOUND)
+            }",
+        );
+    }
+
+    let mut arms = TokenStream::new();
+    let mut allowed_checks = String::new();
+    for route in routes {
+        let slice_pattern = route.pattern.slice_pattern();
+        let guard = route.pattern.guard();
+        arms.extend(code(&format!(
+            "({:?}, {slice_pattern}){guard} =>",
+            route.method
+        )));
+
+        let mut body = code(&route.pattern.captures());
+        body.extend(code("return"));
+        let mut handler = Group::new(Delimiter::Parenthesis, route.handler.clone());
+        handler.set_span(route.handler_span);
+        body.extend([TokenTree::Group(handler)]);
+        body.extend(code("(req);"));
+        arms.extend([TokenTree::Group(Group::new(Delimiter::Brace, body))]);
+
+        allowed_checks.push_str(&format!(
+            "if ::std::matches!(segments.as_slice(), {slice_pattern}{guard})
+                && !allowed.contains(&{method:?})
+            {{
+                allowed.push({method:?});
+            }}",
+            method = route.method,
+        ));
+    }
+    arms.extend(code("_ => {}"));
+
+    let captures = routes
+        .iter()
+        .any(|route| !route.pattern.captures().is_empty());
+    let mut dispatch = code(&format!(
+        "fn dispatch({}req: ::app::Request) -> ::app::Response",
+        if captures { "mut " } else { "" }
+    ));
+    let mut body = code(
+        "let path = req.path.split('?').next().unwrap_or_default().to_string();
+        let segments: ::std::vec::Vec<&str> = path.split('/').collect();
+        let method = req.method.clone();
+        match (method.as_str(), segments.as_slice())",
+    );
+    body.extend([TokenTree::Group(Group::new(Delimiter::Brace, arms))]);
+    body.extend(code(&format!(
+        "let mut allowed: ::std::vec::Vec<&'static str> = ::std::vec::Vec::new();
+        {allowed_checks}
+        if allowed.is_empty() {{
+            return ::app::Response::new(::app::StatusCode::NOT_FOUND);
+        }}
+        let mut response = ::app::Response::new(::app::StatusCode::METHOD_NOT_ALLOWED);
+        response.set_header(\"Allow\", &allowed.join(\", \"));
+        response"
+    )));
+    dispatch.extend([TokenTree::Group(Group::new(Delimiter::Brace, body))]);
+    dispatch
+}
+
+// Parses generated code, which is always valid Rust
+fn code(source: &str) -> TokenStream {
+    source.parse().expect("generated code is valid Rust")
+}
+
+// Builds `compile_error!("message");` pointing at `span`
+fn compile_error(span: Span, message: &str) -> TokenStream {
+    let mut message = Literal::string(message);
+    message.set_span(span);
+    let mut bang = Punct::new('!', Spacing::Alone);
+    bang.set_span(span);
+    let mut group = Group::new(Delimiter::Parenthesis, TokenTree::Literal(message).into());
+    group.set_span(span);
+    let mut semicolon = Punct::new(';', Spacing::Alone);
+    semicolon.set_span(span);
+    TokenStream::from_iter([
+        TokenTree::Ident(Ident::new("compile_error", span)),
+        TokenTree::Punct(bang),
+        TokenTree::Group(group),
+        TokenTree::Punct(semicolon),
+    ])
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn splits_patterns_like_the_router() {
+        assert_eq!(
+            Pattern::parse("/user/:id/*/ignored").segments,
+            [
+                Segment::Literal(String::new()),
+                Segment::Literal("user".to_string()),
+                Segment::Param("id".to_string()),
+                Segment::Rest,
+            ]
+        );
+        assert_eq!(Pattern::parse("/").segments.len(), 2);
+    }
+
+    #[test]
+    fn turns_patterns_into_slice_patterns() {
+        let pattern = Pattern::parse("/user/:id/posts/:post");
+        assert_eq!(
+            pattern.slice_pattern(),
+            r#"["", "user", __p2, "posts", __p4]"#
+        );
+        assert_eq!(pattern.guard(), " if !__p2.is_empty() && !__p4.is_empty()");
+        assert_eq!(
+            pattern.captures(),
+            r#"req.params.insert("id".to_string(), __p2.to_string());req.params.insert("post".to_string(), __p4.to_string());"#
+        );
+
+        let rest = Pattern::parse("/static/*");
+        assert_eq!(rest.slice_pattern(), r#"["", "static", __rest @ ..]"#);
+        assert_eq!(rest.guard(), "");
+        assert_eq!(Pattern::parse("/").slice_pattern(), r#"["", ""]"#);
+    }
+
+    #[test]
+    fn escapes_literal_segments() {
+        let pattern = Pattern::parse("/say \"hi\"");
+        assert_eq!(pattern.slice_pattern(), r#"["", "say \"hi\""]"#);
+    }
+
+    #[test]
+    fn reads_plain_and_raw_strings() {
+        assert_eq!(string_value(r#""/user/:id""#).as_deref(), Some("/user/:id"));
+        assert_eq!(string_value(r#"r"/a""#).as_deref(), Some("/a"));
+        assert_eq!(string_value(r##"r#"/a#"#"##).as_deref(), Some("/a#"));
+        assert_eq!(string_value(r#""/a\n""#), None);
+        assert_eq!(string_value("42"), None);
+        assert_eq!(string_value("b\"/a\""), None);
+    }
+}
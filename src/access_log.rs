@@ -0,0 +1,144 @@
+use std::fmt;
+use std::fs::OpenOptions;
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex, MutexGuard};
+
+use crate::json::json_string;
+
+// The access logs opened for `ServerConfig::json_access_log`, shared by every server using the path
+static OPEN_LOGS: Mutex<Vec<(PathBuf, Arc<JsonAccessLog>)>> = Mutex::new(Vec::new());
+
+/// One handled request, as recorded in a JSON Lines access log.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RequestLog {
+    /// When the response was sent, in RFC 3339 UTC, e.g. `2026-10-14T09:30:00.125Z`.
+    pub timestamp: String,
+    /// The request method, e.g. `GET`.
+    pub method: String,
+    /// The request path, with its query string.
+    pub path: String,
+    /// The status code of the response.
+    pub status: u16,
+    /// The bytes written to the connection for the response, head included.
+    pub bytes_sent: usize,
+    /// How long the request took, from accepting it to sending the response.
+    pub duration_ms: u64,
+    /// The IP address of the client, or empty if unknown.
+    pub remote_ip: String,
+    /// Identifies the request across logs; the server uses the trace ID of its `traceparent`.
+    pub request_id: String,
+}
+
+impl RequestLog {
+    /// Formats the record as one line of JSON, without the trailing newline.
+    ///
+    /// The fields appear in declaration order, e.g.
+    /// `{"timestamp":"...","method":"GET","path":"/","status":200,...}`.
+    pub fn to_jsonl(&self) -> String {
+        format!(
+            "{{\"timestamp\":{},\"method\":{},\"path\":{},\"status\":{},\"bytes_sent\":{},\"duration_ms\":{},\"remote_ip\":{},\"request_id\":{}}}",
+            json_string(&self.timestamp),
+            json_string(&self.method),
+            json_string(&self.path),
+            self.status,
+            self.bytes_sent,
+            self.duration_ms,
+            json_string(&self.remote_ip),
+            json_string(&self.request_id),
+        )
+    }
+}
+
+/// Writes [`RequestLog`] records to a sink as JSON Lines, one record per line.
+///
+/// The server opens one for `ServerConfig::json_access_log`; create one
+/// with [`JsonAccessLog::new`] to send records elsewhere, e.g. to stdout.
+/// Each record is written and flushed as a single line under a lock, so
+/// records from concurrent requests never interleave.
+pub struct JsonAccessLog {
+    sink: Mutex<Box<dyn Write + Send>>, // Where the lines go
+}
+
+impl JsonAccessLog {
+    /// Creates a log writing to `sink`.
+    pub fn new(sink: Box<dyn Write + Send>) -> JsonAccessLog {
+        JsonAccessLog {
+            sink: Mutex::new(sink),
+        }
+    }
+
+    /// Opens the file at `path` for appending, creating it if needed.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the file cannot be opened.
+    pub fn open(path: &Path) -> io::Result<JsonAccessLog> {
+        let file = OpenOptions::new().create(true).append(true).open(path)?;
+        Ok(JsonAccessLog::new(Box::new(file)))
+    }
+
+    /// Appends `record` as a line and flushes the sink.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if writing to the sink fails.
+    pub fn write(&self, record: &RequestLog) -> io::Result<()> {
+        let mut line = record.to_jsonl();
+        line.push('\n');
+        let mut sink = self
+            .sink
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner());
+        sink.write_all(line.as_bytes())?;
+        sink.flush()
+    }
+}
+
+impl fmt::Debug for JsonAccessLog {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("JsonAccessLog").finish_non_exhaustive()
+    }
+}
+
+// Returns the log for `path`, opening it on first use
+pub(crate) fn shared_access_log(path: &Path) -> io::Result<Arc<JsonAccessLog>> {
+    let mut logs = lock_open_logs();
+    if let Some((_, log)) = logs.iter().find(|(open, _)| open == path) {
+        return Ok(Arc::clone(log));
+    }
+    let log = Arc::new(JsonAccessLog::open(path)?);
+    logs.push((path.to_path_buf(), Arc::clone(&log)));
+    Ok(log)
+}
+
+// Locks the open logs. Entries are only ever pushed whole, so poisoning is ignored
+fn lock_open_logs() -> MutexGuard<'static, Vec<(PathBuf, Arc<JsonAccessLog>)>> {
+    OPEN_LOGS
+        .lock()
+        .unwrap_or_else(|poisoned| poisoned.into_inner())
+}
+
+// Passes writes on to `inner`, adding the bytes written to `count`
+pub(crate) struct CountingWriter<'a, W: Write> {
+    inner: W,           // The writer the bytes go to
+    count: &'a mut u64, // The running total of bytes written
+}
+
+impl<'a, W: Write> CountingWriter<'a, W> {
+    pub(crate) fn new(inner: W, count: &'a mut u64) -> CountingWriter<'a, W> {
+        CountingWriter { inner, count }
+    }
+}
+
+impl<W: Write> Write for CountingWriter<'_, W> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let written = self.inner.write(buf)?;
+        *self.count += written as u64;
+        Ok(written)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.inner.flush()
+    }
+}
@@ -0,0 +1,175 @@
+use std::collections::HashMap;
+use std::net::{IpAddr, SocketAddr};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use crate::{Request, Response, ServerConfig, StatusCode};
+
+/// What the server knows about one open connection.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ConnectionInfo {
+    /// A number identifying the connection, unique for the life of the server.
+    pub id: u64,
+    /// The client's address, if the connection has one.
+    pub remote_addr: Option<SocketAddr>,
+    /// When the connection was accepted.
+    pub started_at: SystemTime,
+    /// How many requests have been answered on the connection so far.
+    pub requests_served: u64,
+}
+
+/// The set of connections currently being handled.
+///
+/// `handle_connection` registers each connection for as long as it serves
+/// it, and [`AdminHandler`] reports on them.
+#[derive(Debug, Default)]
+pub struct ConnectionRegistry {
+    connections: Mutex<HashMap<u64, ConnectionInfo>>, // Open connections by id
+    next_id: AtomicU64,                               // The id given to the next connection
+}
+
+impl ConnectionRegistry {
+    /// Creates an empty registry.
+    pub fn new() -> ConnectionRegistry {
+        ConnectionRegistry::default()
+    }
+
+    /// Records a newly accepted connection. It is removed when the returned handle is dropped.
+    pub fn register(self: &Arc<Self>, remote_addr: Option<SocketAddr>) -> ConnectionHandle {
+        let id = self.next_id.fetch_add(1, Ordering::Relaxed);
+        let info = ConnectionInfo {
+            id,
+            remote_addr,
+            started_at: SystemTime::now(),
+            requests_served: 0,
+        };
+        self.connections.lock().unwrap().insert(id, info);
+        ConnectionHandle {
+            registry: Arc::clone(self),
+            id,
+        }
+    }
+
+    /// Returns the open connections, oldest first.
+    pub fn snapshot(&self) -> Vec<ConnectionInfo> {
+        let mut connections: Vec<ConnectionInfo> =
+            self.connections.lock().unwrap().values().cloned().collect();
+        connections.sort_by_key(|info| info.id);
+        connections
+    }
+}
+
+/// Keeps a connection listed in its [`ConnectionRegistry`] until dropped.
+#[derive(Debug)]
+pub struct ConnectionHandle {
+    registry: Arc<ConnectionRegistry>, // The registry the connection is listed in
+    id: u64,                           // The connection's id in the registry
+}
+
+impl ConnectionHandle {
+    /// Counts one more request as answered on this connection.
+    pub fn request_served(&self) {
+        if let Some(info) = self.registry.connections.lock().unwrap().get_mut(&self.id) {
+            info.requests_served += 1;
+        }
+    }
+}
+
+impl Drop for ConnectionHandle {
+    fn drop(&mut self) {
+        self.registry.connections.lock().unwrap().remove(&self.id);
+    }
+}
+
+/// The built-in admin endpoints, enabled by setting `ServerConfig::admin_path`.
+///
+/// `GET <admin_path>/connections` answers with the open connections and the
+/// number of requests in flight as JSON, and `GET <admin_path>/metrics` with
+/// the [`MetricsRegistry`](crate::MetricsRegistry) of `ServerConfig::metrics`
+/// in the Prometheus text format. Only clients whose address is in
+/// `ServerConfig::admin_allowed_ips` may use it; with an empty allowlist only
+/// loopback clients may. Everyone else gets `403 Forbidden`.
+pub struct AdminHandler;
+
+impl AdminHandler {
+    /// Answers `req` if it is addressed to the admin endpoints.
+    ///
+    /// # Returns
+    ///
+    /// `None` if the admin endpoints are disabled or `req` is not under the
+    /// admin path, so it should be routed as usual.
+    pub fn handle(req: &Request, config: &ServerConfig) -> Option<Response> {
+        let admin_path = config.admin_path.as_deref()?.trim_end_matches('/');
+        let path = req.path.split('?').next().unwrap_or_default();
+        let endpoint = path.strip_prefix(admin_path)?;
+        if !endpoint.is_empty() && !endpoint.starts_with('/') {
+            return None;
+        }
+
+        if !is_allowed(req.remote_addr, &config.admin_allowed_ips) {
+            return Some(Response::new(StatusCode::FORBIDDEN));
+        }
+        let response = match (req.method.as_str(), endpoint) {
+            ("GET", "/connections") => Response::with_body(
+                StatusCode::OK,
+                "application/json",
+                connections_json(config).into_bytes(),
+            ),
+            ("GET", "/metrics") => Response::with_body(
+                StatusCode::OK,
+                "text/plain; version=0.0.4",
+                config.metrics.render_prometheus().into_bytes(),
+            ),
+            (_, "/connections" | "/metrics") => {
+                let mut response = Response::new(StatusCode::METHOD_NOT_ALLOWED);
+                response.set_header("Allow", "GET");
+                response
+            }
+            _ => Response::new(StatusCode::NOT_FOUND),
+        };
+        Some(response)
+    }
+}
+
+// Checks the client against the allowlist, which defaults to loopback only
+fn is_allowed(remote_addr: Option<SocketAddr>, allowed_ips: &[IpAddr]) -> bool {
+    let Some(ip) = remote_addr.map(|addr| addr.ip()) else {
+        return false;
+    };
+    if allowed_ips.is_empty() {
+        ip.is_loopback()
+    } else {
+        allowed_ips.contains(&ip)
+    }
+}
+
+// Serializes the open connections, e.g. {"active_requests":1,"connections":[...]}
+fn connections_json(config: &ServerConfig) -> String {
+    let connections: Vec<String> = config
+        .connections
+        .snapshot()
+        .iter()
+        .map(|info| {
+            let remote_addr = match info.remote_addr {
+                Some(addr) => format!("\"{addr}\""),
+                None => "null".to_string(),
+            };
+            let started_at = info
+                .started_at
+                .duration_since(UNIX_EPOCH)
+                .map(|elapsed| elapsed.as_secs())
+                .unwrap_or_default();
+            format!(
+                "{{\"id\":{},\"remote_addr\":{remote_addr},\"started_at\":{started_at},\"requests_served\":{}}}",
+                info.id, info.requests_served
+            )
+        })
+        .collect();
+
+    format!(
+        "{{\"active_requests\":{},\"connections\":[{}]}}",
+        config.requests.active(),
+        connections.join(",")
+    )
+}
@@ -0,0 +1,160 @@
+use std::collections::HashMap;
+
+use crate::base64;
+use crate::headers::{split_unquoted, unquote};
+use crate::HttpError;
+
+/// The credentials in an `Authorization` header, by scheme.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum AuthScheme {
+    /// `Basic` credentials (RFC 7617), decoded from base64.
+    Basic { username: String, password: String },
+    /// A `Bearer` token (RFC 6750), e.g. a JWT.
+    Bearer(String),
+    /// The parameters of `Digest` credentials (RFC 7616), with lowercase
+    /// names and unquoted values.
+    Digest(HashMap<String, String>),
+    /// Any other scheme, with everything after the scheme name left as sent.
+    Other { scheme: String, params: String },
+}
+
+/// Parses an `Authorization` header value.
+///
+/// Scheme names are case-insensitive. `Basic` credentials must be padded
+/// base64 of UTF-8 `username:password`; the password may contain colons.
+///
+/// # Errors
+///
+/// Returns `HttpError::InvalidAuthorization` if the value is empty, or if
+/// `Basic`, `Bearer` or `Digest` credentials are missing or malformed.
+pub fn parse_authorization(header: &str) -> Result<AuthScheme, HttpError> {
+    let header = header.trim();
+    let (scheme, credentials) = header.split_once(' ').unwrap_or((header, ""));
+    let credentials = credentials.trim();
+    if scheme.is_empty() {
+        return Err(HttpError::InvalidAuthorization("missing scheme"));
+    }
+
+    if scheme.eq_ignore_ascii_case("Basic") {
+        let invalid = || HttpError::InvalidAuthorization("malformed Basic credentials");
+        let decoded = base64::decode(credentials)
+            .and_then(|bytes| String::from_utf8(bytes).ok())
+            .ok_or_else(invalid)?;
+        let (username, password) = decoded.split_once(':').ok_or_else(invalid)?;
+        Ok(AuthScheme::Basic {
+            username: username.to_string(),
+            password: password.to_string(),
+        })
+    } else if scheme.eq_ignore_ascii_case("Bearer") {
+        if credentials.is_empty() || credentials.contains(char::is_whitespace) {
+            return Err(HttpError::InvalidAuthorization("malformed Bearer token"));
+        }
+        Ok(AuthScheme::Bearer(credentials.to_string()))
+    } else if scheme.eq_ignore_ascii_case("Digest") {
+        let params: HashMap<String, String> = split_unquoted(credentials, ',')
+            .into_iter()
+            .filter_map(|param| param.split_once('='))
+            .map(|(name, value)| (name.trim().to_ascii_lowercase(), unquote(value.trim())))
+            .collect();
+        if params.is_empty() {
+            return Err(HttpError::InvalidAuthorization(
+                "malformed Digest credentials",
+            ));
+        }
+        Ok(AuthScheme::Digest(params))
+    } else {
+        Ok(AuthScheme::Other {
+            scheme: scheme.to_string(),
+            params: credentials.to_string(),
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn basic(username: &str, password: &str) -> AuthScheme {
+        AuthScheme::Basic {
+            username: username.to_string(),
+            password: password.to_string(),
+        }
+    }
+
+    fn is_invalid(header: &str) -> bool {
+        matches!(
+            parse_authorization(header),
+            Err(HttpError::InvalidAuthorization(_))
+        )
+    }
+
+    #[test]
+    fn parses_scheme_names_case_insensitively() {
+        let credentials = basic("Aladdin", "open sesame");
+        for scheme in ["Basic", "basic", "BASIC"] {
+            let header = format!("{scheme} QWxhZGRpbjpvcGVuIHNlc2FtZQ==");
+            assert_eq!(parse_authorization(&header).unwrap(), credentials);
+        }
+        let token = AuthScheme::Bearer("abc.def.ghi".to_string());
+        assert_eq!(parse_authorization("bEaReR abc.def.ghi").unwrap(), token);
+        assert!(matches!(
+            parse_authorization("digest nonce=1").unwrap(),
+            AuthScheme::Digest(_)
+        ));
+    }
+
+    #[test]
+    fn decodes_basic_credentials() {
+        let unpadded = parse_authorization("Basic dXNlcjpwYXNz").unwrap();
+        assert_eq!(unpadded, basic("user", "pass"));
+        let padded = parse_authorization(" Basic   dXNlcjpwYQ== ").unwrap();
+        assert_eq!(padded, basic("user", "pa"));
+        let colons = parse_authorization("Basic YWRtaW46cDp3OmQ=").unwrap();
+        assert_eq!(colons, basic("admin", "p:w:d"));
+    }
+
+    #[test]
+    fn rejects_malformed_basic_credentials() {
+        assert!(is_invalid("Basic dXNlcjpwYQ"));
+        assert!(is_invalid("Basic dXNlcjpwYQ="));
+        assert!(is_invalid("Basic dXNl*jpwYXNz"));
+        assert!(is_invalid("Basic bm9jb2xvbg=="));
+        assert!(is_invalid("Basic /zp4"));
+        assert!(is_invalid("Basic"));
+    }
+
+    #[test]
+    fn rejects_missing_schemes_and_tokens() {
+        assert!(is_invalid(""));
+        assert!(is_invalid("   "));
+        assert!(is_invalid("Bearer"));
+        assert!(is_invalid("Bearer two tokens"));
+        assert!(is_invalid("Digest"));
+        assert!(is_invalid("Digest no-parameters"));
+    }
+
+    #[test]
+    fn unquotes_digest_parameters() {
+        let header = r#"Digest Username="Mu\"fa\\sa", realm="a, b", nonce=abc, qop = auth"#;
+        let AuthScheme::Digest(params) = parse_authorization(header).unwrap() else {
+            panic!("not Digest credentials");
+        };
+        assert_eq!(params["username"], r#"Mu"fa\sa"#);
+        assert_eq!(params["realm"], "a, b");
+        assert_eq!(params["nonce"], "abc");
+        assert_eq!(params["qop"], "auth");
+        assert_eq!(params.len(), 4);
+    }
+
+    #[test]
+    fn keeps_other_schemes_as_sent() {
+        let other = parse_authorization("Negotiate  YIIGhgYJKoZIhvcSAQICAQBuggZ1").unwrap();
+        assert_eq!(
+            other,
+            AuthScheme::Other {
+                scheme: "Negotiate".to_string(),
+                params: "YIIGhgYJKoZIhvcSAQICAQBuggZ1".to_string(),
+            }
+        );
+    }
+}
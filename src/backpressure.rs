@@ -0,0 +1,77 @@
+use std::io;
+use std::net::{SocketAddr, TcpListener, TcpStream};
+#[cfg(all(feature = "uring", target_os = "linux"))]
+use std::sync::Mutex;
+use std::thread;
+use std::time::Duration;
+
+#[cfg(all(feature = "uring", target_os = "linux"))]
+use crate::uring::UringAcceptor;
+use crate::ThreadPool;
+
+// How long to wait before checking a full queue again
+const RETRY_INTERVAL: Duration = Duration::from_millis(10);
+
+/// A listener that stops accepting connections while the thread pool is backed up.
+///
+/// Connections that are not accepted wait in the OS accept backlog, so a
+/// burst of traffic slows clients down instead of piling jobs onto the pool
+/// queue. Once the backlog is full too, the OS refuses new connections.
+///
+/// With the `uring` feature on Linux, connections are accepted through an
+/// `io_uring` with as many accepts in flight as the queue has room for,
+/// falling back to plain `accept(2)` if the kernel cannot set one up.
+#[derive(Debug)]
+pub struct BackpressureListener {
+    listener: TcpListener, // The bound listening socket
+    threshold: usize,      // Stop accepting once this many jobs are queued
+    #[cfg(all(feature = "uring", target_os = "linux"))]
+    uring: Option<Mutex<UringAcceptor>>, // Accepts through io_uring, when available
+}
+
+impl BackpressureListener {
+    /// Wraps `listener`, accepting only while fewer than `threshold` jobs are queued.
+    pub fn new(listener: TcpListener, threshold: usize) -> BackpressureListener {
+        #[cfg(all(feature = "uring", target_os = "linux"))]
+        let uring = match UringAcceptor::new(&listener) {
+            Ok(uring) => Some(Mutex::new(uring)),
+            Err(err) => {
+                println!("io_uring is unavailable, accepting with accept(2): {err}");
+                None
+            }
+        };
+
+        BackpressureListener {
+            listener,
+            threshold,
+            #[cfg(all(feature = "uring", target_os = "linux"))]
+            uring,
+        }
+    }
+
+    /// Returns the address the listener is bound to.
+    pub fn local_addr(&self) -> io::Result<SocketAddr> {
+        self.listener.local_addr()
+    }
+
+    /// Waits until `pool` has room in its queue, then accepts the next connection.
+    ///
+    /// The queue is checked every 10 ms while it is over the threshold.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if accepting the connection fails.
+    pub fn accept(&self, pool: &ThreadPool) -> io::Result<TcpStream> {
+        let mut queued = pool.metrics().queued;
+        while queued >= self.threshold {
+            thread::sleep(RETRY_INTERVAL);
+            queued = pool.metrics().queued;
+        }
+
+        #[cfg(all(feature = "uring", target_os = "linux"))]
+        if let Some(uring) = &self.uring {
+            return uring.lock().unwrap().accept(self.threshold - queued);
+        }
+        self.listener.accept().map(|(stream, _)| stream)
+    }
+}
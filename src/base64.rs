@@ -0,0 +1,147 @@
+// The standard alphabet (RFC 4648, section 4)
+const ALPHABET: &[u8; 64] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789+/";
+
+/// Encodes `input` as padded standard base64 (RFC 4648, section 4).
+pub(crate) fn encode(input: &[u8]) -> String {
+    let mut output = String::with_capacity(input.len().div_ceil(3) * 4);
+    for chunk in input.chunks(3) {
+        let bytes = [
+            chunk[0],
+            *chunk.get(1).unwrap_or(&0),
+            *chunk.get(2).unwrap_or(&0),
+        ];
+        let group = (u32::from(bytes[0]) << 16) | (u32::from(bytes[1]) << 8) | u32::from(bytes[2]);
+
+        // A chunk of n bytes fills n + 1 characters; the rest is padding
+        for i in 0..4 {
+            if i <= chunk.len() {
+                output.push(ALPHABET[(group >> (18 - 6 * i) & 0x3f) as usize] as char);
+            } else {
+                output.push('=');
+            }
+        }
+    }
+    output
+}
+
+/// Decodes padded standard base64 (RFC 4648, section 4), as used by `Basic` credentials.
+///
+/// # Returns
+///
+/// The decoded bytes, or `None` if `input` is not canonical, padded base64.
+pub(crate) fn decode(input: &str) -> Option<Vec<u8>> {
+    if !input.len().is_multiple_of(4) {
+        return None;
+    }
+    let unpadded = input
+        .strip_suffix("==")
+        .or_else(|| input.strip_suffix('='))
+        .unwrap_or(input);
+    decode_with(unpadded, |byte| match byte {
+        b'+' => Some(62),
+        b'/' => Some(63),
+        _ => None,
+    })
+}
+
+/// Decodes unpadded base64url (RFC 4648, section 5), as used by JWTs.
+///
+/// # Returns
+///
+/// The decoded bytes, or `None` if `input` contains characters outside the
+/// base64url alphabet, padding, has an impossible length, or is not in
+/// canonical form (the unused bits of the last character must be zero).
+pub(crate) fn decode_url(input: &str) -> Option<Vec<u8>> {
+    decode_with(input, |byte| match byte {
+        b'-' => Some(62),
+        b'_' => Some(63),
+        _ => None,
+    })
+}
+
+// Decodes unpadded base64 whose last two alphabet characters are mapped by `extra`
+fn decode_with(input: &str, extra: impl Fn(u8) -> Option<u8>) -> Option<Vec<u8>> {
+    if input.len() % 4 == 1 {
+        return None;
+    }
+
+    let mut output = Vec::with_capacity(input.len() * 3 / 4);
+    let mut buffer = 0u32; // Bits decoded but not yet written out
+    let mut bits = 0; // The number of valid bits in `buffer`
+    for byte in input.bytes() {
+        let value = match byte {
+            b'A'..=b'Z' => byte - b'A',
+            b'a'..=b'z' => byte - b'a' + 26,
+            b'0'..=b'9' => byte - b'0' + 52,
+            _ => extra(byte)?,
+        };
+        buffer = (buffer << 6) | u32::from(value);
+        bits += 6;
+        if bits >= 8 {
+            bits -= 8;
+            output.push((buffer >> bits) as u8);
+        }
+    }
+
+    // Non-zero leftover bits would let several strings decode to the same bytes
+    if buffer & ((1 << bits) - 1) != 0 {
+        return None;
+    }
+    Some(output)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // The test vectors of RFC 4648, section 10
+    const VECTORS: [(&str, &str); 7] = [
+        ("", ""),
+        ("f", "Zg=="),
+        ("fo", "Zm8="),
+        ("foo", "Zm9v"),
+        ("foob", "Zm9vYg=="),
+        ("fooba", "Zm9vYmE="),
+        ("foobar", "Zm9vYmFy"),
+    ];
+
+    #[test]
+    fn round_trips_the_rfc_vectors() {
+        for (plain, encoded) in VECTORS {
+            assert_eq!(encode(plain.as_bytes()), encoded);
+            assert_eq!(decode(encoded).unwrap(), plain.as_bytes());
+            let unpadded = encoded.trim_end_matches('=');
+            assert_eq!(decode_url(unpadded).unwrap(), plain.as_bytes());
+        }
+    }
+
+    #[test]
+    fn requires_padding_for_standard_base64() {
+        assert_eq!(decode("Zg"), None);
+        assert_eq!(decode("Zm8"), None);
+        assert_eq!(decode("Zg="), None);
+        assert_eq!(decode("Zg==="), None);
+        assert_eq!(decode("===="), None);
+        assert_eq!(decode("Z==="), None);
+        assert_eq!(decode("Zg=a"), None);
+    }
+
+    #[test]
+    fn rejects_characters_outside_the_alphabet() {
+        assert_eq!(decode("+/+/"), Some(vec![0xfb, 0xff, 0xbf]));
+        assert_eq!(decode("-_-_"), None);
+        assert_eq!(decode("Zm 9"), None);
+        assert_eq!(decode_url("-_-_"), Some(vec![0xfb, 0xff, 0xbf]));
+        assert_eq!(decode_url("+/+/"), None);
+        assert_eq!(decode_url("Zg=="), None);
+    }
+
+    #[test]
+    fn rejects_non_canonical_input() {
+        // The unused low bits of the last character must be zero
+        assert_eq!(decode("Zh=="), None);
+        assert_eq!(decode("Zm9="), None);
+        assert_eq!(decode_url("Zh"), None);
+        assert_eq!(decode_url("Zm9vY"), None);
+    }
+}
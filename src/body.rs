@@ -0,0 +1,471 @@
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, BufRead, BufWriter, Error, Read, Write};
+use std::path::Path;
+
+use crate::{HeaderMap, HttpError, LimitedBufReader, LimitedReader};
+
+// How much of the body is copied per read when streaming it to disk
+const CHUNK_SIZE: usize = 4 * 1024;
+
+// The longest chunk-size line accepted, chunk extensions and CRLF included
+const MAX_CHUNK_LINE: usize = 4 * 1024;
+
+/// Decodes a `Transfer-Encoding: chunked` body.
+///
+/// Reading from a `ChunkedReader` yields the body with the chunk framing
+/// removed and reaches end-of-stream after the terminating zero-size chunk
+/// and its trailer section have been consumed. Any trailer fields sent after
+/// the last chunk (RFC 7230 §4.1.2) are available from [`ChunkedReader::trailers`].
+///
+/// The framing is bounded as well as the data: a chunk-size line over 4 KiB
+/// fails with `HttpError::PayloadTooLarge`, and a trailer section over
+/// [`ChunkedReader::max_trailer_bytes`] or with more fields than
+/// [`ChunkedReader::max_trailers`] with `HttpError::HeadersTooLarge`, both
+/// wrapped in an `io::Error` of kind `InvalidData`. The byte limit covers
+/// the whole section, so it also bounds each trailer line.
+pub struct ChunkedReader<R: BufRead> {
+    inner: R,                 // The reader positioned at the start of the chunked body
+    remaining: u64,           // Bytes left in the current chunk
+    in_chunk: bool,           // Whether a chunk's data (and its closing CRLF) is pending
+    finished: bool,           // Whether the terminating chunk has been read
+    trailers: HeaderMap,      // The header fields sent after the terminating chunk
+    max_trailer_bytes: usize, // The most bytes the trailer section may take up
+    max_trailers: usize,      // The most fields the trailer section may hold
+}
+
+impl<R: BufRead> ChunkedReader<R> {
+    /// Wraps `inner`, which must be positioned at the first chunk-size line.
+    pub fn new(inner: R) -> ChunkedReader<R> {
+        ChunkedReader {
+            inner,
+            remaining: 0,
+            in_chunk: false,
+            finished: false,
+            trailers: HeaderMap::new(),
+            max_trailer_bytes: 8 * 1024,
+            max_trailers: 100,
+        }
+    }
+
+    /// Sets how many bytes the trailer section may take up, blank line
+    /// included. Defaults to 8 KiB, the default `ServerConfig::max_header_bytes`.
+    pub fn max_trailer_bytes(mut self, max_trailer_bytes: usize) -> ChunkedReader<R> {
+        self.max_trailer_bytes = max_trailer_bytes;
+        self
+    }
+
+    /// Sets how many trailer fields may be sent. Defaults to 100, the
+    /// default `ServerConfig::max_headers`.
+    pub fn max_trailers(mut self, max_trailers: usize) -> ChunkedReader<R> {
+        self.max_trailers = max_trailers;
+        self
+    }
+
+    /// Returns the trailer fields, which are only complete once the body has
+    /// been read to end-of-stream.
+    pub fn trailers(&self) -> &HeaderMap {
+        &self.trailers
+    }
+
+    /// Returns the wrapped reader, positioned after whatever has been decoded so far.
+    pub fn into_inner(self) -> R {
+        self.inner
+    }
+
+    // Moves on to the next chunk once the current one has been fully consumed
+    fn advance(&mut self) -> io::Result<()> {
+        while self.remaining == 0 && !self.finished {
+            if self.in_chunk {
+                expect_crlf(&mut self.inner)?;
+                self.in_chunk = false;
+            }
+
+            let size = read_chunk_size(&mut self.inner)?;
+            if size == 0 {
+                self.trailers = read_trailers(&mut self.inner, self.max_trailer_bytes, self.max_trailers)?;
+                self.finished = true;
+            } else {
+                self.remaining = size;
+                self.in_chunk = true;
+            }
+        }
+        Ok(())
+    }
+}
+
+impl<R: BufRead> Read for ChunkedReader<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let available = self.fill_buf()?;
+        let count = available.len().min(buf.len());
+        buf[..count].copy_from_slice(&available[..count]);
+        self.consume(count);
+        Ok(count)
+    }
+}
+
+impl<R: BufRead> BufRead for ChunkedReader<R> {
+    fn fill_buf(&mut self) -> io::Result<&[u8]> {
+        self.advance()?;
+        if self.finished {
+            return Ok(&[]);
+        }
+
+        let available = self.inner.fill_buf()?;
+        if available.is_empty() {
+            return Err(Error::new(io::ErrorKind::UnexpectedEof, "chunked body ended early"));
+        }
+        let count = available.len().min(self.remaining as usize);
+        Ok(&available[..count])
+    }
+
+    fn consume(&mut self, amount: usize) {
+        self.remaining -= amount as u64;
+        self.inner.consume(amount);
+    }
+}
+
+/// Encodes everything written to it as `Transfer-Encoding: chunked`.
+///
+/// Every `write` call becomes one chunk, so callers control the chunk
+/// boundaries. [`ChunkedWriter::finish`] writes the terminating zero-size
+/// chunk; dropping the writer without calling it leaves the body unterminated.
+pub struct ChunkedWriter<W: Write> {
+    inner: W, // The stream the chunks are written to
+}
+
+impl<W: Write> ChunkedWriter<W> {
+    /// Wraps `inner`, which must be positioned right after the response headers.
+    pub fn new(inner: W) -> ChunkedWriter<W> {
+        ChunkedWriter { inner }
+    }
+
+    /// Writes the terminating chunk and returns the wrapped writer.
+    pub fn finish(mut self) -> io::Result<W> {
+        self.inner.write_all(b"0\r\n\r\n")?;
+        self.inner.flush()?;
+        Ok(self.inner)
+    }
+}
+
+impl<W: Write> Write for ChunkedWriter<W> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        // An empty chunk would terminate the body, so skip it
+        if buf.is_empty() {
+            return Ok(0);
+        }
+        write!(self.inner, "{:x}\r\n", buf.len())?;
+        self.inner.write_all(buf)?;
+        self.inner.write_all(b"\r\n")?;
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.inner.flush()
+    }
+}
+
+/// Reads the body that follows the headers of a request.
+///
+/// Bodies whose `Transfer-Encoding` ends in `chunked` are decoded and their
+/// trailers returned; every trailer must have been announced in the
+/// `Trailer` header. Any other `Transfer-Encoding` is refused.
+/// Otherwise `Content-Length` bytes are read, and a request with neither
+/// header has an empty body. At most `limit` body bytes are read, after
+/// chunked decoding, and the trailer section may take up at most
+/// `max_trailer_bytes` and hold at most `max_trailers` fields.
+///
+/// # Returns
+///
+/// The body bytes and the trailer fields (empty unless the body was chunked).
+///
+/// # Errors
+///
+/// Returns `HttpError::UnframedBody` if `Transfer-Encoding` does not end in
+/// `chunked`, `HttpError::PayloadTooLarge` if the body is longer than `limit`
+/// or a chunk-size line is longer than 4 KiB, `HttpError::HeadersTooLarge`
+/// if the trailers are longer than `max_trailer_bytes` or have more than
+/// `max_trailers` fields,
+/// `HttpError::UndeclaredTrailer` if a trailer was not listed in `Trailer`,
+/// or `HttpError::Io` if the body is malformed or cut short.
+pub(crate) fn read_body<R: BufRead>(reader: &mut R, headers: &HeaderMap, limit: usize, max_trailer_bytes: usize, max_trailers: usize) -> Result<(Vec<u8>, HeaderMap), HttpError> {
+    let mut body = Vec::new();
+
+    if is_chunked(headers)? {
+        let chunked = ChunkedReader::new(reader).max_trailer_bytes(max_trailer_bytes).max_trailers(max_trailers);
+        let mut limited = LimitedReader::new(chunked, limit);
+        limited.read_to_end(&mut body)?;
+        let chunked = limited.into_inner();
+
+        let declared: Vec<&str> = headers
+            .get_all("Trailer")
+            .flat_map(|value| value.split(','))
+            .map(str::trim)
+            .collect();
+        for (name, _) in chunked.trailers().iter() {
+            if !declared.iter().any(|declared| declared.eq_ignore_ascii_case(name)) {
+                return Err(HttpError::UndeclaredTrailer(name.to_string()));
+            }
+        }
+        return Ok((body, chunked.trailers));
+    }
+
+    if let Some(length) = content_length(headers)? {
+        LimitedReader::new(reader.take(length), limit).read_to_end(&mut body)?;
+        if (body.len() as u64) < length {
+            return Err(Error::new(io::ErrorKind::UnexpectedEof, "body shorter than Content-Length").into());
+        }
+    }
+
+    Ok((body, HeaderMap::new()))
+}
+
+/// Returns the body length the `Content-Length` headers give.
+///
+/// A message may repeat the header or list several values in one, as long
+/// as they all agree (RFC 7230 §3.3.2), e.g. `Content-Length: 5, 5`.
+///
+/// # Returns
+///
+/// The length, or `None` if there is no `Content-Length` header.
+///
+/// # Errors
+///
+/// Returns `HttpError::ConflictingContentLength` if the values disagree, or
+/// `HttpError::Io` if one is not a plain decimal number.
+pub(crate) fn content_length(headers: &HeaderMap) -> Result<Option<u64>, HttpError> {
+    let mut length = None;
+    for value in headers.get_all("Content-Length").flat_map(|value| value.split(',')) {
+        // `parse` would also take a sign, which a proxy in front may read differently
+        let value = value.trim();
+        let invalid = || Error::new(io::ErrorKind::InvalidData, "invalid Content-Length");
+        if value.is_empty() || !value.bytes().all(|byte| byte.is_ascii_digit()) {
+            return Err(invalid().into());
+        }
+        let value: u64 = value.parse().map_err(|_| invalid())?;
+        if length.is_some_and(|length| length != value) {
+            return Err(HttpError::ConflictingContentLength);
+        }
+        length = Some(value);
+    }
+    Ok(length)
+}
+
+/// Checks whether the body is framed by chunked transfer coding.
+///
+/// A message with a `Transfer-Encoding` header can only be framed if its
+/// final coding is `chunked`, listed once (RFC 7230 §3.3.3); any other body
+/// would run on into the next request on the connection.
+///
+/// # Returns
+///
+/// `true` if the body is chunked, `false` if there is no `Transfer-Encoding` header.
+///
+/// # Errors
+///
+/// Returns `HttpError::UnframedBody` if the final coding is not `chunked`
+/// or `chunked` is listed more than once.
+pub(crate) fn is_chunked(headers: &HeaderMap) -> Result<bool, HttpError> {
+    if !headers.contains("Transfer-Encoding") {
+        return Ok(false);
+    }
+    let codings: Vec<&str> = headers
+        .get_all("Transfer-Encoding")
+        .flat_map(|value| value.split(','))
+        .map(str::trim)
+        .filter(|coding| !coding.is_empty())
+        .collect();
+    let chunked = |coding: &&str| coding.eq_ignore_ascii_case("chunked");
+    if codings.iter().filter(|coding| chunked(coding)).count() > 1 {
+        return Err(HttpError::UnframedBody("chunked is listed more than once"));
+    }
+    if !codings.last().is_some_and(chunked) {
+        return Err(HttpError::UnframedBody("the final transfer coding is not chunked"));
+    }
+    Ok(true)
+}
+
+/// Streams a request body straight to a file instead of buffering it in memory.
+///
+/// The body is read until `reader` reaches end-of-stream, so callers should
+/// pass a reader bounded to the body, e.g. `reader.take(content_length)` or a
+/// [`ChunkedReader`] for `Transfer-Encoding: chunked` uploads. If anything
+/// goes wrong the partially written file is removed.
+///
+/// # Arguments
+///
+/// * `reader` - The body to copy.
+/// * `dest` - The file to create (or truncate).
+/// * `max_bytes` - The largest body that will be accepted.
+///
+/// # Returns
+///
+/// The number of bytes written to `dest`.
+///
+/// # Errors
+///
+/// Returns `HttpError::PayloadTooLarge` if the body is longer than
+/// `max_bytes`, or `HttpError::Io` if reading or writing fails.
+pub fn stream_to_file<R: BufRead>(reader: &mut R, dest: &Path, max_bytes: u64) -> Result<u64, HttpError> {
+    let file = File::create(dest)?;
+    copy_or_remove(reader, file, dest, max_bytes)
+}
+
+// Streams a body to `dest` as `stream_to_file` does, but fails if `dest`
+// already exists, so a file or symlink planted at a guessable path is
+// neither followed nor truncated
+pub(crate) fn stream_to_new_file<R: BufRead>(reader: &mut R, dest: &Path, max_bytes: u64) -> Result<u64, HttpError> {
+    let file = OpenOptions::new().write(true).create_new(true).open(dest)?;
+    copy_or_remove(reader, file, dest, max_bytes)
+}
+
+// Copies the body into `file`, created at `dest`, and removes it if that fails
+fn copy_or_remove<R: BufRead>(reader: &mut R, file: File, dest: &Path, max_bytes: u64) -> Result<u64, HttpError> {
+    let result = copy_limited(reader, file, max_bytes);
+    if result.is_err() {
+        let _ = fs::remove_file(dest);
+    }
+    result
+}
+
+fn copy_limited<R: BufRead>(reader: &mut R, file: File, max_bytes: u64) -> Result<u64, HttpError> {
+    let mut writer = BufWriter::new(file);
+    let mut buf = [0; CHUNK_SIZE];
+    let mut written = 0;
+
+    loop {
+        let count = reader.read(&mut buf)?;
+        if count == 0 {
+            break;
+        }
+        written += count as u64;
+        if written > max_bytes {
+            return Err(HttpError::PayloadTooLarge);
+        }
+        writer.write_all(&buf[..count])?;
+    }
+
+    writer.flush()?;
+    Ok(written)
+}
+
+// Reads a chunk-size line such as "1a3f;ext=value" and returns the size
+fn read_chunk_size<R: BufRead>(reader: &mut R) -> io::Result<u64> {
+    let mut line = String::new();
+    if reader.take(MAX_CHUNK_LINE as u64).read_line(&mut line)? == 0 {
+        return Err(Error::new(io::ErrorKind::UnexpectedEof, "chunked body ended early"));
+    }
+    if !line.ends_with('\n') && line.len() == MAX_CHUNK_LINE {
+        return Err(HttpError::PayloadTooLarge.into_io());
+    }
+
+    let size = line.trim_end().split(';').next().unwrap_or_default().trim();
+    u64::from_str_radix(size, 16)
+        .map_err(|_| Error::new(io::ErrorKind::InvalidData, "invalid chunk size"))
+}
+
+// Consumes the CRLF that terminates every chunk's data
+fn expect_crlf<R: BufRead>(reader: &mut R) -> io::Result<()> {
+    let mut line = String::new();
+    reader.take(2).read_line(&mut line)?;
+    if line.trim_end_matches(['\r', '\n']).is_empty() && line.ends_with('\n') {
+        Ok(())
+    } else {
+        Err(Error::new(io::ErrorKind::InvalidData, "missing CRLF after chunk data"))
+    }
+}
+
+// Reads the (possibly empty) trailer section after the last chunk, failing
+// with `HttpError::HeadersTooLarge` if it is longer than `max_bytes` or has
+// more than `max_fields` fields
+fn read_trailers<R: BufRead>(reader: &mut R, max_bytes: usize, max_fields: usize) -> io::Result<HeaderMap> {
+    let mut reader = LimitedBufReader::new(reader, max_bytes);
+    let mut trailers = HeaderMap::new();
+    let mut line = String::new();
+    loop {
+        line.clear();
+        if reader.read_line(&mut line)? == 0 || line.trim_end().is_empty() {
+            return Ok(trailers);
+        }
+        match line.split_once(':') {
+            Some(_) if trailers.len() == max_fields => return Err(HttpError::HeadersTooLarge.into_io()),
+            Some((name, value)) => trailers.append(name.trim(), value.trim()),
+            None => return Err(Error::new(io::ErrorKind::InvalidData, "invalid trailer field")),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::StatusCode;
+
+    // Reads `body` as a chunked request body with no body limit, `max_trailer_bytes` and two trailers at most
+    fn read_chunked(body: &str, max_trailer_bytes: usize) -> Result<(Vec<u8>, HeaderMap), HttpError> {
+        let mut headers = HeaderMap::new();
+        headers.insert("Transfer-Encoding", "chunked");
+        headers.insert("Trailer", "X-Checksum, X-Padding");
+        read_body(&mut body.as_bytes(), &headers, usize::MAX, max_trailer_bytes, 2)
+    }
+
+    #[test]
+    fn decodes_chunks_and_trailers() {
+        let (body, trailers) = read_chunked("4;ext=1\r\nWiki\r\n5\r\npedia\r\n0\r\nX-Checksum: abc\r\n\r\n", 1024).unwrap();
+        assert_eq!(body, b"Wikipedia");
+        assert_eq!(trailers.get("X-Checksum"), Some("abc"));
+    }
+
+    #[test]
+    fn rejects_an_endless_chunk_extension() {
+        let body = format!("4;ext={}", "a".repeat(2 * MAX_CHUNK_LINE));
+        assert!(matches!(read_chunked(&body, 1024), Err(HttpError::PayloadTooLarge)));
+    }
+
+    #[test]
+    fn rejects_an_oversized_trailer_section() {
+        let body = format!("0\r\nX-Padding: {}\r\n\r\n", "a".repeat(2048));
+        assert!(matches!(read_chunked(&body, 1024), Err(HttpError::HeadersTooLarge)));
+        assert!(read_chunked(&body, 4096).is_ok());
+    }
+
+    #[test]
+    fn rejects_too_many_trailers() {
+        let body = "0\r\nX-Checksum: a\r\nX-Padding: b\r\nX-Checksum: c\r\n\r\n";
+        assert!(matches!(read_chunked(body, 1024), Err(HttpError::HeadersTooLarge)));
+    }
+
+    // Reads `body` framed by the given `Transfer-Encoding` values
+    fn read_encoded(body: &str, codings: &[&str]) -> Result<(Vec<u8>, HeaderMap), HttpError> {
+        let mut headers = HeaderMap::new();
+        for &coding in codings {
+            headers.append("Transfer-Encoding", coding);
+        }
+        read_body(&mut body.as_bytes(), &headers, usize::MAX, 1024, 2)
+    }
+
+    #[test]
+    fn rejects_a_final_coding_other_than_chunked() {
+        // The body would otherwise be read as empty and parsed as the next request
+        let smuggled = "GET /smuggled HTTP/1.1\r\nHost: a\r\n\r\n";
+        for codings in [&["gzip"][..], &["chunked, gzip"], &["x-custom"], &["chunked", "gzip"], &[""]] {
+            let error = read_encoded(smuggled, codings).err().unwrap();
+            assert!(matches!(error, HttpError::UnframedBody(_)), "{codings:?}");
+            assert_eq!(error.status(), StatusCode::BAD_REQUEST);
+        }
+    }
+
+    #[test]
+    fn rejects_chunked_listed_twice() {
+        for codings in [&["chunked, chunked"][..], &["chunked", "Chunked"], &["chunked, gzip, chunked"]] {
+            let result = read_encoded("0\r\n\r\n", codings);
+            assert!(matches!(result, Err(HttpError::UnframedBody(_))), "{codings:?}");
+        }
+    }
+
+    #[test]
+    fn accepts_chunked_as_the_final_coding() {
+        for codings in [&["CHUNKED"][..], &["gzip, chunked"], &["gzip", " chunked "]] {
+            let (body, _) = read_encoded("3\r\nabc\r\n0\r\n\r\n", codings).unwrap();
+            assert_eq!(body, b"abc", "{codings:?}");
+        }
+    }
+}
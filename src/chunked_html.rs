@@ -0,0 +1,55 @@
+use std::io::{Result, Write};
+
+// Sent before the handler's content, so the browser can start parsing right away
+const OPENING_TAGS: &str = "<!DOCTYPE html>\n<html>\n";
+
+// Sent after the handler's content, even if rendering failed part way
+const CLOSING_TAGS: &str = "</html>\n";
+
+/// Writes an HTML page to the client piece by piece, as it is rendered.
+///
+/// Handed to the render closure of [`Response::chunked_html`](crate::Response::chunked_html)
+/// once the headers and the opening `<!DOCTYPE html>` and `<html>` tags
+/// have been sent. Everything written becomes a chunk of the
+/// `Transfer-Encoding: chunked` body; [`ChunkedHtmlWriter::write_html`]
+/// also flushes it, so the browser can display the page so far while the
+/// rest is still being produced. The closing `</html>` tag and the
+/// terminating chunk are sent after the closure returns.
+pub struct ChunkedHtmlWriter<'a> {
+    body: &'a mut dyn Write, // The body's transfer codings, positioned after the opening tags
+}
+
+impl<'a> ChunkedHtmlWriter<'a> {
+    // Starts the page on `body`, which applies the response's transfer codings
+    pub(crate) fn start(body: &'a mut dyn Write) -> Result<ChunkedHtmlWriter<'a>> {
+        body.write_all(OPENING_TAGS.as_bytes())?;
+        body.flush()?;
+        Ok(ChunkedHtmlWriter { body })
+    }
+
+    /// Sends `html` as one chunk and flushes it to the client.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if writing to the connection fails, e.g. because the
+    /// client went away.
+    pub fn write_html(&mut self, html: &str) -> Result<()> {
+        self.body.write_all(html.as_bytes())?;
+        self.body.flush()
+    }
+
+    // Sends the closing tags; the terminating chunk is up to the caller
+    pub(crate) fn finish(self) -> Result<()> {
+        self.body.write_all(CLOSING_TAGS.as_bytes())
+    }
+}
+
+impl Write for ChunkedHtmlWriter<'_> {
+    fn write(&mut self, buf: &[u8]) -> Result<usize> {
+        self.body.write(buf)
+    }
+
+    fn flush(&mut self) -> Result<()> {
+        self.body.flush()
+    }
+}
@@ -0,0 +1,268 @@
+use std::io::{self, BufReader, Write};
+use std::net::TcpStream;
+
+use crate::{HeaderMap, HttpError, HttpMethod, Response};
+
+/// A minimal blocking HTTP/1.1 client for plain `http://` URLs.
+///
+/// Every request opens a new connection and asks the server to close it
+/// once the response has been sent, except that [`HttpClient::pipeline`]
+/// sends a batch of requests on one connection.
+#[derive(Debug, Clone, Default)]
+pub struct HttpClient {
+    default_headers: HeaderMap, // Headers added to every request
+}
+
+impl HttpClient {
+    /// Creates a client with no default headers.
+    pub fn new() -> HttpClient {
+        HttpClient::default()
+    }
+
+    /// Adds a header that is sent with every request made by this client.
+    pub fn default_header(mut self, name: &str, value: &str) -> HttpClient {
+        self.default_headers.insert(name, value);
+        self
+    }
+
+    /// Sends a `GET` request to `url`.
+    ///
+    /// # Errors
+    ///
+    /// See [`RequestBuilder::send`].
+    pub fn get(&self, url: &str) -> Result<Response, HttpError> {
+        self.request(HttpMethod::Get, url).send()
+    }
+
+    /// Sends `requests` on one connection, without waiting for each response.
+    ///
+    /// All requests are written back-to-back (HTTP/1.1 pipelining), and only
+    /// the last one asks the server to close the connection. The responses
+    /// are then read in order. Saves a round trip per request when checking
+    /// many URLs on the same server.
+    ///
+    /// # Returns
+    ///
+    /// One result per request, in order. If the server closes the
+    /// connection part way (many servers answer just one request per
+    /// connection), the responses it sent are returned followed by an
+    /// `HttpError::Io` for every request left unanswered.
+    ///
+    /// # Errors
+    ///
+    /// Returns `HttpError::InvalidUrl` if a URL is not a valid `http://` URL
+    /// or names a different host or port than the first request, or
+    /// `HttpError::Io` if connecting fails.
+    pub fn pipeline(
+        &self,
+        requests: Vec<RequestBuilder>,
+    ) -> Result<Vec<Result<Response, HttpError>>, HttpError> {
+        let Some(first) = requests.first() else {
+            return Ok(Vec::new());
+        };
+        let target = Url::parse(&first.url)?;
+
+        let mut bytes = Vec::new();
+        for (index, request) in requests.iter().enumerate() {
+            let url = Url::parse(&request.url)?;
+            if url.host != target.host || url.port != target.port {
+                return Err(HttpError::InvalidUrl(request.url.clone()));
+            }
+            let last = index + 1 == requests.len();
+            bytes.extend_from_slice(&request.to_bytes(&url, last));
+        }
+
+        let mut stream = TcpStream::connect((target.host.as_str(), target.port))?;
+        // A server that stops reading early refuses the rest; what it did answer is still read
+        let _ = stream.write_all(&bytes).and_then(|()| stream.flush());
+
+        let mut reader = BufReader::new(stream);
+        let mut responses = Vec::with_capacity(requests.len());
+        let mut closed = false;
+        for request in &requests {
+            if closed {
+                responses.push(Err(unanswered()));
+                continue;
+            }
+            let has_body = request.method != HttpMethod::Head;
+            let response =
+                Response::read_message(&mut reader, has_body).map(|(status, headers, body)| {
+                    let mut response = Response::new(status);
+                    response.headers = headers;
+                    response.body = body;
+                    response
+                });
+            closed = match &response {
+                Ok(response) => response
+                    .headers
+                    .connection_tokens()
+                    .iter()
+                    .any(|token| token == "close"),
+                Err(_) => true,
+            };
+            responses.push(response);
+        }
+        Ok(responses)
+    }
+
+    /// Starts building a request with the given method and URL.
+    pub fn request(&self, method: HttpMethod, url: &str) -> RequestBuilder {
+        RequestBuilder {
+            method,
+            url: url.to_string(),
+            headers: self.default_headers.clone(),
+            body: Vec::new(),
+        }
+    }
+}
+
+/// An outbound request being assembled, created by [`HttpClient::request`].
+#[derive(Debug, Clone)]
+pub struct RequestBuilder {
+    method: HttpMethod, // The request method
+    url: String,        // The absolute http:// URL to send the request to
+    headers: HeaderMap, // The request headers
+    body: Vec<u8>,      // The request body
+}
+
+impl RequestBuilder {
+    /// Sets a header, replacing any value already set under `name`.
+    pub fn header(mut self, name: &str, value: &str) -> RequestBuilder {
+        self.headers.insert(name, value);
+        self
+    }
+
+    /// Sets `Authorization: Bearer <token>`.
+    pub fn bearer_auth(self, token: &str) -> RequestBuilder {
+        self.header("Authorization", &format!("Bearer {token}"))
+    }
+
+    /// Sets the request body. `Content-Length` is filled in when the request is sent.
+    pub fn body(mut self, bytes: Vec<u8>) -> RequestBuilder {
+        self.body = bytes;
+        self
+    }
+
+    /// Connects to the server, sends the request and reads the response.
+    ///
+    /// # Errors
+    ///
+    /// Returns `HttpError::InvalidUrl` if the URL is not a valid `http://`
+    /// URL, or `HttpError::Io` if connecting, sending or reading fails or the
+    /// response is malformed.
+    pub fn send(self) -> Result<Response, HttpError> {
+        let url = Url::parse(&self.url)?;
+        let mut stream = TcpStream::connect((url.host.as_str(), url.port))?;
+
+        stream.write_all(&self.to_bytes(&url, true))?;
+        stream.flush()?;
+
+        let (status, headers, body) = Response::from_stream(&mut BufReader::new(stream))?;
+        let mut response = Response::new(status);
+        response.headers = headers;
+        response.body = body;
+        Ok(response)
+    }
+
+    // Serializes the request line, headers and body, asking the server to
+    // close the connection afterwards if `close` is set
+    fn to_bytes(&self, url: &Url, close: bool) -> Vec<u8> {
+        let mut head = format!("{} {} HTTP/1.1\r\n", self.method, url.path);
+        if !self.headers.contains("Host") {
+            head.push_str(&format!("Host: {}\r\n", url.authority()));
+        }
+        for (name, value) in self.headers.iter() {
+            if !name.eq_ignore_ascii_case("Content-Length") {
+                head.push_str(&format!("{name}: {value}\r\n"));
+            }
+        }
+        if !self.body.is_empty()
+            || matches!(
+                self.method,
+                HttpMethod::Post | HttpMethod::Put | HttpMethod::Patch
+            )
+        {
+            head.push_str(&format!("Content-Length: {}\r\n", self.body.len()));
+        }
+        if close {
+            head.push_str("Connection: close\r\n");
+        }
+        head.push_str("\r\n");
+
+        let mut bytes = head.into_bytes();
+        bytes.extend_from_slice(&self.body);
+        bytes
+    }
+}
+
+// The error for a pipelined request the server closed the connection before answering
+fn unanswered() -> HttpError {
+    io::Error::new(
+        io::ErrorKind::ConnectionAborted,
+        "connection closed before the response was sent",
+    )
+    .into()
+}
+
+// The parts of an http:// URL needed to send a request
+struct Url {
+    host: String, // The host name or IP address (IPv6 without brackets)
+    port: u16,    // The port, 80 unless given
+    path: String, // The path and query, at least "/"
+}
+
+impl Url {
+    fn parse(url: &str) -> Result<Url, HttpError> {
+        let invalid = || HttpError::InvalidUrl(url.to_string());
+
+        let rest = url.strip_prefix("http://").ok_or_else(invalid)?;
+        let (authority, path) = match rest.find(['/', '?']) {
+            Some(index) => (&rest[..index], &rest[index..]),
+            None => (rest, "/"),
+        };
+        let path = if path.starts_with('?') {
+            format!("/{path}")
+        } else {
+            path.to_string()
+        };
+
+        // "[::1]:8080", "[::1]", "example.com:8080" or "example.com"
+        let (host, port) = match authority.strip_prefix('[') {
+            Some(bracketed) => {
+                let (host, after) = bracketed.split_once(']').ok_or_else(invalid)?;
+                (host, after.strip_prefix(':'))
+            }
+            None => match authority.rsplit_once(':') {
+                Some((host, port)) => (host, Some(port)),
+                None => (authority, None),
+            },
+        };
+        let port = match port {
+            Some(port) => port.parse().map_err(|_| invalid())?,
+            None => 80,
+        };
+        if host.is_empty() {
+            return Err(invalid());
+        }
+
+        Ok(Url {
+            host: host.to_string(),
+            port,
+            path,
+        })
+    }
+
+    // The value of the Host header for this URL
+    fn authority(&self) -> String {
+        let host = if self.host.contains(':') {
+            format!("[{}]", self.host)
+        } else {
+            self.host.clone()
+        };
+        if self.port == 80 {
+            host
+        } else {
+            format!("{host}:{}", self.port)
+        }
+    }
+}
@@ -0,0 +1,162 @@
+use std::net::IpAddr;
+use std::path::PathBuf;
+use std::sync::Arc;
+use std::time::Duration;
+
+use crate::{
+    ConnLimit, ConnectionRegistry, CorsConfig, LongPollWaiter, MetricsRegistry, RequestCounter,
+};
+
+/// Settings shared by every connection the server handles.
+#[derive(Debug, Clone)]
+pub struct ServerConfig {
+    /// Maximum number of bytes the request line and headers may take up
+    /// before the request is rejected with `431 Request Header Fields Too Large`.
+    /// The trailer section of a chunked body has the same limit.
+    pub max_header_bytes: usize,
+    /// Maximum number of header fields a request may carry before it is
+    /// rejected with `431 Request Header Fields Too Large`. Defaults to 100.
+    /// The trailer section of a chunked body has the same limit.
+    pub max_headers: usize,
+    /// Maximum number of body bytes a request may carry, after chunked
+    /// decoding, before it is rejected with `413 Payload Too Large`.
+    /// Defaults to 1 MiB. The body is read into memory before the handler
+    /// runs, so raise it only as far as the largest upload a route takes,
+    /// e.g. `Some(50 * 1024 * 1024)`; `None` lifts the limit entirely.
+    pub max_body_bytes: Option<usize>,
+    /// How many connections the OS queues for the listening socket before
+    /// `accept` picks them up, as passed to `listen(2)`. Defaults to 128.
+    /// Raise it if bursts of connections are refused. On Linux the kernel
+    /// silently caps it at `net.core.somaxconn`, so values over 1024 need
+    /// that sysctl raised as well.
+    pub bind_backlog: i32,
+    /// The network interface to listen on, e.g. `eth0`. Connections arriving
+    /// on other interfaces, including `lo`, are then not accepted. Uses
+    /// `SO_BINDTODEVICE` on Linux, which needs `CAP_NET_RAW` (or root), and
+    /// `IP_BOUND_IF` on macOS. Building the server fails on other platforms
+    /// rather than listening on every interface. `None` by default.
+    pub bind_interface: Option<String>,
+    /// The long-polling rendezvous shared by every handler. Clone the `Arc`
+    /// into the handlers that wait on or publish events.
+    pub long_poll: Arc<LongPollWaiter>,
+    /// The application metrics handlers record, served by the admin
+    /// endpoint `GET <admin_path>/metrics`. Clone the `Arc` into the handlers
+    /// that record; clones of the config share the registry.
+    pub metrics: Arc<MetricsRegistry>,
+    /// How long a connection may sit idle after a response, waiting for the
+    /// client's next request, before the server closes it. The worker
+    /// serving the connection waits with it, so keep this short. When
+    /// `None`, every response carries `Connection: close` and its
+    /// connection is closed once it is sent. Defaults to 5 seconds.
+    pub keep_alive_timeout: Option<Duration>,
+    /// OS-level TCP keepalive applied to every accepted connection, so dead
+    /// peers on long-lived connections are detected. Requires the
+    /// `keepalive` feature; without it the setting is ignored.
+    pub tcp_keepalive: Option<TcpKeepalive>,
+    /// The receive buffer size (`SO_RCVBUF`) requested for each accepted
+    /// connection, in bytes. Larger buffers help bulk uploads; see
+    /// [`set_socket_buffer_sizes`](crate::set_socket_buffer_sizes) for how
+    /// the OS adjusts it. `None` keeps the OS default.
+    pub socket_recv_buf: Option<usize>,
+    /// The send buffer size (`SO_SNDBUF`) requested for each accepted
+    /// connection, in bytes. Larger buffers help file serving, smaller ones
+    /// keep latency-sensitive responses from queueing. `None` keeps the OS default.
+    pub socket_send_buf: Option<usize>,
+    /// Counts the requests being handled, so shutdown can wait for them with
+    /// [`RequestCounter::wait_drain`]. Clones of the config share the count.
+    pub requests: RequestCounter,
+    /// The most connections open at once. Past this, new connections get
+    /// `503 Service Unavailable` and are closed straight away. Unlimited when
+    /// `None`, the default.
+    pub max_connections: Option<usize>,
+    /// Counts the open connections against `max_connections`. Clones of the
+    /// config share the count.
+    pub conn_limit: ConnLimit,
+    /// The connections currently being handled, as listed by the admin endpoints.
+    pub connections: Arc<ConnectionRegistry>,
+    /// Where the built-in [`AdminHandler`](crate::AdminHandler) endpoints are
+    /// mounted, e.g. `/__admin`. Disabled when `None`, the default.
+    pub admin_path: Option<String>,
+    /// The client addresses allowed to use the admin endpoints. When empty,
+    /// only loopback clients are allowed.
+    pub admin_allowed_ips: Vec<IpAddr>,
+    /// How CORS pre-flight requests are answered. When `None`, the default,
+    /// `OPTIONS` requests get an `Allow` header but no `Access-Control-*` headers.
+    pub cors_config: Option<CorsConfig>,
+    /// Whether `TRACE` requests are echoed back as `message/http`, the
+    /// default. `Cookie` and `Authorization` are left out of the echo, but
+    /// disable this in production anyway: scripts that can send `TRACE`
+    /// could otherwise read headers they are not meant to see. When
+    /// disabled, `TRACE` requests go to the router like any other method.
+    pub allow_trace: bool,
+    /// Caps how fast each response is sent, in bytes per second, by writing
+    /// it through a [`ThrottledWriter`](crate::ThrottledWriter). Handy for
+    /// trying a site over a simulated slow link. Throttled responses are
+    /// never sent with `sendfile(2)`. Unlimited when `None`, the default.
+    pub max_bytes_per_sec_per_conn: Option<u64>,
+    /// Whether every response is checked against its `Content-Type` with
+    /// [`validate_content_type_matches_body`](crate::validate_content_type_matches_body),
+    /// replacing mismatched ones with `500 Internal Server Error` and a
+    /// description of the problem. Meant for development and tests; off
+    /// by default, when responses are sent unchecked.
+    pub debug_mode: bool,
+    /// The HTML of the error pages the server sends, with `{{status}}`,
+    /// `{{reason}}` and `{{message}}` placeholders for the status code,
+    /// its reason phrase and a description of the error (all escaped).
+    /// The built-in page of [`render_error_page`](crate::render_error_page)
+    /// is used when `None`, the default.
+    pub error_page_template: Option<String>,
+    /// A file to append a [`RequestLog`](crate::RequestLog) line to for every
+    /// request the router answers, in JSON Lines format. The file is created
+    /// if needed and opened once, on the first request. No such log is
+    /// written when `None`, the default.
+    pub json_access_log: Option<PathBuf>,
+    /// Whether request bodies are checked against their `Content-MD5`
+    /// header with [`validate_content_md5`](crate::validate_content_md5)
+    /// before routing, rejecting mismatches with `400 Bad Request`.
+    /// Requests without the header are not affected. Requires the `crypto`
+    /// feature; without it the setting is ignored. Off by default.
+    pub validate_content_md5: bool,
+}
+
+/// TCP keepalive timings for accepted connections.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct TcpKeepalive {
+    /// How long a connection must be idle before the first probe is sent (`TCP_KEEPIDLE`).
+    pub idle: Duration,
+    /// How long to wait between unanswered probes (`TCP_KEEPINTVL`).
+    pub interval: Duration,
+    /// How many unanswered probes close the connection (`TCP_KEEPCNT`).
+    pub retries: u32,
+}
+
+impl Default for ServerConfig {
+    fn default() -> ServerConfig {
+        ServerConfig {
+            max_header_bytes: 8 * 1024,
+            max_headers: 100,
+            max_body_bytes: Some(1024 * 1024),
+            bind_backlog: 128,
+            bind_interface: None,
+            long_poll: Arc::new(LongPollWaiter::new()),
+            metrics: Arc::new(MetricsRegistry::new()),
+            keep_alive_timeout: Some(Duration::from_secs(5)),
+            tcp_keepalive: None,
+            socket_recv_buf: None,
+            socket_send_buf: None,
+            requests: RequestCounter::new(),
+            max_connections: None,
+            conn_limit: ConnLimit::new(),
+            connections: Arc::new(ConnectionRegistry::new()),
+            admin_path: None,
+            admin_allowed_ips: Vec::new(),
+            cors_config: None,
+            allow_trace: true,
+            max_bytes_per_sec_per_conn: None,
+            debug_mode: false,
+            error_page_template: None,
+            json_access_log: None,
+            validate_content_md5: false,
+        }
+    }
+}
@@ -0,0 +1,48 @@
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Arc;
+
+/// Counts open connections and refuses new ones past a maximum.
+///
+/// Clones share the same count. Each accepted connection holds a
+/// [`ConnGuard`] until it is closed, so a burst of clients cannot open
+/// more sockets than the limit allows and run the process out of file
+/// descriptors.
+#[derive(Debug, Clone, Default)]
+pub struct ConnLimit(Arc<AtomicUsize>);
+
+impl ConnLimit {
+    /// Creates a limit with no connections open.
+    pub fn new() -> ConnLimit {
+        ConnLimit::default()
+    }
+
+    /// Counts a new connection, unless `max` connections are already open.
+    ///
+    /// # Returns
+    ///
+    /// A guard that keeps the connection counted until it is dropped, or
+    /// `None` if the limit has been reached.
+    pub fn try_acquire(&self, max: usize) -> Option<ConnGuard> {
+        self.0
+            .fetch_update(Ordering::SeqCst, Ordering::SeqCst, |open| {
+                (open < max).then_some(open + 1)
+            })
+            .ok()
+            .map(|_| ConnGuard(Arc::clone(&self.0)))
+    }
+
+    /// Returns the number of connections currently open.
+    pub fn current(&self) -> usize {
+        self.0.load(Ordering::SeqCst)
+    }
+}
+
+/// Keeps a connection counted as open, created by [`ConnLimit::try_acquire`].
+#[derive(Debug)]
+pub struct ConnGuard(Arc<AtomicUsize>);
+
+impl Drop for ConnGuard {
+    fn drop(&mut self) {
+        self.0.fetch_sub(1, Ordering::SeqCst);
+    }
+}
@@ -0,0 +1,170 @@
+use std::io::{self, Read, Write};
+use std::net::{SocketAddr, TcpStream};
+use std::ops::{Deref, DerefMut};
+use std::sync::{Mutex, MutexGuard};
+use std::time::Duration;
+
+use crate::HttpError;
+
+/// Keeps idle connections to an upstream server open for reuse.
+///
+/// Opening a new TCP connection per upstream request costs a round trip
+/// for the handshake and restarts slow start. [`ConnectionPool::acquire`]
+/// hands out an idle connection when one is still usable and connects
+/// otherwise; the [`PooledStream`] goes back into the pool when dropped.
+///
+/// Only hand a stream back after reading the whole response. A connection
+/// with unread data, or one the server has closed, fails the health check
+/// and is dropped instead of being reused.
+#[derive(Debug)]
+pub struct ConnectionPool {
+    addr: SocketAddr,            // The upstream server
+    idle: Mutex<Vec<TcpStream>>, // Connections waiting to be reused, most recent last
+    max_idle: usize,             // Idle connections beyond this are closed
+    connect_timeout: Duration,   // How long to wait for a new connection
+}
+
+impl ConnectionPool {
+    /// Creates an empty pool for connections to `addr`.
+    ///
+    /// # Arguments
+    ///
+    /// * `addr` - The upstream server to connect to.
+    /// * `max_idle` - How many idle connections to keep; `0` disables reuse.
+    /// * `connect_timeout` - How long to wait when opening a new connection.
+    pub fn new(addr: SocketAddr, max_idle: usize, connect_timeout: Duration) -> ConnectionPool {
+        ConnectionPool {
+            addr,
+            idle: Mutex::new(Vec::new()),
+            max_idle,
+            connect_timeout,
+        }
+    }
+
+    /// Returns an idle connection that is still usable, or opens a new one.
+    ///
+    /// Idle connections are tried most recently used first; those that fail
+    /// the health check are closed along the way.
+    ///
+    /// # Errors
+    ///
+    /// Returns `HttpError::Io` if a new connection is needed and cannot be
+    /// opened within the connect timeout.
+    pub fn acquire(&self) -> Result<PooledStream<'_>, HttpError> {
+        while let Some(stream) = self.lock().pop() {
+            if is_reusable(&stream) {
+                return Ok(PooledStream::new(self, stream));
+            }
+        }
+        let stream = TcpStream::connect_timeout(&self.addr, self.connect_timeout)?;
+        Ok(PooledStream::new(self, stream))
+    }
+
+    /// Closes the idle connections that fail the health check.
+    ///
+    /// Servers close idle keep-alive connections after a while; call this
+    /// periodically so they are not held open on this side.
+    pub fn prune_stale(&self) {
+        self.lock().retain(is_reusable);
+    }
+
+    /// Returns the number of idle connections in the pool.
+    pub fn idle_count(&self) -> usize {
+        self.lock().len()
+    }
+
+    // Locks the idle list. Pushes and pops cannot leave it half changed, so poisoning is ignored
+    fn lock(&self) -> MutexGuard<'_, Vec<TcpStream>> {
+        self.idle
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner())
+    }
+
+    // Takes a connection back, unless it is unusable or the pool is full
+    fn release(&self, stream: TcpStream) {
+        if !is_reusable(&stream) {
+            return;
+        }
+        let mut idle = self.lock();
+        if idle.len() < self.max_idle {
+            idle.push(stream);
+        }
+    }
+}
+
+/// A connection from a [`ConnectionPool`], returned to it when dropped.
+///
+/// Derefs to the `TcpStream` and implements `Read` and `Write` itself.
+/// Call [`PooledStream::discard`] to close a connection that must not be
+/// reused, e.g. after the server answered with `Connection: close`.
+#[derive(Debug)]
+pub struct PooledStream<'a> {
+    pool: &'a ConnectionPool,  // The pool the stream goes back to
+    stream: Option<TcpStream>, // The connection, taken on drop or discard
+}
+
+impl<'a> PooledStream<'a> {
+    fn new(pool: &'a ConnectionPool, stream: TcpStream) -> PooledStream<'a> {
+        PooledStream {
+            pool,
+            stream: Some(stream),
+        }
+    }
+
+    /// Closes the connection instead of returning it to the pool.
+    pub fn discard(mut self) {
+        self.stream.take();
+    }
+}
+
+impl Deref for PooledStream<'_> {
+    type Target = TcpStream;
+
+    fn deref(&self) -> &TcpStream {
+        self.stream.as_ref().expect("stream is only taken on drop")
+    }
+}
+
+impl DerefMut for PooledStream<'_> {
+    fn deref_mut(&mut self) -> &mut TcpStream {
+        self.stream.as_mut().expect("stream is only taken on drop")
+    }
+}
+
+impl Read for PooledStream<'_> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        self.deref_mut().read(buf)
+    }
+}
+
+impl Write for PooledStream<'_> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.deref_mut().write(buf)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.deref_mut().flush()
+    }
+}
+
+impl Drop for PooledStream<'_> {
+    fn drop(&mut self) {
+        if let Some(stream) = self.stream.take() {
+            self.pool.release(stream);
+        }
+    }
+}
+
+// Whether an idle connection can carry another request: peeking without
+// blocking must find nothing to read yet. End of stream means the server
+// closed it, and pending bytes are the rest of an unread response.
+fn is_reusable(stream: &TcpStream) -> bool {
+    if stream.set_nonblocking(true).is_err() {
+        return false;
+    }
+    let idle = matches!(
+        stream.peek(&mut [0; 1]),
+        Err(err) if err.kind() == io::ErrorKind::WouldBlock
+    );
+    stream.set_nonblocking(false).is_ok() && idle
+}
@@ -0,0 +1,52 @@
+use std::any::{Any, TypeId};
+use std::collections::HashMap;
+use std::fmt;
+
+/// Data handlers attach to the connection a request arrived on, one value per type.
+///
+/// `handle_connection` creates one store per connection and shares it with
+/// every request read from that connection, so a value set while handling
+/// one request (e.g. the user a login authenticated) is there for the
+/// next one the client sends on a kept-alive connection; see
+/// [`Request::connection_state`](crate::Request::connection_state) and
+/// `ServerConfig::keep_alive_timeout`.
+/// Values are keyed by their type, so wrap plain types in a newtype to
+/// keep them apart.
+#[derive(Default)]
+pub struct ConnectionState(HashMap<TypeId, Box<dyn Any + Send>>);
+
+impl ConnectionState {
+    /// Creates an empty store.
+    pub fn new() -> ConnectionState {
+        ConnectionState::default()
+    }
+
+    /// Returns the value of type `T`, if one was set.
+    pub fn get<T: 'static>(&self) -> Option<&T> {
+        self.0.get(&TypeId::of::<T>())?.downcast_ref()
+    }
+
+    /// Returns the value of type `T` for changing in place, if one was set.
+    pub fn get_mut<T: 'static>(&mut self) -> Option<&mut T> {
+        self.0.get_mut(&TypeId::of::<T>())?.downcast_mut()
+    }
+
+    /// Stores `val`, replacing the value of the same type, if any.
+    pub fn set<T: Any + Send + 'static>(&mut self, val: T) {
+        self.0.insert(TypeId::of::<T>(), Box::new(val));
+    }
+
+    /// Removes and returns the value of type `T`, if one was set.
+    pub fn remove<T: 'static>(&mut self) -> Option<T> {
+        let value = self.0.remove(&TypeId::of::<T>())?;
+        value.downcast().ok().map(|value| *value)
+    }
+}
+
+impl fmt::Debug for ConnectionState {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("ConnectionState")
+            .field("values", &self.0.len())
+            .finish()
+    }
+}
@@ -0,0 +1,216 @@
+use std::collections::HashMap;
+
+use crate::headers::{split_unquoted, unquote};
+use crate::json::is_valid_json;
+use crate::{HttpError, Response};
+
+/// A parsed `Content-Type` header, e.g. `multipart/form-data; boundary="abc 123"`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ContentType {
+    /// The media type in lowercase, e.g. `application/json`.
+    pub mime: String,
+    /// The parameters, with lowercase names and unquoted values.
+    pub params: HashMap<String, String>,
+}
+
+impl ContentType {
+    /// Parses a `Content-Type` header value; see [`parse_media_type`].
+    ///
+    /// # Returns
+    ///
+    /// The parsed value, or `None` if the media type is not of the form `type/subtype`.
+    pub fn parse(value: &str) -> Option<ContentType> {
+        let (mime, params) = parse_media_type(value).ok()?;
+        Some(ContentType { mime, params })
+    }
+
+    /// Returns `true` for `application/json` and `+json` types like `application/problem+json`.
+    pub fn is_json(&self) -> bool {
+        self.mime == "application/json"
+            || (self.mime.starts_with("application/") && self.mime.ends_with("+json"))
+    }
+
+    /// Returns `true` for form submissions, urlencoded or `multipart/form-data`.
+    pub fn is_form(&self) -> bool {
+        self.mime == "application/x-www-form-urlencoded" || self.mime == "multipart/form-data"
+    }
+
+    /// Returns the `charset` parameter, if any.
+    pub fn charset(&self) -> Option<&str> {
+        self.params.get("charset").map(String::as_str)
+    }
+}
+
+/// Parses a media type with parameters, e.g. `text/html; charset=utf-8`.
+///
+/// The type and subtype are lowercased. Parameter names are
+/// case-insensitive and stored in lowercase; values may be quoted, in which
+/// case they may contain `;` and `\"`, and are returned unquoted.
+/// Parameters without a `=` are skipped rather than rejected.
+///
+/// # Returns
+///
+/// The `type/subtype` and the parameters.
+///
+/// # Errors
+///
+/// Returns `HttpError::InvalidMediaType` if the value does not start with
+/// a `type/subtype` pair.
+pub fn parse_media_type(s: &str) -> Result<(String, HashMap<String, String>), HttpError> {
+    let invalid = || HttpError::InvalidMediaType(s.to_string());
+
+    let mut parts = split_unquoted(s, ';').into_iter();
+    let mime = parts.next().ok_or_else(invalid)?.to_ascii_lowercase();
+    let (kind, subtype) = mime.split_once('/').ok_or_else(invalid)?;
+    if kind.is_empty() || subtype.is_empty() || mime.contains(char::is_whitespace) {
+        return Err(invalid());
+    }
+
+    let params = parts
+        .filter_map(|param| param.split_once('='))
+        .map(|(name, value)| (name.trim().to_ascii_lowercase(), unquote(value.trim())))
+        .filter(|(name, _)| !name.is_empty())
+        .collect();
+    Ok((mime, params))
+}
+
+/// Checks that a response's body is what its `Content-Type` says it is.
+///
+/// Catches handlers that label an error message or a template fragment as
+/// JSON or as a page: a JSON body must parse as JSON (nested at most 128
+/// levels deep), and an HTML body must start with `<!DOCTYPE` or `<html`
+/// (ignoring case and leading whitespace). Other types, empty bodies and
+/// streamed bodies are not checked. The server runs this on every
+/// response when `ServerConfig::debug_mode` is set.
+///
+/// # Errors
+///
+/// Returns a description of the mismatch.
+pub fn validate_content_type_matches_body(response: &Response) -> Result<(), String> {
+    let Some(content_type) = response
+        .headers
+        .get("Content-Type")
+        .and_then(ContentType::parse)
+    else {
+        return Ok(());
+    };
+    if response.body.is_empty() || response.is_streamed() {
+        return Ok(());
+    }
+
+    if content_type.is_json() {
+        let valid = std::str::from_utf8(&response.body).is_ok_and(is_valid_json);
+        if !valid {
+            return Err(format!(
+                "the body is labelled {} but is not valid JSON",
+                content_type.mime
+            ));
+        }
+    } else if content_type.mime == "text/html" {
+        let start = response.body.trim_ascii_start();
+        let is_page = [&b"<!doctype"[..], b"<html"].iter().any(|tag| {
+            start
+                .get(..tag.len())
+                .is_some_and(|prefix| prefix.eq_ignore_ascii_case(tag))
+        });
+        if !is_page {
+            return Err(
+                "the body is labelled text/html but does not start with <!DOCTYPE or <html"
+                    .to_string(),
+            );
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::StatusCode;
+
+    fn response(content_type: &str, body: &str) -> Response {
+        let mut response = Response::new(StatusCode::OK);
+        response.set_header("Content-Type", content_type);
+        response.body = body.as_bytes().to_vec();
+        response
+    }
+
+    #[test]
+    fn lowercases_types_and_parameter_names() {
+        let (mime, params) = parse_media_type("Text/HTML; Charset=UTF-8").unwrap();
+        assert_eq!(mime, "text/html");
+        assert_eq!(params["charset"], "UTF-8");
+        assert_eq!(params.len(), 1);
+    }
+
+    #[test]
+    fn unquotes_parameter_values() {
+        let value = r#"multipart/form-data; boundary="a; b=\"c\"" ;name = plain"#;
+        let content_type = ContentType::parse(value).unwrap();
+        assert_eq!(content_type.mime, "multipart/form-data");
+        assert_eq!(content_type.params["boundary"], r#"a; b="c""#);
+        assert_eq!(content_type.params["name"], "plain");
+    }
+
+    #[test]
+    fn skips_parameters_without_values() {
+        let (mime, params) = parse_media_type("text/plain; flowed; =x; ;charset=utf-8").unwrap();
+        assert_eq!(mime, "text/plain");
+        assert_eq!(params.len(), 1);
+        assert_eq!(params["charset"], "utf-8");
+    }
+
+    #[test]
+    fn rejects_values_without_a_type_and_subtype() {
+        for value in [
+            "",
+            "  ",
+            "text",
+            "text/",
+            "/html",
+            "text /html",
+            "; charset=utf-8",
+        ] {
+            let err = parse_media_type(value).err();
+            assert!(
+                matches!(&err, Some(HttpError::InvalidMediaType(v)) if v == value),
+                "{value:?}"
+            );
+            assert_eq!(ContentType::parse(value), None, "{value:?}");
+        }
+    }
+
+    #[test]
+    fn classifies_json_and_form_types() {
+        let parse = |value| ContentType::parse(value).unwrap();
+        assert!(parse("application/json; charset=utf-8").is_json());
+        assert!(parse("application/problem+json").is_json());
+        assert!(!parse("text/json+x").is_json());
+        assert!(parse("application/x-www-form-urlencoded").is_form());
+        assert!(parse("multipart/form-data; boundary=x").is_form());
+        assert!(!parse("multipart/mixed").is_form());
+        assert_eq!(
+            parse("text/plain;charset=\"latin1\"").charset(),
+            Some("latin1")
+        );
+        assert_eq!(parse("text/plain").charset(), None);
+    }
+
+    #[test]
+    fn checks_bodies_against_their_labels() {
+        assert!(
+            validate_content_type_matches_body(&response("application/json", "{\"a\":[1]}"))
+                .is_ok()
+        );
+        assert!(validate_content_type_matches_body(&response("application/json", "oops")).is_err());
+        assert!(
+            validate_content_type_matches_body(&response("text/html", "\n <!DocType html>"))
+                .is_ok()
+        );
+        assert!(validate_content_type_matches_body(&response("text/html", "<HTML>")).is_ok());
+        assert!(validate_content_type_matches_body(&response("text/html", "<p>hi</p>")).is_err());
+        assert!(validate_content_type_matches_body(&response("text/plain", "{")).is_ok());
+        assert!(validate_content_type_matches_body(&response("application/json", "")).is_ok());
+        assert!(validate_content_type_matches_body(&response("not a type", "{")).is_ok());
+    }
+}
@@ -0,0 +1,318 @@
+use std::time::Duration;
+
+use crate::router::join_methods;
+use crate::{HttpMethod, Middleware, Request, Response, Router, StatusCode};
+
+/// Which cross-origin requests browsers may send.
+///
+/// Set as `ServerConfig::cors_config`, it answers CORS pre-flights for every
+/// route; [`cors_middleware`] also adds `Access-Control-Allow-Origin` to the
+/// actual responses.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct CorsConfig {
+    /// The origins allowed to call the server, e.g. `https://app.example.com`.
+    /// When empty or containing `*`, every origin is allowed.
+    pub allowed_origins: Vec<String>,
+    /// The methods cross-origin requests may use. When empty, the methods
+    /// the path has routes for are allowed, or, for [`cors_middleware`],
+    /// the method the browser asks for.
+    pub allowed_methods: Vec<HttpMethod>,
+    /// The request headers cross-origin requests may carry. When empty, the
+    /// headers the browser asks for are allowed.
+    pub allowed_headers: Vec<String>,
+    /// Whether cross-origin requests may send cookies and credentials.
+    pub allow_credentials: bool,
+    /// How long browsers may cache a pre-flight answer.
+    pub max_age: Option<Duration>,
+}
+
+impl CorsConfig {
+    /// Answers `req` if it is a CORS pre-flight for a path the router has routes for.
+    ///
+    /// A pre-flight is an `OPTIONS` request with `Origin` and
+    /// `Access-Control-Request-Method` headers. It is answered with
+    /// `204 No Content`, an `Allow` header listing the path's methods and,
+    /// when the origin is allowed, the matching `Access-Control-*` headers.
+    /// Paths with their own `OPTIONS` route are left to it.
+    ///
+    /// # Returns
+    ///
+    /// The pre-flight response, or `None` if the router should handle `req`.
+    pub(crate) fn preflight(&self, req: &Request, router: &Router) -> Option<Response> {
+        if req.method != HttpMethod::Options.as_str()
+            || !req.headers.contains("Access-Control-Request-Method")
+        {
+            return None;
+        }
+        let origin = req.headers.get("Origin")?;
+
+        let mut methods = router.allowed_methods(&req.path);
+        if methods.is_empty() || methods.contains(&HttpMethod::Options) {
+            return None;
+        }
+        methods.push(HttpMethod::Options);
+
+        let mut response = Response::new(StatusCode::NO_CONTENT);
+        let allowed = join_methods(&methods);
+        response.set_header("Allow", &allowed);
+        if self.allowed_methods.is_empty() {
+            self.answer_preflight(req, origin, &allowed, &mut response);
+        } else {
+            let methods = join_methods(&self.allowed_methods);
+            self.answer_preflight(req, origin, &methods, &mut response);
+        }
+        Some(response)
+    }
+
+    // Adds the Access-Control-* headers of a pre-flight answer allowing `methods`
+    fn answer_preflight(
+        &self,
+        req: &Request,
+        origin: &str,
+        methods: &str,
+        response: &mut Response,
+    ) {
+        if !self.allow_origin(origin, response) {
+            return;
+        }
+        response.set_header("Access-Control-Allow-Methods", methods);
+
+        let headers = if self.allowed_headers.is_empty() {
+            req.headers
+                .get("Access-Control-Request-Headers")
+                .map(str::to_string)
+        } else {
+            Some(self.allowed_headers.join(", "))
+        };
+        if let Some(headers) = headers {
+            response.set_header("Access-Control-Allow-Headers", &headers);
+        }
+        if let Some(max_age) = self.max_age {
+            response.set_header("Access-Control-Max-Age", &max_age.as_secs().to_string());
+        }
+    }
+
+    // Adds Vary: Origin and, if `origin` is allowed, Access-Control-Allow-Origin
+    // and Access-Control-Allow-Credentials. Returns whether it is allowed.
+    fn allow_origin(&self, origin: &str, response: &mut Response) -> bool {
+        response.vary(&["Origin"]);
+        if !self.allows_origin(origin) {
+            return false;
+        }
+
+        // Credentialed requests are refused by browsers unless the origin is echoed back
+        if self.allows_any_origin() && !self.allow_credentials {
+            response.set_header("Access-Control-Allow-Origin", "*");
+        } else {
+            response.set_header("Access-Control-Allow-Origin", origin);
+        }
+        if self.allow_credentials {
+            response.set_header("Access-Control-Allow-Credentials", "true");
+        }
+        true
+    }
+
+    // Returns whether `origin` may make cross-origin requests
+    fn allows_origin(&self, origin: &str) -> bool {
+        self.allows_any_origin()
+            || self
+                .allowed_origins
+                .iter()
+                .any(|allowed| allowed.eq_ignore_ascii_case(origin))
+    }
+
+    // Returns whether every origin is allowed
+    fn allows_any_origin(&self) -> bool {
+        self.allowed_origins.is_empty() || self.allowed_origins.iter().any(|allowed| allowed == "*")
+    }
+}
+
+/// Creates middleware that applies `config` to cross-origin requests.
+///
+/// Pre-flights (`OPTIONS` with `Origin` and `Access-Control-Request-Method`)
+/// are answered with `204 No Content` and the `Access-Control-Allow-*`
+/// headers without reaching the handler. Other requests with an `Origin`
+/// header run as usual, and their responses get `Access-Control-Allow-Origin`
+/// and `Vary: Origin` when the origin is allowed. Requests without `Origin`
+/// are not cross-origin and are left alone.
+///
+/// `*` is only sent as the allowed origin when `allow_credentials` is
+/// false; browsers reject it on credentialed requests, so the request's
+/// origin is echoed back instead.
+///
+/// Register it with [`Router::middleware`] so it also sees the pre-flights
+/// for paths that have no `OPTIONS` route.
+pub fn cors_middleware(config: CorsConfig) -> impl Middleware {
+    CorsMiddleware(config)
+}
+
+// The middleware returned by `cors_middleware`
+struct CorsMiddleware(CorsConfig);
+
+impl Middleware for CorsMiddleware {
+    fn handle(&self, req: Request, next: &dyn Fn(Request) -> Response) -> Response {
+        let Some(origin) = req.headers.get("Origin").map(str::to_string) else {
+            return next(req);
+        };
+
+        let requested_method = req.headers.get("Access-Control-Request-Method");
+        if let (true, Some(requested)) =
+            (req.method == HttpMethod::Options.as_str(), requested_method)
+        {
+            let methods = if self.0.allowed_methods.is_empty() {
+                requested.trim().to_string()
+            } else {
+                join_methods(&self.0.allowed_methods)
+            };
+            let mut response = Response::new(StatusCode::NO_CONTENT);
+            self.0
+                .answer_preflight(&req, &origin, &methods, &mut response);
+            return response;
+        }
+
+        let mut response = next(req);
+        self.0.allow_origin(&origin, &mut response);
+        response
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const APP: &str = "https://app.example.com";
+
+    fn router(config: CorsConfig) -> Router {
+        let mut router = Router::new();
+        router.get("/items", |_| Response::new(StatusCode::OK));
+        router.post("/items", |_| Response::new(StatusCode::CREATED));
+        router.middleware(cors_middleware(config));
+        router
+    }
+
+    fn request(head: &str) -> Request {
+        Request::new(format!("{head}\r\n\r\n").as_bytes()).unwrap()
+    }
+
+    fn get_from(origin: &str, config: CorsConfig) -> Response {
+        router(config).handle(request(&format!("GET /items HTTP/1.1\r\nOrigin: {origin}")))
+    }
+
+    fn preflight(origin: &str) -> Request {
+        request(&format!(
+            "OPTIONS /items HTTP/1.1\r\nOrigin: {origin}\r\n\
+             Access-Control-Request-Method: POST\r\nAccess-Control-Request-Headers: X-Token"
+        ))
+    }
+
+    fn allowed_origin(response: &Response) -> Option<&str> {
+        response.headers.get("Access-Control-Allow-Origin")
+    }
+
+    #[test]
+    fn allows_any_origin_with_a_wildcard() {
+        for allowed_origins in [vec![], vec!["*".to_string()]] {
+            let config = CorsConfig {
+                allowed_origins,
+                ..CorsConfig::default()
+            };
+            let response = get_from(APP, config);
+            assert_eq!(response.status, StatusCode::OK);
+            assert_eq!(allowed_origin(&response), Some("*"));
+            assert_eq!(
+                response.headers.get("Access-Control-Allow-Credentials"),
+                None
+            );
+            assert_eq!(response.headers.get("Vary"), Some("Origin"));
+        }
+    }
+
+    #[test]
+    fn echoes_the_origin_when_credentials_are_allowed() {
+        let config = CorsConfig {
+            allowed_origins: vec!["*".to_string()],
+            allow_credentials: true,
+            ..CorsConfig::default()
+        };
+        let response = get_from(APP, config);
+        assert_eq!(allowed_origin(&response), Some(APP));
+        assert_eq!(
+            response.headers.get("Access-Control-Allow-Credentials"),
+            Some("true")
+        );
+    }
+
+    #[test]
+    fn echoes_listed_origins_and_refuses_others() {
+        let config = CorsConfig {
+            allowed_origins: vec!["https://APP.example.com".to_string()],
+            ..CorsConfig::default()
+        };
+        let response = get_from(APP, config.clone());
+        assert_eq!(allowed_origin(&response), Some(APP));
+        assert_eq!(response.headers.get("Vary"), Some("Origin"));
+
+        let response = get_from("https://evil.example", config);
+        assert_eq!(response.status, StatusCode::OK);
+        assert_eq!(allowed_origin(&response), None);
+        assert_eq!(response.headers.get("Vary"), Some("Origin"));
+    }
+
+    #[test]
+    fn leaves_same_origin_requests_alone() {
+        let response = router(CorsConfig::default()).handle(request("GET /items HTTP/1.1"));
+        assert_eq!(allowed_origin(&response), None);
+        assert_eq!(response.headers.get("Vary"), None);
+    }
+
+    #[test]
+    fn answers_preflights_from_the_middleware() {
+        let config = CorsConfig {
+            allowed_origins: vec![APP.to_string()],
+            max_age: Some(Duration::from_secs(600)),
+            ..CorsConfig::default()
+        };
+        let response = router(config.clone()).handle(preflight(APP));
+        assert_eq!(response.status, StatusCode::NO_CONTENT);
+        assert_eq!(allowed_origin(&response), Some(APP));
+        let methods = response.headers.get("Access-Control-Allow-Methods");
+        assert_eq!(methods, Some("POST"));
+        let headers = response.headers.get("Access-Control-Allow-Headers");
+        assert_eq!(headers, Some("X-Token"));
+        let max_age = response.headers.get("Access-Control-Max-Age");
+        assert_eq!(max_age, Some("600"));
+
+        let response = router(config).handle(preflight("https://evil.example"));
+        assert_eq!(response.status, StatusCode::NO_CONTENT);
+        assert_eq!(allowed_origin(&response), None);
+        assert_eq!(response.headers.get("Access-Control-Allow-Methods"), None);
+    }
+
+    #[test]
+    fn answers_preflights_with_the_routes_methods() {
+        let mut router = Router::new();
+        router.get("/items", |_| Response::new(StatusCode::OK));
+        router.post("/items", |_| Response::new(StatusCode::CREATED));
+        let config = CorsConfig {
+            allowed_headers: vec!["Content-Type".to_string()],
+            ..CorsConfig::default()
+        };
+
+        let response = config.preflight(&preflight(APP), &router).unwrap();
+        assert_eq!(response.status, StatusCode::NO_CONTENT);
+        assert_eq!(response.headers.get("Allow"), Some("GET, POST, OPTIONS"));
+        let methods = response.headers.get("Access-Control-Allow-Methods");
+        assert_eq!(methods, Some("GET, POST, OPTIONS"));
+        let headers = response.headers.get("Access-Control-Allow-Headers");
+        assert_eq!(headers, Some("Content-Type"));
+        assert_eq!(response.headers.get("Vary"), Some("Origin"));
+
+        assert!(config
+            .preflight(&request("OPTIONS /items HTTP/1.1"), &router)
+            .is_none());
+        let unrouted = request(&format!(
+            "OPTIONS /missing HTTP/1.1\r\nOrigin: {APP}\r\nAccess-Control-Request-Method: GET"
+        ));
+        assert!(config.preflight(&unrouted, &router).is_none());
+    }
+}
@@ -0,0 +1,188 @@
+use std::sync::{Arc, Mutex, OnceLock};
+use std::thread;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+const WEEKDAYS: [&str; 7] = ["Thu", "Fri", "Sat", "Sun", "Mon", "Tue", "Wed"];
+const MONTHS: [&str; 12] = [
+    "Jan", "Feb", "Mar", "Apr", "May", "Jun", "Jul", "Aug", "Sep", "Oct", "Nov", "Dec",
+];
+
+/// Formats `time` as an HTTP-date (RFC 7231 §7.1.1.1), e.g.
+/// `Sun, 06 Nov 1994 08:49:37 GMT`.
+///
+/// Times before the Unix epoch are clamped to the epoch.
+pub fn format_http_date(time: SystemTime) -> String {
+    let secs = time
+        .duration_since(UNIX_EPOCH)
+        .map(|elapsed| elapsed.as_secs())
+        .unwrap_or_default();
+
+    let days = secs / 86_400;
+    let seconds_of_day = secs % 86_400;
+    let (hour, minute, second) = (
+        seconds_of_day / 3600,
+        seconds_of_day % 3600 / 60,
+        seconds_of_day % 60,
+    );
+    let (year, month, day) = civil_from_days(days);
+
+    format!(
+        "{}, {day:02} {} {year} {hour:02}:{minute:02}:{second:02} GMT",
+        WEEKDAYS[(days % 7) as usize],
+        MONTHS[month as usize - 1],
+    )
+}
+
+/// The current time as an HTTP-date, re-formatted once a second by a background thread.
+///
+/// `Date` has a resolution of one second, so formatting the clock for
+/// every response repeats the same work. [`DateCache::get`] only locks and
+/// copies the string. The thread wakes just after each second starts and
+/// exits once the cache is dropped.
+#[derive(Debug)]
+pub struct DateCache {
+    current: Arc<Mutex<String>>, // The formatted time, rewritten by the refresh thread
+}
+
+impl DateCache {
+    /// Formats the current time and starts the thread that keeps it up to date.
+    pub fn new() -> DateCache {
+        let current = Arc::new(Mutex::new(format_http_date(SystemTime::now())));
+        let weak = Arc::downgrade(&current);
+        thread::spawn(move || loop {
+            let now = SystemTime::now();
+            let into_second = now
+                .duration_since(UNIX_EPOCH)
+                .unwrap_or_default()
+                .subsec_nanos();
+            thread::sleep(Duration::from_nanos(u64::from(1_000_000_000 - into_second)));
+
+            let Some(current) = weak.upgrade() else {
+                break;
+            };
+            let formatted = format_http_date(SystemTime::now());
+            *current
+                .lock()
+                .unwrap_or_else(|poisoned| poisoned.into_inner()) = formatted;
+        });
+        DateCache { current }
+    }
+
+    /// Returns the current time as an HTTP-date, at most about a second old.
+    pub fn get(&self) -> String {
+        self.current
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner())
+            .clone()
+    }
+}
+
+impl Default for DateCache {
+    fn default() -> DateCache {
+        DateCache::new()
+    }
+}
+
+// The current time as an HTTP-date, from a cache shared by the whole process
+pub(crate) fn current_http_date() -> String {
+    static CACHE: OnceLock<DateCache> = OnceLock::new();
+    CACHE.get_or_init(DateCache::new).get()
+}
+
+// Formats `time` as an RFC 3339 UTC timestamp with milliseconds, e.g.
+// `1994-11-06T08:49:37.000Z`; times before the epoch are clamped to it
+pub(crate) fn format_rfc3339(time: SystemTime) -> String {
+    let elapsed = time.duration_since(UNIX_EPOCH).unwrap_or_default();
+    let secs = elapsed.as_secs();
+    let seconds_of_day = secs % 86_400;
+    let (year, month, day) = civil_from_days(secs / 86_400);
+    format!(
+        "{year}-{month:02}-{day:02}T{:02}:{:02}:{:02}.{:03}Z",
+        seconds_of_day / 3600,
+        seconds_of_day % 3600 / 60,
+        seconds_of_day % 60,
+        elapsed.subsec_millis(),
+    )
+}
+
+// Converts days since 1970-01-01 into a (year, month, day) civil date
+// (Howard Hinnant's `civil_from_days`, restricted to dates after the epoch)
+fn civil_from_days(days: u64) -> (u64, u64, u64) {
+    let z = days + 719_468;
+    let era = z / 146_097;
+    let day_of_era = z % 146_097;
+    let year_of_era =
+        (day_of_era - day_of_era / 1460 + day_of_era / 36_524 - day_of_era / 146_096) / 365;
+    let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+    let shifted_month = (5 * day_of_year + 2) / 153;
+    let day = day_of_year - (153 * shifted_month + 2) / 5 + 1;
+    let month = if shifted_month < 10 {
+        shifted_month + 3
+    } else {
+        shifted_month - 9
+    };
+    let year = year_of_era + era * 400 + u64::from(month <= 2);
+    (year, month, day)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn at(secs: u64) -> SystemTime {
+        UNIX_EPOCH + Duration::from_secs(secs)
+    }
+
+    #[test]
+    fn formats_the_rfc_example() {
+        assert_eq!(
+            format_http_date(at(784_111_777)),
+            "Sun, 06 Nov 1994 08:49:37 GMT"
+        );
+    }
+
+    #[test]
+    fn formats_leap_days_and_year_ends() {
+        let dates = [
+            (0, "Thu, 01 Jan 1970 00:00:00 GMT"),
+            (5_097_600, "Sun, 01 Mar 1970 00:00:00 GMT"),
+            (951_868_799, "Tue, 29 Feb 2000 23:59:59 GMT"),
+            (1_735_646_400, "Tue, 31 Dec 2024 12:00:00 GMT"),
+            (4_107_542_400, "Mon, 01 Mar 2100 00:00:00 GMT"),
+        ];
+        for (secs, formatted) in dates {
+            assert_eq!(format_http_date(at(secs)), formatted);
+        }
+    }
+
+    #[test]
+    fn clamps_times_before_the_epoch() {
+        let before = UNIX_EPOCH - Duration::from_secs(86_400);
+        assert_eq!(format_http_date(before), "Thu, 01 Jan 1970 00:00:00 GMT");
+        assert_eq!(format_rfc3339(before), "1970-01-01T00:00:00.000Z");
+    }
+
+    #[test]
+    fn converts_days_to_civil_dates() {
+        assert_eq!(civil_from_days(0), (1970, 1, 1));
+        assert_eq!(civil_from_days(59), (1970, 3, 1));
+        assert_eq!(civil_from_days(9_075), (1994, 11, 6));
+        assert_eq!(civil_from_days(11_016), (2000, 2, 29));
+        assert_eq!(civil_from_days(47_541), (2100, 3, 1));
+    }
+
+    #[test]
+    fn formats_rfc3339_with_milliseconds() {
+        let time = at(784_111_777) + Duration::from_micros(45_678);
+        assert_eq!(format_rfc3339(time), "1994-11-06T08:49:37.045Z");
+    }
+
+    #[test]
+    fn caches_the_current_date() {
+        let before = format_http_date(SystemTime::now());
+        let cached = DateCache::new().get();
+        let after = format_http_date(SystemTime::now());
+        assert!(cached == before || cached == after, "{cached}");
+        assert_eq!(cached.len(), "Sun, 06 Nov 1994 08:49:37 GMT".len());
+    }
+}
@@ -0,0 +1,113 @@
+use sha2::{Digest, Sha256, Sha512};
+
+use crate::{base64, HeaderMap, HttpError, Middleware, Request, Response};
+
+/// The algorithms a `Digest` header (RFC 3230) can be computed with.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DigestAlgorithm {
+    /// SHA-256, sent as `SHA-256=<base64>`.
+    Sha256,
+    /// SHA-512, sent as `SHA-512=<base64>`.
+    Sha512,
+}
+
+impl DigestAlgorithm {
+    /// Returns the algorithm's name as used in `Digest` and `Want-Digest`.
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            DigestAlgorithm::Sha256 => "SHA-256",
+            DigestAlgorithm::Sha512 => "SHA-512",
+        }
+    }
+
+    /// Picks the algorithm the client prefers from a `Want-Digest` header.
+    ///
+    /// Entries look like `SHA-512;q=0.3, sha-256`; names are matched
+    /// case-insensitively, a missing `q` counts as `1` and `q=0` rules the
+    /// algorithm out. Unsupported algorithms are ignored.
+    ///
+    /// # Returns
+    ///
+    /// The supported algorithm with the highest weight, or `None` if the
+    /// header names none of them.
+    pub fn from_want_digest(header: &str) -> Option<DigestAlgorithm> {
+        let mut best: Option<(DigestAlgorithm, f32)> = None;
+        for entry in header.split(',') {
+            let mut parts = entry.split(';');
+            let name = parts.next().unwrap_or_default().trim();
+            let algorithm = if name.eq_ignore_ascii_case("SHA-256") {
+                DigestAlgorithm::Sha256
+            } else if name.eq_ignore_ascii_case("SHA-512") {
+                DigestAlgorithm::Sha512
+            } else {
+                continue;
+            };
+
+            let weight = parts
+                .find_map(|param| param.trim().strip_prefix("q="))
+                .map_or(Some(1.0), |q| q.trim().parse::<f32>().ok())
+                .unwrap_or(0.0);
+            if weight > 0.0 && best.is_none_or(|(_, best)| weight > best) {
+                best = Some((algorithm, weight));
+            }
+        }
+        best.map(|(algorithm, _)| algorithm)
+    }
+
+    /// Computes the `Digest` header value of `body`, e.g. `SHA-256=X48E9q...`.
+    pub fn header_value(&self, body: &[u8]) -> String {
+        let hash = match self {
+            DigestAlgorithm::Sha256 => Sha256::digest(body).to_vec(),
+            DigestAlgorithm::Sha512 => Sha512::digest(body).to_vec(),
+        };
+        format!("{}={}", self.as_str(), base64::encode(&hash))
+    }
+}
+
+/// Checks `body` against the `Content-MD5` header (RFC 1864), if there is one.
+///
+/// The header carries the base64-encoded MD5 digest of the body. This only
+/// catches corruption in transit: MD5 is broken, and anyone who can change
+/// the body can change the header to match, so it proves nothing about
+/// who sent the body. The server runs this on every request when
+/// `ServerConfig::validate_content_md5` is set.
+///
+/// # Errors
+///
+/// Returns `HttpError::ContentMd5Mismatch` if the header is present and
+/// does not match the body's digest.
+pub fn validate_content_md5(headers: &HeaderMap, body: &[u8]) -> Result<(), HttpError> {
+    let Some(expected) = headers.get("Content-MD5") else {
+        return Ok(());
+    };
+    if base64::encode(&md5::compute(body).0) != expected.trim() {
+        return Err(HttpError::ContentMd5Mismatch);
+    }
+    Ok(())
+}
+
+/// Adds a `Digest` header to responses whose request sent `Want-Digest`.
+///
+/// The algorithm is chosen with [`DigestAlgorithm::from_want_digest`].
+/// Streamed responses are left alone, since their body is not known until
+/// it has been sent.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct DigestMiddleware;
+
+impl Middleware for DigestMiddleware {
+    fn handle(&self, req: Request, next: &dyn Fn(Request) -> Response) -> Response {
+        let algorithm = req
+            .headers
+            .get("Want-Digest")
+            .and_then(DigestAlgorithm::from_want_digest);
+
+        let mut response = next(req);
+        response.vary(&["Want-Digest"]);
+        if let Some(algorithm) = algorithm {
+            if !response.is_streamed() {
+                response.with_digest(algorithm);
+            }
+        }
+        response
+    }
+}
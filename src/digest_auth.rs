@@ -0,0 +1,375 @@
+use std::collections::HashMap;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+use crate::crypto::constant_time_eq;
+use crate::random::secure_random_u128;
+use crate::{parse_authorization, AuthScheme, Middleware, Request, Response, StatusCode};
+
+// How long a nonce may be used after it was issued, unless configured
+const DEFAULT_NONCE_TTL: Duration = Duration::from_secs(300);
+
+// How many outstanding nonces are kept, unless configured
+const DEFAULT_MAX_NONCES: usize = 1024;
+
+// An issued nonce that has not been used yet
+struct NonceEntry {
+    issued: Instant, // When the nonce was sent to the client
+}
+
+/// Requires HTTP Digest authentication (RFC 7616) with `MD5-sess` and `qop=auth`.
+///
+/// Unlike Basic authentication, the password never crosses the wire: the
+/// client proves it knows it by hashing it with a server-chosen nonce. Each
+/// nonce is good for a single request within its lifetime, and every
+/// successful response carries `Authentication-Info: nextnonce=...` for the
+/// client's next request. Requests without valid credentials get
+/// `401 Unauthorized` with a fresh challenge, marked `stale=true` when only
+/// the nonce was out of date so clients can retry without asking the user.
+///
+/// Outstanding nonces are kept in memory up to a bound; when it is reached,
+/// expired nonces are dropped first and then the oldest ones.
+///
+/// MD5 is weak by today's standards, so this only protects passwords from
+/// passive observers; use HTTPS where that is not enough.
+pub struct DigestAuthMiddleware {
+    realm: String,                  // Shown to the user and mixed into every hash
+    users: HashMap<String, String>, // Username to MD5(username:realm:password), in hex
+    nonces: Mutex<HashMap<String, NonceEntry>>, // Issued, unused nonces
+    nonce_ttl: Duration,            // How long a nonce stays valid
+    max_nonces: usize,              // The most nonces kept at once
+}
+
+impl DigestAuthMiddleware {
+    /// Creates a middleware for `realm` with no users.
+    pub fn new(realm: &str) -> DigestAuthMiddleware {
+        DigestAuthMiddleware {
+            realm: realm.to_string(),
+            users: HashMap::new(),
+            nonces: Mutex::new(HashMap::new()),
+            nonce_ttl: DEFAULT_NONCE_TTL,
+            max_nonces: DEFAULT_MAX_NONCES,
+        }
+    }
+
+    /// Adds a user. Only a hash of the password is kept.
+    pub fn user(mut self, username: &str, password: &str) -> DigestAuthMiddleware {
+        let ha1 = md5_hex(&format!("{username}:{}:{password}", self.realm));
+        self.users.insert(username.to_string(), ha1);
+        self
+    }
+
+    /// Sets how long a nonce may be used after it is issued. Defaults to five minutes.
+    pub fn nonce_ttl(mut self, nonce_ttl: Duration) -> DigestAuthMiddleware {
+        self.nonce_ttl = nonce_ttl;
+        self
+    }
+
+    /// Sets how many unused nonces are kept at once. Defaults to 1024.
+    pub fn max_nonces(mut self, max_nonces: usize) -> DigestAuthMiddleware {
+        self.max_nonces = max_nonces.max(1);
+        self
+    }
+
+    // Creates and records a new nonce, evicting old ones if the store is full
+    fn issue_nonce(&self) -> String {
+        let nonce = format!("{:032x}", secure_random_u128());
+        let mut nonces = self.nonces.lock().unwrap();
+
+        if nonces.len() >= self.max_nonces {
+            nonces.retain(|_, entry| entry.issued.elapsed() < self.nonce_ttl);
+        }
+        while nonces.len() >= self.max_nonces {
+            let oldest = nonces
+                .iter()
+                .min_by_key(|(_, entry)| entry.issued)
+                .map(|(nonce, _)| nonce.clone());
+            match oldest {
+                Some(oldest) => nonces.remove(&oldest),
+                None => break,
+            };
+        }
+
+        nonces.insert(
+            nonce.clone(),
+            NonceEntry {
+                issued: Instant::now(),
+            },
+        );
+        nonce
+    }
+
+    // Removes `nonce` from the store, returning whether it was valid
+    fn take_nonce(&self, nonce: &str) -> bool {
+        self.nonces
+            .lock()
+            .unwrap()
+            .remove(nonce)
+            .is_some_and(|entry| entry.issued.elapsed() < self.nonce_ttl)
+    }
+
+    // Checks the Authorization header of `req`
+    fn verify(&self, req: &Request) -> Verdict {
+        let credentials = req.headers.get("Authorization").map(parse_authorization);
+        let Some(Ok(AuthScheme::Digest(params))) = credentials else {
+            return Verdict::Missing;
+        };
+        let param = |name: &str| params.get(name).map(String::as_str);
+
+        let (Some(username), Some(nonce), Some(uri), Some(response), Some(cnonce), Some(nc)) = (
+            param("username"),
+            param("nonce"),
+            param("uri"),
+            param("response"),
+            param("cnonce"),
+            param("nc"),
+        ) else {
+            return Verdict::Invalid;
+        };
+        if param("realm") != Some(self.realm.as_str())
+            || param("qop") != Some("auth")
+            || !param("algorithm").is_some_and(|alg| alg.eq_ignore_ascii_case("MD5-sess"))
+            || uri != req.path
+        {
+            return Verdict::Invalid;
+        }
+        let Some(user_ha1) = self.users.get(username) else {
+            return Verdict::Invalid;
+        };
+
+        // Each nonce is consumed by its first use, whether or not the response matches
+        if !self.take_nonce(nonce) {
+            return Verdict::Stale;
+        }
+
+        let ha1 = md5_hex(&format!("{user_ha1}:{nonce}:{cnonce}"));
+        let ha2 = md5_hex(&format!("{}:{uri}", req.method));
+        let expected = md5_hex(&format!("{ha1}:{nonce}:{nc}:{cnonce}:auth:{ha2}"));
+        if constant_time_eq(
+            expected.as_bytes(),
+            response.to_ascii_lowercase().as_bytes(),
+        ) {
+            Verdict::Valid
+        } else {
+            Verdict::Invalid
+        }
+    }
+
+    // Builds a 401 response carrying a fresh challenge
+    fn challenge(&self, stale: bool) -> Response {
+        let mut challenge = format!(
+            "Digest realm=\"{}\", qop=\"auth\", algorithm=MD5-sess, nonce=\"{}\"",
+            self.realm.replace(['\\', '"'], ""),
+            self.issue_nonce()
+        );
+        if stale {
+            challenge.push_str(", stale=true");
+        }
+
+        let mut response = Response::new(StatusCode::UNAUTHORIZED);
+        response.set_header("WWW-Authenticate", &challenge);
+        response
+    }
+}
+
+// The outcome of checking a request's credentials
+enum Verdict {
+    Valid,   // The credentials are correct
+    Missing, // No Digest credentials were sent
+    Stale,   // The nonce is unknown, used or expired
+    Invalid, // The credentials are malformed or wrong
+}
+
+impl Middleware for DigestAuthMiddleware {
+    fn handle(&self, req: Request, next: &dyn Fn(Request) -> Response) -> Response {
+        match self.verify(&req) {
+            Verdict::Valid => {
+                let mut response = next(req);
+                let next_nonce = format!("nextnonce=\"{}\"", self.issue_nonce());
+                response.set_header("Authentication-Info", &next_nonce);
+                response
+            }
+            Verdict::Stale => self.challenge(true),
+            Verdict::Missing | Verdict::Invalid => self.challenge(false),
+        }
+    }
+}
+
+// Returns the MD5 digest of `input` as lowercase hex
+fn md5_hex(input: &str) -> String {
+    format!("{:x}", md5::compute(input))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // The example credentials of RFC 7616 §3.9.1
+    const REALM: &str = "test@example.com";
+    const URI: &str = "/dir/index.html";
+    const CNONCE: &str = "f2/wE4q74E6zIJEtWaHKaf5wv/H5QzzpXusqGemxURZJ";
+
+    fn middleware() -> DigestAuthMiddleware {
+        DigestAuthMiddleware::new(REALM).user("Mufasa", "Circle of Life")
+    }
+
+    // Records `nonce` as issued, as a challenge would
+    fn issue(middleware: &DigestAuthMiddleware, nonce: &str) {
+        let entry = NonceEntry {
+            issued: Instant::now(),
+        };
+        middleware
+            .nonces
+            .lock()
+            .unwrap()
+            .insert(nonce.to_string(), entry);
+    }
+
+    // Builds the Authorization header a client would send for `password`
+    fn authorization(nonce: &str, password: &str, params: &[(&str, &str)]) -> String {
+        let mut fields = vec![
+            ("username", "Mufasa".to_string()),
+            ("realm", REALM.to_string()),
+            ("uri", URI.to_string()),
+            ("algorithm", "MD5-sess".to_string()),
+            ("qop", "auth".to_string()),
+            ("nc", "00000001".to_string()),
+            ("cnonce", CNONCE.to_string()),
+            ("nonce", nonce.to_string()),
+        ];
+        for &(name, value) in params {
+            fields.retain(|(field, _)| *field != name);
+            fields.push((name, value.to_string()));
+        }
+        let field = |name: &str| &fields.iter().find(|(field, _)| *field == name).unwrap().1;
+
+        let user_ha1 = md5_hex(&format!("Mufasa:{REALM}:{password}"));
+        let ha1 = md5_hex(&format!("{user_ha1}:{nonce}:{CNONCE}"));
+        let ha2 = md5_hex(&format!("GET:{}", field("uri")));
+        let response = md5_hex(&format!("{ha1}:{nonce}:00000001:{CNONCE}:auth:{ha2}"));
+
+        let mut header = format!("Digest response=\"{response}\"");
+        for (name, value) in &fields {
+            header.push_str(&format!(", {name}=\"{value}\""));
+        }
+        header
+    }
+
+    fn send(middleware: &DigestAuthMiddleware, authorization: Option<&str>) -> Response {
+        let mut head = format!("GET {URI} HTTP/1.1\r\n");
+        if let Some(authorization) = authorization {
+            head.push_str(&format!("Authorization: {authorization}\r\n"));
+        }
+        head.push_str("\r\n");
+        let req = Request::new(head.as_bytes()).unwrap();
+        middleware.handle(req, &|_| Response::new(StatusCode::OK))
+    }
+
+    fn challenge_nonce(response: &Response) -> String {
+        let challenge = response.headers.get("WWW-Authenticate").unwrap();
+        let nonce = challenge.split("nonce=\"").nth(1).unwrap();
+        nonce[..nonce.find('"').unwrap()].to_string()
+    }
+
+    fn is_stale(response: &Response) -> bool {
+        let challenge = response.headers.get("WWW-Authenticate").unwrap();
+        challenge.ends_with(", stale=true")
+    }
+
+    #[test]
+    fn matches_known_md5_sess_answers() {
+        let nonce = "7ypf/xlj9XXwfDPEoM4URrv/xwf94BcCAzFZH4GiTo0v";
+        let middleware = middleware();
+        assert_eq!(
+            middleware.users["Mufasa"],
+            "b3f889f3115e10a1ace872151f45262f"
+        );
+
+        issue(&middleware, nonce);
+        let authorization = format!(
+            "Digest username=\"Mufasa\", realm=\"{REALM}\", nonce=\"{nonce}\", \
+             uri=\"{URI}\", algorithm=MD5-sess, qop=auth, nc=00000001, \
+             cnonce=\"{CNONCE}\", response=\"70AFE51DDC6BAE33EDCF68C47CD83103\""
+        );
+        let response = send(&middleware, Some(&authorization));
+        assert_eq!(response.status, StatusCode::OK);
+        let info = response.headers.get("Authentication-Info").unwrap();
+        assert!(info.starts_with("nextnonce=\""));
+    }
+
+    #[test]
+    fn challenges_requests_without_credentials() {
+        let response = send(&middleware(), None);
+        assert_eq!(response.status, StatusCode::UNAUTHORIZED);
+        let challenge = response.headers.get("WWW-Authenticate").unwrap();
+        assert!(challenge.starts_with(
+            "Digest realm=\"test@example.com\", qop=\"auth\", algorithm=MD5-sess, nonce=\""
+        ));
+        assert!(!is_stale(&response));
+        assert_eq!(challenge_nonce(&response).len(), 32);
+    }
+
+    #[test]
+    fn marks_replayed_and_expired_nonces_stale() {
+        let middleware = middleware();
+        let nonce = challenge_nonce(&send(&middleware, None));
+        let credentials = authorization(&nonce, "Circle of Life", &[]);
+
+        assert_eq!(send(&middleware, Some(&credentials)).status, StatusCode::OK);
+        let replayed = send(&middleware, Some(&credentials));
+        assert_eq!(replayed.status, StatusCode::UNAUTHORIZED);
+        assert!(is_stale(&replayed));
+
+        let middleware = self::middleware().nonce_ttl(Duration::ZERO);
+        issue(&middleware, "abc");
+        let expired = send(
+            &middleware,
+            Some(&authorization("abc", "Circle of Life", &[])),
+        );
+        assert!(is_stale(&expired));
+    }
+
+    #[test]
+    fn refuses_wrong_passwords_and_parameters() {
+        let middleware = middleware();
+        issue(&middleware, "abc");
+        let wrong = [
+            authorization("abc", "Circle of Death", &[]),
+            authorization("abc", "Circle of Life", &[("uri", "/dir/other.html")]),
+            authorization("abc", "Circle of Life", &[("realm", "other@example.com")]),
+            authorization("abc", "Circle of Life", &[("qop", "auth-int")]),
+            authorization("abc", "Circle of Life", &[("algorithm", "MD5")]),
+            authorization("abc", "Circle of Life", &[("username", "Scar")]),
+        ];
+        for authorization in &wrong {
+            let response = send(&middleware, Some(authorization));
+            assert_eq!(response.status, StatusCode::UNAUTHORIZED, "{authorization}");
+            assert!(!is_stale(&response), "{authorization}");
+        }
+        assert!(!is_stale(&send(&middleware, Some("Basic TXVmYXNhOg=="))));
+    }
+
+    #[test]
+    fn evicts_the_oldest_nonces_when_full() {
+        let middleware = middleware().max_nonces(2);
+        // Far enough apart that the issue times always differ
+        let nonces: Vec<String> = (0..3)
+            .map(|_| {
+                std::thread::sleep(Duration::from_millis(2));
+                challenge_nonce(&send(&middleware, None))
+            })
+            .collect();
+        assert_eq!(middleware.nonces.lock().unwrap().len(), 2);
+
+        let evicted = send(
+            &middleware,
+            Some(&authorization(&nonces[0], "Circle of Life", &[])),
+        );
+        assert!(is_stale(&evicted));
+        let kept = send(
+            &middleware,
+            Some(&authorization(&nonces[2], "Circle of Life", &[])),
+        );
+        assert_eq!(kept.status, StatusCode::OK);
+    }
+}
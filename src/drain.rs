@@ -0,0 +1,59 @@
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Arc;
+use std::thread;
+use std::time::{Duration, Instant};
+
+// How often `wait_drain` checks the counter
+const POLL_INTERVAL: Duration = Duration::from_millis(10);
+
+/// Counts the requests that are currently being handled.
+///
+/// Clones share the same count, so one clone can be kept by the shutdown
+/// path while the server hands out [`RequestGuard`]s from another.
+#[derive(Debug, Clone, Default)]
+pub struct RequestCounter(Arc<AtomicUsize>);
+
+impl RequestCounter {
+    /// Creates a counter with no requests in flight.
+    pub fn new() -> RequestCounter {
+        RequestCounter::default()
+    }
+
+    /// Marks a request as started. It counts as in flight until the guard is dropped.
+    pub fn guard(&self) -> RequestGuard {
+        self.0.fetch_add(1, Ordering::SeqCst);
+        RequestGuard(Arc::clone(&self.0))
+    }
+
+    /// Returns the number of requests currently in flight.
+    pub fn active(&self) -> usize {
+        self.0.load(Ordering::SeqCst)
+    }
+
+    /// Blocks until no requests are in flight or `timeout` has elapsed.
+    ///
+    /// # Returns
+    ///
+    /// `true` if every request finished, `false` if the timeout elapsed first.
+    pub fn wait_drain(&self, timeout: Duration) -> bool {
+        let deadline = Instant::now() + timeout;
+        while self.active() > 0 {
+            let now = Instant::now();
+            if now >= deadline {
+                return false;
+            }
+            thread::sleep(POLL_INTERVAL.min(deadline - now));
+        }
+        true
+    }
+}
+
+/// Keeps a request counted as in flight, created by [`RequestCounter::guard`].
+#[derive(Debug)]
+pub struct RequestGuard(Arc<AtomicUsize>);
+
+impl Drop for RequestGuard {
+    fn drop(&mut self) {
+        self.0.fetch_sub(1, Ordering::SeqCst);
+    }
+}
@@ -0,0 +1,137 @@
+use std::error::Error;
+use std::fmt;
+use std::io;
+
+use crate::StatusCode;
+
+/// Errors that can occur while reading or handling an HTTP message.
+#[derive(Debug)]
+pub enum HttpError {
+    /// The underlying stream failed or the message could not be parsed.
+    Io(io::Error),
+    /// The request line and headers exceeded `ServerConfig::max_header_bytes`,
+    /// or there were more header fields than `ServerConfig::max_headers`.
+    HeadersTooLarge,
+    /// A header line is malformed, e.g. it has no colon, whitespace before
+    /// the colon, or a name that is not a token.
+    InvalidHeader(&'static str),
+    /// The request path climbs above the root with `..` segments.
+    InvalidPath,
+    /// The body is longer than the limit the server accepts.
+    PayloadTooLarge,
+    /// The request sent both `Content-Length` and `Transfer-Encoding`, so its
+    /// body could be framed two ways (a request smuggling attempt).
+    AmbiguousBody,
+    /// The request sent several `Content-Length` values that disagree, so
+    /// its body could be framed more than one way (a request smuggling attempt).
+    ConflictingContentLength,
+    /// The request's `Transfer-Encoding` does not end in `chunked`, or lists
+    /// it more than once, so the length of its body cannot be determined.
+    UnframedBody(&'static str),
+    /// A chunked body carried a trailer field not announced in the `Trailer` header.
+    UndeclaredTrailer(String),
+    /// A URL given to the `HttpClient` is not a valid `http://` URL.
+    InvalidUrl(String),
+    /// A server component was configured with inconsistent settings.
+    InvalidConfig(&'static str),
+    /// An `Authorization` header could not be parsed.
+    InvalidAuthorization(&'static str),
+    /// A `Transfer-Encoding` header lists codings that cannot be applied as given.
+    InvalidTransferEncoding(&'static str),
+    /// A strictly rendered template used a placeholder that was given no value.
+    MissingTemplateVariable(String),
+    /// A media type such as a `Content-Type` value is not of the form `type/subtype`.
+    InvalidMediaType(String),
+    /// The body does not match the MD5 digest its `Content-MD5` header claims.
+    ContentMd5Mismatch,
+    /// The request has no usable `Host` header to build a URL from.
+    MissingHost,
+    /// A `Link` header could not be parsed.
+    InvalidLinkHeader(&'static str),
+}
+
+impl HttpError {
+    /// Returns the status code that should be sent to the client for this error.
+    pub fn status(&self) -> StatusCode {
+        match self {
+            HttpError::Io(_) => StatusCode::BAD_REQUEST,
+            HttpError::HeadersTooLarge => StatusCode::REQUEST_HEADER_FIELDS_TOO_LARGE,
+            HttpError::InvalidHeader(_) => StatusCode::BAD_REQUEST,
+            HttpError::InvalidPath => StatusCode::BAD_REQUEST,
+            HttpError::PayloadTooLarge => StatusCode::PAYLOAD_TOO_LARGE,
+            HttpError::AmbiguousBody => StatusCode::BAD_REQUEST,
+            HttpError::ConflictingContentLength => StatusCode::BAD_REQUEST,
+            HttpError::UnframedBody(_) => StatusCode::BAD_REQUEST,
+            HttpError::UndeclaredTrailer(_) => StatusCode::BAD_REQUEST,
+            HttpError::InvalidUrl(_) => StatusCode::BAD_REQUEST,
+            HttpError::InvalidConfig(_) => StatusCode::INTERNAL_SERVER_ERROR,
+            HttpError::InvalidAuthorization(_) => StatusCode::BAD_REQUEST,
+            HttpError::InvalidTransferEncoding(_) => StatusCode::INTERNAL_SERVER_ERROR,
+            HttpError::MissingTemplateVariable(_) => StatusCode::INTERNAL_SERVER_ERROR,
+            HttpError::InvalidMediaType(_) => StatusCode::BAD_REQUEST,
+            HttpError::ContentMd5Mismatch => StatusCode::BAD_REQUEST,
+            HttpError::MissingHost => StatusCode::BAD_REQUEST,
+            HttpError::InvalidLinkHeader(_) => StatusCode::BAD_REQUEST,
+        }
+    }
+
+    /// Wraps this error in an `io::Error` so it can travel through `Read`
+    /// and `BufRead` implementations; `From<io::Error>` unwraps it again.
+    pub(crate) fn into_io(self) -> io::Error {
+        io::Error::new(io::ErrorKind::InvalidData, self)
+    }
+}
+
+impl fmt::Display for HttpError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            HttpError::Io(err) => write!(f, "{err}"),
+            HttpError::HeadersTooLarge => write!(f, "request headers too large"),
+            HttpError::InvalidHeader(reason) => write!(f, "invalid header field: {reason}"),
+            HttpError::InvalidPath => write!(f, "request path escapes the root"),
+            HttpError::PayloadTooLarge => write!(f, "request body too large"),
+            HttpError::AmbiguousBody => {
+                write!(f, "request has both Content-Length and Transfer-Encoding")
+            }
+            HttpError::ConflictingContentLength => {
+                write!(f, "request has conflicting Content-Length values")
+            }
+            HttpError::UnframedBody(reason) => write!(f, "request body cannot be framed: {reason}"),
+            HttpError::UndeclaredTrailer(name) => write!(f, "undeclared trailer field: {name}"),
+            HttpError::InvalidUrl(url) => write!(f, "invalid URL: {url}"),
+            HttpError::InvalidConfig(reason) => write!(f, "invalid configuration: {reason}"),
+            HttpError::InvalidAuthorization(reason) => {
+                write!(f, "invalid Authorization header: {reason}")
+            }
+            HttpError::InvalidTransferEncoding(reason) => {
+                write!(f, "invalid Transfer-Encoding: {reason}")
+            }
+            HttpError::MissingTemplateVariable(key) => {
+                write!(f, "no value for template placeholder {{{{{key}}}}}")
+            }
+            HttpError::InvalidMediaType(value) => write!(f, "invalid media type: {value}"),
+            HttpError::ContentMd5Mismatch => write!(f, "body does not match Content-MD5"),
+            HttpError::MissingHost => write!(f, "request has no usable Host header"),
+            HttpError::InvalidLinkHeader(reason) => write!(f, "invalid Link header: {reason}"),
+        }
+    }
+}
+
+impl Error for HttpError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match self {
+            HttpError::Io(err) => Some(err),
+            _ => None,
+        }
+    }
+}
+
+impl From<io::Error> for HttpError {
+    fn from(err: io::Error) -> HttpError {
+        // Recover errors that were smuggled through an io::Error by `into_io`
+        if err.get_ref().is_some_and(|inner| inner.is::<HttpError>()) {
+            return *err.into_inner().unwrap().downcast::<HttpError>().unwrap();
+        }
+        HttpError::Io(err)
+    }
+}
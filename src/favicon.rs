@@ -0,0 +1,52 @@
+use crate::static_files::etag_of;
+use crate::{Request, Response, StatusCode};
+
+// A 1x1 transparent PNG; browsers accept PNG data at /favicon.ico
+const FAVICON: &[u8] = include_bytes!("../assets/favicon.png");
+
+// Long enough that browsers do not ask again on every page view
+const FAVICON_CACHE_CONTROL: &str = "max-age=86400";
+
+/// Serves a built-in transparent 1x1 PNG at `/favicon.ico`.
+///
+/// Browsers request `/favicon.ico` on every visit, so without it each new
+/// visitor costs a 404. [`Router`](crate::Router) answers `GET` and `HEAD`
+/// for `/favicon.ico` with this handler when no route matches, so mounting
+/// a `/favicon.ico` route of your own replaces it.
+#[derive(Debug, Clone)]
+pub struct FaviconHandler {
+    etag: String, // The strong ETag of the icon, computed once
+}
+
+impl FaviconHandler {
+    /// Creates the handler, computing the icon's `ETag`.
+    pub fn new() -> FaviconHandler {
+        FaviconHandler {
+            etag: etag_of(FAVICON),
+        }
+    }
+
+    /// Returns the icon, or `304 Not Modified` if `If-None-Match` has its `ETag`.
+    ///
+    /// Either way the response carries `Cache-Control: max-age=86400`.
+    pub fn serve(&self, req: &Request) -> Response {
+        let cached = req.headers.get("If-None-Match").is_some_and(|tags| {
+            tags.split(',')
+                .any(|tag| tag.trim() == self.etag || tag.trim() == "*")
+        });
+        let mut response = if cached {
+            Response::new(StatusCode::NOT_MODIFIED)
+        } else {
+            Response::with_body(StatusCode::OK, "image/png", FAVICON.to_vec())
+        };
+        response.set_header("ETag", &self.etag);
+        response.set_header("Cache-Control", FAVICON_CACHE_CONTROL);
+        response
+    }
+}
+
+impl Default for FaviconHandler {
+    fn default() -> FaviconHandler {
+        FaviconHandler::new()
+    }
+}
@@ -0,0 +1,274 @@
+use std::net::IpAddr;
+
+use crate::headers::{split_unquoted, unquote};
+use crate::HeaderMap;
+
+/// What a proxy reported about one hop of a forwarded request (RFC 7239).
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct ForwardedInfo {
+    /// The address of the client that made the request to the proxy.
+    pub forwarded_for: Option<IpAddr>,
+    /// The protocol the client used, e.g. `https`.
+    pub proto: Option<String>,
+    /// The address of the proxy's interface the request came in on.
+    pub by: Option<IpAddr>,
+    /// The `Host` the client asked for.
+    pub host: Option<String>,
+}
+
+/// Parses the values of every `Forwarded` header into one entry per hop.
+///
+/// Several headers, or several comma-separated elements in one header, form
+/// a single list ordered from the original client to the last proxy. Values
+/// may be quoted, and node names may be IPv6 literals with or without a port
+/// (`"[2001:db8::1]:4711"`). Obfuscated or `unknown` nodes are reported as
+/// `None`, and unknown parameters are ignored.
+pub fn parse_forwarded<'a>(values: impl IntoIterator<Item = &'a str>) -> Vec<ForwardedInfo> {
+    let mut hops = Vec::new();
+    for value in values {
+        for element in split_unquoted(value, ',') {
+            let mut info = ForwardedInfo::default();
+            for pair in split_unquoted(element, ';') {
+                let Some((name, value)) = pair.split_once('=') else {
+                    continue;
+                };
+                let value = unquote(value.trim());
+                match name.trim().to_ascii_lowercase().as_str() {
+                    "for" => info.forwarded_for = parse_node(&value),
+                    "by" => info.by = parse_node(&value),
+                    "proto" => info.proto = Some(value.to_ascii_lowercase()),
+                    "host" => info.host = Some(value),
+                    _ => {}
+                }
+            }
+            hops.push(info);
+        }
+    }
+    hops
+}
+
+/// Returns what the proxies closest to the client reported about a request.
+///
+/// The first hop of the `Forwarded` headers is used. Without a `Forwarded`
+/// header, the first address of `X-Forwarded-For` and the values of
+/// `X-Forwarded-Proto` and `X-Forwarded-Host` are used instead. These headers
+/// are set by clients as easily as by proxies, so only trust them when the
+/// server is reachable through trusted proxies alone.
+pub(crate) fn forwarded_info(headers: &HeaderMap) -> ForwardedInfo {
+    if headers.contains("Forwarded") {
+        return parse_forwarded(headers.get_all("Forwarded"))
+            .into_iter()
+            .next()
+            .unwrap_or_default();
+    }
+
+    let first = |name: &str| {
+        headers
+            .get(name)
+            .and_then(|value| value.split(',').next())
+            .map(|value| value.trim().to_string())
+            .filter(|value| !value.is_empty())
+    };
+    ForwardedInfo {
+        forwarded_for: first("X-Forwarded-For").and_then(|node| parse_node(&node)),
+        proto: first("X-Forwarded-Proto").map(|proto| proto.to_ascii_lowercase()),
+        by: None,
+        host: first("X-Forwarded-Host"),
+    }
+}
+
+/// How many proxies in front of the server may be believed about the client's address.
+///
+/// Every proxy appends the address it received the request from to
+/// `X-Forwarded-For`, but a client can send the header too, pre-filled with
+/// any addresses it likes. Taking the first entry as the client therefore
+/// lets anyone claim any IP, e.g. to dodge a rate limit or get past an IP
+/// allowlist. Only the entries appended by your own proxies can be
+/// believed, and those are at the end of the list: with `trusted_hops`
+/// proxies, the entry `trusted_hops` from the right is the address the
+/// outermost of them saw, and anything left of it is up to the client.
+/// [`Request::client_ip`](crate::Request::client_ip) picks that entry.
+///
+/// Set `trusted_hops` to the number of proxies every request passes through:
+///
+/// * Clients connect directly: `0`. `X-Forwarded-For` is ignored and the
+///   socket's peer address is used.
+/// * One reverse proxy or load balancer: `1`. The last entry, which it
+///   appended, is the client.
+/// * A CDN in front of a load balancer: `2`. The second entry from the
+///   right, appended by the CDN, is the client.
+///
+/// Too high a count lets clients spoof their address again; too low a count
+/// reports one of your proxies as the client.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct TrustProxy {
+    /// The number of trusted proxies between the clients and the server.
+    pub trusted_hops: usize,
+}
+
+impl TrustProxy {
+    /// Trusts the last `trusted_hops` entries of `X-Forwarded-For`.
+    pub fn new(trusted_hops: usize) -> TrustProxy {
+        TrustProxy { trusted_hops }
+    }
+
+    /// Returns the client's address as seen by the outermost trusted proxy.
+    ///
+    /// # Returns
+    ///
+    /// The `X-Forwarded-For` entry `trusted_hops` from the right, or
+    /// `peer` if `trusted_hops` is 0, the header has fewer entries, or the
+    /// entry is not an IP address.
+    pub fn client_ip(&self, headers: &HeaderMap, peer: Option<IpAddr>) -> Option<IpAddr> {
+        if self.trusted_hops == 0 {
+            return peer;
+        }
+        let entries: Vec<&str> = headers
+            .get_all("X-Forwarded-For")
+            .flat_map(|value| value.split(','))
+            .map(str::trim)
+            .collect();
+        entries
+            .len()
+            .checked_sub(self.trusted_hops)
+            .and_then(|index| parse_node(entries[index]))
+            .or(peer)
+    }
+}
+
+// Parses a node such as `192.0.2.43`, `192.0.2.43:47011`, `[2001:db8::1]:4711` or `2001:db8::1`
+fn parse_node(node: &str) -> Option<IpAddr> {
+    if let Some(bracketed) = node.strip_prefix('[') {
+        return bracketed.split_once(']')?.0.parse().ok();
+    }
+    node.parse().ok().or_else(|| {
+        // An IPv4 address with a port; bare IPv6 addresses were handled above
+        let (ip, _port) = node.rsplit_once(':')?;
+        ip.parse().ok()
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn headers(fields: &[(&str, &str)]) -> HeaderMap {
+        let mut headers = HeaderMap::new();
+        for &(name, value) in fields {
+            headers.append(name, value);
+        }
+        headers
+    }
+
+    fn ip(addr: &str) -> Option<IpAddr> {
+        Some(addr.parse().unwrap())
+    }
+
+    #[test]
+    fn picks_the_entry_appended_by_the_outermost_trusted_proxy() {
+        let headers = headers(&[("X-Forwarded-For", "203.0.113.9, 198.51.100.1, 10.0.0.2")]);
+        let peer = ip("10.0.0.1");
+        assert_eq!(TrustProxy::new(0).client_ip(&headers, peer), peer);
+        assert_eq!(TrustProxy::new(1).client_ip(&headers, peer), ip("10.0.0.2"));
+        assert_eq!(
+            TrustProxy::new(2).client_ip(&headers, peer),
+            ip("198.51.100.1")
+        );
+        assert_eq!(TrustProxy::default().client_ip(&headers, None), None);
+    }
+
+    #[test]
+    fn joins_several_x_forwarded_for_headers() {
+        let headers = headers(&[
+            ("X-Forwarded-For", "203.0.113.9"),
+            ("X-Forwarded-For", "198.51.100.1, 10.0.0.2"),
+        ]);
+        assert_eq!(
+            TrustProxy::new(3).client_ip(&headers, None),
+            ip("203.0.113.9")
+        );
+        assert_eq!(
+            TrustProxy::new(2).client_ip(&headers, None),
+            ip("198.51.100.1")
+        );
+    }
+
+    #[test]
+    fn falls_back_to_the_peer_for_short_lists_and_junk() {
+        let peer = ip("10.0.0.1");
+        let short = headers(&[("X-Forwarded-For", "203.0.113.9")]);
+        assert_eq!(TrustProxy::new(2).client_ip(&short, peer), peer);
+        assert_eq!(TrustProxy::new(1).client_ip(&HeaderMap::new(), peer), peer);
+
+        let junk = headers(&[("X-Forwarded-For", "203.0.113.9, unknown")]);
+        assert_eq!(TrustProxy::new(1).client_ip(&junk, peer), peer);
+        assert_eq!(TrustProxy::new(2).client_ip(&junk, peer), ip("203.0.113.9"));
+    }
+
+    #[test]
+    fn reads_ipv6_entries_with_and_without_ports() {
+        let headers = headers(&[(
+            "X-Forwarded-For",
+            "[2001:db8::1]:4711, 2001:db8::2, 192.0.2.43:47011",
+        )]);
+        assert_eq!(
+            TrustProxy::new(3).client_ip(&headers, None),
+            ip("2001:db8::1")
+        );
+        assert_eq!(
+            TrustProxy::new(2).client_ip(&headers, None),
+            ip("2001:db8::2")
+        );
+        assert_eq!(
+            TrustProxy::new(1).client_ip(&headers, None),
+            ip("192.0.2.43")
+        );
+    }
+
+    #[test]
+    fn parses_forwarded_hops_in_order() {
+        let hops = parse_forwarded([
+            "for=192.0.2.60;proto=HTTP;by=203.0.113.43, For=\"[2001:db8:cafe::17]:4711\"",
+            "for=_hidden;host=\"example.com\";secret=1",
+        ]);
+        assert_eq!(hops.len(), 3);
+        assert_eq!(hops[0].forwarded_for, ip("192.0.2.60"));
+        assert_eq!(hops[0].proto.as_deref(), Some("http"));
+        assert_eq!(hops[0].by, ip("203.0.113.43"));
+        assert_eq!(hops[1].forwarded_for, ip("2001:db8:cafe::17"));
+        assert_eq!(hops[2].forwarded_for, None);
+        assert_eq!(hops[2].host.as_deref(), Some("example.com"));
+    }
+
+    #[test]
+    fn keeps_separators_inside_quoted_values() {
+        let hops =
+            parse_forwarded(["for=unknown;host=\"a.example;x=1, b\";proto=https, for=192.0.2.1"]);
+        assert_eq!(hops.len(), 2);
+        assert_eq!(hops[0].forwarded_for, None);
+        assert_eq!(hops[0].host.as_deref(), Some("a.example;x=1, b"));
+        assert_eq!(hops[0].proto.as_deref(), Some("https"));
+
+        let hops = parse_forwarded([r#"for=192.0.2.1;host="quoted \"name\"""#]);
+        assert_eq!(hops[0].host.as_deref(), Some("quoted \"name\""));
+    }
+
+    #[test]
+    fn prefers_forwarded_over_x_forwarded_headers() {
+        let both = headers(&[
+            ("Forwarded", "for=192.0.2.60;proto=https"),
+            ("X-Forwarded-For", "203.0.113.9"),
+        ]);
+        assert_eq!(forwarded_info(&both).forwarded_for, ip("192.0.2.60"));
+
+        let legacy = headers(&[
+            ("X-Forwarded-For", "203.0.113.9, 10.0.0.2"),
+            ("X-Forwarded-Proto", "HTTPS"),
+            ("X-Forwarded-Host", "example.com"),
+        ]);
+        let info = forwarded_info(&legacy);
+        assert_eq!(info.forwarded_for, ip("203.0.113.9"));
+        assert_eq!(info.proto.as_deref(), Some("https"));
+        assert_eq!(info.host.as_deref(), Some("example.com"));
+    }
+}
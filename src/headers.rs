@@ -0,0 +1,296 @@
+use std::io::{self, BufRead};
+
+use crate::HttpError;
+
+// Headers that only apply to a single connection (RFC 9110, section 7.6.1)
+const HOP_BY_HOP: [&str; 8] = [
+    "Connection",
+    "Keep-Alive",
+    "Proxy-Authenticate",
+    "Proxy-Authorization",
+    "TE",
+    "Trailer",
+    "Transfer-Encoding",
+    "Upgrade",
+];
+
+/// A collection of HTTP headers.
+///
+/// Header names are compared case-insensitively and a name may appear more
+/// than once (e.g. `Set-Cookie`). Insertion order is preserved so headers are
+/// written back out in the order they were added.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct HeaderMap {
+    entries: Vec<(String, String)>, // (name, value) pairs in insertion order
+}
+
+impl HeaderMap {
+    /// Creates an empty `HeaderMap`.
+    pub fn new() -> HeaderMap {
+        HeaderMap {
+            entries: Vec::new(),
+        }
+    }
+
+    /// Returns the first value stored under `name`, if any.
+    pub fn get(&self, name: &str) -> Option<&str> {
+        self.entries
+            .iter()
+            .find(|(key, _)| key.eq_ignore_ascii_case(name))
+            .map(|(_, value)| value.as_str())
+    }
+
+    /// Returns every value stored under `name`, in insertion order.
+    pub fn get_all<'a>(&'a self, name: &'a str) -> impl Iterator<Item = &'a str> + 'a {
+        self.entries
+            .iter()
+            .filter(move |(key, _)| key.eq_ignore_ascii_case(name))
+            .map(|(_, value)| value.as_str())
+    }
+
+    /// Returns `true` if at least one value is stored under `name`.
+    pub fn contains(&self, name: &str) -> bool {
+        self.get(name).is_some()
+    }
+
+    /// Sets `name` to `value`, replacing any values already stored under it.
+    pub fn insert(&mut self, name: impl Into<String>, value: impl Into<String>) {
+        let name = name.into();
+        self.remove(&name);
+        self.entries.push((name, value.into()));
+    }
+
+    /// Adds `value` under `name` without touching existing values.
+    pub fn append(&mut self, name: impl Into<String>, value: impl Into<String>) {
+        self.entries.push((name.into(), value.into()));
+    }
+
+    /// Removes every value stored under `name` and returns the first of them.
+    pub fn remove(&mut self, name: &str) -> Option<String> {
+        let mut removed = None;
+        self.entries.retain(|(key, value)| {
+            if !key.eq_ignore_ascii_case(name) {
+                return true;
+            }
+            if removed.is_none() {
+                removed = Some(value.clone());
+            }
+            false
+        });
+        removed
+    }
+
+    /// Iterates over all `(name, value)` pairs in insertion order.
+    pub fn iter(&self) -> impl Iterator<Item = (&str, &str)> {
+        self.entries
+            .iter()
+            .map(|(name, value)| (name.as_str(), value.as_str()))
+    }
+
+    /// Returns the number of stored `(name, value)` pairs.
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    /// Returns `true` if no headers are stored.
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+
+    /// Returns the lowercase tokens of every `Connection` header, e.g. `["keep-alive", "upgrade"]`.
+    pub fn connection_tokens(&self) -> Vec<String> {
+        self.get_all("Connection")
+            .flat_map(parse_connection_header)
+            .collect()
+    }
+
+    /// Removes the headers that must not be forwarded by a proxy.
+    ///
+    /// These are the standard hop-by-hop headers plus any header named in
+    /// `Connection`, which is how a sender marks its own extensions as
+    /// applying to this connection only.
+    pub fn remove_hop_by_hop(&mut self) {
+        for name in self.connection_tokens() {
+            self.remove(&name);
+        }
+        for name in HOP_BY_HOP {
+            self.remove(name);
+        }
+    }
+}
+
+/// Splits a `Connection` header value into lowercase tokens.
+///
+/// Tokens are case-insensitive, so `Keep-Alive, Upgrade` yields
+/// `["keep-alive", "upgrade"]`. Empty entries are skipped.
+pub fn parse_connection_header(value: &str) -> Vec<String> {
+    value
+        .split(',')
+        .map(|token| token.trim().to_ascii_lowercase())
+        .filter(|token| !token.is_empty())
+        .collect()
+}
+
+/// Combines the values of a header that was set more than once into one value.
+///
+/// RFC 7230 (section 3.2.2) lets a sender fold repeated fields into a single
+/// comma-separated list, since recipients treat the two forms the same. The
+/// exception is `Set-Cookie`, whose values can themselves contain commas
+/// (e.g. in `Expires`) and must each be sent on their own line.
+///
+/// # Returns
+///
+/// `None` for `Set-Cookie` (compared case-insensitively), otherwise the
+/// values joined with `", "`.
+pub fn fold_headers(name: &str, values: &[&str]) -> Option<String> {
+    if name.eq_ignore_ascii_case("Set-Cookie") {
+        return None;
+    }
+    Some(values.join(", "))
+}
+
+// Splits `value` on `separator`, ignoring separators inside quoted strings
+pub(crate) fn split_unquoted(value: &str, separator: char) -> Vec<&str> {
+    let mut parts = Vec::new();
+    let mut in_quotes = false;
+    let mut escaped = false;
+    let mut start = 0;
+    for (index, c) in value.char_indices() {
+        match c {
+            _ if escaped => escaped = false,
+            '\\' if in_quotes => escaped = true,
+            '"' => in_quotes = !in_quotes,
+            c if c == separator && !in_quotes => {
+                parts.push(value[start..index].trim());
+                start = index + 1;
+            }
+            _ => {}
+        }
+    }
+    parts.push(value[start..].trim());
+    parts.retain(|part| !part.is_empty());
+    parts
+}
+
+// Removes the quotes and escapes of a quoted string, or returns a token as is
+pub(crate) fn unquote(value: &str) -> String {
+    let Some(quoted) = value
+        .strip_prefix('"')
+        .and_then(|value| value.strip_suffix('"'))
+    else {
+        return value.to_string();
+    };
+
+    let mut unquoted = String::with_capacity(quoted.len());
+    let mut chars = quoted.chars();
+    while let Some(c) = chars.next() {
+        match c {
+            '\\' => unquoted.extend(chars.next()),
+            c => unquoted.push(c),
+        }
+    }
+    unquoted
+}
+
+// Reads header lines up to and including the blank line that ends them.
+// Values are trimmed, so `Name:value` and `Name:  value` both parse, but the
+// name must be a token right before the colon (RFC 7230 §3.2.4), so a
+// proxy cannot read it as a different field. Lines without a colon and
+// obs-fold continuation lines fail with `HttpError::InvalidHeader`, a stream
+// that ends before the blank line with `HttpError::Io`, and more than
+// `max_fields` fields with `HttpError::HeadersTooLarge`.
+pub(crate) fn parse_headers<R: BufRead>(
+    mut reader: R,
+    max_fields: usize,
+) -> Result<HeaderMap, HttpError> {
+    let mut headers = HeaderMap::new();
+    let mut line = String::new();
+    loop {
+        line.clear();
+        if reader.read_line(&mut line)? == 0 {
+            return Err(io::Error::new(io::ErrorKind::UnexpectedEof, "headers cut short").into());
+        }
+        let line = line.trim_end_matches(['\r', '\n']);
+        if line.is_empty() {
+            return Ok(headers);
+        }
+        if line.starts_with([' ', '\t']) {
+            return Err(HttpError::InvalidHeader("obsolete line folding"));
+        }
+        let Some((name, value)) = line.split_once(':') else {
+            return Err(HttpError::InvalidHeader("no colon after the field name"));
+        };
+        if !is_token(name) {
+            return Err(HttpError::InvalidHeader("the field name is not a token"));
+        }
+        if headers.len() == max_fields {
+            return Err(HttpError::HeadersTooLarge);
+        }
+        headers.append(name, value.trim());
+    }
+}
+
+// Checks that `s` is a non-empty token: letters, digits and !#$%&'*+-.^_`|~
+fn is_token(s: &str) -> bool {
+    !s.is_empty()
+        && s.bytes()
+            .all(|byte| byte.is_ascii_alphanumeric() || b"!#$%&'*+-.^_`|~".contains(&byte))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::StatusCode;
+
+    fn parse(head: &str) -> Result<HeaderMap, HttpError> {
+        parse_headers(head.as_bytes(), 100)
+    }
+
+    #[test]
+    fn parses_fields_up_to_the_blank_line() {
+        let headers = parse("Host: a\r\nX-Empty:\r\nX-Tight:value \r\n\r\nBody: no\r\n").unwrap();
+        assert_eq!(headers.get("host"), Some("a"));
+        assert_eq!(headers.get("X-Empty"), Some(""));
+        assert_eq!(headers.get("X-Tight"), Some("value"));
+        assert!(!headers.contains("Body"));
+    }
+
+    #[test]
+    fn rejects_whitespace_before_the_colon() {
+        for head in [
+            "Transfer-Encoding : chunked\r\n\r\n",
+            "Host\t: a\r\n\r\n",
+            " Host: a\r\n\r\n",
+        ] {
+            let error = parse(head).err().unwrap();
+            assert!(matches!(error, HttpError::InvalidHeader(_)), "{head:?}");
+            assert_eq!(error.status(), StatusCode::BAD_REQUEST);
+        }
+    }
+
+    #[test]
+    fn rejects_malformed_lines() {
+        for head in [
+            "Host: a\r\nno colon here\r\n\r\n",
+            "Host: a\r\n  folded: continuation\r\n\r\n",
+            "X-Folded: a\r\n\tb\r\n\r\n",
+            ": no name\r\n\r\n",
+            "X(Bad): a\r\n\r\n",
+            "X\"Quoted\": a\r\n\r\n",
+        ] {
+            assert!(
+                matches!(parse(head), Err(HttpError::InvalidHeader(_))),
+                "{head:?}"
+            );
+        }
+    }
+
+    #[test]
+    fn rejects_headers_cut_short() {
+        for head in ["", "Host: a\r\n", "Host: a"] {
+            let error = parse(head).err().unwrap();
+            assert!(matches!(error, HttpError::Io(_)), "{head:?}");
+            assert_eq!(error.status(), StatusCode::BAD_REQUEST);
+        }
+    }
+}
@@ -0,0 +1,147 @@
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex, Weak};
+use std::thread;
+use std::time::Duration;
+
+use crate::json::json_string;
+use crate::{Response, StatusCode};
+
+// How often the checks run, unless configured
+const DEFAULT_INTERVAL: Duration = Duration::from_secs(10);
+
+/// A dependency the server needs in order to serve traffic, e.g. a database.
+pub trait HealthCheck {
+    /// Returns the name the check is reported under.
+    fn name(&self) -> &str;
+
+    /// Returns `true` if the dependency is up. May block, e.g. on a network round trip.
+    fn check(&self) -> bool;
+}
+
+/// Runs health checks on a background thread so `/health` can answer without waiting on them.
+///
+/// ```ignore
+/// let health = HealthChecker::new().check(DatabaseCheck::new(pool)).start();
+/// router.get("/health", move |_| health.response());
+/// ```
+pub struct HealthChecker {
+    checks: Vec<Box<dyn HealthCheck + Send>>, // The checks to run, in order
+    interval: Duration,                       // The pause between rounds of checks
+}
+
+impl HealthChecker {
+    /// Creates a checker with no checks that runs every 10 seconds.
+    pub fn new() -> HealthChecker {
+        HealthChecker {
+            checks: Vec::new(),
+            interval: DEFAULT_INTERVAL,
+        }
+    }
+
+    /// Adds a check.
+    pub fn check(mut self, check: impl HealthCheck + Send + 'static) -> HealthChecker {
+        self.checks.push(Box::new(check));
+        self
+    }
+
+    /// Sets the pause between rounds of checks. Defaults to 10 seconds.
+    pub fn interval(mut self, interval: Duration) -> HealthChecker {
+        self.interval = interval;
+        self
+    }
+
+    /// Starts running the checks on a background thread.
+    ///
+    /// The first round starts right away; until a check has completed it is
+    /// reported as failing. The thread exits once every clone of the
+    /// returned [`HealthStatus`] has been dropped.
+    pub fn start(self) -> HealthStatus {
+        let results: HashMap<String, bool> = self
+            .checks
+            .iter()
+            .map(|check| (check.name().to_string(), false))
+            .collect();
+        let status = HealthStatus {
+            results: Arc::new(Mutex::new(results)),
+        };
+
+        let results = Arc::downgrade(&status.results);
+        thread::spawn(move || self.run(results));
+        status
+    }
+
+    // Runs the checks every interval until nobody holds the results any more
+    fn run(self, results: Weak<Mutex<HashMap<String, bool>>>) {
+        loop {
+            for check in &self.checks {
+                // Run the check before locking, so a slow one does not block `/health`
+                let passed = check.check();
+                let Some(results) = results.upgrade() else {
+                    return;
+                };
+                results
+                    .lock()
+                    .unwrap()
+                    .insert(check.name().to_string(), passed);
+            }
+            thread::sleep(self.interval);
+            if results.strong_count() == 0 {
+                return;
+            }
+        }
+    }
+}
+
+impl Default for HealthChecker {
+    fn default() -> HealthChecker {
+        HealthChecker::new()
+    }
+}
+
+/// The latest results of a running [`HealthChecker`]. Clones share the same results.
+#[derive(Debug, Clone)]
+pub struct HealthStatus {
+    results: Arc<Mutex<HashMap<String, bool>>>, // Whether each check last passed, by name
+}
+
+impl HealthStatus {
+    /// Returns whether each check last passed, by name.
+    pub fn results(&self) -> HashMap<String, bool> {
+        self.results.lock().unwrap().clone()
+    }
+
+    /// Returns `true` if every check last passed.
+    pub fn is_healthy(&self) -> bool {
+        self.results.lock().unwrap().values().all(|passed| *passed)
+    }
+
+    /// Builds the `/health` response: `200 OK` if every check passed,
+    /// `503 Service Unavailable` otherwise.
+    ///
+    /// The JSON body lists each check, e.g.
+    /// `{"status":"unavailable","checks":{"cache":true,"database":false}}`.
+    pub fn response(&self) -> Response {
+        let mut results: Vec<(String, bool)> = self.results().into_iter().collect();
+        results.sort();
+        let healthy = results.iter().all(|(_, passed)| *passed);
+
+        let checks: Vec<String> = results
+            .iter()
+            .map(|(name, passed)| format!("{}:{passed}", json_string(name)))
+            .collect();
+        let body = format!(
+            "{{\"status\":\"{}\",\"checks\":{{{}}}}}",
+            if healthy { "ok" } else { "unavailable" },
+            checks.join(",")
+        );
+
+        let status = if healthy {
+            StatusCode::OK
+        } else {
+            StatusCode::SERVICE_UNAVAILABLE
+        };
+        let mut response = Response::with_body(status, "application/json", body.into_bytes());
+        response.set_header("Cache-Control", "no-store");
+        response
+    }
+}
@@ -0,0 +1,85 @@
+use std::io::{Read, Result, Write};
+use std::net::{Shutdown, SocketAddr, TcpStream};
+
+use crate::{CloseStream, TryClone};
+
+/// A raw connection taken over from the server, with no HTTP framing.
+///
+/// Writes go straight to the socket, so it can be handed to WebSocket, SSE
+/// or any other protocol code. Reads first return the bytes the client sent
+/// right behind the request, which the server had already buffered, and
+/// then come from the socket. Created by [`hijack`], or passed to the
+/// closure of [`Response::hijack`](crate::Response::hijack) and to
+/// [`UpgradeHandler::upgraded`](crate::UpgradeHandler::upgraded).
+#[derive(Debug)]
+pub struct HijackedStream {
+    stream: TcpStream, // The client socket
+    buffered: Vec<u8>, // Bytes read from the socket ahead of the takeover, not yet returned
+}
+
+/// Takes over `stream`, so the server applies no further framing to it.
+pub fn hijack(stream: TcpStream) -> HijackedStream {
+    hijack_buffered(stream, Vec::new())
+}
+
+// Takes over `stream`, whose client already sent `buffered` past the request
+pub(crate) fn hijack_buffered(stream: TcpStream, buffered: Vec<u8>) -> HijackedStream {
+    HijackedStream { stream, buffered }
+}
+
+impl HijackedStream {
+    /// Returns the address of the client.
+    pub fn peer_addr(&self) -> Result<SocketAddr> {
+        self.stream.peer_addr()
+    }
+
+    /// Returns the underlying socket and the buffered bytes not read yet.
+    ///
+    /// The bytes came from the client before the socket, so a protocol
+    /// that reads the socket directly must consume them first.
+    pub fn into_parts(self) -> (TcpStream, Vec<u8>) {
+        (self.stream, self.buffered)
+    }
+
+    /// Returns the underlying socket, dropping any buffered bytes not read
+    /// yet; see [`HijackedStream::into_parts`].
+    pub fn into_inner(self) -> TcpStream {
+        self.stream
+    }
+}
+
+impl Read for HijackedStream {
+    fn read(&mut self, buf: &mut [u8]) -> Result<usize> {
+        if self.buffered.is_empty() {
+            return self.stream.read(buf);
+        }
+        let count = buf.len().min(self.buffered.len());
+        buf[..count].copy_from_slice(&self.buffered[..count]);
+        self.buffered.drain(..count);
+        Ok(count)
+    }
+}
+
+impl Write for HijackedStream {
+    fn write(&mut self, buf: &[u8]) -> Result<usize> {
+        self.stream.write(buf)
+    }
+
+    fn flush(&mut self) -> Result<()> {
+        self.stream.flush()
+    }
+}
+
+// The clone shares the socket but not the buffered bytes, which are only
+// read through the original
+impl TryClone for HijackedStream {
+    fn try_clone(&self) -> Result<HijackedStream> {
+        self.stream.try_clone().map(hijack)
+    }
+}
+
+impl CloseStream for HijackedStream {
+    fn close(&self) -> Result<()> {
+        self.stream.shutdown(Shutdown::Both)
+    }
+}
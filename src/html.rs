@@ -0,0 +1,50 @@
+/// Escapes the characters of `s` that are special in HTML.
+///
+/// `<`, `>`, `&`, `"` and `'` become character references, so the result
+/// is safe to put between tags and inside quoted attribute values. Use it
+/// for every user-controlled value (paths, file names, header values) that
+/// ends up in server-generated HTML; [`render_template`](crate::render_template)
+/// already does for its placeholders.
+pub fn escape_html(s: &str) -> String {
+    let mut escaped = String::with_capacity(s.len());
+    for c in s.chars() {
+        match c {
+            '<' => escaped.push_str("&lt;"),
+            '>' => escaped.push_str("&gt;"),
+            '&' => escaped.push_str("&amp;"),
+            '"' => escaped.push_str("&quot;"),
+            '\'' => escaped.push_str("&#x27;"),
+            c => escaped.push(c),
+        }
+    }
+    escaped
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn escapes_the_five_special_characters() {
+        assert_eq!(escape_html("<>&\"'"), "&lt;&gt;&amp;&quot;&#x27;");
+        assert_eq!(
+            escape_html("<a href=\"x\" title='y'>Tom & Jerry</a>"),
+            "&lt;a href=&quot;x&quot; title=&#x27;y&#x27;&gt;Tom &amp; Jerry&lt;/a&gt;"
+        );
+    }
+
+    #[test]
+    fn escapes_existing_character_references_again() {
+        assert_eq!(escape_html("&lt;"), "&amp;lt;");
+    }
+
+    #[test]
+    fn keeps_other_text_unchanged() {
+        assert_eq!(escape_html(""), "");
+        assert_eq!(
+            escape_html("plain text / 100% = ok"),
+            "plain text / 100% = ok"
+        );
+        assert_eq!(escape_html("grüße, 日本 🎉"), "grüße, 日本 🎉");
+    }
+}
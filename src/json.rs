@@ -0,0 +1,346 @@
+// Quotes `value` as a JSON string
+pub(crate) fn json_string(value: &str) -> String {
+    let mut quoted = String::from("\"");
+    for c in value.chars() {
+        match c {
+            '"' => quoted.push_str("\\\""),
+            '\\' => quoted.push_str("\\\\"),
+            c if c.is_control() => quoted.push_str(&format!("\\u{:04x}", c as u32)),
+            c => quoted.push(c),
+        }
+    }
+    quoted.push('"');
+    quoted
+}
+
+// How many objects and arrays may be nested; deeper nesting is rejected
+// rather than risking the stack
+const MAX_DEPTH: usize = 128;
+
+// Whether `text` is exactly one JSON value (RFC 8259), surrounded by optional whitespace
+pub(crate) fn is_valid_json(text: &str) -> bool {
+    let bytes = text.as_bytes();
+    let mut pos = 0;
+    skip_whitespace(bytes, &mut pos);
+    if !parse_value(bytes, &mut pos, 0) {
+        return false;
+    }
+    skip_whitespace(bytes, &mut pos);
+    pos == bytes.len()
+}
+
+// The members of the JSON object `text`, as each name with its escapes
+// decoded and the raw text of its value, e.g. `("exp", "1700000000")`.
+// Returns `None` if `text` is not exactly one valid JSON object.
+pub(crate) fn object_members(text: &str) -> Option<Vec<(String, &str)>> {
+    let bytes = text.as_bytes();
+    let mut pos = 0;
+    let mut members = Vec::new();
+    skip_whitespace(bytes, &mut pos);
+    if !eat(bytes, &mut pos, b"{") {
+        return None;
+    }
+    skip_whitespace(bytes, &mut pos);
+    if !eat(bytes, &mut pos, b"}") {
+        loop {
+            let name_start = pos;
+            if bytes.get(pos) != Some(&b'"') || !parse_string(bytes, &mut pos) {
+                return None;
+            }
+            let name = unescape(&text[name_start + 1..pos - 1])?;
+            skip_whitespace(bytes, &mut pos);
+            if !eat(bytes, &mut pos, b":") {
+                return None;
+            }
+            skip_whitespace(bytes, &mut pos);
+            let value_start = pos;
+            if !parse_value(bytes, &mut pos, 1) {
+                return None;
+            }
+            members.push((name, &text[value_start..pos]));
+            skip_whitespace(bytes, &mut pos);
+            if eat(bytes, &mut pos, b"}") {
+                break;
+            }
+            if !eat(bytes, &mut pos, b",") {
+                return None;
+            }
+            skip_whitespace(bytes, &mut pos);
+        }
+    }
+    skip_whitespace(bytes, &mut pos);
+    (pos == bytes.len()).then_some(members)
+}
+
+// Decodes the escapes in the contents of a string already checked by `parse_string`.
+// Returns `None` for an unpaired surrogate.
+fn unescape(contents: &str) -> Option<String> {
+    let mut unescaped = String::with_capacity(contents.len());
+    let mut chars = contents.chars();
+    while let Some(c) = chars.next() {
+        if c != '\\' {
+            unescaped.push(c);
+            continue;
+        }
+        let c = match chars.next()? {
+            'b' => '\u{8}',
+            'f' => '\u{c}',
+            'n' => '\n',
+            'r' => '\r',
+            't' => '\t',
+            'u' => {
+                let mut code = hex_code(&mut chars)?;
+                if (0xd800..0xdc00).contains(&code) {
+                    if chars.next() != Some('\\') || chars.next() != Some('u') {
+                        return None;
+                    }
+                    let low = hex_code(&mut chars)?;
+                    if !(0xdc00..0xe000).contains(&low) {
+                        return None;
+                    }
+                    code = 0x10000 + ((code - 0xd800) << 10) + (low - 0xdc00);
+                }
+                char::from_u32(code)?
+            }
+            c => c,
+        };
+        unescaped.push(c);
+    }
+    Some(unescaped)
+}
+
+// Reads the four hex digits of a `\u` escape
+fn hex_code(chars: &mut std::str::Chars<'_>) -> Option<u32> {
+    let hex: String = chars.by_ref().take(4).collect();
+    u32::from_str_radix(&hex, 16).ok()
+}
+
+fn skip_whitespace(bytes: &[u8], pos: &mut usize) {
+    while bytes.get(*pos).is_some_and(|b| b" \t\r\n".contains(b)) {
+        *pos += 1;
+    }
+}
+
+// Consumes `literal` if the input continues with it
+fn eat(bytes: &[u8], pos: &mut usize, literal: &[u8]) -> bool {
+    if bytes[*pos..].starts_with(literal) {
+        *pos += literal.len();
+        return true;
+    }
+    false
+}
+
+fn parse_value(bytes: &[u8], pos: &mut usize, depth: usize) -> bool {
+    match bytes.get(*pos) {
+        Some(b'{') => parse_container(bytes, pos, depth, b'}', true),
+        Some(b'[') => parse_container(bytes, pos, depth, b']', false),
+        Some(b'"') => parse_string(bytes, pos),
+        Some(b'-' | b'0'..=b'9') => parse_number(bytes, pos),
+        Some(_) => {
+            eat(bytes, pos, b"true") || eat(bytes, pos, b"false") || eat(bytes, pos, b"null")
+        }
+        None => false,
+    }
+}
+
+// Parses an object (whose members are `"key": value`) or an array nested in
+// `depth` others, starting at its opening bracket
+fn parse_container(bytes: &[u8], pos: &mut usize, depth: usize, close: u8, keyed: bool) -> bool {
+    if depth >= MAX_DEPTH {
+        return false;
+    }
+    *pos += 1;
+    skip_whitespace(bytes, pos);
+    if eat(bytes, pos, &[close]) {
+        return true;
+    }
+    loop {
+        if keyed {
+            if bytes.get(*pos) != Some(&b'"') || !parse_string(bytes, pos) {
+                return false;
+            }
+            skip_whitespace(bytes, pos);
+            if !eat(bytes, pos, b":") {
+                return false;
+            }
+            skip_whitespace(bytes, pos);
+        }
+        if !parse_value(bytes, pos, depth + 1) {
+            return false;
+        }
+        skip_whitespace(bytes, pos);
+        if eat(bytes, pos, &[close]) {
+            return true;
+        }
+        if !eat(bytes, pos, b",") {
+            return false;
+        }
+        skip_whitespace(bytes, pos);
+    }
+}
+
+// Parses a string, starting at its opening quote
+fn parse_string(bytes: &[u8], pos: &mut usize) -> bool {
+    *pos += 1;
+    while let Some(&b) = bytes.get(*pos) {
+        *pos += 1;
+        match b {
+            b'"' => return true,
+            b'\\' => match bytes.get(*pos) {
+                Some(b'"' | b'\\' | b'/' | b'b' | b'f' | b'n' | b'r' | b't') => *pos += 1,
+                Some(b'u') => {
+                    let hex = bytes.get(*pos + 1..*pos + 5);
+                    if !hex.is_some_and(|hex| hex.iter().all(u8::is_ascii_hexdigit)) {
+                        return false;
+                    }
+                    *pos += 5;
+                }
+                _ => return false,
+            },
+            0x00..=0x1f => return false,
+            _ => {}
+        }
+    }
+    false
+}
+
+// Parses a number: an optional minus, an integer part without leading zeros,
+// then an optional fraction and exponent
+fn parse_number(bytes: &[u8], pos: &mut usize) -> bool {
+    let digits = |pos: &mut usize| {
+        let start = *pos;
+        while bytes.get(*pos).is_some_and(u8::is_ascii_digit) {
+            *pos += 1;
+        }
+        *pos > start
+    };
+
+    eat(bytes, pos, b"-");
+    if !eat(bytes, pos, b"0") && !digits(pos) {
+        return false;
+    }
+    if eat(bytes, pos, b".") && !digits(pos) {
+        return false;
+    }
+    if eat(bytes, pos, b"e") || eat(bytes, pos, b"E") {
+        if !eat(bytes, pos, b"+") {
+            eat(bytes, pos, b"-");
+        }
+        if !digits(pos) {
+            return false;
+        }
+    }
+    true
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn nested(depth: usize) -> String {
+        format!("{}1{}", "[".repeat(depth), "]".repeat(depth))
+    }
+
+    #[test]
+    fn accepts_every_kind_of_value() {
+        let valid = [
+            "null",
+            " true ",
+            "false",
+            "0",
+            "-12.5e+3",
+            "1E-2",
+            r#""a \"quoted\" \\ \/ \n \u00e9 string""#,
+            "[]",
+            "{}",
+            "\n[1, \"two\", {\"three\": [null]}]\r\n",
+            r#"{"a": {"b": [true, false]}, "c": -0.0}"#,
+        ];
+        for text in valid {
+            assert!(is_valid_json(text), "{text}");
+        }
+    }
+
+    #[test]
+    fn rejects_invalid_literals_and_numbers() {
+        let invalid = [
+            "",
+            " ",
+            "nul",
+            "True",
+            "nullx",
+            "NaN",
+            "undefined",
+            "01",
+            "-",
+            "1.",
+            ".5",
+            "1e",
+            "+1",
+            "0x10",
+            "'single'",
+        ];
+        for text in invalid {
+            assert!(!is_valid_json(text), "{text}");
+        }
+    }
+
+    #[test]
+    fn rejects_malformed_strings_and_containers() {
+        let invalid = [
+            "\"unterminated",
+            "\"bad \\x escape\"",
+            "\"short \\u12\"",
+            "\"raw \n newline\"",
+            "[1, 2",
+            "[1 2]",
+            "[1,]",
+            "{\"a\" 1}",
+            "{\"a\": 1,}",
+            "{a: 1}",
+            "{1: 1}",
+            "[] []",
+        ];
+        for text in invalid {
+            assert!(!is_valid_json(text), "{text}");
+        }
+    }
+
+    #[test]
+    fn limits_nesting_depth() {
+        assert!(is_valid_json(&nested(MAX_DEPTH)));
+        assert!(!is_valid_json(&nested(MAX_DEPTH + 1)));
+        assert!(!is_valid_json(&"[".repeat(100_000)));
+
+        let objects = format!("{}{}", "{\"a\":".repeat(MAX_DEPTH), "}".repeat(MAX_DEPTH));
+        assert!(!is_valid_json(&objects));
+        let objects = format!("{}1{}", "{\"a\":".repeat(MAX_DEPTH), "}".repeat(MAX_DEPTH));
+        assert!(is_valid_json(&objects));
+    }
+
+    #[test]
+    fn reads_object_members_with_raw_values() {
+        let members = object_members(r#" {"sub": "ab\u0063", "n\"ame": [1, 2], "exp": 17} "#);
+        assert_eq!(
+            members.unwrap(),
+            [
+                ("sub".to_string(), r#""ab\u0063""#),
+                ("n\"ame".to_string(), "[1, 2]"),
+                ("exp".to_string(), "17"),
+            ]
+        );
+        assert_eq!(object_members("{}").unwrap(), []);
+        assert_eq!(object_members("[1]"), None);
+        assert_eq!(object_members("{\"a\": 1} x"), None);
+        assert_eq!(object_members(r#"{"\ud800": 1}"#), None);
+        let emoji = object_members(r#"{"\ud83d\ude00": 1}"#).unwrap();
+        assert_eq!(emoji[0].0, "😀");
+    }
+
+    #[test]
+    fn quotes_strings() {
+        assert_eq!(json_string("plain"), "\"plain\"");
+        assert_eq!(json_string("a\"b\\c\n\u{1}"), r#""a\"b\\c\u000a\u0001""#);
+        assert!(is_valid_json(&json_string("tab\t and é")));
+    }
+}
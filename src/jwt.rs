@@ -0,0 +1,214 @@
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use crate::crypto::{constant_time_eq, hmac_sha256};
+use crate::json::object_members;
+use crate::{base64, parse_authorization, AuthScheme, Middleware, Request, Response, StatusCode};
+
+/// The signature algorithms [`JwtMiddleware`] can verify.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum JwtAlgorithm {
+    /// HMAC with SHA-256, `"alg": "HS256"`.
+    Hs256,
+}
+
+impl JwtAlgorithm {
+    /// Returns the name used in the token header's `alg` field.
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            JwtAlgorithm::Hs256 => "HS256",
+        }
+    }
+
+    // Computes the signature of `signing_input` under `secret`
+    fn sign(&self, secret: &[u8], signing_input: &[u8]) -> Vec<u8> {
+        match self {
+            JwtAlgorithm::Hs256 => hmac_sha256(secret, signing_input).to_vec(),
+        }
+    }
+}
+
+/// Rejects requests that do not carry a valid JSON Web Token (RFC 7519).
+///
+/// The token is read from `Authorization: Bearer <token>`. It must be signed
+/// with the configured algorithm and secret, and its `exp` and `nbf` claims,
+/// when present, must put the current time inside the token's validity
+/// window. Requests that pass have the decoded payload JSON stored in
+/// `Request::jwt_payload`; all others get `401 Unauthorized`.
+#[derive(Debug, Clone)]
+pub struct JwtMiddleware {
+    secret: Vec<u8>,         // The shared key tokens are signed with
+    algorithm: JwtAlgorithm, // The only algorithm accepted in the token header
+}
+
+impl JwtMiddleware {
+    /// Creates a middleware that accepts tokens signed with `secret` using `algorithm`.
+    pub fn new(secret: impl Into<Vec<u8>>, algorithm: JwtAlgorithm) -> JwtMiddleware {
+        JwtMiddleware {
+            secret: secret.into(),
+            algorithm,
+        }
+    }
+
+    /// Verifies `token` and returns its decoded payload.
+    ///
+    /// # Returns
+    ///
+    /// The payload JSON, or `None` if the token is malformed, uses another
+    /// algorithm, has a bad signature, has expired or is not yet valid.
+    pub fn verify(&self, token: &str) -> Option<Vec<u8>> {
+        let mut parts = token.split('.');
+        let (header, payload, signature) = (parts.next()?, parts.next()?, parts.next()?);
+        if parts.next().is_some() {
+            return None;
+        }
+
+        // Check the algorithm before the signature so "none" can never be accepted
+        let header_json = String::from_utf8(base64::decode_url(header)?).ok()?;
+        let header_fields = parse_object(&header_json)?;
+        if member(&header_fields, "alg")? != format!("\"{}\"", self.algorithm.as_str()) {
+            return None;
+        }
+
+        let signing_input = &token[..header.len() + 1 + payload.len()];
+        let expected = self.algorithm.sign(&self.secret, signing_input.as_bytes());
+        if !constant_time_eq(&base64::decode_url(signature)?, &expected) {
+            return None;
+        }
+
+        let payload = base64::decode_url(payload)?;
+        let claims_json = std::str::from_utf8(&payload).ok()?;
+        let claims = parse_object(claims_json)?;
+        let now = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|elapsed| elapsed.as_secs())
+            .unwrap_or_default();
+        if let Some(exp) = member(&claims, "exp") {
+            if now >= parse_numeric_date(exp)? {
+                return None;
+            }
+        }
+        if let Some(nbf) = member(&claims, "nbf") {
+            if now < parse_numeric_date(nbf)? {
+                return None;
+            }
+        }
+
+        Some(payload)
+    }
+}
+
+impl Middleware for JwtMiddleware {
+    fn handle(&self, mut req: Request, next: &dyn Fn(Request) -> Response) -> Response {
+        let payload = req
+            .headers
+            .get("Authorization")
+            .and_then(|value| match parse_authorization(value) {
+                Ok(AuthScheme::Bearer(token)) => self.verify(&token),
+                _ => None,
+            });
+
+        match payload {
+            Some(payload) => {
+                req.jwt_payload = Some(payload);
+                next(req)
+            }
+            None => {
+                let mut response = Response::new(StatusCode::UNAUTHORIZED);
+                response.set_header("WWW-Authenticate", "Bearer");
+                response
+            }
+        }
+    }
+}
+
+// Parses a token header or claims set into its members. Objects that
+// repeat a member name are rejected, as RFC 7519 section 4 allows, so a
+// second `exp` can never shadow the first.
+fn parse_object(json: &str) -> Option<Vec<(String, &str)>> {
+    let members = object_members(json)?;
+    for (index, (name, _)) in members.iter().enumerate() {
+        if members[..index].iter().any(|(earlier, _)| earlier == name) {
+            return None;
+        }
+    }
+    Some(members)
+}
+
+// Returns the raw JSON value of the top-level member `name`, e.g. `"HS256"` or `1700000000`
+fn member<'a>(members: &[(String, &'a str)], name: &str) -> Option<&'a str> {
+    members
+        .iter()
+        .find(|(member, _)| member == name)
+        .map(|(_, value)| *value)
+}
+
+// Parses a NumericDate claim, allowing a fractional part. Returns `None`
+// for anything but a non-negative JSON number without an exponent.
+fn parse_numeric_date(value: &str) -> Option<u64> {
+    let (seconds, fraction) = value.split_once('.').unwrap_or((value, "0"));
+    if fraction.is_empty() || !fraction.bytes().all(|b| b.is_ascii_digit()) {
+        return None;
+    }
+    seconds.parse().ok()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const SECRET: &[u8] = b"secret";
+
+    // Signs `claims` as an HS256 token under `SECRET`
+    fn token(claims: &str) -> String {
+        let encode = |json: &str| {
+            base64::encode(json.as_bytes())
+                .trim_end_matches('=')
+                .replace('+', "-")
+                .replace('/', "_")
+        };
+        let signing_input = format!(
+            "{}.{}",
+            encode(r#"{"alg":"HS256","typ":"JWT"}"#),
+            encode(claims)
+        );
+        let signature = hmac_sha256(SECRET, signing_input.as_bytes());
+        let signature = base64::encode(&signature)
+            .trim_end_matches('=')
+            .replace('+', "-")
+            .replace('/', "_");
+        format!("{signing_input}.{signature}")
+    }
+
+    fn verify(claims: &str) -> Option<Vec<u8>> {
+        JwtMiddleware::new(SECRET, JwtAlgorithm::Hs256).verify(&token(claims))
+    }
+
+    #[test]
+    fn rejects_expired_token() {
+        assert_eq!(verify(r#"{"sub":"u","exp":1}"#), None);
+    }
+
+    #[test]
+    fn rejects_expired_token_with_exp_as_a_claim_value() {
+        assert_eq!(verify(r#"{"sub":"exp","exp":1}"#), None);
+        assert_eq!(verify(r#"{"sub":"nbf","nbf":99999999999}"#), None);
+    }
+
+    #[test]
+    fn ignores_nested_exp() {
+        assert_eq!(verify(r#"{"data":{"exp":99999999999},"exp":1}"#), None);
+        assert!(verify(r#"{"data":{"exp":1},"exp":99999999999}"#).is_some());
+    }
+
+    #[test]
+    fn rejects_non_numeric_or_repeated_exp() {
+        assert_eq!(verify(r#"{"exp":"99999999999"}"#), None);
+        assert_eq!(verify(r#"{"exp":99999999999,"exp":1}"#), None);
+    }
+
+    #[test]
+    fn accepts_valid_token() {
+        let claims = r#"{"sub":"exp","exp":99999999999.5,"nbf":1}"#;
+        assert_eq!(verify(claims).as_deref(), Some(claims.as_bytes()));
+    }
+}
@@ -1,19 +1,281 @@
 use std::{
-    io::{prelude::*, BufReader},
-    net::TcpStream,
-    sync::{mpsc, Arc, Mutex},
-    thread
+    io::BufReader,
+    panic::{self, AssertUnwindSafe},
+    cmp::Ordering as CmpOrdering,
+    collections::BinaryHeap,
+    any::Any,
+    sync::{atomic::{AtomicBool, AtomicUsize, Ordering}, mpsc, Arc, Barrier, Condvar, Mutex, MutexGuard},
+    thread,
+    time::Duration,
 };
 
+// `define_routes!` names this crate `::app`, so it also expands here
+extern crate self as app;
+
+mod access_log;
+mod admin;
+mod auth;
+mod backpressure;
+mod base64;
+mod body;
+mod chunked_html;
+mod client;
+mod config;
+mod conn_limit;
+mod conn_pool;
+mod conn_state;
+mod content_type;
+mod cors;
+mod crypto;
+mod date;
+#[cfg(feature = "crypto")]
+mod digest;
+#[cfg(feature = "crypto")]
+mod digest_auth;
+mod drain;
+mod error;
+mod favicon;
+mod forwarded;
+mod headers;
+mod health;
+mod hijack;
+mod html;
+mod json;
+mod jwt;
+mod limit;
+mod link;
+mod longpoll;
+mod method;
+mod metrics;
+mod middleware;
+mod multipart;
+mod negotiate;
+mod openapi;
+mod path;
+mod plugin;
+mod prefer;
+mod proxy;
+mod random;
+mod range;
+mod response;
+mod retry;
+mod router;
+mod security;
+mod serve_range;
+mod semaphore;
+#[cfg(target_os = "linux")]
+mod sendfile;
+mod server;
+mod static_files;
+mod stream;
+mod tempfile;
+mod template;
+mod throttle;
+mod timeout;
+mod trace;
+mod transfer_encoding;
+mod tunnel;
+mod upgrade;
+#[cfg(all(feature = "uring", target_os = "linux"))]
+mod uring;
+mod weak_cache;
+mod work_stealing;
+
+use body::{content_length, is_chunked, read_body};
+use forwarded::forwarded_info;
+use headers::parse_headers;
+use prefer::parse_preferences;
+pub use access_log::{JsonAccessLog, RequestLog};
+pub use admin::{AdminHandler, ConnectionHandle, ConnectionInfo, ConnectionRegistry};
+pub use auth::{parse_authorization, AuthScheme};
+pub use backpressure::BackpressureListener;
+pub use body::{stream_to_file, ChunkedReader, ChunkedWriter};
+pub use chunked_html::ChunkedHtmlWriter;
+pub use client::{HttpClient, RequestBuilder};
+pub use config::{ServerConfig, TcpKeepalive};
+pub use conn_limit::{ConnGuard, ConnLimit};
+pub use conn_pool::{ConnectionPool, PooledStream};
+pub use conn_state::ConnectionState;
+pub use content_type::{parse_media_type, validate_content_type_matches_body, ContentType};
+pub use cors::{cors_middleware, CorsConfig};
+pub use date::{format_http_date, DateCache};
+#[cfg(feature = "crypto")]
+pub use digest::{validate_content_md5, DigestAlgorithm, DigestMiddleware};
+#[cfg(feature = "crypto")]
+pub use digest_auth::DigestAuthMiddleware;
+pub use drain::{RequestCounter, RequestGuard};
+pub use error::HttpError;
+pub use favicon::FaviconHandler;
+pub use forwarded::{parse_forwarded, ForwardedInfo, TrustProxy};
+pub use headers::{fold_headers, parse_connection_header, HeaderMap};
+pub use health::{HealthCheck, HealthChecker, HealthStatus};
+pub use hijack::{hijack, HijackedStream};
+pub use html::escape_html;
+pub use jwt::{JwtAlgorithm, JwtMiddleware};
+pub use limit::{LimitedBufReader, LimitedReader};
+pub use link::{parse_link_header, LinkRelation};
+pub use longpoll::LongPollWaiter;
+pub use method::HttpMethod;
+pub use metrics::MetricsRegistry;
+pub use middleware::Middleware;
+pub use multipart::MultipartResponseWriter;
+pub use negotiate::{negotiate_locale, parse_accept_language, AcceptedFormat, ContentNegotiationLayer, NegotiatedResponse};
+pub use openapi::OpenApiInfo;
+pub use path::{detect_path_traversal, normalize_path};
+pub use plugin::Plugin;
+pub use prefer::Preference;
+pub use proxy::CachingProxy;
+pub use range::{build_multipart_range_response, parse_range};
+pub use response::{Response, StatusCode};
+pub use retry::{retry_after_seconds, retry_after_value, RetryReason};
+pub use router::{RouteGroup, RouteHandle, Router};
+pub use rust_http_macros::define_routes;
+pub use security::{build_redirect_url, HstsConfig, HttpsRedirectMiddleware, NonceMiddleware, SecurityHeadersMiddleware};
+pub use semaphore::{Semaphore, SemaphorePermit};
+#[cfg(feature = "mmap")]
+pub use serve_range::MmapFile;
+pub use serve_range::ServeRange;
+#[cfg(target_os = "linux")]
+pub use sendfile::sendfile;
+pub use server::{handle_connection, set_socket_buffer_sizes, Server, ServerBuilder};
+pub use static_files::{detect_binary_body, detect_charset, serve_memory_assets, CachePolicies, CachePolicy, CachePolicyBuilder, StaticFileServer};
+pub use stream::{CloseStream, Connection, MockStream, TryClone};
+pub use tempfile::TempFile;
+pub use template::{render_error_page, render_template, render_template_strict};
+pub use throttle::ThrottledWriter;
+pub use timeout::TimeoutMiddleware;
+pub use trace::{TraceContext, TraceGuard};
+pub use transfer_encoding::TransferEncoding;
+pub use tunnel::tunnel;
+pub use upgrade::UpgradeHandler;
+pub use weak_cache::WeakCache;
+pub use work_stealing::WorkStealingPool;
+
 // ThreadPool struct represents a pool of worker threads
 pub struct ThreadPool {
-    workers: Vec<Worker>,              // A vector to hold the worker threads
-    sender: Option<mpsc::Sender<Job>>, // A channel sender to send jobs to the workers
+    workers: Vec<Worker>,        // A vector to hold the worker threads
+    queue: Arc<JobQueue>,        // The jobs waiting for a worker, shared with the workers
+    counters: Arc<PoolCounters>, // Job counts shared with the workers
 }
 
 // Job type alias represents a closure that can be sent to a worker thread
 type Job = Box<dyn FnOnce() + Send + 'static>;
 
+// The priority `execute` uses, halfway between the most and least urgent
+const DEFAULT_PRIORITY: u8 = 128;
+
+// The first and longest pause of a worker that keeps finding the queue lock poisoned
+const MIN_BACKOFF: Duration = Duration::from_millis(1);
+const MAX_BACKOFF: Duration = Duration::from_secs(1);
+
+// A job waiting in the queue, ordered so the heap pops the most urgent one first
+struct PrioritizedJob {
+    priority: u8,                          // Lower numbers run first
+    seq: u64,                              // Submission order, so equal priorities run first-in first-out
+    job: Job,                              // The closure to run
+    cancel_token: Option<Arc<AtomicBool>>, // Set to skip the job if no worker has started it yet
+}
+
+impl PartialEq for PrioritizedJob {
+    fn eq(&self, other: &Self) -> bool {
+        self.cmp(other) == CmpOrdering::Equal
+    }
+}
+
+impl Eq for PrioritizedJob {}
+
+impl PartialOrd for PrioritizedJob {
+    fn partial_cmp(&self, other: &Self) -> Option<CmpOrdering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl Ord for PrioritizedJob {
+    // BinaryHeap is a max-heap, so the lowest (priority, seq) must compare greatest
+    fn cmp(&self, other: &Self) -> CmpOrdering {
+        (other.priority, other.seq).cmp(&(self.priority, self.seq))
+    }
+}
+
+// The job heap and the condition variable idle workers sleep on
+#[derive(Default)]
+struct JobQueue {
+    state: Mutex<QueueState>, // The queued jobs and whether the pool is shutting down
+    available: Condvar,       // Signalled when a job is queued or the pool shuts down
+}
+
+// The part of the queue protected by the mutex
+#[derive(Default)]
+struct QueueState {
+    jobs: BinaryHeap<PrioritizedJob>, // Jobs waiting for a worker, most urgent on top
+    next_seq: u64,                    // The sequence number of the next job
+    closed: bool,                     // Set on drop; workers exit once the heap is empty
+}
+
+impl JobQueue {
+    // Locks the state, recovering it if another thread panicked while holding the lock.
+    // The state only changes through single pushes, pops and flag writes, so it
+    // stays consistent. Also returns whether the lock was poisoned.
+    fn lock(&self) -> (MutexGuard<'_, QueueState>, bool) {
+        match self.state.lock() {
+            Ok(state) => (state, false),
+            Err(poisoned) => {
+                self.state.clear_poison();
+                (poisoned.into_inner(), true)
+            }
+        }
+    }
+
+    // Waits for the condvar, recovering the state like `lock` does
+    fn wait<'a>(&self, state: MutexGuard<'a, QueueState>) -> MutexGuard<'a, QueueState> {
+        self.available.wait(state).unwrap_or_else(|poisoned| {
+            self.state.clear_poison();
+            poisoned.into_inner()
+        })
+    }
+}
+
+/// Waits for the result of a job queued with `ThreadPool::execute_blocking`.
+///
+/// Mirrors `std::thread::JoinHandle`: dropping the handle detaches the job,
+/// which still runs.
+#[derive(Debug)]
+pub struct JoinHandle<R> {
+    receiver: mpsc::Receiver<thread::Result<R>>, // Gets the job's result, or its panic payload
+}
+
+impl<R> JoinHandle<R> {
+    /// Blocks until the job has run and returns its result.
+    ///
+    /// # Errors
+    ///
+    /// Returns the panic payload if the job panicked, as `thread::JoinHandle::join` does.
+    pub fn join(self) -> Result<R, Box<dyn Any + Send>> {
+        match self.receiver.recv() {
+            Ok(result) => result,
+            // Only happens if the job was dropped without running
+            Err(err) => Err(Box::new(err)),
+        }
+    }
+}
+
+// Job counts updated by the workers
+#[derive(Default)]
+struct PoolCounters {
+    busy: AtomicUsize, // Workers currently running a job
+}
+
+/// A snapshot of how loaded a `ThreadPool` is, returned by `ThreadPool::metrics`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct PoolMetrics {
+    /// The number of worker threads.
+    pub workers: usize,
+    /// The number of workers currently running a job.
+    pub busy: usize,
+    /// The number of jobs waiting for a free worker.
+    pub queued: usize,
+}
+
 impl ThreadPool {
     /// Create a new ThreadPool.
     ///
@@ -25,47 +287,163 @@ impl ThreadPool {
     pub fn new(size: usize) -> ThreadPool {
         assert!(size > 0); // Ensure that the size is greater than zero
 
-        // Create a new channel for communication between the threads
-        let (sender, receiver) = mpsc::channel();
+        // Create the queue the workers take jobs from
+        let queue = Arc::new(JobQueue::default());
 
-        // Wrap the receiver in an Arc and Mutex for shared ownership and thread safety
-        let receiver = Arc::new(Mutex::new(receiver));
+        // Create the job counters shared by the pool and its workers
+        let counters = Arc::new(PoolCounters::default());
 
         // Create a vector to hold the workers
         let mut workers = Vec::with_capacity(size);
 
         // Create worker threads and store them in the vector
         for id in 0..size {
-            workers.push(Worker::new(id, Arc::clone(&receiver)));
+            workers.push(Worker::new(id, Arc::clone(&queue), Arc::clone(&counters)));
         }
 
         // Return a new ThreadPool instance
         ThreadPool {
             workers,
-            sender: Some(sender),
+            queue,
+            counters,
         }
     }
 
     /// Execute a closure on a worker thread.
     ///
     /// The closure must be `Send` and `'static` so that it can be safely moved to another thread.
+    /// It is queued at the default priority of 128; see `execute_at_priority`.
     pub fn execute<F>(&self, f: F)
     where
         F: FnOnce() + Send + 'static,
     {
-        // Create a new job from the closure
-        let job = Box::new(f);
+        self.execute_at_priority(f, DEFAULT_PRIORITY);
+    }
+
+    /// Execute a closure on a worker thread ahead of less urgent jobs.
+    ///
+    /// Queued jobs run in order of `priority`, lowest number first, and in
+    /// submission order among equal priorities. A job that is already running
+    /// is never interrupted, and a steady stream of urgent jobs can starve
+    /// less urgent ones.
+    pub fn execute_at_priority<F>(&self, f: F, priority: u8)
+    where
+        F: FnOnce() + Send + 'static,
+    {
+        self.push(Box::new(f), priority, None);
+    }
+
+    /// Execute a closure on a worker thread unless it is cancelled first.
+    ///
+    /// The job is queued at the default priority. Setting the returned token
+    /// to `true` while the job is still queued makes the worker that picks
+    /// it up drop it without running it; once a worker has started the job,
+    /// the token has no effect. A cancelled job still counts as queued in
+    /// `metrics` until a worker gets to it.
+    pub fn execute_cancellable<F>(&self, f: F) -> Arc<AtomicBool>
+    where
+        F: FnOnce() + Send + 'static,
+    {
+        let cancel_token = Arc::new(AtomicBool::new(false));
+        self.push(Box::new(f), DEFAULT_PRIORITY, Some(Arc::clone(&cancel_token)));
+        cancel_token
+    }
+
+    /// Runs `f` on a worker thread and returns a handle to wait for its result.
+    ///
+    /// Meant for CPU-bound work a handler needs the answer of, such as
+    /// resizing an image. The job is queued at the default priority. A panic
+    /// in `f` is caught on the worker, which carries on with other jobs, and
+    /// handed to the caller by [`JoinHandle::join`].
+    ///
+    /// Calling `join` from a job on the same pool blocks that worker, and
+    /// can deadlock once every worker waits this way.
+    pub fn execute_blocking<F, R>(&self, f: F) -> JoinHandle<R>
+    where
+        F: FnOnce() -> R + Send + 'static,
+        R: Send + 'static,
+    {
+        let (sender, receiver) = mpsc::sync_channel(1);
+        self.execute(move || {
+            // The caller may have dropped its handle, so nobody is waiting
+            let _ = sender.send(panic::catch_unwind(AssertUnwindSafe(f)));
+        });
+        JoinHandle { receiver }
+    }
+
+    // Queues a job and wakes a worker for it
+    fn push(&self, job: Job, priority: u8, cancel_token: Option<Arc<AtomicBool>>) {
+        // Push the job onto the heap under the lock
+        let (mut state, _) = self.queue.lock();
+        let seq = state.next_seq;
+        state.next_seq += 1;
+        state.jobs.push(PrioritizedJob {
+            priority,
+            seq,
+            job,
+            cancel_token,
+        });
+        drop(state);
 
-        // Send the job to a worker thread via the channel
-        self.sender.as_ref().unwrap().send(job).unwrap();
+        // Wake one idle worker to pick it up
+        self.queue.available.notify_one();
+    }
+
+    /// Returns how many workers are busy and how many jobs are waiting for one.
+    pub fn metrics(&self) -> PoolMetrics {
+        self.metrics_reader()()
+    }
+
+    // Returns a closure that reads `metrics` without borrowing the pool, for the metrics endpoint
+    pub(crate) fn metrics_reader(&self) -> impl Fn() -> PoolMetrics + Send + Sync + 'static {
+        let (workers, queue, counters) = (self.workers.len(), Arc::clone(&self.queue), Arc::clone(&self.counters));
+        move || PoolMetrics {
+            workers,
+            busy: counters.busy.load(Ordering::SeqCst),
+            queued: queue.lock().0.jobs.len(),
+        }
+    }
+
+    /// Run a closure once on every worker thread and wait for all of them to finish.
+    ///
+    /// Useful for warming per-thread caches or initializing `thread_local!`
+    /// state before traffic starts. Each copy of the closure waits for the
+    /// others before its worker is released, which is what guarantees that
+    /// no worker runs two copies.
+    ///
+    /// This blocks until every worker has picked up its copy, so it must not
+    /// be called from inside a job running on the same pool.
+    pub fn broadcast<F>(&self, f: F)
+    where
+        F: FnOnce() + Send + Clone + 'static,
+    {
+        // One slot per worker plus one for the calling thread
+        let barrier = Arc::new(Barrier::new(self.workers.len() + 1));
+
+        for _ in 0..self.workers.len() {
+            let f = f.clone();
+            let barrier = Arc::clone(&barrier);
+            self.execute(move || {
+                // Reach the barrier even if the closure panics so the caller is not stuck
+                let result = panic::catch_unwind(AssertUnwindSafe(f));
+                barrier.wait();
+                if let Err(payload) = result {
+                    panic::resume_unwind(payload);
+                }
+            });
+        }
+
+        // Wait until every worker has run its copy
+        barrier.wait();
     }
 }
 
 // Implement the Drop trait for ThreadPool to clean up worker threads on drop
 impl Drop for ThreadPool {
     fn drop(&mut self) {
-        // Drop the sender to close the channel and signal to the workers that there are no more jobs
-        drop(self.sender.take());
+        // Close the queue and wake every worker so they exit once the remaining jobs are done
+        self.queue.lock().0.closed = true;
+        self.queue.available.notify_all();
 
         // Iterate over the workers and shut them down
         for worker in &mut self.workers {
@@ -88,24 +466,51 @@ struct Worker {
 impl Worker {
     /// Create a new worker thread.
     ///
-    /// The worker will listen for jobs on the receiver and execute them.
-    fn new(id: usize, receiver: Arc<Mutex<mpsc::Receiver<Job>>>) -> Worker {
+    /// The worker will take jobs from the queue, most urgent first, and execute them.
+    /// If it keeps finding the queue lock poisoned, it backs off exponentially
+    /// between attempts, from 1 ms up to 1 s.
+    fn new(id: usize, queue: Arc<JobQueue>, counters: Arc<PoolCounters>) -> Worker {
         // Spawn a new thread
-        let thread = thread::spawn(move || loop {
-            // Receive a job from the channel
-            let message = receiver.lock().unwrap().recv();
-
-            // Handle the message
-            match message {
-                Ok(job) => {
-                    // Execute the job
-                    println!("Worker {id} got a job; executing.");
-                    job();
+        let thread = thread::spawn(move || {
+            let mut backoff = MIN_BACKOFF;
+            loop {
+                // Take the lock, backing off while other threads keep panicking with it held
+                let (mut state, poisoned) = queue.lock();
+                if poisoned {
+                    drop(state);
+                    println!("Worker {id} found the job queue lock poisoned; retrying in {backoff:?}");
+                    thread::sleep(backoff);
+                    backoff = (backoff * 2).min(MAX_BACKOFF);
+                    continue;
                 }
-                Err(_) => {
-                    // Shut down the worker if the channel is disconnected
-                    println!("Worker {id} disconnected; shutting down.");
-                    break;
+                backoff = MIN_BACKOFF;
+
+                // Wait until there is a job or the pool is shutting down
+                while state.jobs.is_empty() && !state.closed {
+                    state = queue.wait(state);
+                }
+                let message = state.jobs.pop();
+                drop(state);
+
+                // Handle the message
+                match message {
+                    Some(PrioritizedJob { cancel_token: Some(token), .. }) if token.load(Ordering::SeqCst) => {
+                        println!("Worker {id} skipped a cancelled job.");
+                    }
+                    Some(PrioritizedJob { job, .. }) => {
+                        counters.busy.fetch_add(1, Ordering::SeqCst);
+
+                        // Execute the job
+                        println!("Worker {id} got a job; executing.");
+                        job();
+
+                        counters.busy.fetch_sub(1, Ordering::SeqCst);
+                    }
+                    None => {
+                        // Shut down the worker once the pool is closed and the queue is empty
+                        println!("Worker {id} disconnected; shutting down.");
+                        break;
+                    }
                 }
             }
         });
@@ -118,28 +523,42 @@ impl Worker {
     }
 }
 
-
 use std::collections::HashMap;
-use std::io::{BufRead, Error, Lines, Result};
+use std::io::{self, BufRead, Error, Read, Write};
+use std::net::{IpAddr, SocketAddr};
 
 /// Represents an HTTP request.
 pub struct Request {
     /// The HTTP method of the request (e.g., "GET", "POST").
     pub method: String,
-    /// The path of the request (e.g., "/index.html").
+    /// The normalized path of the request (e.g., "/index.html").
     pub path: String,
     /// The HTTP version of the request (e.g., "HTTP/1.1").
     pub version: String,
     /// The headers of the request.
-    pub headers: HashMap<String, String>,
+    pub headers: HeaderMap,
+    /// The path parameters captured by the matching route (e.g. `id` for `/users/:id`).
+    pub params: HashMap<String, String>,
+    /// The body of the request, with any chunked framing removed.
+    pub body: Vec<u8>,
+    /// The trailer fields sent after a chunked body.
+    pub trailers: HeaderMap,
+    /// The payload JSON of the bearer token, once verified by `JwtMiddleware`.
+    pub jwt_payload: Option<Vec<u8>>,
+    /// The address of the client, if known. Set by `handle_connection`.
+    pub remote_addr: Option<SocketAddr>,
+    // The Content-Security-Policy nonce of this request, set by `NonceMiddleware`
+    csp_nonce: Option<String>,
+    // The data handlers attached to the connection, shared by its requests
+    connection_state: Arc<Mutex<ConnectionState>>,
 }
 
 impl Request {
-    /// Creates a new `Request` from a `TcpStream`.
+    /// Creates a new `Request` from a stream such as a `TcpStream` or `MockStream`.
     ///
     /// # Arguments
     ///
-    /// * `stream` - The `TcpStream` to read the request from.
+    /// * `stream` - The stream to read the request from.
     ///
     /// # Returns
     ///
@@ -147,34 +566,265 @@ impl Request {
     ///
     /// # Errors
     ///
-    /// Returns an error if there is a problem reading from the `TcpStream`
+    /// Returns an error if there is a problem reading from the stream
     /// or parsing the request.
-    pub fn new(mut stream: TcpStream) -> Result<Request> {
-        let buf_reader = BufReader::new(&mut stream);
-        let mut lines: Lines<BufReader<&mut TcpStream>> = buf_reader.lines();
+    pub fn new(stream: impl Read) -> Result<Request, HttpError> {
+        Request::with_config(stream, &ServerConfig::default())
+    }
+
+    /// Creates a new `Request` from a stream, applying the limits in `config`.
+    ///
+    /// # Arguments
+    ///
+    /// * `stream` - The stream to read the request from.
+    /// * `config` - The server settings, e.g. the maximum header size.
+    ///
+    /// # Errors
+    ///
+    /// Returns `HttpError::HeadersTooLarge` if the request line and headers
+    /// exceed `config.max_header_bytes` or there are more than
+    /// `config.max_headers` header fields, `HttpError::AmbiguousBody` if both
+    /// `Content-Length` and `Transfer-Encoding` are present (RFC 7230
+    /// §3.3.3), `HttpError::ConflictingContentLength` if `Content-Length`
+    /// values disagree, or another error if there is a problem reading
+    /// from the stream or parsing the request.
+    pub fn with_config(stream: impl Read, config: &ServerConfig) -> Result<Request, HttpError> {
+        Request::read_from(&mut BufReader::new(stream), config, |_| Ok(()))
+    }
+
+    /// Reads a request from a connection, answering `Expect: 100-continue` on it.
+    ///
+    /// Like [`Request::with_config`], but once the headers are in, an
+    /// HTTP/1.1 request with `Expect: 100-continue` (see
+    /// [`Request::expect_continue`]) gets `100 Continue` written back before
+    /// the body is read. Clients such as `curl` send this for large uploads
+    /// and hold the body back until they see it.
+    ///
+    /// # Errors
+    ///
+    /// See [`Request::with_config`]; failing to send `100 Continue` is an
+    /// `HttpError::Io`.
+    pub fn from_connection<S: Read + Write>(stream: S, config: &ServerConfig) -> Result<Request, HttpError> {
+        Request::next_from_connection(&mut BufReader::new(stream), config)
+    }
+
+    // Like `from_connection`, but leaves whatever follows the request in
+    // `reader`, so the next request on the connection can be read from it
+    pub(crate) fn next_from_connection<S: Read + Write>(reader: &mut BufReader<S>, config: &ServerConfig) -> Result<Request, HttpError> {
+        Request::read_from(reader, config, |stream| {
+            stream.write_all(b"HTTP/1.1 100 Continue\r\n\r\n")?;
+            stream.flush()
+        })
+    }
+
+    // Reads a request, calling `send_continue` between the headers and the
+    // body if the client waits for `100 Continue`
+    fn read_from<S: Read>(
+        buf_reader: &mut BufReader<S>,
+        config: &ServerConfig,
+        send_continue: impl FnOnce(&mut S) -> io::Result<()>,
+    ) -> Result<Request, HttpError> {
+        let mut reader = LimitedBufReader::new(buf_reader, config.max_header_bytes);
 
-        let request_line = lines.next().ok_or(Error::new(std::io::ErrorKind::InvalidData, "empty stream"))??;
+        let request_line = (&mut reader).lines().next().ok_or(Error::new(std::io::ErrorKind::InvalidData, "empty stream"))??;
         let (method, path, version) = parse_request_line(&request_line)?;
+        let path = normalize_path(&path)?;
 
-        let mut headers = HashMap::new();
-        for line in lines.take_while(|line| match line {
-            Ok(line) => !line.is_empty(),
-            Err(_) => false,
-        }) {
-            let line = line?;
-            let parts: Vec<&str> = line.split(": ").collect();
-            if parts.len() == 2 {
-                headers.insert(parts[0].to_string(), parts[1].to_string());
+        let headers = parse_headers(&mut reader, config.max_headers)?;
+
+        // A proxy in front may frame the body by the other header, letting a second request hide in it
+        let values = |name| headers.get_all(name).collect::<Vec<_>>().join(", ");
+        if headers.contains("Content-Length") && headers.contains("Transfer-Encoding") {
+            println!(
+                "Rejecting ambiguous body framing for {method} {path}: Content-Length: {}, Transfer-Encoding: {}",
+                values("Content-Length"),
+                values("Transfer-Encoding")
+            );
+            return Err(HttpError::AmbiguousBody);
+        }
+        // Likewise if it picks another of several Content-Length values
+        if let Err(err) = content_length(&headers) {
+            if matches!(err, HttpError::ConflictingContentLength) {
+                println!("Rejecting conflicting body lengths for {method} {path}: Content-Length: {}", values("Content-Length"));
             }
+            return Err(err);
+        }
+        // Or frames a body whose final coding is not chunked some other way
+        if let Err(err) = is_chunked(&headers) {
+            println!("Rejecting unframed body for {method} {path}: Transfer-Encoding: {}", values("Transfer-Encoding"));
+            return Err(err);
         }
 
+        // The header limit does not apply to the body
+        let reader = reader.into_inner();
+        if version == "HTTP/1.1" && expects_continue(&headers) {
+            send_continue(reader.get_mut())?;
+        }
+        let (body, trailers) = read_body(reader, &headers, config.max_body_bytes.unwrap_or(usize::MAX), config.max_header_bytes, config.max_headers)?;
+
         Ok(Request {
             method,
             path,
             version,
-            headers
+            headers,
+            params: HashMap::new(),
+            body,
+            trailers,
+            jwt_payload: None,
+            remote_addr: None,
+            csp_nonce: None,
+            connection_state: Arc::new(Mutex::new(ConnectionState::new())),
         })
     }
+
+    /// Returns the path with `.`, `..` and empty segments resolved.
+    ///
+    /// `Request::new` already normalizes the path it stores, so this only
+    /// differs from `path` if the field was modified afterwards. A path that
+    /// would escape the root is clamped to `/`.
+    pub fn path_normalized(&self) -> String {
+        normalize_path(&self.path).unwrap_or_else(|_| "/".to_string())
+    }
+
+    /// Picks the locale from `available` that best matches the `Accept-Language` header.
+    ///
+    /// Falls back to the first available locale if the header is missing or
+    /// nothing in it matches; see `negotiate_locale` for the matching rules.
+    pub fn preferred_locale<'a>(&self, available: &[&'a str]) -> Option<&'a str> {
+        let requested = parse_accept_language(self.headers.get("Accept-Language").unwrap_or_default());
+        negotiate_locale(&requested, available)
+    }
+
+    /// Returns what the proxies in front of the server reported about the client.
+    ///
+    /// Reads the `Forwarded` header (RFC 7239), falling back to
+    /// `X-Forwarded-For`, `X-Forwarded-Proto` and `X-Forwarded-Host`. Only the
+    /// hop closest to the client is returned; see `parse_forwarded` for all of
+    /// them. Clients can send these headers themselves, so only trust them
+    /// behind a proxy that overwrites them.
+    pub fn forwarded_headers(&self) -> ForwardedInfo {
+        forwarded_info(&self.headers)
+    }
+
+    /// Returns the client's IP address, believing only the proxies `trust` allows.
+    ///
+    /// Picks the `X-Forwarded-For` entry `trust.trusted_hops` from the right,
+    /// falling back to the peer address in `remote_addr`; see `TrustProxy`
+    /// for why the leftmost entry cannot be trusted and how to set the count.
+    ///
+    /// # Returns
+    ///
+    /// The client's address, or `None` if it came from neither the header
+    /// nor a known peer address.
+    pub fn client_ip(&self, trust: &TrustProxy) -> Option<IpAddr> {
+        trust.client_ip(&self.headers, self.remote_addr.map(|addr| addr.ip()))
+    }
+
+    /// Returns the extra path info after the script path of a `Router::cgi_route`.
+    ///
+    /// For `/cgi-bin/app.cgi/extra/path` routed to the script
+    /// `/cgi-bin/app.cgi` this is `/extra/path`, and it is empty when the
+    /// script itself was requested. Requests that did not go through a CGI
+    /// route get `None`.
+    pub fn path_info(&self) -> Option<&str> {
+        self.params.get("PATH_INFO").map(String::as_str)
+    }
+
+    /// Returns the parsed `Content-Type` header, or `None` if it is missing or malformed.
+    pub fn content_type(&self) -> Option<ContentType> {
+        self.headers.get("Content-Type").and_then(ContentType::parse)
+    }
+
+    /// Returns `true` if the request was sent by script (AJAX), i.e. it carries
+    /// `X-Requested-With: XMLHttpRequest`.
+    pub fn is_xhr(&self) -> bool {
+        self.headers
+            .get("X-Requested-With")
+            .is_some_and(|value| value.trim().eq_ignore_ascii_case("XMLHttpRequest"))
+    }
+
+    /// Returns how long the client will wait for a response, from `Prefer: wait=N` (RFC 7240).
+    ///
+    /// Long-polling handlers should not hold the request longer than this;
+    /// [`LongPollWaiter::wait_for_request`] takes it into account. `None` if
+    /// the client sent no (valid) `wait` preference.
+    pub fn preferred_wait(&self) -> Option<Duration> {
+        parse_preferences(&self.headers).into_iter().find_map(|preference| match preference {
+            Preference::Wait(wait) => Some(wait),
+            _ => None,
+        })
+    }
+
+    /// Returns `true` if the client prefers HTTPS, i.e. it sent `Upgrade-Insecure-Requests: 1`.
+    ///
+    /// Browsers send this on navigations; see `HttpsRedirectMiddleware` for
+    /// redirecting them.
+    pub fn upgrade_insecure(&self) -> bool {
+        self.headers
+            .get("Upgrade-Insecure-Requests")
+            .is_some_and(|value| value.trim() == "1")
+    }
+
+    /// Returns the nonce scripts must carry to run under this response's `Content-Security-Policy`.
+    ///
+    /// Set by `NonceMiddleware`, which allows it in the policy's `script-src`;
+    /// embed it as `<script nonce="...">`. `None` if the middleware did not
+    /// run for this request.
+    pub fn csp_nonce(&self) -> Option<&str> {
+        self.csp_nonce.as_deref()
+    }
+
+    // Sets the nonce returned by `csp_nonce`
+    pub(crate) fn set_csp_nonce(&mut self, nonce: String) {
+        self.csp_nonce = Some(nonce);
+    }
+
+    /// Returns the data handlers attached to the connection this request arrived on.
+    ///
+    /// The store outlives the request and is shared with the other requests
+    /// on the same connection; see `ConnectionState`. The lock is held until
+    /// the guard is dropped. A request not read by `handle_connection` (e.g.
+    /// one built with `Request::new`) has a store of its own.
+    pub fn connection_state(&self) -> MutexGuard<'_, ConnectionState> {
+        self.connection_state.lock().unwrap_or_else(|poisoned| poisoned.into_inner())
+    }
+
+    // Makes the request share `state` with the other requests on its connection
+    pub(crate) fn set_connection_state(&mut self, state: Arc<Mutex<ConnectionState>>) {
+        self.connection_state = state;
+    }
+
+    /// Returns `true` if the client sent `Expect: 100-continue`.
+    ///
+    /// Such a client waits for `100 Continue` before sending the body, which
+    /// [`Request::from_connection`] (and so `handle_connection`) sends once
+    /// the headers have been read. By the time a handler runs, the body has
+    /// been received either way.
+    pub fn expect_continue(&self) -> bool {
+        expects_continue(&self.headers)
+    }
+
+    /// Returns `true` if the client wants the connection kept open after this request.
+    ///
+    /// HTTP/1.1 connections stay open unless `Connection` contains `close`;
+    /// HTTP/1.0 ones only stay open if it contains `keep-alive`.
+    pub fn is_keep_alive(&self) -> bool {
+        let tokens = self.headers.connection_tokens();
+        if self.version == "HTTP/1.0" {
+            tokens.iter().any(|token| token == "keep-alive")
+        } else {
+            !tokens.iter().any(|token| token == "close")
+        }
+    }
+
+    /// Returns `true` if the client asks to switch the connection to `protocol`
+    /// (e.g. `websocket`) with `Connection: upgrade` and a matching `Upgrade` header.
+    pub fn is_upgrade(&self, protocol: &str) -> bool {
+        self.headers.connection_tokens().iter().any(|token| token == "upgrade")
+            && self.headers.get("Upgrade").is_some_and(|upgrade| {
+                upgrade.split(',').any(|offered| offered.trim().eq_ignore_ascii_case(protocol))
+            })
+    }
 }
 
 /// Parses the request line of an HTTP request.
@@ -190,7 +840,7 @@ impl Request {
 /// # Errors
 ///
 /// Returns an error if the request line is invalid.
-fn parse_request_line(request_line: &str) -> Result<(String, String, String)> {
+fn parse_request_line(request_line: &str) -> io::Result<(String, String, String)> {
     let parts: Vec<&str> = request_line.split(' ').collect();
     if parts.len() != 3 {
         return Err(Error::new(
@@ -203,3 +853,104 @@ fn parse_request_line(request_line: &str) -> Result<(String, String, String)> {
     let version = parts[2].to_string();
     Ok((method, path, version))
 }
+
+// Whether the headers carry `Expect: 100-continue`, the only expectation defined
+fn expects_continue(headers: &HeaderMap) -> bool {
+    headers
+        .get("Expect")
+        .is_some_and(|expect| expect.trim().eq_ignore_ascii_case("100-continue"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Reads `request` with the defaults, but at most `max_header_bytes` and `max_headers`
+    fn read(request: &str, max_header_bytes: usize, max_headers: usize) -> Result<Request, HttpError> {
+        let config = ServerConfig { max_header_bytes, max_headers, ..ServerConfig::default() };
+        Request::with_config(request.as_bytes(), &config)
+    }
+
+    // A GET request with `count` headers named `X-Header-<n>`
+    fn request_with_headers(count: usize) -> String {
+        let headers: String = (0..count).map(|n| format!("X-Header-{n}: value\r\n")).collect();
+        format!("GET / HTTP/1.1\r\nHost: example.com\r\n{headers}\r\n")
+    }
+
+    #[test]
+    fn rejects_conflicting_content_lengths() {
+        let repeated = "POST / HTTP/1.1\r\nContent-Length: 5\r\nContent-Length: 50\r\n\r\nhello";
+        let joined = "POST / HTTP/1.1\r\nContent-Length: 5, 50\r\n\r\nhello";
+        for request in [repeated, joined] {
+            let error = read(request, 8 * 1024, 100).err().unwrap();
+            assert!(matches!(error, HttpError::ConflictingContentLength));
+            assert_eq!(error.status(), StatusCode::BAD_REQUEST);
+        }
+    }
+
+    #[test]
+    fn rejects_content_lengths_that_are_not_plain_digits() {
+        for length in ["+5", "-1", "5 5", "", "0x5", "5_0"] {
+            let request = format!("POST / HTTP/1.1\r\nContent-Length: {length}\r\n\r\nhello");
+            let error = read(&request, 8 * 1024, 100).err().unwrap();
+            assert!(matches!(error, HttpError::Io(_)), "{length:?}");
+            assert_eq!(error.status(), StatusCode::BAD_REQUEST);
+        }
+    }
+
+    #[test]
+    fn leaves_no_smuggled_request_behind_an_unframed_body() {
+        let request = "POST / HTTP/1.1\r\nTransfer-Encoding: gzip\r\n\r\nGET /smuggled HTTP/1.1\r\n\r\n";
+        let error = read(request, 8 * 1024, 100).err().unwrap();
+        assert!(matches!(error, HttpError::UnframedBody(_)));
+        assert_eq!(error.status(), StatusCode::BAD_REQUEST);
+    }
+
+    #[test]
+    fn accepts_repeated_equal_content_lengths() {
+        let request = "POST / HTTP/1.1\r\nContent-Length: 5\r\nContent-Length: 5, 5\r\n\r\nhello";
+        assert_eq!(read(request, 8 * 1024, 100).unwrap().body, b"hello");
+    }
+
+    #[test]
+    fn limits_bodies_to_one_mebibyte_by_default() {
+        let request = |length: usize| format!("POST / HTTP/1.1\r\nContent-Length: {length}\r\n\r\n{}", "a".repeat(length));
+        assert_eq!(read(&request(1024 * 1024), 8 * 1024, 100).unwrap().body.len(), 1024 * 1024);
+        let error = read(&request(1024 * 1024 + 1), 8 * 1024, 100).err().unwrap();
+        assert!(matches!(error, HttpError::PayloadTooLarge));
+
+        let unlimited = ServerConfig { max_body_bytes: None, ..ServerConfig::default() };
+        let request = request(2 * 1024 * 1024);
+        assert_eq!(Request::with_config(request.as_bytes(), &unlimited).unwrap().body.len(), 2 * 1024 * 1024);
+    }
+
+    #[test]
+    fn accepts_headers_within_the_limits() {
+        let request = request_with_headers(9);
+        let parsed = read(&request, request.len(), 10).unwrap();
+        assert_eq!(parsed.headers.len(), 10);
+        assert_eq!(parsed.headers.get("X-Header-8"), Some("value"));
+    }
+
+    #[test]
+    fn rejects_headers_over_the_byte_limit() {
+        let request = request_with_headers(9);
+        let result = read(&request, request.len() - 1, 100);
+        assert!(matches!(result, Err(HttpError::HeadersTooLarge)));
+    }
+
+    #[test]
+    fn rejects_one_long_header_line() {
+        let request = format!("GET / HTTP/1.1\r\nX-Long: {}\r\n\r\n", "a".repeat(9000));
+        let result = read(&request, 8 * 1024, 100);
+        assert!(matches!(result, Err(HttpError::HeadersTooLarge)));
+    }
+
+    #[test]
+    fn rejects_headers_over_the_count_limit() {
+        let request = request_with_headers(10);
+        let error = read(&request, 8 * 1024, 10).err().unwrap();
+        assert!(matches!(error, HttpError::HeadersTooLarge));
+        assert_eq!(error.status(), StatusCode::REQUEST_HEADER_FIELDS_TOO_LARGE);
+    }
+}
@@ -0,0 +1,115 @@
+use std::io::{BufRead, Read, Result};
+
+use crate::HttpError;
+
+/// A `BufRead` wrapper that fails once more than `limit` bytes have been read.
+///
+/// Used around the header block of a request so a client cannot make the
+/// server buffer an unbounded request line or header section.
+pub struct LimitedBufReader<R: BufRead> {
+    inner: R,     // The reader being limited
+    limit: usize, // The maximum number of bytes that may be consumed
+    read: usize,  // The number of bytes consumed so far
+}
+
+impl<R: BufRead> LimitedBufReader<R> {
+    /// Wraps `inner` so that at most `limit` bytes can be read from it.
+    pub fn new(inner: R, limit: usize) -> LimitedBufReader<R> {
+        LimitedBufReader {
+            inner,
+            limit,
+            read: 0,
+        }
+    }
+
+    /// Returns the number of bytes consumed so far.
+    pub fn bytes_read(&self) -> usize {
+        self.read
+    }
+
+    /// Removes the limit and returns the wrapped reader, e.g. to read a body.
+    pub fn into_inner(self) -> R {
+        self.inner
+    }
+}
+
+impl<R: BufRead> Read for LimitedBufReader<R> {
+    fn read(&mut self, buf: &mut [u8]) -> Result<usize> {
+        let available = self.fill_buf()?;
+        let count = available.len().min(buf.len());
+        buf[..count].copy_from_slice(&available[..count]);
+        self.consume(count);
+        Ok(count)
+    }
+}
+
+impl<R: BufRead> BufRead for LimitedBufReader<R> {
+    fn fill_buf(&mut self) -> Result<&[u8]> {
+        let remaining = self.limit - self.read;
+        let available = self.inner.fill_buf()?;
+
+        // Only fail if the caller actually wants more than the limit allows;
+        // hitting the limit exactly at the end of the stream is fine.
+        if remaining == 0 && !available.is_empty() {
+            return Err(HttpError::HeadersTooLarge.into_io());
+        }
+        Ok(&available[..available.len().min(remaining)])
+    }
+
+    fn consume(&mut self, amount: usize) {
+        self.read += amount;
+        self.inner.consume(amount);
+    }
+}
+
+/// A `Read` wrapper that fails once more than `limit` bytes have been read.
+///
+/// Used around a request body so it is never buffered past
+/// `ServerConfig::max_body_bytes`, however the body is framed. Reading the
+/// limit exactly and then reaching the end of the stream is fine; reading
+/// past it fails with `HttpError::PayloadTooLarge`, wrapped in an
+/// `io::Error` of kind `InvalidData`.
+pub struct LimitedReader<R: Read> {
+    inner: R,     // The reader being limited
+    read: usize,  // The number of bytes read so far
+    limit: usize, // The maximum number of bytes that may be read
+}
+
+impl<R: Read> LimitedReader<R> {
+    /// Wraps `inner` so that at most `limit` bytes can be read from it.
+    pub fn new(inner: R, limit: usize) -> LimitedReader<R> {
+        LimitedReader {
+            inner,
+            read: 0,
+            limit,
+        }
+    }
+
+    /// Returns the number of bytes read so far.
+    pub fn bytes_read(&self) -> usize {
+        self.read
+    }
+
+    /// Removes the limit and returns the wrapped reader.
+    pub fn into_inner(self) -> R {
+        self.inner
+    }
+}
+
+impl<R: Read> Read for LimitedReader<R> {
+    fn read(&mut self, buf: &mut [u8]) -> Result<usize> {
+        let remaining = self.limit - self.read;
+        if remaining == 0 && !buf.is_empty() {
+            // Only a byte beyond the limit makes the body too large, not the end of the stream
+            return match self.inner.read(&mut [0; 1])? {
+                0 => Ok(0),
+                _ => Err(HttpError::PayloadTooLarge.into_io()),
+            };
+        }
+
+        let wanted = buf.len().min(remaining);
+        let count = self.inner.read(&mut buf[..wanted])?;
+        self.read += count;
+        Ok(count)
+    }
+}
@@ -0,0 +1,178 @@
+use std::collections::HashMap;
+
+use crate::HttpError;
+
+/// One link of a `Link` header (RFC 8288), e.g. `</users?page=2>; rel="next"`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct LinkRelation {
+    /// The target URI, without the angle brackets. It may be relative.
+    pub uri: String,
+    /// The relation type, e.g. `next`; several types are separated by spaces.
+    pub rel: String,
+    /// The other parameters, with lowercase names and unquoted values, e.g. `title`.
+    pub attrs: HashMap<String, String>,
+}
+
+/// Parses a `Link` header value into its links, in order.
+///
+/// Links are separated by commas, which may also appear inside the angle
+/// brackets or in quoted values. Parameter names are case-insensitive and
+/// values may be tokens or quoted strings. If a parameter is repeated, its
+/// first value is kept, as RFC 8288 requires for `rel`. An empty value
+/// gives no links.
+///
+/// # Errors
+///
+/// Returns `HttpError::InvalidLinkHeader` if a link does not start with a
+/// `<uri>`, a quoted string is not closed, or a link has no `rel` parameter.
+pub fn parse_link_header(value: &str) -> Result<Vec<LinkRelation>, HttpError> {
+    let mut links = Vec::new();
+    let mut rest = value.trim_start_matches([' ', '\t', ',']);
+    while !rest.is_empty() {
+        let (link, after) = parse_link(rest)?;
+        links.push(link);
+        rest = after.trim_start_matches([' ', '\t', ',']);
+    }
+    Ok(links)
+}
+
+// Parses the link at the start of `s`, returning it and what follows it
+fn parse_link(s: &str) -> Result<(LinkRelation, &str), HttpError> {
+    let target = s
+        .strip_prefix('<')
+        .ok_or(HttpError::InvalidLinkHeader("a link must start with <uri>"))?;
+    let (uri, mut rest) = target
+        .split_once('>')
+        .ok_or(HttpError::InvalidLinkHeader("unclosed <uri>"))?;
+
+    let mut attrs = HashMap::new();
+    loop {
+        rest = rest.trim_start();
+        let Some(param) = rest.strip_prefix(';') else {
+            break;
+        };
+        let end = param.find(['=', ';', ',']).unwrap_or(param.len());
+        let name = param[..end].trim().to_ascii_lowercase();
+        rest = &param[end..];
+
+        let value = match rest.strip_prefix('=') {
+            Some(value) => {
+                let (value, after) = parse_value(value.trim_start())?;
+                rest = after;
+                value
+            }
+            None => String::new(),
+        };
+        if !name.is_empty() {
+            attrs.entry(name).or_insert(value);
+        }
+    }
+    if !rest.is_empty() && !rest.starts_with(',') {
+        return Err(HttpError::InvalidLinkHeader("unexpected text after a link"));
+    }
+
+    let rel = attrs.remove("rel").ok_or(HttpError::InvalidLinkHeader(
+        "a link must have a rel parameter",
+    ))?;
+    let link = LinkRelation {
+        uri: uri.trim().to_string(),
+        rel,
+        attrs,
+    };
+    Ok((link, rest))
+}
+
+// Parses the token or quoted string at the start of `s`, returning it
+// unquoted and what follows it
+fn parse_value(s: &str) -> Result<(String, &str), HttpError> {
+    let Some(quoted) = s.strip_prefix('"') else {
+        let end = s.find([';', ',']).unwrap_or(s.len());
+        return Ok((s[..end].trim_end().to_string(), &s[end..]));
+    };
+
+    let mut value = String::new();
+    let mut chars = quoted.char_indices();
+    while let Some((index, c)) = chars.next() {
+        match c {
+            '\\' => value.extend(chars.next().map(|(_, c)| c)),
+            '"' => return Ok((value, &quoted[index + 1..])),
+            c => value.push(c),
+        }
+    }
+    Err(HttpError::InvalidLinkHeader("unclosed quoted string"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn error(value: &str) -> Option<&'static str> {
+        match parse_link_header(value) {
+            Err(HttpError::InvalidLinkHeader(reason)) => Some(reason),
+            _ => None,
+        }
+    }
+
+    #[test]
+    fn parses_links_in_order() {
+        let links = parse_link_header(
+            r#"</users?page=2>; rel="next", <https://example.com/users?page=9>;REL=last"#,
+        )
+        .unwrap();
+        assert_eq!(links.len(), 2);
+        assert_eq!(links[0].uri, "/users?page=2");
+        assert_eq!(links[0].rel, "next");
+        assert_eq!(links[1].uri, "https://example.com/users?page=9");
+        assert_eq!(links[1].rel, "last");
+        assert!(links[1].attrs.is_empty());
+    }
+
+    #[test]
+    fn keeps_commas_inside_uris_and_quoted_values() {
+        let value = r#"</a,b>; rel="prev next"; Title="One, \"two\"; three", </c>; rel=up"#;
+        let links = parse_link_header(value).unwrap();
+        assert_eq!(links.len(), 2);
+        assert_eq!(links[0].uri, "/a,b");
+        assert_eq!(links[0].rel, "prev next");
+        assert_eq!(links[0].attrs["title"], r#"One, "two"; three"#);
+        assert_eq!(links[1].uri, "/c");
+    }
+
+    #[test]
+    fn keeps_the_first_of_repeated_parameters() {
+        let links =
+            parse_link_header("</a>; rel=next; rel=prev; type=a; type=b; hreflang").unwrap();
+        assert_eq!(links[0].rel, "next");
+        assert_eq!(links[0].attrs["type"], "a");
+        assert_eq!(links[0].attrs["hreflang"], "");
+    }
+
+    #[test]
+    fn gives_no_links_for_empty_values() {
+        assert!(parse_link_header("").unwrap().is_empty());
+        assert!(parse_link_header(" , ,").unwrap().is_empty());
+    }
+
+    #[test]
+    fn rejects_malformed_links() {
+        assert_eq!(error("/a; rel=next"), Some("a link must start with <uri>"));
+        assert_eq!(error("</a; rel=next"), Some("unclosed <uri>"));
+        assert_eq!(error(r#"</a>; rel="next"#), Some("unclosed quoted string"));
+        assert_eq!(
+            error("</a>; title=x"),
+            Some("a link must have a rel parameter")
+        );
+        assert_eq!(
+            error("</a>; rel=next, </b>"),
+            Some("a link must have a rel parameter")
+        );
+        assert_eq!(
+            error(r#"</a>; rel="next" junk"#),
+            Some("unexpected text after a link")
+        );
+        assert_eq!(
+            error("</a>junk; rel=next"),
+            Some("unexpected text after a link")
+        );
+    }
+}
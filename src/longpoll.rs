@@ -0,0 +1,137 @@
+use std::collections::HashMap;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{mpsc, Mutex};
+use std::time::Duration;
+
+use crate::{Preference, Request, Response, StatusCode};
+
+// A waiting request: its ID and the one-shot sender that wakes it
+type Waiter = (u64, mpsc::Sender<Vec<u8>>);
+
+// How long `wait_for_request` holds a request unless configured otherwise
+const DEFAULT_MAX_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// Parks long-polling requests until an event is published for their key.
+///
+/// A handler calls [`LongPollWaiter::wait`] to block its worker thread until
+/// another handler or a background thread calls [`LongPollWaiter::notify`]
+/// for the same key, or until the timeout elapses. Each waiter gets its own
+/// single-use channel, so one notification wakes every request waiting at
+/// that moment and later requests wait for the next one.
+#[derive(Debug)]
+pub struct LongPollWaiter {
+    // The one-shot senders of the requests currently waiting, by key
+    channels: Mutex<HashMap<String, Vec<Waiter>>>,
+    // Identifies each waiter so a timed-out one can remove its own sender
+    next_id: AtomicU64,
+    // The longest `wait_for_request` holds a request
+    max_timeout: Duration,
+}
+
+impl LongPollWaiter {
+    /// Creates a waiter with nobody waiting and a maximum timeout of 30 seconds.
+    pub fn new() -> LongPollWaiter {
+        LongPollWaiter::with_max_timeout(DEFAULT_MAX_TIMEOUT)
+    }
+
+    /// Creates a waiter whose [`LongPollWaiter::wait_for_request`] holds requests for at most `max_timeout`.
+    pub fn with_max_timeout(max_timeout: Duration) -> LongPollWaiter {
+        LongPollWaiter {
+            channels: Mutex::new(HashMap::new()),
+            next_id: AtomicU64::new(0),
+            max_timeout,
+        }
+    }
+
+    /// Returns the longest [`LongPollWaiter::wait_for_request`] holds a request.
+    pub fn max_timeout(&self) -> Duration {
+        self.max_timeout
+    }
+
+    /// Blocks until data is published for `key` or `timeout` elapses.
+    ///
+    /// # Returns
+    ///
+    /// The published data, or `None` if the timeout elapsed first.
+    pub fn wait(&self, key: &str, timeout: Duration) -> Option<Vec<u8>> {
+        let id = self.next_id.fetch_add(1, Ordering::Relaxed);
+        let (sender, receiver) = mpsc::channel();
+        self.channels
+            .lock()
+            .unwrap()
+            .entry(key.to_string())
+            .or_default()
+            .push((id, sender));
+
+        let received = receiver.recv_timeout(timeout).ok();
+        if received.is_none() {
+            self.forget(key, id);
+        }
+        received
+    }
+
+    /// Waits like [`LongPollWaiter::wait`] and turns the outcome into a response:
+    /// `200 OK` with the published data, or `204 No Content` on timeout.
+    pub fn wait_response(&self, key: &str, timeout: Duration, content_type: &str) -> Response {
+        match self.wait(key, timeout) {
+            Some(data) => Response::with_body(StatusCode::OK, content_type, data),
+            None => Response::new(StatusCode::NO_CONTENT),
+        }
+    }
+
+    /// Waits for data on `key` for as long as both the server and `req`'s client allow.
+    ///
+    /// The timeout is the maximum timeout, shortened to the client's
+    /// `Prefer: wait=N` if it asked for less (see [`Request::preferred_wait`]);
+    /// the response then carries `Preference-Applied: wait=N`. Answers
+    /// `200 OK` with the published data, or `304 Not Modified` if nothing
+    /// was published in time, so the client polls again for the same state.
+    pub fn wait_for_request(&self, req: &Request, key: &str, content_type: &str) -> Response {
+        let preferred = req.preferred_wait().filter(|wait| *wait < self.max_timeout);
+        let timeout = preferred.unwrap_or(self.max_timeout);
+
+        let mut response = match self.wait(key, timeout) {
+            Some(data) => Response::with_body(StatusCode::OK, content_type, data),
+            None => Response::new(StatusCode::NOT_MODIFIED),
+        };
+        if let Some(wait) = preferred {
+            response.with_preference_applied(Preference::Wait(wait));
+        }
+        response
+    }
+
+    /// Wakes every request currently waiting on `key` with a copy of `data`.
+    ///
+    /// # Returns
+    ///
+    /// The number of waiting requests that received the data.
+    pub fn notify(&self, key: &str, data: Vec<u8>) -> usize {
+        let waiting = self
+            .channels
+            .lock()
+            .unwrap()
+            .remove(key)
+            .unwrap_or_default();
+        waiting
+            .into_iter()
+            .filter(|(_, sender)| sender.send(data.clone()).is_ok())
+            .count()
+    }
+
+    // Drops the sender of a waiter that gave up so it does not pile up under `key`
+    fn forget(&self, key: &str, id: u64) {
+        let mut channels = self.channels.lock().unwrap();
+        if let Some(waiting) = channels.get_mut(key) {
+            waiting.retain(|(waiter_id, _)| *waiter_id != id);
+            if waiting.is_empty() {
+                channels.remove(key);
+            }
+        }
+    }
+}
+
+impl Default for LongPollWaiter {
+    fn default() -> LongPollWaiter {
+        LongPollWaiter::new()
+    }
+}
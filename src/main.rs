@@ -1,41 +1,36 @@
-use std::{
-    fs, io::Write, net::{TcpListener, TcpStream}, thread, time::Duration
-};
+use std::{fs, thread, time::Duration};
 
-use app::Request;
-use app::ThreadPool;
+use app::http_plugin;
+use app::{Response, StatusCode};
+use app::ServerBuilder;
 
 const ADDR: &str = "127.0.0.1:7990";
 
+// The demo pages served by this binary
+struct Pages;
+
+http_plugin!(Pages, |router| {
+    router.get("/", |_| page(StatusCode::OK, "hello.html"));
+    router.get("/sleep", |_| {
+        thread::sleep(Duration::from_secs(5));
+        page(StatusCode::OK, "hello.html")
+    });
+    router.not_found(|_| page(StatusCode::NOT_FOUND, "404.html"));
+});
+
 fn main() {
-    let listener: TcpListener = TcpListener::bind(ADDR).unwrap();
-    let thread_pool: ThreadPool = ThreadPool::new(10);
+    let server = ServerBuilder::new(ADDR)
+        .threads(10)
+        .add_plugin(Pages)
+        .build()
+        .unwrap();
 
     println!("started listning on addr http://{}", ADDR);
 
-    listener
-        .incoming()
-        .for_each(|stream: Result<TcpStream, std::io::Error>| {
-            let stream = stream.unwrap();
-            thread_pool.execute(|| handle_connection(stream));
-        });
+    server.run();
 }
 
-fn handle_connection(mut stream: TcpStream){
-    let request = Request::new(stream.try_clone().unwrap()).unwrap();
-
-    let (status_line, filename) = if request.path == "/" {
-        ("HTTP/1.1 200 OK", "hello.html")
-    } else if request.path == "/sleep" {
-        thread::sleep(Duration::from_secs(5));
-        ("HTTP/1.1 200 OK", "hello.html")
-    } else {
-        ("HTTP/1.1 404 NOT FOUND", "404.html")
-    };
-
-    let content = fs::read_to_string(filename).unwrap();
-    let length = content.len();
-
-    let response = format!("{status_line}\r\nContent-Length: {length}\r\n\r\n{content}");
-    stream.write_all(response.as_bytes()).unwrap();
+fn page(status: StatusCode, filename: &str) -> Response {
+    let content = fs::read(filename).unwrap();
+    Response::with_body(status, "text/html", content)
 }
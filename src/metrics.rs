@@ -0,0 +1,269 @@
+use std::collections::HashMap;
+use std::fmt;
+use std::sync::{Mutex, MutexGuard};
+
+use crate::PoolMetrics;
+
+// The quantiles reported for each histogram
+const QUANTILES: [f64; 3] = [0.5, 0.9, 0.99];
+
+// Reads the current load of the server's thread pool
+type PoolReader = Box<dyn Fn() -> PoolMetrics + Send + Sync>;
+
+/// Application metrics recorded by handlers, served in the Prometheus text format.
+///
+/// Shared through `ServerConfig::metrics`; clone the `Arc` into the
+/// handlers that record, e.g. orders processed or errors by type. The admin
+/// endpoint `GET <admin_path>/metrics` serves [`MetricsRegistry::render_prometheus`],
+/// which also reports the load of the server's thread pool.
+///
+/// Histograms keep every recorded value, so they suit low-volume
+/// measurements; record one value per business event, not per byte.
+#[derive(Default)]
+pub struct MetricsRegistry {
+    counters: Mutex<HashMap<String, u64>>, // Running totals by name
+    histograms: Mutex<HashMap<String, Vec<f64>>>, // Every recorded value by name
+    pool: Mutex<Option<PoolReader>>,       // The pool of the server using the registry
+}
+
+impl MetricsRegistry {
+    /// Creates a registry with no metrics.
+    pub fn new() -> MetricsRegistry {
+        MetricsRegistry::default()
+    }
+
+    /// Adds `delta` to the counter `name`, creating it at zero first if needed.
+    pub fn counter_inc(&self, name: &str, delta: u64) {
+        let mut counters = lock(&self.counters);
+        match counters.get_mut(name) {
+            Some(count) => *count = count.saturating_add(delta),
+            None => {
+                counters.insert(name.to_string(), delta);
+            }
+        }
+    }
+
+    /// Records `value` in the histogram `name`, e.g. an order's total or a job's duration.
+    pub fn histogram_record(&self, name: &str, value: f64) {
+        lock(&self.histograms)
+            .entry(name.to_string())
+            .or_default()
+            .push(value);
+    }
+
+    /// Formats every metric in the Prometheus text exposition format (version 0.0.4).
+    ///
+    /// Counters become `counter` metrics. Histograms become `summary`
+    /// metrics with the 0.5, 0.9 and 0.99 quantiles, `_sum` and `_count`.
+    /// Metrics are sorted by name, and characters Prometheus does not allow
+    /// in names are replaced with `_`. Once a server uses the registry, its
+    /// pool is reported as the `thread_pool_workers`, `thread_pool_busy`
+    /// and `thread_pool_queued` gauges.
+    pub fn render_prometheus(&self) -> String {
+        let mut out = String::new();
+
+        if let Some(read_pool) = lock(&self.pool).as_ref() {
+            let pool = read_pool();
+            for (name, value) in [
+                ("thread_pool_workers", pool.workers),
+                ("thread_pool_busy", pool.busy),
+                ("thread_pool_queued", pool.queued),
+            ] {
+                out.push_str(&format!("# TYPE {name} gauge\n{name} {value}\n"));
+            }
+        }
+
+        let mut counters: Vec<(String, u64)> = lock(&self.counters)
+            .iter()
+            .map(|(name, count)| (metric_name(name), *count))
+            .collect();
+        counters.sort();
+        for (name, count) in counters {
+            out.push_str(&format!("# TYPE {name} counter\n{name} {count}\n"));
+        }
+
+        let mut histograms: Vec<(String, Vec<f64>)> = lock(&self.histograms)
+            .iter()
+            .map(|(name, values)| (metric_name(name), values.clone()))
+            .collect();
+        histograms.sort_by(|(a, _), (b, _)| a.cmp(b));
+        for (name, mut values) in histograms {
+            values.sort_by(f64::total_cmp);
+            out.push_str(&format!("# TYPE {name} summary\n"));
+            for quantile in QUANTILES {
+                let value = nearest_rank(&values, quantile);
+                out.push_str(&format!(
+                    "{name}{{quantile=\"{quantile}\"}} {}\n",
+                    PromValue(value)
+                ));
+            }
+            let sum: f64 = values.iter().sum();
+            out.push_str(&format!("{name}_sum {}\n", PromValue(sum)));
+            out.push_str(&format!("{name}_count {}\n", values.len()));
+        }
+        out
+    }
+
+    // Reports `read_pool` from now on, replacing the pool of a previous server
+    pub(crate) fn track_pool(&self, read_pool: impl Fn() -> PoolMetrics + Send + Sync + 'static) {
+        *lock(&self.pool) = Some(Box::new(read_pool));
+    }
+}
+
+impl fmt::Debug for MetricsRegistry {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("MetricsRegistry")
+            .field("counters", &lock(&self.counters).len())
+            .field("histograms", &lock(&self.histograms).len())
+            .finish_non_exhaustive()
+    }
+}
+
+// Locks one of the maps. Every update is a single insert or push, so poisoning is ignored
+fn lock<T>(mutex: &Mutex<T>) -> MutexGuard<'_, T> {
+    mutex
+        .lock()
+        .unwrap_or_else(|poisoned| poisoned.into_inner())
+}
+
+// Makes `name` a valid Prometheus metric name, [a-zA-Z_:][a-zA-Z0-9_:]*
+fn metric_name(name: &str) -> String {
+    let mut valid: String = name
+        .chars()
+        .map(|c| match c {
+            'a'..='z' | 'A'..='Z' | '0'..='9' | '_' | ':' => c,
+            _ => '_',
+        })
+        .collect();
+    if !valid.starts_with(|c: char| c.is_ascii_alphabetic() || c == '_' || c == ':') {
+        valid.insert(0, '_');
+    }
+    valid
+}
+
+// The value at `quantile` of the sorted `values` by the nearest-rank method, NaN if empty
+fn nearest_rank(values: &[f64], quantile: f64) -> f64 {
+    if values.is_empty() {
+        return f64::NAN;
+    }
+    let rank = (quantile * values.len() as f64).ceil() as usize;
+    values[rank.clamp(1, values.len()) - 1]
+}
+
+// Formats a sample value the way Prometheus parses it: `+Inf`, `-Inf` and `NaN`
+struct PromValue(f64);
+
+impl fmt::Display for PromValue {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.0 {
+            value if value.is_nan() => f.write_str("NaN"),
+            f64::INFINITY => f.write_str("+Inf"),
+            f64::NEG_INFINITY => f.write_str("-Inf"),
+            value => write!(f, "{value}"),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn renders_counters_sorted_by_name() {
+        let metrics = MetricsRegistry::new();
+        metrics.counter_inc("orders_total", 2);
+        metrics.counter_inc("errors_total", 1);
+        metrics.counter_inc("orders_total", 3);
+        assert_eq!(
+            metrics.render_prometheus(),
+            "# TYPE errors_total counter\nerrors_total 1\n\
+             # TYPE orders_total counter\norders_total 5\n"
+        );
+    }
+
+    #[test]
+    fn saturates_counters() {
+        let metrics = MetricsRegistry::new();
+        metrics.counter_inc("big", u64::MAX);
+        metrics.counter_inc("big", 1);
+        assert!(metrics
+            .render_prometheus()
+            .ends_with(&format!("big {}\n", u64::MAX)));
+    }
+
+    #[test]
+    fn renders_histograms_as_summaries() {
+        let metrics = MetricsRegistry::new();
+        for value in (1..=10).rev() {
+            metrics.histogram_record("order_value", f64::from(value));
+        }
+        assert_eq!(
+            metrics.render_prometheus(),
+            "# TYPE order_value summary\n\
+             order_value{quantile=\"0.5\"} 5\n\
+             order_value{quantile=\"0.9\"} 9\n\
+             order_value{quantile=\"0.99\"} 10\n\
+             order_value_sum 55\n\
+             order_value_count 10\n"
+        );
+    }
+
+    #[test]
+    fn formats_special_sample_values() {
+        let metrics = MetricsRegistry::new();
+        metrics.histogram_record("latency", 0.25);
+        metrics.histogram_record("latency", f64::INFINITY);
+        let rendered = metrics.render_prometheus();
+        assert!(rendered.contains("latency{quantile=\"0.5\"} 0.25\n"));
+        assert!(rendered.contains("latency{quantile=\"0.99\"} +Inf\n"));
+        assert!(rendered.contains("latency_sum +Inf\n"));
+        assert_eq!(PromValue(f64::NAN).to_string(), "NaN");
+        assert_eq!(PromValue(f64::NEG_INFINITY).to_string(), "-Inf");
+    }
+
+    #[test]
+    fn sanitises_metric_names() {
+        assert_eq!(metric_name("http:requests_total"), "http:requests_total");
+        assert_eq!(
+            metric_name("orders.processed-total"),
+            "orders_processed_total"
+        );
+        assert_eq!(metric_name("2xx responses"), "_2xx_responses");
+        assert_eq!(metric_name("héllo"), "h_llo");
+        assert_eq!(metric_name(""), "_");
+
+        let metrics = MetricsRegistry::new();
+        metrics.counter_inc("jobs.done", 1);
+        assert_eq!(
+            metrics.render_prometheus(),
+            "# TYPE jobs_done counter\njobs_done 1\n"
+        );
+    }
+
+    #[test]
+    fn picks_quantiles_by_nearest_rank() {
+        assert!(nearest_rank(&[], 0.5).is_nan());
+        assert_eq!(nearest_rank(&[7.0], 0.99), 7.0);
+        assert_eq!(nearest_rank(&[1.0, 2.0, 3.0, 4.0], 0.5), 2.0);
+        assert_eq!(nearest_rank(&[1.0, 2.0, 3.0, 4.0], 0.9), 4.0);
+        assert_eq!(nearest_rank(&[1.0, 2.0, 3.0, 4.0], 0.0), 1.0);
+    }
+
+    #[test]
+    fn reports_the_tracked_pool_first() {
+        let metrics = MetricsRegistry::new();
+        metrics.counter_inc("a", 1);
+        metrics.track_pool(|| PoolMetrics {
+            workers: 4,
+            busy: 1,
+            queued: 0,
+        });
+        assert_eq!(
+            metrics.render_prometheus(),
+            "# TYPE thread_pool_workers gauge\nthread_pool_workers 4\n\
+             # TYPE thread_pool_busy gauge\nthread_pool_busy 1\n\
+             # TYPE thread_pool_queued gauge\nthread_pool_queued 0\n\
+             # TYPE a counter\na 1\n"
+        );
+    }
+}
@@ -0,0 +1,21 @@
+use crate::{Request, Response};
+
+/// Code that runs around a handler, e.g. to add headers or reject requests.
+///
+/// A middleware receives the request and `next`, which runs the rest of the
+/// chain (further middleware, then the handler). It may change the request
+/// before calling `next`, change the response `next` returns, or answer
+/// without calling `next` at all.
+pub trait Middleware: Send + Sync {
+    /// Handles `req`, calling `next` to continue down the chain.
+    fn handle(&self, req: Request, next: &dyn Fn(Request) -> Response) -> Response;
+}
+
+impl<F> Middleware for F
+where
+    F: Fn(Request, &dyn Fn(Request) -> Response) -> Response + Send + Sync,
+{
+    fn handle(&self, req: Request, next: &dyn Fn(Request) -> Response) -> Response {
+        self(req, next)
+    }
+}
@@ -0,0 +1,83 @@
+use std::io::Result;
+
+use crate::random::random_u128;
+use crate::{Response, StatusCode};
+
+/// Combines several responses into one `multipart/mixed` response, as batch APIs return.
+///
+/// Each part is `Content-Type: application/http` and holds a complete
+/// response: status line, headers and body, serialized as `write_to` would
+/// send it. Parts can carry a `Content-ID` so clients can match them to the
+/// requests of the batch. The boundary is random, so it is all but
+/// impossible for a part body to contain it by accident.
+///
+/// ```ignore
+/// let batch = MultipartResponseWriter::new()
+///     .part_with_id("response-1", get_user(first))
+///     .part_with_id("response-2", get_user(second))
+///     .finish()?;
+/// ```
+pub struct MultipartResponseWriter {
+    boundary: String,                       // Separates the parts of the body
+    parts: Vec<(Option<String>, Response)>, // Each part's Content-ID and response
+}
+
+impl MultipartResponseWriter {
+    /// Creates a writer with no parts and a random boundary.
+    pub fn new() -> MultipartResponseWriter {
+        MultipartResponseWriter {
+            boundary: format!("batch_{:032x}", random_u128()),
+            parts: Vec::new(),
+        }
+    }
+
+    /// Adds `response` as the next part.
+    pub fn part(mut self, response: Response) -> MultipartResponseWriter {
+        self.parts.push((None, response));
+        self
+    }
+
+    /// Adds `response` as the next part, labeled with a `Content-ID` header.
+    pub fn part_with_id(mut self, content_id: &str, response: Response) -> MultipartResponseWriter {
+        let content_id = content_id.replace(['\r', '\n'], "");
+        self.parts.push((Some(content_id), response));
+        self
+    }
+
+    /// Returns the boundary between parts.
+    pub fn boundary(&self) -> &str {
+        &self.boundary
+    }
+
+    /// Serializes the parts into a `200 OK` response with a `multipart/mixed` body.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if reading the body of a streamed or file-backed part fails.
+    pub fn finish(self) -> Result<Response> {
+        let mut body = Vec::new();
+        for (content_id, response) in self.parts {
+            body.extend_from_slice(format!("--{}\r\n", self.boundary).as_bytes());
+            body.extend_from_slice(b"Content-Type: application/http\r\n");
+            if let Some(content_id) = content_id {
+                body.extend_from_slice(format!("Content-ID: {content_id}\r\n").as_bytes());
+            }
+            body.extend_from_slice(b"\r\n");
+            response.write_to(&mut body)?;
+            body.extend_from_slice(b"\r\n");
+        }
+        body.extend_from_slice(format!("--{}--\r\n", self.boundary).as_bytes());
+
+        Ok(Response::with_body(
+            StatusCode::OK,
+            &format!("multipart/mixed; boundary={}", self.boundary),
+            body,
+        ))
+    }
+}
+
+impl Default for MultipartResponseWriter {
+    fn default() -> MultipartResponseWriter {
+        MultipartResponseWriter::new()
+    }
+}
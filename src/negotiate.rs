@@ -0,0 +1,410 @@
+use std::sync::Arc;
+
+use crate::{parse_media_type, Request, Response, StatusCode};
+
+/// Parses an `Accept-Language` header into language tags and their weights.
+///
+/// Entries without a `q` parameter get a weight of `1.0`. Malformed entries
+/// are skipped. The result is sorted from most to least preferred, keeping
+/// the header's order between entries of equal weight.
+///
+/// # Arguments
+///
+/// * `header` - The header value, e.g. `en-US,en;q=0.9,fr;q=0.7`.
+pub fn parse_accept_language(header: &str) -> Vec<(String, f32)> {
+    let mut languages: Vec<(String, f32)> = header
+        .split(',')
+        .filter_map(|entry| {
+            let mut parts = entry.split(';');
+            let tag = parts.next()?.trim();
+            if tag.is_empty() {
+                return None;
+            }
+
+            let mut weight = 1.0;
+            for param in parts {
+                if let Some(q) = param.trim().strip_prefix("q=") {
+                    weight = q.trim().parse().ok().filter(|q| (0.0..=1.0).contains(q))?;
+                }
+            }
+            Some((tag.to_string(), weight))
+        })
+        .collect();
+
+    languages.sort_by(|a, b| b.1.total_cmp(&a.1));
+    languages
+}
+
+/// Picks the available locale that best matches the requested ones.
+///
+/// Requested locales are tried from most to least preferred. Each one
+/// matches an available locale with the same tag (`en-US` = `en-US`), or
+/// failing that one with the same primary language (`en-US` ~ `en-GB`).
+/// `*` matches anything and locales with a weight of zero are never
+/// chosen. If nothing matches, the first available locale is returned.
+///
+/// # Arguments
+///
+/// * `requested` - The client's preferences, as returned by [`parse_accept_language`].
+/// * `available` - The locales the application can serve, in order of preference.
+///
+/// # Returns
+///
+/// The chosen locale, or `None` if `available` is empty.
+pub fn negotiate_locale<'a>(requested: &[(String, f32)], available: &[&'a str]) -> Option<&'a str> {
+    for (tag, weight) in requested {
+        if *weight <= 0.0 {
+            continue;
+        }
+        if tag == "*" {
+            return available.first().copied();
+        }
+
+        let exact = available
+            .iter()
+            .find(|locale| locale.eq_ignore_ascii_case(tag));
+        let same_language = || {
+            available
+                .iter()
+                .find(|locale| primary_language(locale).eq_ignore_ascii_case(primary_language(tag)))
+        };
+        if let Some(locale) = exact.or_else(same_language) {
+            return Some(locale);
+        }
+    }
+
+    available.first().copied()
+}
+
+// Returns the primary language subtag of a language tag, e.g. "en" for "en-US"
+fn primary_language(tag: &str) -> &str {
+    tag.split(['-', '_']).next().unwrap_or(tag)
+}
+
+/// A representation [`ContentNegotiationLayer`] can pick for a response.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum AcceptedFormat {
+    /// `text/html`, for browsers.
+    Html,
+    /// `application/json`, for API clients.
+    Json,
+    /// `application/xml`.
+    Xml,
+}
+
+impl AcceptedFormat {
+    /// Returns the `Content-Type` the format is sent with.
+    pub fn content_type(&self) -> &'static str {
+        match self {
+            AcceptedFormat::Html => "text/html; charset=utf-8",
+            AcceptedFormat::Json => "application/json",
+            AcceptedFormat::Xml => "application/xml",
+        }
+    }
+
+    // The format a media range from `Accept` asks for, if it names one
+    fn from_media_range(range: &str) -> Option<AcceptedFormat> {
+        match range {
+            "text/html" | "application/xhtml+xml" | "text/*" => Some(AcceptedFormat::Html),
+            "application/json" | "application/*" => Some(AcceptedFormat::Json),
+            "application/xml" | "text/xml" => Some(AcceptedFormat::Xml),
+            range if range.starts_with("application/") && range.ends_with("+json") => {
+                Some(AcceptedFormat::Json)
+            }
+            _ => None,
+        }
+    }
+}
+
+/// The representations of a resource a negotiated handler can produce.
+///
+/// Fill in the formats the resource supports; [`ContentNegotiationLayer`]
+/// sends the one the client prefers. The bodies are sent as given, so
+/// `json` and `xml` must already be serialized.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct NegotiatedResponse {
+    /// The status of the response, whichever format is sent.
+    pub status: StatusCode,
+    /// The HTML page, sent as `text/html; charset=utf-8`.
+    pub html: Option<String>,
+    /// The JSON document, sent as `application/json`.
+    pub json: Option<String>,
+    /// The XML document, sent as `application/xml`.
+    pub xml: Option<String>,
+}
+
+impl NegotiatedResponse {
+    /// Creates a response with `status` and no representations yet.
+    pub fn new(status: StatusCode) -> NegotiatedResponse {
+        NegotiatedResponse {
+            status,
+            html: None,
+            json: None,
+            xml: None,
+        }
+    }
+
+    /// Sets the HTML representation.
+    pub fn html(mut self, html: impl Into<String>) -> NegotiatedResponse {
+        self.html = Some(html.into());
+        self
+    }
+
+    /// Sets the JSON representation, already serialized.
+    pub fn json(mut self, json: impl Into<String>) -> NegotiatedResponse {
+        self.json = Some(json.into());
+        self
+    }
+
+    /// Sets the XML representation, already serialized.
+    pub fn xml(mut self, xml: impl Into<String>) -> NegotiatedResponse {
+        self.xml = Some(xml.into());
+        self
+    }
+
+    // Takes the body of `format`, if the handler produced one
+    fn take(&mut self, format: AcceptedFormat) -> Option<String> {
+        match format {
+            AcceptedFormat::Html => self.html.take(),
+            AcceptedFormat::Json => self.json.take(),
+            AcceptedFormat::Xml => self.xml.take(),
+        }
+    }
+}
+
+/// Serves one route as HTML, JSON or XML, whichever the client's `Accept` prefers.
+///
+/// The wrapped handler is told the preferred format and returns a
+/// [`NegotiatedResponse`] with one or more representations. The preferred
+/// one is sent if present, otherwise the next one the client accepts;
+/// `406 Not Acceptable` is sent if the client accepts none of them.
+/// `text/*` counts as HTML and `application/*` and `+json` types as JSON.
+/// `*/*` prefers the layer's default format but allows the others, and a
+/// missing `Accept` header gets the default format.
+/// Responses carry `Vary: Accept`, so caches keep each format apart.
+///
+/// ```ignore
+/// let negotiate = ContentNegotiationLayer::new(AcceptedFormat::Json);
+/// router.get("/users/:id", negotiate.wrap(|req, _format| {
+///     let user = load_user(&req.params["id"]);
+///     NegotiatedResponse::new(StatusCode::OK)
+///         .html(render_template(USER_PAGE, &user.vars()))
+///         .json(user.to_json())
+/// }));
+/// ```
+#[derive(Debug, Clone, Copy)]
+pub struct ContentNegotiationLayer {
+    default: AcceptedFormat, // Sent when the client accepts anything
+}
+
+impl ContentNegotiationLayer {
+    /// Creates a layer that falls back to `default` for clients without a preference.
+    pub fn new(default: AcceptedFormat) -> ContentNegotiationLayer {
+        ContentNegotiationLayer { default }
+    }
+
+    /// Wraps `handler` so its response is sent in the format the client prefers.
+    pub fn wrap<F>(&self, handler: F) -> impl Fn(Request) -> Response + Send + Sync + 'static
+    where
+        F: Fn(Request, AcceptedFormat) -> NegotiatedResponse + Send + Sync + 'static,
+    {
+        let handler = Arc::new(handler);
+        let default = self.default;
+
+        move |req: Request| {
+            let accepted = accepted_formats(req.headers.get("Accept"), default);
+            let Some(&preferred) = accepted.first() else {
+                let mut response = Response::new(StatusCode::NOT_ACCEPTABLE);
+                response.vary(&["Accept"]);
+                return response;
+            };
+
+            let mut negotiated = handler(req, preferred);
+            let chosen = accepted
+                .iter()
+                .find_map(|&format| Some((format, negotiated.take(format)?)));
+            let mut response = match chosen {
+                Some((format, body)) => {
+                    Response::with_body(negotiated.status, format.content_type(), body.into_bytes())
+                }
+                None => Response::new(StatusCode::NOT_ACCEPTABLE),
+            };
+            response.vary(&["Accept"]);
+            response
+        }
+    }
+}
+
+// The formats an `Accept` header allows, most preferred first. Media ranges
+// are tried in order of weight, keeping the header's order between equal
+// weights, and `*/*` stands for `default` and then the other formats. A
+// format given `q=0` is ruled out even if a wildcard would allow it; a
+// missing header allows only `default`.
+fn accepted_formats(header: Option<&str>, default: AcceptedFormat) -> Vec<AcceptedFormat> {
+    let Some(header) = header else {
+        return vec![default];
+    };
+
+    let mut ranges: Vec<(AcceptedFormat, f32)> = header
+        .split(',')
+        .filter_map(|entry| {
+            let (mime, params) = parse_media_type(entry).ok()?;
+            let weight: f32 = params.get("q").map_or(Some(1.0), |q| q.parse().ok())?;
+            let formats = match mime.as_str() {
+                "*/*" => vec![
+                    default,
+                    AcceptedFormat::Html,
+                    AcceptedFormat::Json,
+                    AcceptedFormat::Xml,
+                ],
+                mime => vec![AcceptedFormat::from_media_range(mime)?],
+            };
+            Some(formats.into_iter().map(move |format| (format, weight)))
+        })
+        .flatten()
+        .collect();
+    ranges.sort_by(|a, b| b.1.total_cmp(&a.1));
+
+    let refused: Vec<AcceptedFormat> = ranges
+        .iter()
+        .filter(|(_, weight)| *weight <= 0.0)
+        .map(|(format, _)| *format)
+        .collect();
+    let mut formats = Vec::new();
+    for (format, _) in ranges {
+        if !refused.contains(&format) && !formats.contains(&format) {
+            formats.push(format);
+        }
+    }
+    formats
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn languages(header: &str) -> Vec<(String, f32)> {
+        parse_accept_language(header)
+    }
+
+    fn locale<'a>(header: &str, available: &[&'a str]) -> Option<&'a str> {
+        negotiate_locale(&languages(header), available)
+    }
+
+    fn get(accept: Option<&str>, negotiated: NegotiatedResponse) -> Response {
+        let mut head = "GET / HTTP/1.1\r\n".to_string();
+        if let Some(accept) = accept {
+            head.push_str(&format!("Accept: {accept}\r\n"));
+        }
+        head.push_str("\r\n");
+        let handler =
+            ContentNegotiationLayer::new(AcceptedFormat::Json).wrap(move |_, _| negotiated.clone());
+        handler(Request::new(head.as_bytes()).unwrap())
+    }
+
+    #[test]
+    fn sorts_languages_by_weight() {
+        let parsed = languages("fr;q=0.7, en-US, de;q=0.7, en;q=0.9, *;q=0");
+        let tags: Vec<&str> = parsed.iter().map(|(tag, _)| tag.as_str()).collect();
+        assert_eq!(tags, ["en-US", "en", "fr", "de", "*"]);
+        assert_eq!(parsed[1].1, 0.9);
+        assert_eq!(parsed[4].1, 0.0);
+    }
+
+    #[test]
+    fn skips_malformed_languages() {
+        let parsed = languages("en;q=2, fr;q=x, , de;q=-1, it ; q=0.5");
+        assert_eq!(parsed, [("it".to_string(), 0.5)]);
+    }
+
+    #[test]
+    fn negotiates_exact_then_primary_language() {
+        let available = ["en-GB", "fr", "pt-BR"];
+        assert_eq!(locale("fr-CA, en-GB;q=0.8", &available), Some("fr"));
+        assert_eq!(locale("en-gb, fr;q=0.9", &available), Some("en-GB"));
+        assert_eq!(locale("pt_PT", &available), Some("pt-BR"));
+        assert_eq!(locale("de, *;q=0.1", &["it", "es"]), Some("it"));
+        assert_eq!(locale("de, ja", &available), Some("en-GB"));
+        assert_eq!(locale("fr", &[]), None);
+    }
+
+    #[test]
+    fn never_negotiates_refused_languages() {
+        let available = ["fr", "en"];
+        assert_eq!(locale("fr;q=0, en;q=0.1", &available), Some("en"));
+        assert_eq!(locale("*;q=0, en;q=0.5", &available), Some("en"));
+    }
+
+    #[test]
+    fn orders_formats_by_weight() {
+        let formats = accepted_formats(
+            Some("application/xml;q=0.5, text/html;q=0.9, application/problem+json"),
+            AcceptedFormat::Html,
+        );
+        assert_eq!(
+            formats,
+            [
+                AcceptedFormat::Json,
+                AcceptedFormat::Html,
+                AcceptedFormat::Xml
+            ]
+        );
+        let formats = accepted_formats(Some("text/*, image/png"), AcceptedFormat::Json);
+        assert_eq!(formats, [AcceptedFormat::Html]);
+    }
+
+    #[test]
+    fn expands_wildcards_to_the_default_first() {
+        let all = accepted_formats(Some("*/*"), AcceptedFormat::Xml);
+        assert_eq!(
+            all,
+            [
+                AcceptedFormat::Xml,
+                AcceptedFormat::Html,
+                AcceptedFormat::Json
+            ]
+        );
+        let missing = accepted_formats(None, AcceptedFormat::Xml);
+        assert_eq!(missing, [AcceptedFormat::Xml]);
+    }
+
+    #[test]
+    fn excludes_formats_refused_with_q_zero() {
+        let formats = accepted_formats(Some("*/*, application/json;q=0"), AcceptedFormat::Json);
+        assert_eq!(formats, [AcceptedFormat::Html, AcceptedFormat::Xml]);
+        let formats = accepted_formats(Some("text/html;q=0"), AcceptedFormat::Html);
+        assert!(formats.is_empty());
+        let formats = accepted_formats(Some("text/html;q=high"), AcceptedFormat::Html);
+        assert!(formats.is_empty());
+    }
+
+    #[test]
+    fn sends_the_best_representation_the_handler_has() {
+        let both = NegotiatedResponse::new(StatusCode::CREATED)
+            .html("<p>hi</p>")
+            .json("{}");
+
+        let response = get(Some("text/html, application/json;q=0.5"), both.clone());
+        assert_eq!(response.status, StatusCode::CREATED);
+        let content_type = response.headers.get("Content-Type");
+        assert_eq!(content_type, Some("text/html; charset=utf-8"));
+        assert_eq!(response.body, b"<p>hi</p>");
+        assert_eq!(response.headers.get("Vary"), Some("Accept"));
+
+        let response = get(None, both.clone());
+        assert_eq!(
+            response.headers.get("Content-Type"),
+            Some("application/json")
+        );
+
+        let response = get(Some("application/xml, */*;q=0.1"), both.clone());
+        assert_eq!(
+            response.headers.get("Content-Type"),
+            Some("application/json")
+        );
+
+        let response = get(Some("application/xml"), both);
+        assert_eq!(response.status, StatusCode::NOT_ACCEPTABLE);
+        assert_eq!(response.headers.get("Vary"), Some("Accept"));
+    }
+}
@@ -0,0 +1,105 @@
+use crate::json::json_string;
+use crate::HttpMethod;
+
+/// The `info` section of a generated OpenAPI document.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct OpenApiInfo {
+    /// The name of the API.
+    pub title: String,
+    /// The version of the API (not of OpenAPI), e.g. `1.2.0`.
+    pub version: String,
+    /// A longer description of the API, if any.
+    pub description: Option<String>,
+}
+
+/// Builds an OpenAPI 3.0 document listing `routes`, given as method and pattern.
+///
+/// Routes sharing a pattern are grouped under one path, in the order they
+/// were registered. `:name` segments become `{name}` path parameters and a
+/// final `*` becomes a `{*}` parameter. `CONNECT` has no OpenAPI operation
+/// and is left out.
+pub(crate) fn openapi_spec<'a>(
+    info: &OpenApiInfo,
+    routes: impl Iterator<Item = (HttpMethod, &'a str)>,
+) -> String {
+    // Each path with its operations, in registration order
+    let mut paths: Vec<(String, Vec<String>, Vec<String>)> = Vec::new();
+    for (method, pattern) in routes {
+        if method == HttpMethod::Connect {
+            continue;
+        }
+        let (path, params) = openapi_path(pattern);
+        let index = match paths.iter().position(|(existing, _, _)| *existing == path) {
+            Some(index) => index,
+            None => {
+                paths.push((path, params, Vec::new()));
+                paths.len() - 1
+            }
+        };
+
+        let operation = method.as_str().to_ascii_lowercase();
+        let operations = &mut paths[index].2;
+        if !operations.contains(&operation) {
+            operations.push(operation);
+        }
+    }
+
+    let paths: Vec<String> = paths
+        .iter()
+        .map(|(path, params, operations)| {
+            let parameters: Vec<String> = params
+                .iter()
+                .map(|name| {
+                    format!(
+                        "{{\"name\":{},\"in\":\"path\",\"required\":true,\"schema\":{{\"type\":\"string\"}}}}",
+                        json_string(name)
+                    )
+                })
+                .collect();
+            let operations: Vec<String> = operations
+                .iter()
+                .map(|operation| {
+                    format!(
+                        "\"{operation}\":{{\"parameters\":[{}],\"responses\":{{\"default\":{{\"description\":\"Response\"}}}}}}",
+                        parameters.join(",")
+                    )
+                })
+                .collect();
+            format!("{}:{{{}}}", json_string(path), operations.join(","))
+        })
+        .collect();
+
+    let mut info_json = format!(
+        "\"title\":{},\"version\":{}",
+        json_string(&info.title),
+        json_string(&info.version)
+    );
+    if let Some(description) = &info.description {
+        info_json.push_str(&format!(",\"description\":{}", json_string(description)));
+    }
+
+    format!(
+        "{{\"openapi\":\"3.0.3\",\"info\":{{{info_json}}},\"paths\":{{{}}}}}",
+        paths.join(",")
+    )
+}
+
+// Converts a route pattern to an OpenAPI path, returning it with its parameter names
+fn openapi_path(pattern: &str) -> (String, Vec<String>) {
+    let mut params = Vec::new();
+    let segments: Vec<String> = pattern
+        .split('/')
+        .map(|segment| {
+            if let Some(name) = segment.strip_prefix(':') {
+                params.push(name.to_string());
+                format!("{{{name}}}")
+            } else if segment == "*" {
+                params.push("*".to_string());
+                "{*}".to_string()
+            } else {
+                segment.to_string()
+            }
+        })
+        .collect();
+    (segments.join("/"), params)
+}
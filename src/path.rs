@@ -0,0 +1,159 @@
+use crate::HttpError;
+
+/// Canonicalizes the path of a request target.
+///
+/// Empty segments (`//`) and `.` segments are removed and `..` removes the
+/// segment before it. A trailing slash is kept, and so is a query string,
+/// which is passed through untouched. Percent-encoded dots (`%2E`) count as
+/// dots so they cannot be used to sneak a `..` past the check, while encoded
+/// slashes (`%2F`) are left alone and stay part of their segment.
+///
+/// Targets that are not paths, such as `*` or the `host:port` of a
+/// `CONNECT` request, are returned unchanged.
+///
+/// # Arguments
+///
+/// * `path` - The request target to normalize, e.g. `/./api/../v1//users`.
+///
+/// # Returns
+///
+/// The normalized path, e.g. `/v1/users`.
+///
+/// # Errors
+///
+/// Returns `HttpError::InvalidPath` if a `..` segment would climb above the root.
+pub fn normalize_path(path: &str) -> Result<String, HttpError> {
+    if !path.starts_with('/') {
+        return Ok(path.to_string());
+    }
+
+    let (path, query) = match path.split_once('?') {
+        Some((path, query)) => (path, Some(query)),
+        None => (path, None),
+    };
+
+    let mut segments: Vec<&str> = Vec::new();
+    for segment in path.split('/') {
+        match decode_dots(segment).as_str() {
+            "" | "." => {}
+            ".." => {
+                segments.pop().ok_or(HttpError::InvalidPath)?;
+            }
+            _ => segments.push(segment),
+        }
+    }
+
+    // "/a/b/", "/a/b/." and "/a/b/c/.." all name the directory "/a/b/"
+    let last = path.rsplit('/').next().unwrap_or_default();
+    let trailing_slash = matches!(decode_dots(last).as_str(), "" | "." | "..");
+
+    let mut normalized = format!("/{}", segments.join("/"));
+    if trailing_slash && !segments.is_empty() {
+        normalized.push('/');
+    }
+    if let Some(query) = query {
+        normalized.push('?');
+        normalized.push_str(query);
+    }
+    Ok(normalized)
+}
+
+/// Returns `true` if `path` could reach outside the directory it is resolved against.
+///
+/// The path is percent-decoded first, so `%2e%2e%2f` counts as `../`. It is
+/// rejected if any segment is `..`, if it contains a backslash (a separator
+/// on Windows), or if it is absolute once its single leading `/` is
+/// removed: `//etc/passwd` or a drive such as `/C:/Windows`. A query string
+/// is not part of the path and must be removed by the caller.
+///
+/// # Arguments
+///
+/// * `path` - The request path, e.g. `/css/site.css`.
+pub fn detect_path_traversal(path: &str) -> bool {
+    let decoded = percent_decode(path);
+    let relative = decoded.strip_prefix('/').unwrap_or(&decoded);
+
+    let bytes = relative.as_bytes();
+    let has_drive = bytes.len() >= 2 && bytes[0].is_ascii_alphabetic() && bytes[1] == b':';
+    relative.starts_with('/')
+        || has_drive
+        || relative.contains('\\')
+        || relative.split('/').any(|segment| segment == "..")
+}
+
+// Decodes every `%XX` escape; invalid escapes are kept as they are
+fn percent_decode(input: &str) -> String {
+    let bytes = input.as_bytes();
+    let mut decoded = Vec::with_capacity(bytes.len());
+    let mut i = 0;
+    while i < bytes.len() {
+        let escape = bytes.get(i + 1..i + 3).and_then(|hex| {
+            let hex = std::str::from_utf8(hex).ok()?;
+            u8::from_str_radix(hex, 16).ok()
+        });
+        match (bytes[i], escape) {
+            (b'%', Some(byte)) => {
+                decoded.push(byte);
+                i += 3;
+            }
+            (byte, _) => {
+                decoded.push(byte);
+                i += 1;
+            }
+        }
+    }
+    String::from_utf8_lossy(&decoded).into_owned()
+}
+
+// Decodes `%2E`/`%2e` so dot segments are recognised however they are spelled
+fn decode_dots(segment: &str) -> String {
+    segment.replace("%2E", ".").replace("%2e", ".")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn rejects_climbing_above_the_root() {
+        assert!(matches!(normalize_path("/../"), Err(HttpError::InvalidPath)));
+        assert!(matches!(normalize_path("/../etc/passwd"), Err(HttpError::InvalidPath)));
+        assert!(matches!(normalize_path("/a/b/../../.."), Err(HttpError::InvalidPath)));
+        assert!(matches!(normalize_path("/a/%2e%2E/.."), Err(HttpError::InvalidPath)));
+    }
+
+    #[test]
+    fn removes_dot_segments() {
+        assert_eq!(normalize_path("/.").unwrap(), "/");
+        assert_eq!(normalize_path("/./").unwrap(), "/");
+        assert_eq!(normalize_path("/a/b/../..").unwrap(), "/");
+        assert_eq!(normalize_path("/./api/../v1//users").unwrap(), "/v1/users");
+        assert_eq!(normalize_path("/a/b/.").unwrap(), "/a/b/");
+        assert_eq!(normalize_path("/a/./b/?q=/../x").unwrap(), "/a/b/?q=/../x");
+    }
+
+    #[test]
+    fn keeps_encoded_slashes_in_their_segment() {
+        assert_eq!(normalize_path("/a%2Fb/c").unwrap(), "/a%2Fb/c");
+        assert_eq!(normalize_path("/a%2Fb/..").unwrap(), "/");
+        assert_eq!(normalize_path("/x/..%2F..%2Fetc").unwrap(), "/x/..%2F..%2Fetc");
+    }
+
+    #[test]
+    fn leaves_non_path_targets_alone() {
+        assert_eq!(normalize_path("*").unwrap(), "*");
+        assert_eq!(normalize_path("example.com:443").unwrap(), "example.com:443");
+    }
+
+    #[test]
+    fn detects_traversal() {
+        assert!(detect_path_traversal("/../etc/passwd"));
+        assert!(detect_path_traversal("/x/..%2F..%2Fetc"));
+        assert!(detect_path_traversal("/%2e%2e/secret"));
+        assert!(detect_path_traversal("//etc/passwd"));
+        assert!(detect_path_traversal("/C:/Windows"));
+        assert!(detect_path_traversal("/a\\b"));
+        assert!(!detect_path_traversal("/css/site.css"));
+        assert!(!detect_path_traversal("/a%2Fb"));
+    }
+}
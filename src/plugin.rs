@@ -0,0 +1,87 @@
+use crate::Router;
+
+/// A bundle of routes that can be registered on a server in one call.
+///
+/// Plugins let an application split its routes across modules or crates
+/// instead of listing every route in `main`. A separate crate depends on
+/// this one, implements `Plugin` for one of its types and exports it:
+///
+/// ```ignore
+/// // users/src/lib.rs
+/// use app::{Plugin, Request, Response, Router, StatusCode};
+///
+/// pub struct UsersPlugin;
+///
+/// impl Plugin for UsersPlugin {
+///     fn register(&self, router: &mut Router) {
+///         router.get("/users/:id", show_user);
+///     }
+/// }
+///
+/// fn show_user(req: Request) -> Response {
+///     let id = &req.params["id"];
+///     Response::with_body(StatusCode::OK, "text/plain", format!("user {id}").into_bytes())
+/// }
+/// ```
+///
+/// The application then registers it on its `ServerBuilder`:
+///
+/// ```ignore
+/// // src/main.rs
+/// fn main() {
+///     let server = ServerBuilder::new("127.0.0.1:7990")
+///         .add_plugin(users::UsersPlugin)
+///         .build()
+///         .unwrap();
+///     server.run();
+/// }
+/// ```
+pub trait Plugin: Send + Sync {
+    /// Adds the plugin's routes to `router`.
+    fn register(&self, router: &mut Router);
+}
+
+/// Implements [`Plugin`] for a type.
+///
+/// With just a type, the type must have an inherent
+/// `fn routes(&self, router: &mut Router)` method which becomes the body of
+/// `Plugin::register`:
+///
+/// ```ignore
+/// struct Pages;
+///
+/// impl Pages {
+///     fn routes(&self, router: &mut Router) {
+///         router.get("/", index);
+///     }
+/// }
+///
+/// http_plugin!(Pages);
+/// ```
+///
+/// Alternatively the routes can be given inline as a closure-like block:
+///
+/// ```ignore
+/// struct Pages;
+///
+/// http_plugin!(Pages, |router| {
+///     router.get("/", index);
+/// });
+/// ```
+#[macro_export]
+macro_rules! http_plugin {
+    ($plugin:ty) => {
+        impl $crate::Plugin for $plugin {
+            fn register(&self, router: &mut $crate::Router) {
+                <$plugin>::routes(self, router)
+            }
+        }
+    };
+    ($plugin:ty, |$router:ident| $body:block) => {
+        impl $crate::Plugin for $plugin {
+            fn register(&self, $router: &mut $crate::Router) {
+                $body
+            }
+        }
+    };
+}
@@ -0,0 +1,136 @@
+use std::fmt;
+use std::time::Duration;
+
+use crate::headers::{split_unquoted, unquote};
+use crate::HeaderMap;
+
+/// A preference a client can express in the `Prefer` header (RFC 7240).
+///
+/// Servers that honour a preference can say so by echoing it in
+/// `Preference-Applied`; see [`Response::with_preference_applied`](crate::Response::with_preference_applied).
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Preference {
+    /// `wait=N`: the client will wait at most this long for a response.
+    Wait(Duration),
+    /// `respond-async`: the client would rather get `202 Accepted` than wait.
+    RespondAsync,
+    /// `return=minimal`: the client does not need the resource in the response.
+    ReturnMinimal,
+    /// `return=representation`: the client wants the resource in the response.
+    ReturnRepresentation,
+}
+
+impl fmt::Display for Preference {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Preference::Wait(wait) => write!(f, "wait={}", wait.as_secs()),
+            Preference::RespondAsync => f.write_str("respond-async"),
+            Preference::ReturnMinimal => f.write_str("return=minimal"),
+            Preference::ReturnRepresentation => f.write_str("return=representation"),
+        }
+    }
+}
+
+// Returns the preferences in the `Prefer` headers, in order. Names are
+// case-insensitive, values may be quoted, and parameters after `;` and
+// preferences this crate does not know are ignored.
+pub(crate) fn parse_preferences(headers: &HeaderMap) -> Vec<Preference> {
+    headers
+        .get_all("Prefer")
+        .flat_map(|value| split_unquoted(value, ','))
+        .filter_map(|entry| {
+            let preference = split_unquoted(entry, ';').into_iter().next()?;
+            let (name, value) = match preference.split_once('=') {
+                Some((name, value)) => (name.trim(), unquote(value.trim())),
+                None => (preference, String::new()),
+            };
+            match name.to_ascii_lowercase().as_str() {
+                "wait" => value
+                    .parse()
+                    .ok()
+                    .map(Duration::from_secs)
+                    .map(Preference::Wait),
+                "respond-async" => Some(Preference::RespondAsync),
+                "return" if value.eq_ignore_ascii_case("minimal") => {
+                    Some(Preference::ReturnMinimal)
+                }
+                "return" if value.eq_ignore_ascii_case("representation") => {
+                    Some(Preference::ReturnRepresentation)
+                }
+                _ => None,
+            }
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn preferences(values: &[&str]) -> Vec<Preference> {
+        let mut headers = HeaderMap::new();
+        for &value in values {
+            headers.append("Prefer", value);
+        }
+        parse_preferences(&headers)
+    }
+
+    #[test]
+    fn parses_preferences_in_order_across_headers() {
+        assert_eq!(
+            preferences(&["respond-async, wait=10", "return=minimal"]),
+            [
+                Preference::RespondAsync,
+                Preference::Wait(Duration::from_secs(10)),
+                Preference::ReturnMinimal,
+            ]
+        );
+        assert!(preferences(&[]).is_empty());
+    }
+
+    #[test]
+    fn folds_case_and_unquotes_values() {
+        assert_eq!(
+            preferences(&["Return=\"Representation\", WAIT = \"5\", Respond-Async"]),
+            [
+                Preference::ReturnRepresentation,
+                Preference::Wait(Duration::from_secs(5)),
+                Preference::RespondAsync,
+            ]
+        );
+    }
+
+    #[test]
+    fn ignores_parameters_and_unknown_preferences() {
+        assert_eq!(
+            preferences(&["handling=lenient; x=\"a, b\", return=minimal; foo=bar"]),
+            [Preference::ReturnMinimal]
+        );
+        assert_eq!(
+            preferences(&["return=everything, wait=-1, wait=soon, wait, return"]),
+            []
+        );
+    }
+
+    #[test]
+    fn formats_preferences_for_preference_applied() {
+        let formatted: Vec<String> = [
+            Preference::Wait(Duration::from_millis(2500)),
+            Preference::RespondAsync,
+            Preference::ReturnMinimal,
+            Preference::ReturnRepresentation,
+        ]
+        .iter()
+        .map(Preference::to_string)
+        .collect();
+        assert_eq!(
+            formatted,
+            [
+                "wait=2",
+                "respond-async",
+                "return=minimal",
+                "return=representation"
+            ]
+        );
+    }
+}
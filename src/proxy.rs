@@ -0,0 +1,361 @@
+use std::fs::{self, File};
+use std::io::{self, BufReader};
+use std::net::SocketAddr;
+use std::path::{Path, PathBuf};
+use std::time::{Duration, SystemTime};
+
+use crate::crypto::sha256;
+use crate::random::random_u64;
+use crate::{HttpClient, HttpError, HttpMethod, Request, Response, StatusCode};
+
+/// A reverse proxy that keeps upstream responses on disk and replays them while fresh.
+///
+/// Successful `GET` responses are stored under `cache_dir`, one file per
+/// URL named after its SHA-256, and served from there until they are older
+/// than `max_age`; replayed responses carry an `Age` header. Requests with
+/// `Cache-Control: no-cache` or `Pragma: no-cache` always go upstream, and
+/// the fresh response replaces the stored one. Other methods are forwarded
+/// as they are and never cached.
+///
+/// Following RFC 7234 §3, responses that set cookies or carry `Vary: *`
+/// are never stored, and neither are responses to requests with
+/// `Authorization` or `Cookie` unless the upstream marks them `public` or
+/// gives an `s-maxage`. A response with `Vary` is stored once for each
+/// combination of the request headers it names.
+///
+/// ```ignore
+/// let proxy = CachingProxy::new(upstream, "/var/cache/app", Duration::from_secs(60));
+/// router.get("/*", move |req| proxy.serve(&req));
+/// ```
+#[derive(Debug, Clone)]
+pub struct CachingProxy {
+    upstream: SocketAddr, // The server requests are forwarded to
+    cache_dir: PathBuf,   // Where stored responses live, one file per URL and variant
+    max_age: Duration,    // How long a stored response is served without going upstream
+}
+
+impl CachingProxy {
+    /// Creates a proxy for `upstream` that stores responses in `cache_dir`.
+    ///
+    /// The directory is created on the first store if it does not exist.
+    pub fn new(
+        upstream: SocketAddr,
+        cache_dir: impl Into<PathBuf>,
+        max_age: Duration,
+    ) -> CachingProxy {
+        CachingProxy {
+            upstream,
+            cache_dir: cache_dir.into(),
+            max_age,
+        }
+    }
+
+    /// Answers `req` from the cache, or forwards it upstream.
+    ///
+    /// Upstream failures get `502 Bad Gateway` and unknown methods `501 Not
+    /// Implemented`. A response that cannot be stored is still returned; the
+    /// next request simply goes upstream again.
+    pub fn serve(&self, req: &Request) -> Response {
+        let url = format!("http://{}{}", self.upstream, req.path);
+        let cacheable = req.method == HttpMethod::Get.as_str();
+
+        if cacheable && !bypasses_cache(req) {
+            if let Some(response) = self.cached(&url, req) {
+                return response;
+            }
+        }
+
+        let Ok(method) = req.method.parse::<HttpMethod>() else {
+            return Response::new(StatusCode::NOT_IMPLEMENTED);
+        };
+        let response = match self.forward(method, req, &url) {
+            Ok(response) => response,
+            Err(err) => {
+                println!("Proxy request to {url} failed: {err}");
+                return Response::new(StatusCode::BAD_GATEWAY);
+            }
+        };
+        if cacheable && is_storable(req, &response) {
+            if let Err(err) = self.store(&url, req, &response) {
+                println!("Failed to cache {url}: {err}");
+            }
+        }
+        response
+    }
+
+    // The file the response for `url` is stored in, for the request header
+    // values in `variant`. A URL whose responses have no `Vary` has one file
+    fn cache_path(&self, url: &str, variant: &str) -> PathBuf {
+        let name: String = sha256(format!("{url}\n{variant}").as_bytes())
+            .iter()
+            .map(|byte| format!("{byte:02x}"))
+            .collect();
+        self.cache_dir.join(name)
+    }
+
+    // The file listing the request headers the latest stored response for `url` varies on
+    fn vary_path(&self, url: &str) -> PathBuf {
+        self.cache_path(url, "").with_extension("vary")
+    }
+
+    // Reads back the stored response matching `req`, if there is one younger than max_age
+    fn cached(&self, url: &str, req: &Request) -> Option<Response> {
+        let vary = fs::read_to_string(self.vary_path(url)).unwrap_or_default();
+        let file = File::open(self.cache_path(url, &variant(req, &vary))).ok()?;
+        let age = SystemTime::now()
+            .duration_since(file.metadata().ok()?.modified().ok()?)
+            .unwrap_or_default();
+        if age >= self.max_age {
+            return None;
+        }
+
+        let (status, headers, body) = Response::from_stream(&mut BufReader::new(file)).ok()?;
+        let mut response = Response::new(status);
+        response.headers = headers;
+        response.body = body;
+        response.set_header("Age", &age.as_secs().to_string());
+        Some(response)
+    }
+
+    // Sends the request to the upstream server, without hop-by-hop headers
+    fn forward(&self, method: HttpMethod, req: &Request, url: &str) -> Result<Response, HttpError> {
+        let mut headers = req.headers.clone();
+        headers.remove_hop_by_hop();
+        headers.remove("Host");
+
+        let mut request = HttpClient::new().request(method, url);
+        for (name, value) in headers.iter() {
+            request = request.header(name, value);
+        }
+        let mut response = request.body(req.body.clone()).send()?;
+        response.headers.remove_hop_by_hop();
+        Ok(response)
+    }
+
+    // Stores the response to `req` under the variant its `Vary` header
+    // picks, and records which headers that is for later lookups
+    fn store(&self, url: &str, req: &Request, response: &Response) -> io::Result<()> {
+        fs::create_dir_all(&self.cache_dir)?;
+        let mut stored = Response::new(response.status);
+        stored.headers = response.headers.clone();
+        stored.body = response.body.clone();
+
+        let vary = vary_names(response).join(", ");
+        let mut entry = Vec::new();
+        stored.write_to(&mut entry)?;
+        write_atomically(&self.cache_path(url, &variant(req, &vary)), &entry)?;
+        write_atomically(&self.vary_path(url), vary.as_bytes())
+    }
+}
+
+// Writes `contents` to a temp file, then renames it into place so readers
+// never see a partial file
+fn write_atomically(path: &Path, contents: &[u8]) -> io::Result<()> {
+    let temp_path = path.with_extension(format!("{:016x}.tmp", random_u64()));
+    let result = fs::write(&temp_path, contents).and_then(|()| fs::rename(&temp_path, path));
+    if result.is_err() {
+        let _ = fs::remove_file(&temp_path);
+    }
+    result
+}
+
+// The lowercased names of the request headers the response varies on, sorted
+fn vary_names(response: &Response) -> Vec<String> {
+    let mut names: Vec<String> = response
+        .headers
+        .get_all("Vary")
+        .flat_map(|value| value.split(','))
+        .map(|name| name.trim().to_ascii_lowercase())
+        .filter(|name| !name.is_empty())
+        .collect();
+    names.sort();
+    names.dedup();
+    names
+}
+
+// The values `req` has for each of the comma-separated header names in
+// `vary`, one `name: value` line per header
+fn variant(req: &Request, vary: &str) -> String {
+    vary.split(',')
+        .map(str::trim)
+        .filter(|name| !name.is_empty())
+        .map(|name| {
+            let values: Vec<&str> = req.headers.get_all(name).collect();
+            format!("{name}: {}\n", values.join(", "))
+        })
+        .collect()
+}
+
+// Whether the client asked for a response from the origin rather than a stored one
+fn bypasses_cache(req: &Request) -> bool {
+    let no_cache = |value: &str| {
+        value
+            .split(',')
+            .any(|directive| directive.trim().eq_ignore_ascii_case("no-cache"))
+    };
+    req.headers.get_all("Cache-Control").any(no_cache)
+        || req.headers.get_all("Pragma").any(no_cache)
+}
+
+// Only complete successful responses the upstream allows a shared cache to
+// store are cached. One client's credentials and cookies must not reach another
+fn is_storable(req: &Request, response: &Response) -> bool {
+    let directives: Vec<String> = response
+        .headers
+        .get_all("Cache-Control")
+        .flat_map(|value| value.split(','))
+        .map(|directive| directive.trim().to_ascii_lowercase())
+        .collect();
+    let has = |name: &str| {
+        directives
+            .iter()
+            .any(|directive| directive.split('=').next() == Some(name))
+    };
+    if response.status != StatusCode::OK || has("no-store") || has("private") {
+        return false;
+    }
+
+    let personal = req.headers.contains("Authorization") || req.headers.contains("Cookie");
+    if personal && !has("public") && !has("s-maxage") {
+        return false;
+    }
+    !response.headers.contains("Set-Cookie") && !vary_names(response).iter().any(|name| name == "*")
+}
+
+#[cfg(test)]
+mod tests {
+    use std::net::TcpListener;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::sync::Arc;
+    use std::thread;
+
+    use super::*;
+
+    // An upstream answering every request with `respond`, and a proxy caching it
+    struct Fixture {
+        proxy: CachingProxy,
+        hits: Arc<AtomicUsize>, // Requests the upstream has answered
+    }
+
+    impl Fixture {
+        fn new(respond: impl Fn(&Request) -> Response + Send + 'static) -> Fixture {
+            let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+            let upstream = listener.local_addr().unwrap();
+            let hits = Arc::new(AtomicUsize::new(0));
+            let counter = Arc::clone(&hits);
+            thread::spawn(move || {
+                for mut stream in listener.incoming().flatten() {
+                    let Ok(req) = Request::new(&stream) else {
+                        continue;
+                    };
+                    counter.fetch_add(1, Ordering::SeqCst);
+                    let _ = respond(&req).write_to(&mut stream);
+                }
+            });
+
+            let cache_dir = std::env::temp_dir().join(format!("proxy-test-{:016x}", random_u64()));
+            let proxy = CachingProxy::new(upstream, cache_dir, Duration::from_secs(60));
+            Fixture { proxy, hits }
+        }
+
+        // Sends `GET /page` with `headers` through the proxy
+        fn get(&self, headers: &str) -> Response {
+            let head = format!("GET /page HTTP/1.1\r\nHost: proxy\r\n{headers}\r\n");
+            self.proxy.serve(&Request::new(head.as_bytes()).unwrap())
+        }
+
+        fn hits(&self) -> usize {
+            self.hits.load(Ordering::SeqCst)
+        }
+    }
+
+    impl Drop for Fixture {
+        fn drop(&mut self) {
+            let _ = fs::remove_dir_all(&self.proxy.cache_dir);
+        }
+    }
+
+    fn page(headers: &[(&str, &str)]) -> Response {
+        let mut response = Response::with_body(StatusCode::OK, "text/plain", b"page".to_vec());
+        for &(name, value) in headers {
+            response.headers.append(name, value);
+        }
+        response
+    }
+
+    #[test]
+    fn replays_a_stored_response() {
+        let fixture = Fixture::new(|_| page(&[]));
+        assert!(!fixture.get("").headers.contains("Age"));
+        let replayed = fixture.get("");
+        assert_eq!(replayed.body, b"page");
+        assert!(replayed.headers.contains("Age"));
+        assert_eq!(fixture.hits(), 1);
+    }
+
+    #[test]
+    fn does_not_store_responses_to_credentialed_requests() {
+        let fixture =
+            Fixture::new(|req| page(&[("X-User", req.headers.get("Authorization").unwrap_or(""))]));
+        fixture.get("Authorization: Basic YWxpY2U6cHc=\r\n");
+        let other = fixture.get("Authorization: Basic Ym9iOnB3\r\n");
+        assert_eq!(other.headers.get("X-User"), Some("Basic Ym9iOnB3"));
+        assert_eq!(fixture.hits(), 2);
+
+        let fixture = Fixture::new(|_| page(&[]));
+        fixture.get("Cookie: session=alice\r\n");
+        fixture.get("Cookie: session=bob\r\n");
+        assert_eq!(fixture.hits(), 2);
+    }
+
+    #[test]
+    fn stores_credentialed_responses_marked_shareable() {
+        for cache_control in ["public", "s-maxage=60"] {
+            let fixture = Fixture::new(move |_| page(&[("Cache-Control", cache_control)]));
+            fixture.get("Authorization: Basic YWxpY2U6cHc=\r\n");
+            fixture.get("Authorization: Basic Ym9iOnB3\r\n");
+            assert_eq!(fixture.hits(), 1, "{cache_control}");
+        }
+    }
+
+    #[test]
+    fn does_not_store_responses_that_set_cookies_or_vary_on_everything() {
+        let fixture = Fixture::new(|_| page(&[("Set-Cookie", "session=alice")]));
+        fixture.get("");
+        assert!(fixture.get("").headers.contains("Set-Cookie"));
+        assert_eq!(fixture.hits(), 2);
+
+        let fixture = Fixture::new(|_| page(&[("Vary", "Accept, *")]));
+        fixture.get("");
+        fixture.get("");
+        assert_eq!(fixture.hits(), 2);
+
+        let fixture = Fixture::new(|_| page(&[("Cache-Control", "private=\"X-User\"")]));
+        fixture.get("");
+        fixture.get("");
+        assert_eq!(fixture.hits(), 2);
+    }
+
+    #[test]
+    fn stores_one_variant_per_vary_header_value() {
+        let fixture = Fixture::new(|req| {
+            let language = req
+                .headers
+                .get("Accept-Language")
+                .unwrap_or("none")
+                .to_string();
+            page(&[("Vary", "Accept-Language"), ("Content-Language", &language)])
+        });
+        fixture.get("Accept-Language: en\r\n");
+        let french = fixture.get("Accept-Language: fr\r\n");
+        assert_eq!(french.headers.get("Content-Language"), Some("fr"));
+        assert_eq!(fixture.hits(), 2);
+
+        let english = fixture.get("Accept-Language: en\r\n");
+        assert_eq!(english.headers.get("Content-Language"), Some("en"));
+        assert!(english.headers.contains("Age"));
+        let none = fixture.get("");
+        assert_eq!(none.headers.get("Content-Language"), Some("none"));
+        assert_eq!(fixture.hits(), 3);
+    }
+}
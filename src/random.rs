@@ -0,0 +1,85 @@
+use std::collections::hash_map::RandomState;
+use std::hash::{BuildHasher, Hasher};
+use std::io;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+// Mixed into every value so two calls in the same nanosecond still differ
+static COUNTER: AtomicU64 = AtomicU64::new(0);
+
+/// Returns a pseudo-random `u64`.
+///
+/// Values come from the randomly keyed SipHash behind `RandomState`, which is
+/// plenty for identifiers and boundaries but is not a cryptographic RNG.
+pub(crate) fn random_u64() -> u64 {
+    let nanos = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|elapsed| elapsed.as_nanos())
+        .unwrap_or_default();
+
+    let mut hasher = RandomState::new().build_hasher();
+    hasher.write_u64(COUNTER.fetch_add(1, Ordering::Relaxed));
+    hasher.write_u128(nanos);
+    hasher.finish()
+}
+
+/// Returns a pseudo-random `u128` built from two calls to [`random_u64`].
+pub(crate) fn random_u128() -> u128 {
+    (u128::from(random_u64()) << 64) | u128::from(random_u64())
+}
+
+/// Returns a `u128` from the operating system's cryptographic RNG.
+///
+/// For values an attacker must not be able to predict, such as CSP and
+/// Digest nonces. Reads `getrandom(2)` on Linux and `/dev/urandom` elsewhere.
+///
+/// # Panics
+///
+/// Panics if the OS cannot provide random bytes, which only happens on a
+/// badly broken system; a request handled at the time is answered with `500`.
+pub(crate) fn secure_random_u128() -> u128 {
+    let mut bytes = [0u8; 16];
+    fill_from_os(&mut bytes).expect("the OS random number generator is unavailable");
+    u128::from_ne_bytes(bytes)
+}
+
+#[cfg(target_os = "linux")]
+fn fill_from_os(buf: &mut [u8]) -> io::Result<()> {
+    let mut filled = 0;
+    while filled < buf.len() {
+        let rest = &mut buf[filled..];
+        // SAFETY: `rest` is valid for writes of `rest.len()` bytes for the whole call
+        let result = unsafe { libc::getrandom(rest.as_mut_ptr().cast(), rest.len(), 0) };
+        if result < 0 {
+            let err = io::Error::last_os_error();
+            if err.kind() != io::ErrorKind::Interrupted {
+                return Err(err);
+            }
+            continue;
+        }
+        filled += result as usize;
+    }
+    Ok(())
+}
+
+#[cfg(not(target_os = "linux"))]
+fn fill_from_os(buf: &mut [u8]) -> io::Result<()> {
+    use std::io::Read;
+
+    std::fs::File::open("/dev/urandom")?.read_exact(buf)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn draws_distinct_secure_values() {
+        let values: Vec<u128> = (0..64).map(|_| secure_random_u128()).collect();
+        for (index, value) in values.iter().enumerate() {
+            assert!(!values[index + 1..].contains(value));
+        }
+        // Every bit position is set in some value
+        assert_eq!(values.iter().fold(0, |bits, value| bits | value), u128::MAX);
+    }
+}
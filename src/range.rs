@@ -0,0 +1,231 @@
+use crate::random::random_u64;
+use crate::{HttpError, Response, ServeRange, StatusCode};
+
+// The most separate ranges one request may select. A multipart body costs a
+// part head per range, so more than a handful is more likely abuse than use
+const MAX_RANGES: usize = 32;
+
+/// Parses a `Range` header into the byte ranges it selects.
+///
+/// Only the `bytes` unit is understood. Ranges are returned as inclusive
+/// `(first, last)` offsets clamped to the resource; suffix ranges (`-500`)
+/// and open ranges (`9500-`) are resolved against `len`. Ranges that start
+/// past the end of the resource are dropped, and overlapping or adjacent
+/// ranges are merged (RFC 7233, section 4.1), so the result is sorted and
+/// selects no byte twice.
+///
+/// # Arguments
+///
+/// * `header` - The header value, e.g. `bytes=0-99,200-299`.
+/// * `len` - The length of the resource in bytes.
+///
+/// # Returns
+///
+/// `None` if the header is malformed or selects more than 32 separate
+/// ranges, in which case it should be ignored, otherwise the satisfiable
+/// ranges, which may be empty.
+pub fn parse_range(header: &str, len: u64) -> Option<Vec<(u64, u64)>> {
+    let specs = header.trim().strip_prefix("bytes=")?;
+
+    let mut ranges = Vec::new();
+    for spec in specs.split(',') {
+        let (first, last) = spec.trim().split_once('-')?;
+        let (first, last) = match (first.trim(), last.trim()) {
+            ("", "") => return None,
+            ("", suffix) => {
+                let suffix: u64 = suffix.parse().ok()?;
+                if suffix == 0 {
+                    continue;
+                }
+                (len.saturating_sub(suffix), len.saturating_sub(1))
+            }
+            (first, "") => (first.parse().ok()?, len.saturating_sub(1)),
+            (first, last) => {
+                let (first, last): (u64, u64) = (first.parse().ok()?, last.parse().ok()?);
+                if last < first {
+                    return None;
+                }
+                (first, last.min(len.saturating_sub(1)))
+            }
+        };
+        if first < len {
+            ranges.push((first, last));
+        }
+    }
+
+    ranges.sort_unstable();
+    let mut merged: Vec<(u64, u64)> = Vec::with_capacity(ranges.len());
+    for (first, last) in ranges {
+        match merged.last_mut() {
+            Some((_, end)) if first <= end.saturating_add(1) => *end = (*end).max(last),
+            _ => merged.push((first, last)),
+        }
+    }
+    (merged.len() <= MAX_RANGES).then_some(merged)
+}
+
+/// Builds a `206 Partial Content` response carrying several ranges of a file.
+///
+/// The body is a `multipart/byteranges` document (RFC 9110, section 14.6)
+/// with one part per range, each with its own `Content-Type` and
+/// `Content-Range`. The boundary is random so it cannot collide with the
+/// file's contents in practice.
+///
+/// # Arguments
+///
+/// * `file_data` - The complete contents of the file.
+/// * `ranges` - Inclusive `(first, last)` byte offsets, e.g. from [`parse_range`].
+///   Offsets past the end of the file are clamped and empty ranges skipped.
+/// * `content_type` - The MIME type of the file.
+pub fn build_multipart_range_response(
+    file_data: &[u8],
+    ranges: &[(u64, u64)],
+    content_type: &str,
+) -> Response {
+    multipart_range_response(&file_data, ranges, content_type)
+        .unwrap_or_else(|_| Response::new(StatusCode::INTERNAL_SERVER_ERROR))
+}
+
+// Builds the multipart/byteranges response, reading each part from `source`
+fn multipart_range_response(
+    source: &dyn ServeRange,
+    ranges: &[(u64, u64)],
+    content_type: &str,
+) -> Result<Response, HttpError> {
+    let len = source.size();
+    let boundary = format!("{:016x}{:016x}", random_u64(), random_u64());
+
+    let mut body = Vec::new();
+    for &(first, last) in ranges {
+        let last = last.min(len.saturating_sub(1));
+        if first >= len || last < first {
+            continue;
+        }
+
+        body.extend_from_slice(
+            format!(
+                "\r\n--{boundary}\r\nContent-Type: {content_type}\r\nContent-Range: bytes {first}-{last}/{len}\r\n\r\n"
+            )
+            .as_bytes(),
+        );
+        body.extend_from_slice(&source.read_range(first, last)?);
+    }
+    body.extend_from_slice(format!("\r\n--{boundary}--\r\n").as_bytes());
+
+    Ok(Response::with_body(
+        StatusCode::PARTIAL_CONTENT,
+        &format!("multipart/byteranges; boundary={boundary}"),
+        body,
+    ))
+}
+
+/// Answers a `Range` request from `source`.
+///
+/// A single range gets a plain `206 Partial Content` with `Content-Range`,
+/// several ranges get a `multipart/byteranges` body, and a header selecting
+/// nothing gets `416 Range Not Satisfiable`. A malformed header, or one
+/// selecting too many ranges, is ignored and the whole source is sent with
+/// `200 OK`. Failing to read the source gets
+/// `500 Internal Server Error`.
+pub(crate) fn range_response(source: &dyn ServeRange, content_type: &str, range: &str) -> Response {
+    let len = source.size();
+
+    let response = match parse_range(range, len).as_deref() {
+        None if len == 0 => Ok(Response::with_body(
+            StatusCode::OK,
+            content_type,
+            Vec::new(),
+        )),
+        None => source
+            .read_range(0, len - 1)
+            .map(|body| Response::with_body(StatusCode::OK, content_type, body)),
+        Some([]) => {
+            let mut response = Response::new(StatusCode::RANGE_NOT_SATISFIABLE);
+            response.set_header("Content-Range", &format!("bytes */{len}"));
+            Ok(response)
+        }
+        Some(&[(first, last)]) => source.read_range(first, last).map(|part| {
+            let mut response = Response::with_body(StatusCode::PARTIAL_CONTENT, content_type, part);
+            response.set_header("Content-Range", &format!("bytes {first}-{last}/{len}"));
+            response
+        }),
+        Some(ranges) => multipart_range_response(source, ranges, content_type),
+    };
+    let mut response =
+        response.unwrap_or_else(|_| Response::new(StatusCode::INTERNAL_SERVER_ERROR));
+    response.set_header("Accept-Ranges", "bytes");
+    response
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn resolves_suffix_and_open_ranges() {
+        assert_eq!(parse_range("bytes=-3", 10), Some(vec![(7, 9)]));
+        assert_eq!(parse_range("bytes=-30", 10), Some(vec![(0, 9)]));
+        assert_eq!(parse_range("bytes=4-", 10), Some(vec![(4, 9)]));
+        assert_eq!(parse_range("bytes=2-50", 10), Some(vec![(2, 9)]));
+        assert_eq!(parse_range("bytes=10-,-0", 10), Some(vec![]));
+    }
+
+    #[test]
+    fn merges_overlapping_and_adjacent_ranges() {
+        assert_eq!(parse_range("bytes=0-,0-,0-,0-", 10), Some(vec![(0, 9)]));
+        assert_eq!(parse_range("bytes=5-7, 0-2, 3-4", 10), Some(vec![(0, 7)]));
+        assert_eq!(
+            parse_range("bytes=6-8,0-1,1-3", 10),
+            Some(vec![(0, 3), (6, 8)])
+        );
+        assert_eq!(parse_range("bytes=0-0,-1", 10), Some(vec![(0, 0), (9, 9)]));
+    }
+
+    #[test]
+    fn ignores_excessive_ranges() {
+        let separate: Vec<String> = (0..=MAX_RANGES)
+            .map(|n| format!("{0}-{0}", n * 2))
+            .collect();
+        let header = format!("bytes={}", separate.join(","));
+        assert_eq!(parse_range(&header, 1000), None);
+
+        let header = format!("bytes={}", separate[..MAX_RANGES].join(","));
+        assert_eq!(
+            parse_range(&header, 1000).map(|ranges| ranges.len()),
+            Some(MAX_RANGES)
+        );
+
+        // An 8 KiB header of copies of the whole file selects it once
+        let header = format!("bytes={}", ["0-"; 2700].join(","));
+        assert_eq!(parse_range(&header, 1000), Some(vec![(0, 999)]));
+    }
+
+    #[test]
+    fn rejects_malformed_headers() {
+        for header in [
+            "items=0-1",
+            "bytes=",
+            "bytes=-",
+            "bytes=5-2",
+            "bytes=a-b",
+            "bytes=0-1;2-3",
+        ] {
+            assert_eq!(parse_range(header, 10), None, "{header}");
+        }
+    }
+
+    #[test]
+    fn answers_excessive_ranges_with_the_whole_body() {
+        let data = b"0123456789".to_vec();
+        let header = format!("bytes={}", ["1-1,3-3,5-5,7-7"; 10].join(","));
+        let response = range_response(&data, "text/plain", &header);
+        assert_eq!(response.status, StatusCode::PARTIAL_CONTENT);
+
+        let separate: Vec<String> = (0..40).map(|n| format!("{0}-{0}", n * 2)).collect();
+        let header = format!("bytes={}", separate.join(","));
+        let data = vec![b'x'; 100];
+        let response = range_response(&data, "text/plain", &header);
+        assert_eq!(response.status, StatusCode::OK);
+        assert_eq!(response.body, data);
+    }
+}
@@ -0,0 +1,842 @@
+use std::fmt;
+use std::fs::File;
+use std::io::{self, BufRead, Error, Read, Result, Write};
+use std::net::TcpStream;
+use std::sync::Arc;
+use std::time::Duration;
+
+use crate::access_log::CountingWriter;
+use crate::body::{is_chunked, read_body};
+use crate::headers::{fold_headers, parse_headers, HeaderMap};
+use crate::transfer_encoding::write_encoded;
+use crate::hijack::hijack_buffered;
+use crate::{ChunkedHtmlWriter, HijackedStream, HttpError, Preference, TransferEncoding};
+
+// Takes over the connection once a hijacking response's head has been sent
+type HijackFn = Box<dyn FnOnce(HijackedStream) + Send + 'static>;
+
+// Writes the content of a progressively rendered HTML page
+type RenderFn = Box<dyn FnOnce(&mut ChunkedHtmlWriter<'_>) -> Result<()> + Send + 'static>;
+
+// How much of a streamed body is read per chunk
+const STREAM_CHUNK_SIZE: usize = 8 * 1024;
+
+/// An HTTP status code (e.g. `200 OK`, `404 Not Found`).
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct StatusCode(u16);
+
+impl StatusCode {
+    pub const CONTINUE: StatusCode = StatusCode(100);
+    pub const SWITCHING_PROTOCOLS: StatusCode = StatusCode(101);
+    pub const OK: StatusCode = StatusCode(200);
+    pub const CREATED: StatusCode = StatusCode(201);
+    pub const NO_CONTENT: StatusCode = StatusCode(204);
+    pub const PARTIAL_CONTENT: StatusCode = StatusCode(206);
+    pub const MOVED_PERMANENTLY: StatusCode = StatusCode(301);
+    pub const FOUND: StatusCode = StatusCode(302);
+    pub const NOT_MODIFIED: StatusCode = StatusCode(304);
+    pub const TEMPORARY_REDIRECT: StatusCode = StatusCode(307);
+    pub const PERMANENT_REDIRECT: StatusCode = StatusCode(308);
+    pub const BAD_REQUEST: StatusCode = StatusCode(400);
+    pub const UNAUTHORIZED: StatusCode = StatusCode(401);
+    pub const FORBIDDEN: StatusCode = StatusCode(403);
+    pub const NOT_FOUND: StatusCode = StatusCode(404);
+    pub const METHOD_NOT_ALLOWED: StatusCode = StatusCode(405);
+    pub const NOT_ACCEPTABLE: StatusCode = StatusCode(406);
+    pub const REQUEST_TIMEOUT: StatusCode = StatusCode(408);
+    pub const PAYLOAD_TOO_LARGE: StatusCode = StatusCode(413);
+    pub const URI_TOO_LONG: StatusCode = StatusCode(414);
+    pub const UNSUPPORTED_MEDIA_TYPE: StatusCode = StatusCode(415);
+    pub const RANGE_NOT_SATISFIABLE: StatusCode = StatusCode(416);
+    pub const EXPECTATION_FAILED: StatusCode = StatusCode(417);
+    pub const TOO_MANY_REQUESTS: StatusCode = StatusCode(429);
+    pub const REQUEST_HEADER_FIELDS_TOO_LARGE: StatusCode = StatusCode(431);
+    pub const INTERNAL_SERVER_ERROR: StatusCode = StatusCode(500);
+    pub const NOT_IMPLEMENTED: StatusCode = StatusCode(501);
+    pub const BAD_GATEWAY: StatusCode = StatusCode(502);
+    pub const SERVICE_UNAVAILABLE: StatusCode = StatusCode(503);
+    pub const GATEWAY_TIMEOUT: StatusCode = StatusCode(504);
+    pub const HTTP_VERSION_NOT_SUPPORTED: StatusCode = StatusCode(505);
+
+    /// Creates a `StatusCode` from its numeric value.
+    ///
+    /// Returns `None` if `code` is not a three-digit number.
+    pub fn from_u16(code: u16) -> Option<StatusCode> {
+        (100..=999).contains(&code).then_some(StatusCode(code))
+    }
+
+    /// Returns the numeric value of the status code.
+    pub fn as_u16(&self) -> u16 {
+        self.0
+    }
+
+    /// Returns the canonical reason phrase, or an empty string for codes
+    /// this crate does not know about.
+    pub fn reason_phrase(&self) -> &'static str {
+        match self.0 {
+            100 => "Continue",
+            101 => "Switching Protocols",
+            200 => "OK",
+            201 => "Created",
+            204 => "No Content",
+            206 => "Partial Content",
+            301 => "Moved Permanently",
+            302 => "Found",
+            304 => "Not Modified",
+            307 => "Temporary Redirect",
+            308 => "Permanent Redirect",
+            400 => "Bad Request",
+            401 => "Unauthorized",
+            403 => "Forbidden",
+            404 => "Not Found",
+            405 => "Method Not Allowed",
+            406 => "Not Acceptable",
+            408 => "Request Timeout",
+            413 => "Payload Too Large",
+            414 => "URI Too Long",
+            415 => "Unsupported Media Type",
+            416 => "Range Not Satisfiable",
+            417 => "Expectation Failed",
+            429 => "Too Many Requests",
+            431 => "Request Header Fields Too Large",
+            500 => "Internal Server Error",
+            501 => "Not Implemented",
+            502 => "Bad Gateway",
+            503 => "Service Unavailable",
+            504 => "Gateway Timeout",
+            505 => "HTTP Version Not Supported",
+            _ => "",
+        }
+    }
+}
+
+impl fmt::Display for StatusCode {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{} {}", self.0, self.reason_phrase())
+    }
+}
+
+/// Represents an HTTP response.
+pub struct Response {
+    /// The status code of the response.
+    pub status: StatusCode,
+    /// The headers of the response.
+    pub headers: HeaderMap,
+    /// The body of the response. Ignored when the response is streamed or its body is shared.
+    pub body: Vec<u8>,
+    // A body produced on the fly, sent with chunked encoding by `write_to`
+    stream: Option<Box<dyn Read + Send + 'static>>,
+    // A body sent straight from a file of the given length, with sendfile(2) where possible
+    file: Option<(File, u64)>,
+    // A body shared with other responses, e.g. a file read once for a burst of requests
+    shared: Option<Arc<Vec<u8>>>,
+    // Given the raw connection after the head is sent, instead of a body
+    hijack: Option<HijackFn>,
+    // Renders an HTML body straight onto the connection, sent with chunked encoding
+    render: Option<RenderFn>,
+}
+
+impl Response {
+    /// Creates an empty response with the given status code.
+    pub fn new(status: StatusCode) -> Response {
+        Response {
+            status,
+            headers: HeaderMap::new(),
+            body: Vec::new(),
+            stream: None,
+            file: None,
+            shared: None,
+            hijack: None,
+            render: None,
+        }
+    }
+
+    /// Creates a response with a body and a matching `Content-Type` header.
+    ///
+    /// # Arguments
+    ///
+    /// * `status` - The status code of the response.
+    /// * `content_type` - The value of the `Content-Type` header.
+    /// * `body` - The bytes to send as the response body.
+    pub fn with_body(status: StatusCode, content_type: &str, body: Vec<u8>) -> Response {
+        let mut response = Response::new(status);
+        response.set_header("Content-Type", content_type);
+        response.body = body;
+        response
+    }
+
+    /// Creates a response whose body is read from `body` while it is being sent.
+    ///
+    /// The body is sent with `Transfer-Encoding: chunked`, so its length does
+    /// not need to be known up front. This is the common path for file
+    /// downloads, server-sent events and any other body produced on the fly.
+    ///
+    /// # Arguments
+    ///
+    /// * `status` - The status code of the response.
+    /// * `content_type` - The value of the `Content-Type` header.
+    /// * `body` - The reader the body is pulled from; it is consumed by `write_to`.
+    pub fn stream(
+        status: StatusCode,
+        content_type: &str,
+        body: Box<dyn Read + Send + 'static>,
+    ) -> Response {
+        let mut response = Response::new(status);
+        response.set_header("Content-Type", content_type);
+        response.stream = Some(body);
+        response
+    }
+
+    /// Creates a response whose body is the contents of `file`.
+    ///
+    /// The file is not read into memory. On Linux, when the response is
+    /// written to a TCP socket, its bytes go straight from the page cache to
+    /// the socket with [`sendfile`](crate::sendfile); elsewhere they are
+    /// copied. `Content-Length` is the file's size when this is called.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the file's size cannot be read.
+    pub fn file(status: StatusCode, content_type: &str, file: File) -> Result<Response> {
+        let len = file.metadata()?.len();
+        let mut response = Response::new(status);
+        response.set_header("Content-Type", content_type);
+        response.file = Some((file, len));
+        Ok(response)
+    }
+
+    /// Creates a `text/html` response whose body `render` writes while it is being sent.
+    ///
+    /// The headers and the opening `<!DOCTYPE html>` and `<html>` tags are
+    /// sent first, then `render` runs on the worker with a
+    /// [`ChunkedHtmlWriter`] onto the connection, so the browser can start
+    /// displaying the page before it is complete. Once `render` returns, the
+    /// closing `</html>` tag and the terminating chunk are sent, even if it
+    /// failed.
+    ///
+    /// ```ignore
+    /// router.get("/report", |_| {
+    ///     Response::chunked_html(StatusCode::OK, |html| {
+    ///         html.write_html("<head><title>Report</title></head><body>")?;
+    ///         for row in slow_query() {
+    ///             html.write_html(&format!("<p>{row}</p>"))?;
+    ///         }
+    ///         html.write_html("</body>")
+    ///     })
+    /// });
+    /// ```
+    pub fn chunked_html<F>(status: StatusCode, render: F) -> Response
+    where
+        F: FnOnce(&mut ChunkedHtmlWriter<'_>) -> Result<()> + Send + 'static,
+    {
+        let mut response = Response::new(status);
+        response.set_header("Content-Type", "text/html; charset=utf-8");
+        response.render = Some(Box::new(render));
+        response
+    }
+
+    /// Creates a response whose body is shared with other responses instead of copied.
+    ///
+    /// The bytes are sent with a `Content-Length` like a `body`, but the
+    /// response only holds a reference to them, so many responses in flight
+    /// at once can send the same bytes; see [`WeakCache`](crate::WeakCache).
+    ///
+    /// # Arguments
+    ///
+    /// * `status` - The status code of the response.
+    /// * `content_type` - The value of the `Content-Type` header.
+    /// * `body` - The bytes to send as the response body.
+    pub fn shared(status: StatusCode, content_type: &str, body: Arc<Vec<u8>>) -> Response {
+        let mut response = Response::new(status);
+        response.set_header("Content-Type", content_type);
+        response.shared = Some(body);
+        response
+    }
+
+    /// Creates a response that takes over the connection once its head is sent.
+    ///
+    /// The status line and headers are written without `Content-Length` or
+    /// `Transfer-Encoding`, then `takeover` gets the raw socket and the server
+    /// is done with the connection. Use it for protocols negotiated inside a
+    /// handler, e.g. `101 Switching Protocols` for WebSocket. It only works on
+    /// TCP connections; elsewhere the client gets `501 Not Implemented`.
+    ///
+    /// # Arguments
+    ///
+    /// * `status` - The status code of the response, e.g. `101 Switching Protocols`.
+    /// * `takeover` - Runs on the pool worker with the socket; long-lived
+    ///   protocols should move it to their own thread.
+    pub fn hijack<F>(status: StatusCode, takeover: F) -> Response
+    where
+        F: FnOnce(HijackedStream) + Send + 'static,
+    {
+        let mut response = Response::new(status);
+        response.hijack = Some(Box::new(takeover));
+        response
+    }
+
+    /// Formats one Server-Sent Events frame, ready to be sent on a `text/event-stream` body.
+    ///
+    /// Multi-line `data` is split into one `data:` line per line, which the
+    /// client joins back together with `\n`; `\r\n` and lone `\r` count as
+    /// line breaks too. Line breaks in `id` and `event` would end the field
+    /// early, so they are dropped.
+    ///
+    /// # Arguments
+    ///
+    /// * `id` - The event id, which the client echoes in `Last-Event-ID` when it reconnects.
+    /// * `event` - The event type; clients treat a missing type as `message`.
+    /// * `data` - The event payload.
+    /// * `retry` - How many milliseconds the client should wait before reconnecting.
+    pub fn sse_event(
+        id: Option<&str>,
+        event: Option<&str>,
+        data: &str,
+        retry: Option<u64>,
+    ) -> Vec<u8> {
+        let single_line = |value: &str| value.replace(['\r', '\n'], "");
+
+        let mut frame = String::new();
+        if let Some(id) = id {
+            frame.push_str(&format!("id:{}\n", single_line(id)));
+        }
+        if let Some(event) = event {
+            frame.push_str(&format!("event:{}\n", single_line(event)));
+        }
+        for line in data.replace("\r\n", "\n").split(['\n', '\r']) {
+            frame.push_str(&format!("data:{line}\n"));
+        }
+        if let Some(retry) = retry {
+            frame.push_str(&format!("retry:{retry}\n"));
+        }
+        frame.push('\n');
+        frame.into_bytes()
+    }
+
+    /// Parses an HTTP response, e.g. one read back from an upstream server.
+    ///
+    /// This is the counterpart of `Request::new`: the status line is parsed
+    /// first, then the headers up to the blank line, then the body. The body
+    /// is delimited by `Content-Length` or `Transfer-Encoding: chunked` (whose
+    /// trailers are merged into the headers); without either, it runs until
+    /// the server closes the connection. `1xx`, `204` and `304` responses
+    /// never have a body.
+    ///
+    /// # Arguments
+    ///
+    /// * `reader` - The stream to read the response from.
+    ///
+    /// # Returns
+    ///
+    /// The status code, headers and body of the response.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if there is a problem reading from the stream or if
+    /// the status line, headers or body are malformed.
+    pub fn from_stream<R: BufRead>(
+        reader: &mut R,
+    ) -> std::result::Result<(StatusCode, HeaderMap, Vec<u8>), HttpError> {
+        Response::read_message(reader, true)
+    }
+
+    /// Parses an HTTP response along with its reason phrase.
+    ///
+    /// Reads the same way as [`Response::from_stream`], for callers such as
+    /// tests that check the status line as it was sent. The reader is
+    /// consumed; pass `&mut reader` to keep reading from it afterwards.
+    ///
+    /// # Arguments
+    ///
+    /// * `reader` - The stream to read the response from.
+    ///
+    /// # Returns
+    ///
+    /// The status code, reason phrase (empty if there was none), headers and
+    /// body of the response.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if there is a problem reading from the stream or if
+    /// the status line, headers or body are malformed.
+    pub fn from_reader<R: BufRead>(
+        mut reader: R,
+    ) -> std::result::Result<(u16, String, HeaderMap, Vec<u8>), HttpError> {
+        let (status, reason, headers, body) = read_response(&mut reader, true)?;
+        Ok((status.as_u16(), reason, headers, body))
+    }
+
+    // Reads a response, whose body is skipped when `has_body` is false (the
+    // answer to a HEAD request); only then can the next response follow it
+    pub(crate) fn read_message<R: BufRead>(
+        reader: &mut R,
+        has_body: bool,
+    ) -> std::result::Result<(StatusCode, HeaderMap, Vec<u8>), HttpError> {
+        let (status, _, headers, body) = read_response(reader, has_body)?;
+        Ok((status, headers, body))
+    }
+
+    /// Returns `true` if the response takes over the connection; see [`Response::hijack`].
+    pub fn is_hijacked(&self) -> bool {
+        self.hijack.is_some()
+    }
+
+    /// Returns `true` if the body is streamed or sent from a file rather than held in memory.
+    pub fn is_streamed(&self) -> bool {
+        self.stream.is_some() || self.file.is_some() || self.render.is_some()
+    }
+
+    /// Sets a header, replacing any existing value with the same name.
+    pub fn set_header(&mut self, name: &str, value: &str) -> &mut Response {
+        self.headers.insert(name, value);
+        self
+    }
+
+    /// Tells the client that a preference from its `Prefer` header was honoured.
+    ///
+    /// Adds `pref` to `Preference-Applied` (RFC 7240, section 3), e.g.
+    /// `wait=30` once a long poll waited no longer than the client asked.
+    pub fn with_preference_applied(&mut self, pref: Preference) -> &mut Response {
+        self.headers.append("Preference-Applied", pref.to_string());
+        self
+    }
+
+    /// Adds a link to `uri` with relation type `rel` (RFC 8288), e.g. for pagination.
+    ///
+    /// Each call appends a `Link: <uri>; rel="rel"` header, so a page can
+    /// link to both its `next` and `prev` neighbours. Clients read them back
+    /// with [`parse_link_header`](crate::parse_link_header).
+    pub fn add_link(&mut self, uri: &str, rel: &str) -> &mut Response {
+        self.headers
+            .append("Link", format!("<{uri}>; rel=\"{rel}\""));
+        self
+    }
+
+    /// Makes browsers download the body as a file named `filename` instead of displaying it.
+    ///
+    /// Sets `Content-Disposition: attachment` with a quoted `filename`. Names
+    /// containing non-ASCII characters also get a UTF-8 `filename*` parameter
+    /// (RFC 5987), with the plain `filename` kept as an ASCII fallback for
+    /// older clients.
+    pub fn as_attachment(&mut self, filename: &str) -> &mut Response {
+        let mut value = String::from("attachment; filename=\"");
+        for c in filename.chars() {
+            match c {
+                '"' | '\\' => {
+                    value.push('\\');
+                    value.push(c);
+                }
+                c if c.is_ascii() && !c.is_ascii_control() => value.push(c),
+                _ => value.push('_'),
+            }
+        }
+        value.push('"');
+
+        if !filename.is_ascii() {
+            value.push_str("; filename*=UTF-8''");
+            for byte in filename.bytes() {
+                if byte.is_ascii_alphanumeric() || b"!#$&+-.^_`|~".contains(&byte) {
+                    value.push(byte as char);
+                } else {
+                    value.push_str(&format!("%{byte:02X}"));
+                }
+            }
+        }
+
+        self.set_header("Content-Disposition", &value)
+    }
+
+    /// Adds `headers` to the `Vary` header, so caches keep one copy per variant.
+    ///
+    /// Call this whenever a request header picks between versions of the
+    /// response, e.g. `Accept-Encoding` or `Accept-Language`. Names already
+    /// listed are skipped, ignoring case, and existing `Vary` headers are
+    /// merged into one. `*` means the response varies on things no header
+    /// captures, so once it is present (or added) it is the only value kept.
+    pub fn vary(&mut self, headers: &[&str]) -> &mut Response {
+        let mut names: Vec<String> = self
+            .headers
+            .get_all("Vary")
+            .flat_map(|value| value.split(','))
+            .map(|name| name.trim().to_string())
+            .filter(|name| !name.is_empty())
+            .collect();
+
+        for header in headers {
+            let header = header.trim();
+            if !header.is_empty() && !names.iter().any(|name| name.eq_ignore_ascii_case(header)) {
+                names.push(header.to_string());
+            }
+        }
+
+        if names.iter().any(|name| name == "*") {
+            return self.set_header("Vary", "*");
+        }
+        if names.is_empty() {
+            return self;
+        }
+        self.set_header("Vary", &names.join(", "))
+    }
+
+    /// Adds `no-store` to `Cache-Control`: no cache may keep a copy of the response.
+    ///
+    /// The cache control helpers merge their directive into the existing
+    /// `Cache-Control` header, so they can be chained, e.g.
+    /// `response.no_store().max_age(Duration::ZERO).must_revalidate()` sends
+    /// `Cache-Control: no-store, max-age=0, must-revalidate`. A directive
+    /// already present is replaced rather than repeated, ignoring case.
+    pub fn no_store(&mut self) -> &mut Response {
+        self.add_cache_directive("no-store")
+    }
+
+    /// Adds `no-cache` to `Cache-Control`: caches must revalidate before every reuse.
+    pub fn no_cache(&mut self) -> &mut Response {
+        self.add_cache_directive("no-cache")
+    }
+
+    /// Adds `must-revalidate` to `Cache-Control`: caches must not serve the response once stale.
+    pub fn must_revalidate(&mut self) -> &mut Response {
+        self.add_cache_directive("must-revalidate")
+    }
+
+    /// Adds `private` to `Cache-Control`, removing `public`: only the browser may cache it.
+    pub fn private(&mut self) -> &mut Response {
+        self.remove_cache_directive("public");
+        self.add_cache_directive("private")
+    }
+
+    /// Adds `public` to `Cache-Control`, removing `private`: shared caches may keep it too.
+    pub fn public(&mut self) -> &mut Response {
+        self.remove_cache_directive("private");
+        self.add_cache_directive("public")
+    }
+
+    /// Sets `max-age` in `Cache-Control`: how long the response stays fresh, in whole seconds.
+    pub fn max_age(&mut self, max_age: Duration) -> &mut Response {
+        self.add_cache_directive(&format!("max-age={}", max_age.as_secs()))
+    }
+
+    /// Sets `s-maxage` in `Cache-Control`: like `max-age`, but for shared caches only.
+    pub fn s_maxage(&mut self, s_maxage: Duration) -> &mut Response {
+        self.add_cache_directive(&format!("s-maxage={}", s_maxage.as_secs()))
+    }
+
+    /// Adds `immutable` to `Cache-Control`: the response never changes while fresh,
+    /// so browsers skip revalidating it on reload. Meant for fingerprinted assets.
+    pub fn immutable(&mut self) -> &mut Response {
+        self.add_cache_directive("immutable")
+    }
+
+    // The directives of the Cache-Control headers, trimmed and without empty ones
+    fn cache_directives(&self) -> Vec<String> {
+        self.headers
+            .get_all("Cache-Control")
+            .flat_map(|value| value.split(','))
+            .map(|directive| directive.trim().to_string())
+            .filter(|directive| !directive.is_empty())
+            .collect()
+    }
+
+    // Merges `directive` into Cache-Control, replacing one with the same name
+    fn add_cache_directive(&mut self, directive: &str) -> &mut Response {
+        let name = cache_directive_name(directive);
+        let mut directives = self.cache_directives();
+        match directives
+            .iter_mut()
+            .find(|existing| cache_directive_name(existing).eq_ignore_ascii_case(name))
+        {
+            Some(existing) => *existing = directive.to_string(),
+            None => directives.push(directive.to_string()),
+        }
+        self.set_header("Cache-Control", &directives.join(", "))
+    }
+
+    // Drops the directive called `name` from Cache-Control, if present
+    fn remove_cache_directive(&mut self, name: &str) {
+        let mut directives = self.cache_directives();
+        directives.retain(|directive| !cache_directive_name(directive).eq_ignore_ascii_case(name));
+        if directives.is_empty() {
+            self.headers.remove("Cache-Control");
+        } else {
+            self.set_header("Cache-Control", &directives.join(", "));
+        }
+    }
+
+    /// Sets `Retry-After` to the number of seconds to wait for `reason`.
+    ///
+    /// Meant for `429 Too Many Requests` and `503 Service Unavailable`
+    /// responses; see [`retry_after_value`](crate::retry_after_value) for
+    /// the HTTP-date form.
+    pub fn retry_after(&mut self, reason: crate::RetryReason) -> &mut Response {
+        let value = crate::retry_after_value(reason, false);
+        self.set_header("Retry-After", &value)
+    }
+
+    /// Sets a `Digest` header (RFC 3230) computed over the body with `algorithm`.
+    ///
+    /// The digest covers `body`, so call this after the body is final. It is
+    /// meaningless for streamed responses.
+    #[cfg(feature = "crypto")]
+    pub fn with_digest(&mut self, algorithm: crate::DigestAlgorithm) -> &mut Response {
+        let body = self
+            .shared
+            .as_ref()
+            .map_or(&self.body, |shared| shared.as_ref());
+        let value = algorithm.header_value(body);
+        self.set_header("Digest", &value)
+    }
+
+    /// Returns the transfer codings the body is sent with, in the order they are applied.
+    ///
+    /// These are the codings of a `Transfer-Encoding` header the handler
+    /// set, without `identity`, which means no coding at all. `chunked` is
+    /// added last whenever the length of the encoded body is not known up
+    /// front: for streamed and `chunked_html` bodies, and whenever another
+    /// coding is applied. An empty list means the body is sent as it is,
+    /// with a `Content-Length`.
+    ///
+    /// # Errors
+    ///
+    /// Returns `HttpError::InvalidTransferEncoding` if the header fails
+    /// [`TransferEncoding::parse_list`], or if it asks for `gzip` or
+    /// `deflate` without the `compress` feature.
+    pub fn transfer_encoding(&self) -> std::result::Result<Vec<TransferEncoding>, HttpError> {
+        let header: Vec<&str> = self.headers.get_all("Transfer-Encoding").collect();
+        let mut codings = TransferEncoding::parse_list(&header.join(","))?;
+        codings.retain(|coding| *coding != TransferEncoding::Identity);
+
+        let compressed = codings
+            .iter()
+            .any(|coding| matches!(coding, TransferEncoding::Gzip | TransferEncoding::Deflate));
+        if compressed && cfg!(not(feature = "compress")) {
+            return Err(HttpError::InvalidTransferEncoding(
+                "gzip and deflate require the `compress` feature",
+            ));
+        }
+
+        let unknown_length = self.stream.is_some() || self.render.is_some() || !codings.is_empty();
+        if unknown_length && codings.last() != Some(&TransferEncoding::Chunked) {
+            codings.push(TransferEncoding::Chunked);
+        }
+        Ok(codings)
+    }
+
+    /// Serializes the response onto `writer`.
+    ///
+    /// A `Content-Length` header matching the body is written for every
+    /// status that can have a body (all but `1xx`, `204` and `304`), so
+    /// handlers do not need to set one themselves. Streamed responses, and
+    /// responses whose handler set a `Transfer-Encoding` other than
+    /// `identity`, are sent with the codings of [`Response::transfer_encoding`]
+    /// instead, always ending with `chunked`.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error, before anything is written, if the transfer codings
+    /// are invalid. Otherwise returns an error if writing to `writer` fails,
+    /// or if the reader of a streamed response or the render closure of a
+    /// `chunked_html` one fails. In the latter cases the terminating chunk is
+    /// still sent so the client sees a well-formed (if truncated) body.
+    pub fn write_to<W: Write>(self, writer: &mut W) -> Result<()> {
+        let codings = self.transfer_encoding().map_err(HttpError::into_io)?;
+        writer.write_all(self.head(&codings).as_bytes())?;
+        if codings.is_empty() {
+            match (self.file, &self.shared) {
+                (Some((file, len)), _) => io::copy(&mut file.take(len), writer).map(drop)?,
+                (None, Some(shared)) => writer.write_all(shared)?,
+                (None, None) => writer.write_all(&self.body)?,
+            }
+            return writer.flush();
+        }
+
+        let (mut stream, render, file, shared, body) =
+            (self.stream, self.render, self.file, self.shared, self.body);
+        write_encoded(writer, &codings, |encoded| {
+            if let Some(render) = render {
+                let mut html = ChunkedHtmlWriter::start(encoded)?;
+                let rendered = render(&mut html);
+                html.finish()?;
+                return rendered;
+            }
+            if let Some((file, len)) = file {
+                return io::copy(&mut file.take(len), encoded).map(drop);
+            }
+            let Some(stream) = &mut stream else {
+                return encoded.write_all(shared.as_deref().unwrap_or(&body));
+            };
+
+            let mut buf = [0; STREAM_CHUNK_SIZE];
+            loop {
+                match stream.read(&mut buf) {
+                    Ok(0) => return Ok(()),
+                    Ok(count) => encoded.write_all(&buf[..count])?,
+                    Err(err) if err.kind() == std::io::ErrorKind::Interrupted => continue,
+                    Err(err) => return Err(err),
+                }
+            }
+        })
+    }
+
+    /// Serializes the response onto a socket, like `write_to`, using `sendfile(2)`
+    /// for file bodies on Linux. Returns the number of bytes written.
+    pub(crate) fn write_to_socket(self, mut socket: &TcpStream) -> Result<u64> {
+        #[cfg(target_os = "linux")]
+        if let (Some((file, len)), Ok([])) = (&self.file, self.transfer_encoding().as_deref()) {
+            let head = self.head(&[]);
+            socket.write_all(head.as_bytes())?;
+            let sent = crate::sendfile(file, socket, 0, *len).map_err(io::Error::other)?;
+            if sent < *len {
+                // The file shrank after Content-Length was sent, so the body cannot be completed
+                return Err(Error::new(io::ErrorKind::UnexpectedEof, "file ended early"));
+            }
+            return Ok(head.len() as u64 + sent);
+        }
+        let mut sent = 0;
+        self.write_to(&mut CountingWriter::new(&mut socket, &mut sent))?;
+        Ok(sent)
+    }
+
+    /// Sends the head of a hijacking response on `socket`, then hands the
+    /// socket over along with the bytes the client already sent past the request.
+    pub(crate) fn write_hijacked(mut self, mut socket: TcpStream, buffered: Vec<u8>) -> Result<()> {
+        let takeover = self.hijack.take();
+        socket.write_all(format!("{}\r\n", self.head_without_framing()).as_bytes())?;
+        socket.flush()?;
+        if let Some(takeover) = takeover {
+            takeover(hijack_buffered(socket, buffered));
+        }
+        Ok(())
+    }
+
+    /// Serializes the status line and headers onto `writer`, without the body.
+    ///
+    /// This is the answer to a `HEAD` request: the headers, `Content-Length`
+    /// included, are the ones `write_to` would send for the same response.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if writing to `writer` fails or the transfer codings are invalid.
+    pub fn write_head_to<W: Write>(self, writer: &mut W) -> Result<()> {
+        let codings = self.transfer_encoding().map_err(HttpError::into_io)?;
+        writer.write_all(self.head(&codings).as_bytes())?;
+        writer.flush()
+    }
+
+    // Formats the status line and headers, ending with the blank line; the
+    // body is framed by `codings` if there are any, or by Content-Length
+    fn head(&self, codings: &[TransferEncoding]) -> String {
+        let mut head = self.head_without_framing();
+
+        // 1xx, 204 and 304 responses never have a body, so they get no framing headers
+        let status = self.status.as_u16();
+        if (100..200).contains(&status) || status == 204 || status == 304 {
+            head.push_str("\r\n");
+        } else if !codings.is_empty() {
+            let codings: Vec<&str> = codings.iter().map(TransferEncoding::as_str).collect();
+            head.push_str(&format!(
+                "Transfer-Encoding: {}\r\n\r\n",
+                codings.join(", ")
+            ));
+        } else if let Some((_, len)) = &self.file {
+            head.push_str(&format!("Content-Length: {len}\r\n\r\n"));
+        } else if let Some(shared) = &self.shared {
+            head.push_str(&format!("Content-Length: {}\r\n\r\n", shared.len()));
+        } else {
+            head.push_str(&format!("Content-Length: {}\r\n\r\n", self.body.len()));
+        }
+        head
+    }
+
+    // Formats the status line and headers other than Content-Length and
+    // Transfer-Encoding; `head` adds those and the blank line. A header set
+    // more than once is folded into one line where its first value was,
+    // except for those `fold_headers` refuses to fold.
+    fn head_without_framing(&self) -> String {
+        let mut head = format!("HTTP/1.1 {}\r\n", self.status);
+        let mut written: Vec<&str> = Vec::new();
+        for (name, _) in self.headers.iter() {
+            if name.eq_ignore_ascii_case("Content-Length")
+                || name.eq_ignore_ascii_case("Transfer-Encoding")
+                || written.iter().any(|done| done.eq_ignore_ascii_case(name))
+            {
+                continue;
+            }
+            written.push(name);
+
+            let values: Vec<&str> = self.headers.get_all(name).collect();
+            match fold_headers(name, &values) {
+                Some(folded) => head.push_str(&format!("{name}: {folded}\r\n")),
+                None => {
+                    for value in values {
+                        head.push_str(&format!("{name}: {value}\r\n"));
+                    }
+                }
+            }
+        }
+        head
+    }
+}
+
+// Reads the status line, headers and (if `has_body`) body of a response
+fn read_response<R: BufRead>(
+    reader: &mut R,
+    has_body: bool,
+) -> std::result::Result<(StatusCode, String, HeaderMap, Vec<u8>), HttpError> {
+    let mut line = String::new();
+    if reader.read_line(&mut line)? == 0 {
+        return Err(Error::new(io::ErrorKind::UnexpectedEof, "empty stream").into());
+    }
+    let (status, reason) = parse_status_line(line.trim_end())?;
+    let reason = reason.to_string();
+    let mut headers = parse_headers(&mut *reader, usize::MAX)?;
+
+    let code = status.as_u16();
+    if !has_body || code < 200 || code == 204 || code == 304 {
+        return Ok((status, reason, headers, Vec::new()));
+    }
+
+    // A response whose final coding is not chunked runs until the connection closes
+    let delimited = match is_chunked(&headers) {
+        Ok(chunked) => chunked || headers.contains("Content-Length"),
+        Err(_) => false,
+    };
+    if !delimited {
+        let mut body = Vec::new();
+        reader.read_to_end(&mut body)?;
+        return Ok((status, reason, headers, body));
+    }
+
+    let (body, trailers) = read_body(reader, &headers, usize::MAX, usize::MAX, usize::MAX)?;
+    for (name, value) in trailers.iter() {
+        headers.append(name, value);
+    }
+    Ok((status, reason, headers, body))
+}
+
+/// Parses the status line of an HTTP response (e.g. `HTTP/1.1 404 Not Found`) into
+/// the status code and reason phrase.
+///
+/// # Errors
+///
+/// Returns an error if the status line is invalid.
+fn parse_status_line(status_line: &str) -> Result<(StatusCode, &str)> {
+    let invalid = || Error::new(io::ErrorKind::InvalidData, "invalid status line");
+
+    // The reason phrase is optional and may contain spaces
+    let mut parts = status_line.splitn(3, ' ');
+    let version = parts.next().ok_or_else(invalid)?;
+    if !version.starts_with("HTTP/") {
+        return Err(invalid());
+    }
+    let status = parts
+        .next()
+        .and_then(|code| code.parse().ok())
+        .and_then(StatusCode::from_u16)
+        .ok_or_else(invalid)?;
+    Ok((status, parts.next().unwrap_or_default()))
+}
+
+// The name of a Cache-Control directive, e.g. "max-age" for "max-age=60"
+fn cache_directive_name(directive: &str) -> &str {
+    directive.split('=').next().unwrap_or_default().trim()
+}
@@ -0,0 +1,49 @@
+use std::time::{Duration, Instant, SystemTime};
+
+use crate::format_http_date;
+
+// Suggested wait when the server turns work away for lack of capacity;
+// slots free up as soon as a request finishes, so a short wait is enough
+pub(crate) const OVERLOAD_RETRY_SECONDS: u32 = 1;
+
+/// Why a client is being asked to come back later.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RetryReason {
+    /// The client used up its allowance; it is replenished at `reset_at`.
+    RateLimited { reset_at: Instant },
+    /// The server is at capacity and expects to have room in about `estimated_seconds`.
+    Overloaded { estimated_seconds: u32 },
+}
+
+/// Returns how many seconds a client should wait before retrying.
+///
+/// A rate limit's remaining time is rounded up to whole seconds, so a
+/// client that waits exactly that long finds the limit reset; a reset time
+/// already in the past gives `0`. Overload estimates are used as they are.
+pub fn retry_after_seconds(reason: RetryReason) -> u32 {
+    match reason {
+        RetryReason::RateLimited { reset_at } => {
+            let remaining = reset_at.saturating_duration_since(Instant::now());
+            let seconds = remaining.as_secs() + u64::from(remaining.subsec_nanos() > 0);
+            u32::try_from(seconds).unwrap_or(u32::MAX)
+        }
+        RetryReason::Overloaded { estimated_seconds } => estimated_seconds,
+    }
+}
+
+/// Formats a `Retry-After` value (RFC 7231 §7.1.3) for `reason`.
+///
+/// # Arguments
+///
+/// * `reason` - Why the client has to wait.
+/// * `as_date` - Whether to send an HTTP-date instead of a number of
+///   seconds. Seconds are immune to clock skew between client and server,
+///   so prefer them unless a client needs a date.
+pub fn retry_after_value(reason: RetryReason, as_date: bool) -> String {
+    let seconds = retry_after_seconds(reason);
+    if as_date {
+        format_http_date(SystemTime::now() + Duration::from_secs(u64::from(seconds)))
+    } else {
+        seconds.to_string()
+    }
+}
@@ -0,0 +1,1072 @@
+use std::collections::HashMap;
+use std::io;
+use std::net::{IpAddr, TcpStream};
+use std::ops::{Deref, DerefMut};
+use std::panic::{self, AssertUnwindSafe};
+use std::path::PathBuf;
+use std::sync::Arc;
+
+use crate::date::current_http_date;
+use crate::openapi::openapi_spec;
+use crate::retry::OVERLOAD_RETRY_SECONDS;
+use crate::{
+    CorsConfig, FaviconHandler, HeaderMap, HttpMethod, Middleware, OpenApiInfo, Request, Response,
+    RetryReason, Semaphore, StaticFileServer, StatusCode, UpgradeHandler,
+};
+
+// The `Server` header sent once default headers are enabled, unless overridden
+const SERVER_NAME: &str = "rust-http/0.1";
+
+// A boxed route handler that can be shared between worker threads
+type Handler = Box<dyn Fn(Request) -> Response + Send + Sync>;
+
+// Opens the upstream connection of a CONNECT tunnel to the given "host:port"
+type ConnectHandler = Box<dyn Fn(&str) -> io::Result<TcpStream> + Send + Sync>;
+
+// A single registered route
+struct Route {
+    method: HttpMethod,                   // The method the route answers to
+    pattern: String,                      // The path pattern, e.g. "/users/:id"
+    handler: Handler,                     // The closure that produces the response
+    middleware: Vec<Arc<dyn Middleware>>, // Runs around this route's handler only
+}
+
+/// Dispatches requests to handlers based on their method and path.
+///
+/// Patterns are matched segment by segment. A segment starting with `:`
+/// captures that segment into `Request::params` under the name that follows,
+/// and a final `*` segment captures the rest of the path under `"*"`. Routes
+/// are tried in the order they were registered and the first match wins.
+///
+/// Middleware added with [`Router::middleware`] runs around every request,
+/// including ones that fall through to the not-found handler. `GET` and
+/// `HEAD` requests for `/favicon.ico` that no route matches get a built-in
+/// transparent icon from [`FaviconHandler`] instead of a 404.
+///
+/// A request whose handler or middleware panics is answered with `500
+/// Internal Server Error` and the panic is logged; the worker goes on
+/// serving other requests.
+pub struct Router {
+    routes: Vec<Route>,         // All registered routes, in registration order
+    not_found: Option<Handler>, // Called when no route matches
+    middleware: Vec<Box<dyn Middleware>>, // Runs around every request, outermost first
+    connect_routes: Vec<(String, ConnectHandler)>, // CONNECT targets and their connectors
+    upgrades: Vec<(String, Box<dyn UpgradeHandler>)>, // Protocols connections may switch to
+    default_headers: Option<HeaderMap>, // Added to responses that do not set them
+    openapi: Option<(String, OpenApiInfo)>, // Where the OpenAPI document is served, and its info
+    favicon: FaviconHandler,    // Answers /favicon.ico when no route does
+    acls: Vec<(String, Vec<IpAddr>)>, // Restricted prefixes and who may use them, longest first
+    redirect_trailing_slash: bool, // Whether unmatched paths redirect to their other slash form
+}
+
+impl Router {
+    /// Creates a router with no routes.
+    pub fn new() -> Router {
+        Router {
+            routes: Vec::new(),
+            not_found: None,
+            middleware: Vec::new(),
+            connect_routes: Vec::new(),
+            upgrades: Vec::new(),
+            default_headers: None,
+            openapi: None,
+            favicon: FaviconHandler::new(),
+            acls: Vec::new(),
+            redirect_trailing_slash: false,
+        }
+    }
+
+    /// Registers `handler` for requests with the given method and path pattern.
+    ///
+    /// The returned [`RouteHandle`] can add middleware to just this route,
+    /// and otherwise works like the router itself for chaining.
+    pub fn route<F>(&mut self, method: HttpMethod, pattern: &str, handler: F) -> RouteHandle<'_>
+    where
+        F: Fn(Request) -> Response + Send + Sync + 'static,
+    {
+        self.routes.push(Route {
+            method,
+            pattern: pattern.to_string(),
+            handler: Box::new(handler),
+            middleware: Vec::new(),
+        });
+        RouteHandle { router: self }
+    }
+
+    /// Registers a `GET` route.
+    pub fn get<F>(&mut self, pattern: &str, handler: F) -> RouteHandle<'_>
+    where
+        F: Fn(Request) -> Response + Send + Sync + 'static,
+    {
+        self.route(HttpMethod::Get, pattern, handler)
+    }
+
+    /// Registers a `POST` route.
+    pub fn post<F>(&mut self, pattern: &str, handler: F) -> RouteHandle<'_>
+    where
+        F: Fn(Request) -> Response + Send + Sync + 'static,
+    {
+        self.route(HttpMethod::Post, pattern, handler)
+    }
+
+    /// Registers a `PUT` route.
+    pub fn put<F>(&mut self, pattern: &str, handler: F) -> RouteHandle<'_>
+    where
+        F: Fn(Request) -> Response + Send + Sync + 'static,
+    {
+        self.route(HttpMethod::Put, pattern, handler)
+    }
+
+    /// Registers a `DELETE` route.
+    pub fn delete<F>(&mut self, pattern: &str, handler: F) -> RouteHandle<'_>
+    where
+        F: Fn(Request) -> Response + Send + Sync + 'static,
+    {
+        self.route(HttpMethod::Delete, pattern, handler)
+    }
+
+    /// Accepts `CONNECT` requests whose target matches `pattern`, for HTTP tunneling.
+    ///
+    /// The target is the request's `host:port`, so `*` accepts any target and
+    /// `:target` any target without a slash. `connect` receives the target and
+    /// opens the upstream connection, e.g. `|target| TcpStream::connect(target)`, or returns
+    /// an error to refuse it with `502 Bad Gateway`. Once it succeeds the client
+    /// gets `200 Connection established` and bytes are relayed with
+    /// [`tunnel`](crate::tunnel) until either side closes. Middleware does not
+    /// run for tunnels.
+    pub fn connect<F>(&mut self, pattern: &str, connect: F) -> &mut Router
+    where
+        F: Fn(&str) -> io::Result<TcpStream> + Send + Sync + 'static,
+    {
+        self.connect_routes
+            .push((pattern.to_string(), Box::new(connect)));
+        self
+    }
+
+    /// Opens the upstream connection for a `CONNECT` request.
+    ///
+    /// # Returns
+    ///
+    /// `None` if `req` is not a `CONNECT` request or no connect route matches
+    /// its target, otherwise the result of the matching connector.
+    pub(crate) fn connect_target(&self, req: &Request) -> Option<io::Result<TcpStream>> {
+        if req.method != HttpMethod::Connect.as_str() {
+            return None;
+        }
+        self.connect_routes
+            .iter()
+            .find(|(pattern, _)| match_pattern(pattern, &req.path).is_some())
+            .map(|(_, connect)| connect(&req.path))
+    }
+
+    /// Lets connections switch to `protocol` (e.g. `websocket` or `h2c`) with `handler`.
+    ///
+    /// A request with `Connection: Upgrade` and `Upgrade: <protocol>` is
+    /// answered by `handler` instead of the routes; see [`UpgradeHandler`].
+    /// Protocol names are matched case-insensitively, and upgrades are only
+    /// possible on TCP connections.
+    pub fn upgrade(&mut self, protocol: &str, handler: impl UpgradeHandler) -> &mut Router {
+        self.upgrades
+            .push((protocol.to_string(), Box::new(handler)));
+        self
+    }
+
+    /// Returns the handler for the protocol `req` asks to upgrade to, if any is registered.
+    pub(crate) fn upgrade_handler(&self, req: &Request) -> Option<&dyn UpgradeHandler> {
+        self.upgrades
+            .iter()
+            .find(|(protocol, _)| req.is_upgrade(protocol))
+            .map(|(_, handler)| handler.as_ref())
+    }
+
+    /// Serves the files under `fs_root` at `url_prefix`, e.g. `/assets` and `./public`.
+    ///
+    /// `/assets/css/site.css` is answered with `./public/css/site.css` by a
+    /// [`StaticFileServer`] with the default cache policies. `GET` and `HEAD`
+    /// are served and every other method gets `405 Method Not Allowed`.
+    pub fn static_files(&mut self, url_prefix: &str, fs_root: impl Into<PathBuf>) -> &mut Router {
+        let files = Arc::new(StaticFileServer::new(fs_root));
+        let pattern = format!("{}/*", url_prefix.trim_end_matches('/'));
+
+        for method in [HttpMethod::Get, HttpMethod::Head] {
+            let files = Arc::clone(&files);
+            self.route(method, &pattern, move |mut req| {
+                req.path = format!("/{}", req.params["*"]);
+                files.serve(&req)
+            });
+        }
+        for method in [
+            HttpMethod::Post,
+            HttpMethod::Put,
+            HttpMethod::Delete,
+            HttpMethod::Patch,
+            HttpMethod::Options,
+            HttpMethod::Trace,
+        ] {
+            self.route(method, &pattern, |_| {
+                let mut response = Response::new(StatusCode::METHOD_NOT_ALLOWED);
+                response.set_header("Allow", "GET, HEAD");
+                response
+            });
+        }
+        self
+    }
+
+    /// Registers `handler` for a CGI-style script at `script_path`, e.g. `/cgi-bin/app.cgi`.
+    ///
+    /// The script answers its own path and every path below it, for every
+    /// method but `CONNECT` and `TRACE`. The part of the path after
+    /// `script_path` is the script's extra path info, so
+    /// `/cgi-bin/app.cgi/extra/path` gets `/extra/path`; see
+    /// [`Request::path_info`]. The handler also finds these CGI
+    /// meta-variables in `Request::params`:
+    ///
+    /// * `SCRIPT_NAME` - `script_path`, without a trailing slash.
+    /// * `PATH_INFO` - The extra path info, empty if there is none.
+    /// * `QUERY_STRING` - The query string without the `?`, empty if there is none.
+    /// * `REQUEST_METHOD` - The request method, e.g. `GET`.
+    pub fn cgi_route<F>(&mut self, script_path: &str, handler: F) -> &mut Router
+    where
+        F: Fn(Request) -> Response + Send + Sync + 'static,
+    {
+        let handler = Arc::new(handler);
+        let script_name = script_path.trim_end_matches('/').to_string();
+        let pattern = format!("{script_name}/*");
+
+        for method in [
+            HttpMethod::Get,
+            HttpMethod::Head,
+            HttpMethod::Post,
+            HttpMethod::Put,
+            HttpMethod::Delete,
+            HttpMethod::Patch,
+            HttpMethod::Options,
+        ] {
+            let handler = Arc::clone(&handler);
+            let script_name = script_name.clone();
+            self.route(method, &pattern, move |mut req| {
+                let (path, query) = req.path.split_once('?').unwrap_or((&req.path, ""));
+                let path_info = path[script_name.len()..].to_string();
+                let query = query.to_string();
+
+                req.params
+                    .insert("SCRIPT_NAME".to_string(), script_name.clone());
+                req.params.insert("PATH_INFO".to_string(), path_info);
+                req.params.insert("QUERY_STRING".to_string(), query);
+                req.params
+                    .insert("REQUEST_METHOD".to_string(), req.method.clone());
+                handler(req)
+            });
+        }
+        self
+    }
+
+    /// Sets the handler used when no route matches. Defaults to an empty `404 Not Found`.
+    pub fn not_found<F>(&mut self, handler: F) -> &mut Router
+    where
+        F: Fn(Request) -> Response + Send + Sync + 'static,
+    {
+        self.not_found = Some(Box::new(handler));
+        self
+    }
+
+    /// Starts a group of routes that share `prefix` and their own middleware.
+    ///
+    /// The routes are added to this router when [`RouteGroup::finish`] is called.
+    ///
+    /// ```ignore
+    /// router
+    ///     .group("/api/v1")
+    ///     .middleware(JwtMiddleware::new(secret, JwtAlgorithm::Hs256))
+    ///     .get("/users/:id", get_user)
+    ///     .post("/users", create_user)
+    ///     .finish();
+    /// ```
+    pub fn group(&mut self, prefix: &str) -> RouteGroup<'_> {
+        RouteGroup {
+            router: self,
+            prefix: prefix.trim_end_matches('/').to_string(),
+            middleware: Vec::new(),
+            routes: Vec::new(),
+        }
+    }
+
+    /// Adds middleware that runs around every request.
+    ///
+    /// Middleware runs in the order it was added: the first one added is the
+    /// outermost and sees the request first and the response last.
+    pub fn middleware(&mut self, middleware: impl Middleware + 'static) -> &mut Router {
+        self.middleware.push(Box::new(middleware));
+        self
+    }
+
+    /// Caps how many requests the routes registered under `pattern` handle at once.
+    ///
+    /// Every route registered so far with exactly this pattern (e.g.
+    /// `/reports/:id`), whatever its method, shares one [`Semaphore`] of
+    /// `limit` permits. A request that finds them all taken gets
+    /// `503 Service Unavailable` straight away instead of waiting, so a slow
+    /// upstream behind one route cannot tie up every worker and starve the
+    /// others. The limit is checked before the route's own middleware runs.
+    pub fn with_concurrency_limit(&mut self, pattern: &str, limit: usize) -> &mut Router {
+        let limiter: Arc<dyn Middleware> =
+            Arc::new(ConcurrencyLimit(Arc::new(Semaphore::new(limit))));
+        for route in self
+            .routes
+            .iter_mut()
+            .filter(|route| route.pattern == pattern)
+        {
+            route.middleware.insert(0, Arc::clone(&limiter));
+        }
+        self
+    }
+
+    /// Only lets clients from `allowed_ips` reach the paths under `path_prefix`, e.g. `/admin`.
+    ///
+    /// The prefix matches whole segments, so `/admin` covers `/admin` and
+    /// `/admin/users` but not `/administrator`. When prefixes overlap, the
+    /// longest one that matches decides, so `/admin/health` can be opened up
+    /// wider than the rest of `/admin`. Other clients, and requests whose
+    /// client address is unknown, get `403 Forbidden` before any middleware
+    /// runs or CORS pre-flight is answered, whether or not a route matches.
+    /// IPv4-mapped IPv6 addresses are compared as IPv4. Restricting the same
+    /// prefix again replaces its list.
+    pub fn restrict(&mut self, path_prefix: &str, allowed_ips: Vec<IpAddr>) -> &mut Router {
+        let prefix = path_prefix.trim_end_matches('/').to_string();
+        let allowed_ips = allowed_ips.iter().map(IpAddr::to_canonical).collect();
+
+        self.acls.retain(|(existing, _)| *existing != prefix);
+        let index = self
+            .acls
+            .partition_point(|(existing, _)| existing.len() >= prefix.len());
+        self.acls.insert(index, (prefix, allowed_ips));
+        self
+    }
+
+    /// Returns the methods registered for routes whose pattern matches `path`.
+    ///
+    /// Methods are listed once each, in the order their routes were
+    /// registered. A query string on `path` is ignored. The path `*` stands
+    /// for the whole server, as in `OPTIONS *`, and matches every route.
+    pub fn allowed_methods(&self, path: &str) -> Vec<HttpMethod> {
+        let path = path.split('?').next().unwrap_or_default();
+        let matches = |pattern: &str| path == "*" || match_pattern(pattern, path).is_some();
+
+        let mut methods = Vec::new();
+        for route in &self.routes {
+            if !methods.contains(&route.method) && matches(&route.pattern) {
+                methods.push(route.method);
+            }
+        }
+        methods
+    }
+
+    /// Adds `headers` to every response the router produces, along with `Server` and `Date`.
+    ///
+    /// This covers handler responses as well as the not-found and automatic
+    /// `OPTIONS` responses. `Server` defaults to `rust-http/0.1` and `Date` is
+    /// the time the response is produced; either can be replaced through
+    /// `headers`. A handler that sets a header itself keeps its own value.
+    /// Calling this again replaces the defaults with the same names and keeps
+    /// the others.
+    pub fn default_headers(&mut self, headers: HeaderMap) -> &mut Router {
+        let defaults = self.default_headers.get_or_insert_with(|| {
+            let mut defaults = HeaderMap::new();
+            defaults.insert("Server", SERVER_NAME);
+            defaults
+        });
+        for (name, _) in headers.iter() {
+            defaults.remove(name);
+        }
+        for (name, value) in headers.iter() {
+            defaults.append(name, value);
+        }
+        self
+    }
+
+    /// Returns an OpenAPI 3.0 JSON document listing every route's path and methods.
+    ///
+    /// `:id` segments become `{id}` path parameters. Request and response
+    /// schemas are not described, so every operation has only a `default`
+    /// response.
+    pub fn openapi_spec(&self, info: OpenApiInfo) -> String {
+        let routes = self
+            .routes
+            .iter()
+            .map(|route| (route.method, route.pattern.as_str()));
+        openapi_spec(&info, routes)
+    }
+
+    /// Redirects requests that only miss a route by a trailing slash, when `enabled`.
+    ///
+    /// A request for `/about/` that no route matches is redirected to
+    /// `/about` if a route for its method matches that, and `/about` to
+    /// `/about/` if only the latter is registered; the query string is kept.
+    /// `GET` and `HEAD` requests get `301 Moved Permanently`; other methods
+    /// get `308 Permanent Redirect`, which clients must repeat with the same
+    /// method and body. Disabled by default, when such requests get a 404.
+    pub fn redirect_trailing_slash(&mut self, enabled: bool) -> &mut Router {
+        self.redirect_trailing_slash = enabled;
+        self
+    }
+
+    /// Serves [`Router::openapi_spec`] at `path` for `GET` requests, e.g. `/openapi.json`.
+    ///
+    /// The document is built on each request, so it lists routes registered
+    /// after this call too. It is answered after the middleware runs.
+    pub fn serve_openapi(&mut self, path: &str, info: OpenApiInfo) -> &mut Router {
+        self.openapi = Some((path.to_string(), info));
+        self
+    }
+
+    /// Runs `req` through the middleware and the matching route and returns the response.
+    ///
+    /// `OPTIONS` requests for a path with routes but no `OPTIONS` handler are
+    /// answered with `204 No Content` and an `Allow` header listing the
+    /// path's methods, so they need not be registered by hand. `OPTIONS *`
+    /// lists the methods of every route.
+    pub fn handle(&self, req: Request) -> Response {
+        self.handle_with_cors(req, None)
+    }
+
+    // Handles `req` as `handle` does, answering CORS pre-flights from `cors`
+    // once the client passes the ACLs. Pre-flights skip the middleware, as
+    // browsers send them without credentials
+    pub(crate) fn handle_with_cors(&self, req: Request, cors: Option<&CorsConfig>) -> Response {
+        let mut response = if !self.is_allowed(&req) {
+            Response::new(StatusCode::FORBIDDEN)
+        } else if let Some(preflight) = cors.and_then(|cors| cors.preflight(&req, self)) {
+            preflight
+        } else {
+            run_catching_panics(req, |req| self.run_middleware(0, req))
+        };
+        self.add_default_headers(&mut response);
+        response
+    }
+
+    // Checks the client against the longest restricted prefix covering the path, if any
+    fn is_allowed(&self, req: &Request) -> bool {
+        let path = req.path.split('?').next().unwrap_or_default();
+        let covers = |prefix: &str| {
+            path.strip_prefix(prefix)
+                .is_some_and(|rest| rest.is_empty() || rest.starts_with('/'))
+        };
+        let Some((_, allowed_ips)) = self.acls.iter().find(|(prefix, _)| covers(prefix)) else {
+            return true;
+        };
+        req.remote_addr
+            .is_some_and(|addr| allowed_ips.contains(&addr.ip().to_canonical()))
+    }
+
+    // Adds the default headers `response` does not already have
+    fn add_default_headers(&self, response: &mut Response) {
+        let Some(defaults) = &self.default_headers else {
+            return;
+        };
+
+        let missing: Vec<(&str, &str)> = defaults
+            .iter()
+            .filter(|(name, _)| !response.headers.contains(name))
+            .collect();
+        for (name, value) in missing {
+            response.headers.append(name, value);
+        }
+        if !response.headers.contains("Date") {
+            response.set_header("Date", &current_http_date());
+        }
+    }
+
+    // Runs the middleware at `index`, then the rest of the chain
+    fn run_middleware(&self, index: usize, req: Request) -> Response {
+        match self.middleware.get(index) {
+            Some(middleware) => middleware.handle(req, &|req| self.run_middleware(index + 1, req)),
+            None => self.dispatch(req),
+        }
+    }
+
+    // Finds the route matching `req` and returns the response its handler produces
+    fn dispatch(&self, mut req: Request) -> Response {
+        let path = req.path.split('?').next().unwrap_or_default().to_string();
+
+        if let Some((openapi_path, info)) = &self.openapi {
+            if req.method == HttpMethod::Get.as_str() && path == *openapi_path {
+                let spec = self.openapi_spec(info.clone());
+                return Response::with_body(StatusCode::OK, "application/json", spec.into_bytes());
+            }
+        }
+
+        for route in &self.routes {
+            if route.method.as_str() != req.method {
+                continue;
+            }
+            if let Some(params) = match_pattern(&route.pattern, &path) {
+                req.params = params;
+                return run_chain(&route.middleware, &route.handler, req);
+            }
+        }
+
+        let fetches =
+            req.method == HttpMethod::Get.as_str() || req.method == HttpMethod::Head.as_str();
+        if fetches && path == "/favicon.ico" {
+            return self.favicon.serve(&req);
+        }
+
+        if self.redirect_trailing_slash {
+            if let Some(response) = self.trailing_slash_redirect(&req, &path) {
+                return response;
+            }
+        }
+
+        if req.method == HttpMethod::Options.as_str() {
+            let mut methods = self.allowed_methods(&path);
+            if !methods.is_empty() {
+                if !methods.contains(&HttpMethod::Options) {
+                    methods.push(HttpMethod::Options);
+                }
+                let mut response = Response::new(StatusCode::NO_CONTENT);
+                response.set_header("Allow", &join_methods(&methods));
+                return response;
+            }
+        }
+
+        match &self.not_found {
+            Some(handler) => handler(req),
+            None => Response::new(StatusCode::NOT_FOUND),
+        }
+    }
+
+    // Redirects to `path` with its trailing slash added or removed, if a
+    // route for the request's method matches that form instead
+    fn trailing_slash_redirect(&self, req: &Request, path: &str) -> Option<Response> {
+        let other = match path.strip_suffix('/') {
+            Some("") => return None,
+            Some(stripped) => stripped.to_string(),
+            None => format!("{path}/"),
+        };
+        // "//host" would be followed to another site
+        if other.starts_with("//") {
+            return None;
+        }
+        let matches = self.routes.iter().any(|route| {
+            route.method.as_str() == req.method && match_pattern(&route.pattern, &other).is_some()
+        });
+        if !matches {
+            return None;
+        }
+
+        let location = match req.path.split_once('?') {
+            Some((_, query)) => format!("{other}?{query}"),
+            None => other,
+        };
+        let fetches =
+            req.method == HttpMethod::Get.as_str() || req.method == HttpMethod::Head.as_str();
+        let status = if fetches {
+            StatusCode::MOVED_PERMANENTLY
+        } else {
+            StatusCode::PERMANENT_REDIRECT
+        };
+        let mut response = Response::new(status);
+        response.set_header("Location", &location);
+        Some(response)
+    }
+}
+
+impl Default for Router {
+    fn default() -> Router {
+        Router::new()
+    }
+}
+
+/// Routes that share a path prefix and middleware, created by [`Router::group`].
+///
+/// Group middleware runs inside the router's own middleware and only for the
+/// group's routes, in the order it was added.
+pub struct RouteGroup<'a> {
+    router: &'a mut Router,               // The router the routes are added to
+    prefix: String,                       // Prepended to every pattern, without a trailing slash
+    middleware: Vec<Arc<dyn Middleware>>, // Runs around each of the group's handlers
+    routes: Vec<(HttpMethod, String, Handler)>, // The routes registered so far, unprefixed
+}
+
+impl<'a> RouteGroup<'a> {
+    /// Adds middleware that runs around every route in the group.
+    pub fn middleware(mut self, middleware: impl Middleware + 'static) -> RouteGroup<'a> {
+        self.middleware.push(Arc::new(middleware));
+        self
+    }
+
+    /// Registers `handler` for the given method and pattern, relative to the group prefix.
+    ///
+    /// A pattern of `/` matches the prefix itself.
+    pub fn route<F>(mut self, method: HttpMethod, pattern: &str, handler: F) -> RouteGroup<'a>
+    where
+        F: Fn(Request) -> Response + Send + Sync + 'static,
+    {
+        self.routes
+            .push((method, pattern.to_string(), Box::new(handler)));
+        self
+    }
+
+    /// Registers a `GET` route.
+    pub fn get<F>(self, pattern: &str, handler: F) -> RouteGroup<'a>
+    where
+        F: Fn(Request) -> Response + Send + Sync + 'static,
+    {
+        self.route(HttpMethod::Get, pattern, handler)
+    }
+
+    /// Registers a `POST` route.
+    pub fn post<F>(self, pattern: &str, handler: F) -> RouteGroup<'a>
+    where
+        F: Fn(Request) -> Response + Send + Sync + 'static,
+    {
+        self.route(HttpMethod::Post, pattern, handler)
+    }
+
+    /// Registers a `PUT` route.
+    pub fn put<F>(self, pattern: &str, handler: F) -> RouteGroup<'a>
+    where
+        F: Fn(Request) -> Response + Send + Sync + 'static,
+    {
+        self.route(HttpMethod::Put, pattern, handler)
+    }
+
+    /// Registers a `DELETE` route.
+    pub fn delete<F>(self, pattern: &str, handler: F) -> RouteGroup<'a>
+    where
+        F: Fn(Request) -> Response + Send + Sync + 'static,
+    {
+        self.route(HttpMethod::Delete, pattern, handler)
+    }
+
+    /// Adds the group's routes to the parent router and returns it.
+    pub fn finish(self) -> &'a mut Router {
+        for (method, pattern, handler) in self.routes {
+            let pattern = match pattern.as_str() {
+                "/" | "" if !self.prefix.is_empty() => self.prefix.clone(),
+                _ => format!("{}{pattern}", self.prefix),
+            };
+            self.router.routes.push(Route {
+                method,
+                pattern,
+                handler,
+                middleware: self.middleware.clone(),
+            });
+        }
+        self.router
+    }
+}
+
+/// A route that was just registered, returned by [`Router::route`] and friends.
+///
+/// Dereferences to the [`Router`], so registrations can still be chained.
+pub struct RouteHandle<'a> {
+    router: &'a mut Router, // The router whose last route this is
+}
+
+impl RouteHandle<'_> {
+    /// Adds middleware that runs around this route's handler only.
+    ///
+    /// Route middleware runs inside the router's own middleware, in the order
+    /// it was added.
+    ///
+    /// ```ignore
+    /// router
+    ///     .get("/admin/dashboard", dashboard)
+    ///     .middleware(JwtMiddleware::new(secret, JwtAlgorithm::Hs256));
+    /// ```
+    pub fn middleware(self, middleware: impl Middleware + 'static) -> Self {
+        if let Some(route) = self.router.routes.last_mut() {
+            route.middleware.push(Arc::new(middleware));
+        }
+        self
+    }
+}
+
+impl Deref for RouteHandle<'_> {
+    type Target = Router;
+
+    fn deref(&self) -> &Router {
+        self.router
+    }
+}
+
+impl DerefMut for RouteHandle<'_> {
+    fn deref_mut(&mut self) -> &mut Router {
+        self.router
+    }
+}
+
+// Refuses requests with 503 while every permit of the route's semaphore is held
+struct ConcurrencyLimit(Arc<Semaphore>);
+
+impl Middleware for ConcurrencyLimit {
+    fn handle(&self, req: Request, next: &dyn Fn(Request) -> Response) -> Response {
+        match self.0.try_acquire() {
+            Some(_permit) => next(req),
+            None => {
+                let mut response = Response::new(StatusCode::SERVICE_UNAVAILABLE);
+                response.retry_after(RetryReason::Overloaded {
+                    estimated_seconds: OVERLOAD_RETRY_SECONDS,
+                });
+                response
+            }
+        }
+    }
+}
+
+// Runs `req` through `middleware`, outermost first, and then `handler`
+fn run_chain(middleware: &[Arc<dyn Middleware>], handler: &Handler, req: Request) -> Response {
+    match middleware.split_first() {
+        Some((first, rest)) => first.handle(req, &|req| run_chain(rest, handler, req)),
+        None => handler(req),
+    }
+}
+
+// Runs `serve` on `req`, answering `500 Internal Server Error` if it
+// panics. The server gives that response its error page; the panic message
+// is only logged, as it may reveal internals
+fn run_catching_panics(req: Request, serve: impl FnOnce(Request) -> Response) -> Response {
+    let path = req.path.clone();
+    match panic::catch_unwind(AssertUnwindSafe(|| serve(req))) {
+        Ok(response) => response,
+        Err(payload) => {
+            let message = payload
+                .downcast_ref::<&str>()
+                .copied()
+                .or_else(|| payload.downcast_ref::<String>().map(String::as_str))
+                .unwrap_or("non-string panic payload");
+            println!("Request for {path} panicked: {message}");
+            Response::new(StatusCode::INTERNAL_SERVER_ERROR)
+        }
+    }
+}
+
+// Formats `methods` as a header value, e.g. "GET, POST, OPTIONS"
+pub(crate) fn join_methods(methods: &[HttpMethod]) -> String {
+    methods
+        .iter()
+        .map(HttpMethod::as_str)
+        .collect::<Vec<_>>()
+        .join(", ")
+}
+
+/// Matches `path` against `pattern`.
+///
+/// # Returns
+///
+/// The captured path parameters, or `None` if the path does not match.
+fn match_pattern(pattern: &str, path: &str) -> Option<HashMap<String, String>> {
+    let mut params = HashMap::new();
+    let mut path_segments = path.split('/');
+
+    for pattern_segment in pattern.split('/') {
+        if pattern_segment == "*" {
+            let rest: Vec<&str> = path_segments.collect();
+            params.insert("*".to_string(), rest.join("/"));
+            return Some(params);
+        }
+
+        let path_segment = path_segments.next()?;
+        if let Some(name) = pattern_segment.strip_prefix(':') {
+            if path_segment.is_empty() {
+                return None;
+            }
+            params.insert(name.to_string(), path_segment.to_string());
+        } else if pattern_segment != path_segment {
+            return None;
+        }
+    }
+
+    // Every path segment must have been consumed by the pattern
+    path_segments.next().is_none().then_some(params)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn get(router: &Router, path: &str) -> Response {
+        let head = format!("GET {path} HTTP/1.1\r\n\r\n");
+        router.handle(Request::new(head.as_bytes()).unwrap())
+    }
+
+    #[test]
+    fn answers_500_when_a_handler_panics() {
+        let mut router = Router::new();
+        router.get("/boom", |_| panic!("handler failed"));
+        router.get("/ok", |_| Response::new(StatusCode::OK));
+
+        assert_eq!(
+            get(&router, "/boom").status,
+            StatusCode::INTERNAL_SERVER_ERROR
+        );
+        assert_eq!(get(&router, "/ok").status, StatusCode::OK);
+    }
+
+    #[test]
+    fn answers_500_when_middleware_panics() {
+        let mut router = Router::new();
+        router.get("/ok", |_| Response::new(StatusCode::OK));
+        router.middleware(|req: Request, next: &dyn Fn(Request) -> Response| {
+            if req.path == "/ok" {
+                panic!("middleware failed");
+            }
+            next(req)
+        });
+
+        assert_eq!(
+            get(&router, "/ok").status,
+            StatusCode::INTERNAL_SERVER_ERROR
+        );
+        assert_eq!(get(&router, "/missing").status, StatusCode::NOT_FOUND);
+    }
+
+    #[test]
+    fn answers_500_when_route_middleware_or_not_found_panics() {
+        let mut router = Router::new();
+        router
+            .get("/limited", |_| Response::new(StatusCode::OK))
+            .middleware(|_: Request, _: &dyn Fn(Request) -> Response| -> Response {
+                panic!("route middleware failed")
+            });
+        router.not_found(|_| panic!("not found failed"));
+
+        assert_eq!(
+            get(&router, "/limited").status,
+            StatusCode::INTERNAL_SERVER_ERROR
+        );
+        assert_eq!(
+            get(&router, "/missing").status,
+            StatusCode::INTERNAL_SERVER_ERROR
+        );
+    }
+
+    fn get_from(router: &Router, path: &str, client: Option<&str>) -> StatusCode {
+        let head = format!("GET {path} HTTP/1.1\r\n\r\n");
+        let mut req = Request::new(head.as_bytes()).unwrap();
+        req.remote_addr = client.map(|addr| addr.parse().unwrap());
+        router.handle(req).status
+    }
+
+    fn restricted_router() -> Router {
+        let mut router = Router::new();
+        for path in ["/admin", "/admin/users", "/admin/health", "/administrator"] {
+            router.get(path, |_| Response::new(StatusCode::OK));
+        }
+        router.restrict("/admin", vec!["10.0.0.1".parse().unwrap()]);
+        router.restrict(
+            "/admin/health/",
+            vec!["10.0.0.1".parse().unwrap(), "10.0.0.2".parse().unwrap()],
+        );
+        router
+    }
+
+    #[test]
+    fn restricts_whole_path_segments_only() {
+        let router = restricted_router();
+        let outsider = Some("192.0.2.7:4000");
+
+        assert_eq!(get_from(&router, "/admin", outsider), StatusCode::FORBIDDEN);
+        assert_eq!(
+            get_from(&router, "/admin/users", outsider),
+            StatusCode::FORBIDDEN
+        );
+        assert_eq!(
+            get_from(&router, "/admin?x=1", outsider),
+            StatusCode::FORBIDDEN
+        );
+        assert_eq!(
+            get_from(&router, "/admin/missing", outsider),
+            StatusCode::FORBIDDEN
+        );
+        assert_eq!(
+            get_from(&router, "/administrator", outsider),
+            StatusCode::OK
+        );
+        assert_eq!(
+            get_from(&router, "/admin/users", Some("10.0.0.1:4000")),
+            StatusCode::OK
+        );
+    }
+
+    #[test]
+    fn lets_the_longest_restricted_prefix_decide() {
+        let router = restricted_router();
+        let monitor = Some("10.0.0.2:4000");
+
+        assert_eq!(get_from(&router, "/admin/health", monitor), StatusCode::OK);
+        assert_eq!(
+            get_from(&router, "/admin/users", monitor),
+            StatusCode::FORBIDDEN
+        );
+        assert_eq!(
+            get_from(&router, "/admin/health", Some("10.0.0.3:4000")),
+            StatusCode::FORBIDDEN
+        );
+    }
+
+    #[test]
+    fn compares_ipv4_mapped_addresses_as_ipv4() {
+        let router = restricted_router();
+        assert_eq!(
+            get_from(&router, "/admin", Some("[::ffff:10.0.0.1]:4000")),
+            StatusCode::OK
+        );
+
+        let mut router = Router::new();
+        router.get("/admin", |_| Response::new(StatusCode::OK));
+        router.restrict("/admin", vec!["::ffff:10.0.0.1".parse().unwrap()]);
+        assert_eq!(
+            get_from(&router, "/admin", Some("10.0.0.1:4000")),
+            StatusCode::OK
+        );
+        assert_eq!(
+            get_from(&router, "/admin", Some("[::1]:4000")),
+            StatusCode::FORBIDDEN
+        );
+    }
+
+    #[test]
+    fn forbids_clients_with_unknown_addresses() {
+        let router = restricted_router();
+        assert_eq!(get_from(&router, "/admin", None), StatusCode::FORBIDDEN);
+        assert_eq!(get_from(&router, "/administrator", None), StatusCode::OK);
+    }
+
+    #[test]
+    fn replaces_the_list_when_a_prefix_is_restricted_again() {
+        let mut router = restricted_router();
+        router.restrict("/admin", vec!["10.0.0.9".parse().unwrap()]);
+        assert_eq!(
+            get_from(&router, "/admin", Some("10.0.0.1:4000")),
+            StatusCode::FORBIDDEN
+        );
+        assert_eq!(
+            get_from(&router, "/admin", Some("10.0.0.9:4000")),
+            StatusCode::OK
+        );
+    }
+
+    fn text(req: Request, name: &str) -> Response {
+        let body = format!("{name} {:?}", req.params.get("id").or(req.params.get("*")));
+        Response::with_body(StatusCode::OK, "text/plain", body.into_bytes())
+    }
+
+    fn root(req: Request) -> Response {
+        text(req, "root")
+    }
+
+    fn user(req: Request) -> Response {
+        text(req, "user")
+    }
+
+    fn update_user(req: Request) -> Response {
+        text(req, "update")
+    }
+
+    fn files(req: Request) -> Response {
+        text(req, "files")
+    }
+
+    fn send(dispatch: fn(Request) -> Response, method: &str, path: &str) -> (StatusCode, String) {
+        let head = format!("{method} {path} HTTP/1.1\r\n\r\n");
+        let response = dispatch(Request::new(head.as_bytes()).unwrap());
+        let allow = response
+            .headers
+            .get("Allow")
+            .unwrap_or_default()
+            .to_string();
+        let body = String::from_utf8(response.body).unwrap();
+        (response.status, if body.is_empty() { allow } else { body })
+    }
+
+    #[test]
+    fn dispatches_a_compiled_route_table() {
+        crate::define_routes! {
+            GET "/" => root,
+            GET "/user/:id" => user,
+            PUT "/user/:id" => update_user,
+            GET r"/files/*" => files,
+            POST "/echo" => |req: Request| text(req, "echo"),
+        }
+
+        let routes = [
+            ("GET", "/", StatusCode::OK, "root None"),
+            (
+                "GET",
+                "/user/7?tab=posts",
+                StatusCode::OK,
+                "user Some(\"7\")",
+            ),
+            ("PUT", "/user/7", StatusCode::OK, "update Some(\"7\")"),
+            (
+                "GET",
+                "/files/a/b.txt",
+                StatusCode::OK,
+                "files Some(\"a/b.txt\")",
+            ),
+            ("GET", "/files", StatusCode::OK, "files Some(\"\")"),
+            ("POST", "/echo", StatusCode::OK, "echo None"),
+            (
+                "DELETE",
+                "/user/7",
+                StatusCode::METHOD_NOT_ALLOWED,
+                "GET, PUT",
+            ),
+            ("get", "/", StatusCode::METHOD_NOT_ALLOWED, "GET"),
+            ("GET", "/user/", StatusCode::NOT_FOUND, ""),
+            ("GET", "/user/7/posts", StatusCode::NOT_FOUND, ""),
+            ("GET", "/missing", StatusCode::NOT_FOUND, ""),
+        ];
+        for (method, path, status, body) in routes {
+            assert_eq!(
+                send(dispatch, method, path),
+                (status, body.to_string()),
+                "{method} {path}"
+            );
+        }
+    }
+
+    #[test]
+    fn routes_the_compiled_table_like_the_router() {
+        crate::define_routes! {
+            GET "/user/:id" => user,
+            GET "/user/me" => root,
+            PUT "/user/:id" => update_user,
+            GET "/static/*" => files,
+        }
+        let mut router = Router::new();
+        router.get("/user/:id", user);
+        router.get("/user/me", root);
+        router.put("/user/:id", update_user);
+        router.get("/static/*", files);
+
+        let requests = [
+            ("GET", "/user/me"),
+            ("GET", "/user/42"),
+            ("PUT", "/user/42"),
+            ("GET", "/static/css/site.css"),
+            ("GET", "/user//"),
+            ("GET", "/nothing/here"),
+        ];
+        for (method, path) in requests {
+            let head = format!("{method} {path} HTTP/1.1\r\n\r\n");
+            let expected = router.handle(Request::new(head.as_bytes()).unwrap());
+            let (status, body) = send(dispatch, method, path);
+            assert_eq!(status, expected.status, "{method} {path}");
+            if status == StatusCode::OK {
+                assert_eq!(body.as_bytes(), expected.body, "{method} {path}");
+            }
+        }
+    }
+
+    #[test]
+    fn dispatches_an_empty_compiled_table() {
+        crate::define_routes! {}
+        assert_eq!(
+            send(dispatch, "GET", "/"),
+            (StatusCode::NOT_FOUND, String::new())
+        );
+    }
+}
@@ -0,0 +1,369 @@
+use std::time::Duration;
+
+use crate::random::secure_random_u128;
+use crate::{base64, HttpError, Middleware, Request, Response, StatusCode};
+
+// The shortest max-age the HSTS preload list accepts (one year)
+const PRELOAD_MIN_MAX_AGE: Duration = Duration::from_secs(31_536_000);
+
+/// The settings of a `Strict-Transport-Security` header (RFC 6797).
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct HstsConfig {
+    /// How long browsers should only use HTTPS for this host.
+    pub max_age: Duration,
+    /// Whether the policy also covers every subdomain.
+    pub include_subdomains: bool,
+    /// Whether the host asks to be put on the browsers' HSTS preload list.
+    pub preload: bool,
+}
+
+impl HstsConfig {
+    /// Checks that the settings are consistent.
+    ///
+    /// # Errors
+    ///
+    /// Returns `HttpError::InvalidConfig` if `preload` is set without
+    /// `include_subdomains` or with a `max_age` shorter than one year, which
+    /// the preload list would reject.
+    pub fn validate(&self) -> Result<(), HttpError> {
+        if self.preload && !self.include_subdomains {
+            return Err(HttpError::InvalidConfig(
+                "HSTS preload requires includeSubDomains",
+            ));
+        }
+        if self.preload && self.max_age < PRELOAD_MIN_MAX_AGE {
+            return Err(HttpError::InvalidConfig(
+                "HSTS preload requires a max-age of at least one year",
+            ));
+        }
+        Ok(())
+    }
+
+    /// Returns the header value, e.g. `max-age=31536000; includeSubDomains; preload`.
+    pub fn to_header_value(&self) -> String {
+        let mut value = format!("max-age={}", self.max_age.as_secs());
+        if self.include_subdomains {
+            value.push_str("; includeSubDomains");
+        }
+        if self.preload {
+            value.push_str("; preload");
+        }
+        value
+    }
+}
+
+/// Adds common security headers to every response.
+///
+/// By default this sets `X-Content-Type-Options: nosniff`,
+/// `X-Frame-Options: DENY` and `Referrer-Policy: no-referrer`.
+/// `Strict-Transport-Security` is only sent once configured with
+/// [`SecurityHeadersMiddleware::with_hsts`], since it must only be served
+/// over HTTPS. Headers a handler has already set are left alone.
+#[derive(Debug, Clone, Default)]
+pub struct SecurityHeadersMiddleware {
+    hsts: Option<HstsConfig>, // The HSTS policy, if any
+}
+
+impl SecurityHeadersMiddleware {
+    /// Creates the middleware with the default headers and no HSTS.
+    pub fn new() -> SecurityHeadersMiddleware {
+        SecurityHeadersMiddleware::default()
+    }
+
+    /// Also sends `Strict-Transport-Security` built from `hsts`.
+    ///
+    /// # Errors
+    ///
+    /// Returns `HttpError::InvalidConfig` if `hsts` fails [`HstsConfig::validate`].
+    pub fn with_hsts(mut self, hsts: HstsConfig) -> Result<SecurityHeadersMiddleware, HttpError> {
+        hsts.validate()?;
+        self.hsts = Some(hsts);
+        Ok(self)
+    }
+}
+
+impl Middleware for SecurityHeadersMiddleware {
+    fn handle(&self, req: Request, next: &dyn Fn(Request) -> Response) -> Response {
+        let mut response = next(req);
+
+        let mut defaults = vec![
+            ("X-Content-Type-Options", "nosniff".to_string()),
+            ("X-Frame-Options", "DENY".to_string()),
+            ("Referrer-Policy", "no-referrer".to_string()),
+        ];
+        if let Some(hsts) = &self.hsts {
+            defaults.push(("Strict-Transport-Security", hsts.to_header_value()));
+        }
+
+        for (name, value) in defaults {
+            if !response.headers.contains(name) {
+                response.set_header(name, &value);
+            }
+        }
+        response
+    }
+}
+
+/// Redirects browsers that prefer HTTPS from plain HTTP to the same URL over HTTPS.
+///
+/// Requests with `Upgrade-Insecure-Requests: 1` (see [`Request::upgrade_insecure`])
+/// get `307 Temporary Redirect` to `https://` on the same host and
+/// `https_port` (see [`build_redirect_url`]), so the method and body are kept. Requests the proxy in
+/// front reports as already made over HTTPS (`Forwarded: proto=https` or
+/// `X-Forwarded-Proto: https`) and requests without a `Host` header are
+/// passed through. Only register this where HTTPS is actually served on
+/// `https_port`, or browsers are redirected to nothing.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct HttpsRedirectMiddleware {
+    https_port: u16, // The port HTTPS is served on
+}
+
+impl HttpsRedirectMiddleware {
+    /// Creates the middleware, redirecting to `https_port`. Port 443 is left out of the URL.
+    pub fn new(https_port: u16) -> HttpsRedirectMiddleware {
+        HttpsRedirectMiddleware { https_port }
+    }
+}
+
+impl Middleware for HttpsRedirectMiddleware {
+    fn handle(&self, req: Request, next: &dyn Fn(Request) -> Response) -> Response {
+        let already_secure = req
+            .forwarded_headers()
+            .proto
+            .is_some_and(|proto| proto.eq_ignore_ascii_case("https"));
+        if !req.upgrade_insecure() || already_secure {
+            return next(req);
+        }
+        let Ok(location) = build_redirect_url(&req, self.https_port) else {
+            return next(req);
+        };
+
+        let mut response = Response::new(StatusCode::TEMPORARY_REDIRECT);
+        response.set_header("Location", &location);
+        response.set_header("Vary", "Upgrade-Insecure-Requests");
+        response
+    }
+}
+
+/// Builds the `https://` URL of `req` on the same host, for redirecting to HTTPS.
+///
+/// The host comes from the `Host` header with any port stripped, and
+/// `:<https_port>` is appended unless it is 443. The request path and
+/// query string are kept, e.g. `http://example.com:8080/a?b=1` becomes
+/// `https://example.com:8443/a?b=1` for port 8443.
+///
+/// # Errors
+///
+/// Returns `HttpError::MissingHost` if the request has no `Host` header,
+/// or one that is empty or could make the URL point elsewhere (it
+/// contains `/`, `@` or `\`).
+pub fn build_redirect_url(req: &Request, https_port: u16) -> Result<String, HttpError> {
+    let host = req
+        .headers
+        .get("Host")
+        .ok_or(HttpError::MissingHost)?
+        .trim();
+    // "[::1]:8080" keeps its brackets; "example.com:8080" loses its port
+    let host = match host.rfind([':', ']']) {
+        Some(index) if host[index..].starts_with(':') => &host[..index],
+        _ => host,
+    };
+    if host.is_empty() || host.contains(['/', '@', '\\']) {
+        return Err(HttpError::MissingHost);
+    }
+
+    let authority = if https_port == 443 {
+        host.to_string()
+    } else {
+        format!("{host}:{https_port}")
+    };
+    Ok(format!("https://{authority}{}", req.path))
+}
+
+/// Generates a nonce per request and allows it in the `Content-Security-Policy`.
+///
+/// Each request gets 16 random bytes, base64 encoded, which handlers read
+/// with [`Request::csp_nonce`] and put on their inline `<script nonce="...">`
+/// tags. The response's policy then gets `'nonce-<base64>'` added to its
+/// `script-src` directive. A policy without one gets a `script-src` with
+/// the sources of its `default-src`, which scripts would otherwise fall back
+/// to, plus the nonce. A policy the handler set is extended; otherwise the one
+/// given to [`NonceMiddleware::with_policy`] is, or a bare `script-src`.
+#[derive(Debug, Clone, Default)]
+pub struct NonceMiddleware {
+    policy: Option<String>, // The policy sent when the handler sets none
+}
+
+impl NonceMiddleware {
+    /// Creates the middleware, sending `script-src 'nonce-...'` when the handler sets no policy.
+    pub fn new() -> NonceMiddleware {
+        NonceMiddleware::default()
+    }
+
+    /// Sends `policy`, with the nonce added, when the handler sets no policy of its own.
+    ///
+    /// For example `default-src 'self'` becomes
+    /// `default-src 'self'; script-src 'self' 'nonce-...'`.
+    pub fn with_policy(mut self, policy: &str) -> NonceMiddleware {
+        self.policy = Some(policy.to_string());
+        self
+    }
+}
+
+impl Middleware for NonceMiddleware {
+    fn handle(&self, mut req: Request, next: &dyn Fn(Request) -> Response) -> Response {
+        let nonce = base64::encode(&secure_random_u128().to_be_bytes());
+        req.set_csp_nonce(nonce.clone());
+        let mut response = next(req);
+
+        let policy = response
+            .headers
+            .get("Content-Security-Policy")
+            .map(str::to_string)
+            .or_else(|| self.policy.clone())
+            .unwrap_or_default();
+        response.set_header("Content-Security-Policy", &with_nonce(&policy, &nonce));
+        response
+    }
+}
+
+// Adds `'nonce-<nonce>'` to the script-src directive of `policy`. Without
+// one, scripts fall back to default-src, so a script-src is added that keeps
+// default-src's sources
+fn with_nonce(policy: &str, nonce: &str) -> String {
+    let source = format!("'nonce-{nonce}'");
+    let mut directives: Vec<String> = policy
+        .split(';')
+        .map(str::trim)
+        .filter(|directive| !directive.is_empty())
+        .map(str::to_string)
+        .collect();
+
+    let find = |name: &str| {
+        directives.iter().position(|directive| {
+            let directive_name = directive.split_whitespace().next().unwrap_or_default();
+            directive_name.eq_ignore_ascii_case(name)
+        })
+    };
+    match find("script-src") {
+        Some(index) => directives[index] = with_source(&directives[index], &source),
+        None => {
+            let inherited = find("default-src").map_or(String::new(), |index| {
+                let sources = directives[index].split_whitespace().skip(1);
+                sources.collect::<Vec<_>>().join(" ")
+            });
+            let script_src = with_source(&format!("script-src {inherited}"), &source);
+            directives.push(script_src);
+        }
+    }
+    directives.join("; ")
+}
+
+// Appends `source` to `directive`, dropping 'none', which cannot be combined with other sources
+fn with_source(directive: &str, source: &str) -> String {
+    directive
+        .split_whitespace()
+        .filter(|token| !token.eq_ignore_ascii_case("'none'"))
+        .chain([source])
+        .collect::<Vec<_>>()
+        .join(" ")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const YEAR: Duration = Duration::from_secs(31_536_000);
+
+    // Parses a header value by the grammar of RFC 6797 section 6.1, returning
+    // each directive's lowercased name and value, or `None` if it is invalid
+    fn parse_sts(value: &str) -> Option<Vec<(String, Option<String>)>> {
+        let mut directives: Vec<(String, Option<String>)> = Vec::new();
+        for directive in value.split(';').map(str::trim).filter(|d| !d.is_empty()) {
+            let (name, value) = match directive.split_once('=') {
+                Some((name, value)) => (name.trim(), Some(value.trim().to_string())),
+                None => (directive, None),
+            };
+            let name = name.to_ascii_lowercase();
+            // Every directive may appear at most once (section 6.1, rule 2)
+            if directives.iter().any(|(seen, _)| *seen == name) {
+                return None;
+            }
+            directives.push((name, value));
+        }
+        // max-age is required and its value is delta-seconds (section 6.1.1)
+        let (_, max_age) = directives.iter().find(|(name, _)| name == "max-age")?;
+        let max_age = max_age.as_deref()?;
+        if max_age.is_empty() || !max_age.bytes().all(|b| b.is_ascii_digit()) {
+            return None;
+        }
+        Some(directives)
+    }
+
+    fn hsts(max_age: Duration, include_subdomains: bool, preload: bool) -> HstsConfig {
+        HstsConfig {
+            max_age,
+            include_subdomains,
+            preload,
+        }
+    }
+
+    #[test]
+    fn formats_rfc_6797_values() {
+        assert_eq!(
+            hsts(YEAR, false, false).to_header_value(),
+            "max-age=31536000"
+        );
+        assert_eq!(
+            hsts(Duration::from_secs(600), true, false).to_header_value(),
+            "max-age=600; includeSubDomains"
+        );
+        assert_eq!(
+            hsts(YEAR * 2, true, true).to_header_value(),
+            "max-age=63072000; includeSubDomains; preload"
+        );
+        assert_eq!(
+            hsts(Duration::ZERO, false, false).to_header_value(),
+            "max-age=0"
+        );
+    }
+
+    #[test]
+    fn values_parse_as_rfc_6797() {
+        for (include_subdomains, preload) in [(false, false), (true, false), (true, true)] {
+            let value = hsts(YEAR, include_subdomains, preload).to_header_value();
+            let directives = parse_sts(&value).expect("invalid Strict-Transport-Security");
+            assert!(directives.contains(&("max-age".to_string(), Some("31536000".to_string()))));
+            assert_eq!(
+                directives.contains(&("includesubdomains".to_string(), None)),
+                include_subdomains
+            );
+            assert_eq!(directives.contains(&("preload".to_string(), None)), preload);
+        }
+    }
+
+    #[test]
+    fn preload_requires_subdomains_and_a_year() {
+        assert!(hsts(YEAR, true, true).validate().is_ok());
+        assert!(hsts(YEAR, false, true).validate().is_err());
+        assert!(hsts(YEAR - Duration::from_secs(1), true, true)
+            .validate()
+            .is_err());
+        assert!(hsts(Duration::from_secs(60), false, false)
+            .validate()
+            .is_ok());
+    }
+
+    #[test]
+    fn middleware_sends_the_header() {
+        let middleware = SecurityHeadersMiddleware::new()
+            .with_hsts(hsts(YEAR, true, true))
+            .unwrap();
+        let request = Request::new(&b"GET / HTTP/1.1\r\n\r\n"[..]).unwrap();
+        let response = middleware.handle(request, &|_| Response::new(StatusCode::OK));
+        assert_eq!(
+            response.headers.get("Strict-Transport-Security"),
+            Some("max-age=31536000; includeSubDomains; preload")
+        );
+    }
+}
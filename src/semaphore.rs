@@ -0,0 +1,84 @@
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Arc, Condvar, Mutex};
+
+/// A counting semaphore: at most `permits` holders at once.
+///
+/// [`Semaphore::try_acquire`] never blocks, which is what request handling
+/// wants: a worker that is refused a permit answers right away instead of
+/// sitting on the pool while the holders finish. [`Semaphore::acquire`]
+/// waits for a permit, for work that can afford to queue.
+#[derive(Debug)]
+pub struct Semaphore {
+    available: AtomicUsize, // The permits not currently held
+    lock: Mutex<()>,        // Guards waiting on `released`, so no wake-up is missed
+    released: Condvar,      // Signalled whenever a permit is returned
+}
+
+impl Semaphore {
+    /// Creates a semaphore with `permits` permits, all available.
+    pub fn new(permits: usize) -> Semaphore {
+        Semaphore {
+            available: AtomicUsize::new(permits),
+            lock: Mutex::new(()),
+            released: Condvar::new(),
+        }
+    }
+
+    /// Takes a permit if one is available, without blocking.
+    ///
+    /// # Returns
+    ///
+    /// A permit that is returned when dropped, or `None` if every permit is held.
+    pub fn try_acquire(self: &Arc<Self>) -> Option<SemaphorePermit> {
+        self.available
+            .fetch_update(Ordering::SeqCst, Ordering::SeqCst, |available| {
+                available.checked_sub(1)
+            })
+            .ok()
+            .map(|_| SemaphorePermit(Arc::clone(self)))
+    }
+
+    /// Takes a permit, waiting for one to be returned if every permit is held.
+    pub fn acquire(self: &Arc<Self>) -> SemaphorePermit {
+        let mut guard = self
+            .lock
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner());
+        loop {
+            if let Some(permit) = self.try_acquire() {
+                return permit;
+            }
+            guard = self
+                .released
+                .wait(guard)
+                .unwrap_or_else(|poisoned| poisoned.into_inner());
+        }
+    }
+
+    /// Returns the number of permits not currently held.
+    pub fn available(&self) -> usize {
+        self.available.load(Ordering::SeqCst)
+    }
+
+    // Returns a permit and wakes one waiting `acquire`
+    fn release(&self) {
+        // Taking the lock orders this with a waiter's check, so it cannot
+        // miss the notification between checking and waiting
+        let _guard = self
+            .lock
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner());
+        self.available.fetch_add(1, Ordering::SeqCst);
+        self.released.notify_one();
+    }
+}
+
+/// A permit held from a [`Semaphore`], returned when dropped.
+#[derive(Debug)]
+pub struct SemaphorePermit(Arc<Semaphore>);
+
+impl Drop for SemaphorePermit {
+    fn drop(&mut self) {
+        self.0.release();
+    }
+}
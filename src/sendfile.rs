@@ -0,0 +1,52 @@
+use std::fs::File;
+use std::io;
+use std::net::TcpStream;
+use std::os::unix::io::AsRawFd;
+
+use crate::HttpError;
+
+// The most bytes handed to a single sendfile(2) call, which Linux caps at 0x7ffff000 anyway
+const MAX_CHUNK: u64 = 0x7fff_f000;
+
+/// Sends `count` bytes of `file`, starting at `offset`, to `stream` with `sendfile(2)`.
+///
+/// The data goes straight from the page cache to the socket without being
+/// copied through userspace. The file's own read position is left alone.
+///
+/// # Returns
+///
+/// The number of bytes sent, which is less than `count` only if the file
+/// ends first.
+///
+/// # Errors
+///
+/// Returns `HttpError::Io` if a `sendfile(2)` call fails, e.g. because the
+/// client closed the connection.
+pub fn sendfile(
+    file: &File,
+    stream: &TcpStream,
+    offset: u64,
+    count: u64,
+) -> Result<u64, HttpError> {
+    let mut position = offset as libc::off64_t;
+    let mut sent = 0;
+
+    while sent < count {
+        let chunk = (count - sent).min(MAX_CHUNK) as libc::size_t;
+        // SAFETY: both descriptors stay open for the call, since `file` and
+        // `stream` are borrowed, and `position` is a valid off64_t
+        let result =
+            unsafe { libc::sendfile64(stream.as_raw_fd(), file.as_raw_fd(), &mut position, chunk) };
+        match result {
+            0 => break,
+            n if n > 0 => sent += n as u64,
+            _ => {
+                let err = io::Error::last_os_error();
+                if err.kind() != io::ErrorKind::Interrupted {
+                    return Err(err.into());
+                }
+            }
+        }
+    }
+    Ok(sent)
+}
@@ -0,0 +1,159 @@
+use std::fs::File;
+use std::io;
+
+use crate::HttpError;
+
+/// A source of bytes that `Range` requests can be answered from.
+///
+/// [`StaticFileServer`](crate::StaticFileServer) answers range requests
+/// through this trait, so the same `206`, `multipart/byteranges` and `416`
+/// handling works for files on disk, assets in memory and memory-mapped
+/// files alike; see [`StaticFileServer::with_source`](crate::StaticFileServer::with_source)
+/// for serving a source of your own.
+pub trait ServeRange: Send + Sync {
+    /// Returns the length of the source in bytes.
+    fn size(&self) -> u64;
+
+    /// Reads the bytes from `start` to `end`, both inclusive.
+    ///
+    /// # Errors
+    ///
+    /// Returns `HttpError::Io` if reading fails or the range extends past
+    /// the end of the source.
+    fn read_range(&self, start: u64, end: u64) -> Result<Vec<u8>, HttpError>;
+}
+
+impl ServeRange for [u8] {
+    fn size(&self) -> u64 {
+        self.len() as u64
+    }
+
+    fn read_range(&self, start: u64, end: u64) -> Result<Vec<u8>, HttpError> {
+        usize::try_from(start)
+            .ok()
+            .zip(usize::try_from(end).ok())
+            .and_then(|(start, end)| self.get(start..=end))
+            .map(<[u8]>::to_vec)
+            .ok_or_else(|| out_of_bounds().into())
+    }
+}
+
+impl ServeRange for Vec<u8> {
+    fn size(&self) -> u64 {
+        self.as_slice().size()
+    }
+
+    fn read_range(&self, start: u64, end: u64) -> Result<Vec<u8>, HttpError> {
+        self.as_slice().read_range(start, end)
+    }
+}
+
+impl<T: ServeRange + ?Sized> ServeRange for &T {
+    fn size(&self) -> u64 {
+        (**self).size()
+    }
+
+    fn read_range(&self, start: u64, end: u64) -> Result<Vec<u8>, HttpError> {
+        (**self).read_range(start, end)
+    }
+}
+
+// Reads at an offset instead of seeking, so one `File` can serve concurrent
+// requests without them moving each other's cursor
+impl ServeRange for File {
+    fn size(&self) -> u64 {
+        self.metadata()
+            .map(|metadata| metadata.len())
+            .unwrap_or_default()
+    }
+
+    fn read_range(&self, start: u64, end: u64) -> Result<Vec<u8>, HttpError> {
+        if end < start {
+            return Err(out_of_bounds().into());
+        }
+        let len = usize::try_from(end - start + 1).map_err(|_| out_of_bounds())?;
+        let mut buf = vec![0; len];
+        read_exact_at(self, &mut buf, start)?;
+        Ok(buf)
+    }
+}
+
+#[cfg(unix)]
+fn read_exact_at(file: &File, buf: &mut [u8], offset: u64) -> io::Result<()> {
+    std::os::unix::fs::FileExt::read_exact_at(file, buf, offset)
+}
+
+#[cfg(windows)]
+fn read_exact_at(file: &File, mut buf: &mut [u8], mut offset: u64) -> io::Result<()> {
+    use std::os::windows::fs::FileExt;
+
+    while !buf.is_empty() {
+        match file.seek_read(buf, offset)? {
+            0 => return Err(io::Error::from(io::ErrorKind::UnexpectedEof)),
+            read => {
+                buf = &mut buf[read..];
+                offset += read as u64;
+            }
+        }
+    }
+    Ok(())
+}
+
+/// A file mapped into memory, whose ranges are copied straight out of the mapping.
+///
+/// Large files that get many range requests (video, disk images) are read
+/// by the kernel only where they are actually requested, and concurrent
+/// requests share the page cache without a read per request.
+///
+/// The file must not be truncated while it is mapped: reading the missing
+/// pages would crash the process with `SIGBUS`. Only map files nothing else
+/// modifies while the server runs.
+#[cfg(feature = "mmap")]
+#[derive(Debug)]
+pub struct MmapFile {
+    map: memmap2::Mmap, // The file's contents, mapped read-only
+}
+
+#[cfg(feature = "mmap")]
+impl MmapFile {
+    /// Maps `file` read-only.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the file cannot be mapped, e.g. because it is not
+    /// a regular file.
+    pub fn new(file: &File) -> io::Result<MmapFile> {
+        // SAFETY: the mapping is read-only, and the type documents that the
+        // file must not be truncated while it is mapped
+        let map = unsafe { memmap2::Mmap::map(file)? };
+        Ok(MmapFile { map })
+    }
+
+    /// Opens and maps the file at `path`.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the file cannot be opened or mapped.
+    pub fn open(path: impl AsRef<std::path::Path>) -> io::Result<MmapFile> {
+        MmapFile::new(&File::open(path)?)
+    }
+}
+
+#[cfg(feature = "mmap")]
+impl ServeRange for MmapFile {
+    fn size(&self) -> u64 {
+        self.map[..].size()
+    }
+
+    fn read_range(&self, start: u64, end: u64) -> Result<Vec<u8>, HttpError> {
+        self.map[..].read_range(start, end)
+    }
+}
+
+// The error for a range that does not lie within the source
+fn out_of_bounds() -> io::Error {
+    io::Error::new(
+        io::ErrorKind::InvalidInput,
+        "range extends past the end of the source",
+    )
+}
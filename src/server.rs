@@ -0,0 +1,1002 @@
+use std::io::{self, BufRead, BufReader, Write};
+use std::net::{SocketAddr, TcpListener, TcpStream, ToSocketAddrs};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex, MutexGuard};
+use std::thread;
+use std::time::{Duration, Instant, SystemTime};
+
+use socket2::{Domain, Protocol, Socket, Type};
+
+use crate::access_log::{shared_access_log, CountingWriter};
+use crate::date::{current_http_date, format_rfc3339};
+use crate::hijack::hijack_buffered;
+use crate::retry::OVERLOAD_RETRY_SECONDS;
+use crate::template::error_page_html;
+use crate::{
+    tunnel, validate_content_type_matches_body, AdminHandler, BackpressureListener, ConnLimit,
+    Connection, ConnectionState, HttpError, HttpMethod, Plugin, PoolMetrics, Request,
+    RequestCounter, RequestLog, Response, RetryReason, Router, ServerConfig, StatusCode,
+    TcpKeepalive, ThreadPool, ThrottledWriter, TraceContext, UpgradeHandler,
+};
+
+// How often connections waiting for a worker are checked for clients that hung up
+const HANG_UP_CHECK_INTERVAL: Duration = Duration::from_millis(10);
+
+/// Builds a [`Server`] from an address, settings and a set of routes.
+pub struct ServerBuilder {
+    addr: String,         // The address to listen on, e.g. "127.0.0.1:7990"
+    threads: usize,       // The number of worker threads
+    max_queued: usize,    // Stop accepting once this many connections wait for a worker
+    config: ServerConfig, // The settings shared by every connection
+    router: Router,       // The routes registered so far
+}
+
+impl ServerBuilder {
+    /// Starts building a server that will listen on `addr`.
+    pub fn new(addr: &str) -> ServerBuilder {
+        ServerBuilder {
+            addr: addr.to_string(),
+            threads: 10,
+            max_queued: 64,
+            config: ServerConfig::default(),
+            router: Router::new(),
+        }
+    }
+
+    /// Sets the number of worker threads. Defaults to 10.
+    pub fn threads(mut self, threads: usize) -> ServerBuilder {
+        self.threads = threads;
+        self
+    }
+
+    /// Sets how many accepted connections may wait for a free worker. Defaults to 64.
+    ///
+    /// Past this, the server stops accepting and leaves new connections in the
+    /// OS accept backlog until the workers catch up.
+    pub fn max_queued(mut self, max_queued: usize) -> ServerBuilder {
+        self.max_queued = max_queued;
+        self
+    }
+
+    /// Replaces the default server settings.
+    pub fn config(mut self, config: ServerConfig) -> ServerBuilder {
+        self.config = config;
+        self
+    }
+
+    /// Registers the routes of `plugin`.
+    pub fn add_plugin(mut self, plugin: impl Plugin) -> ServerBuilder {
+        plugin.register(&mut self.router);
+        self
+    }
+
+    /// Registers routes directly on the router.
+    pub fn routes(mut self, register: impl FnOnce(&mut Router)) -> ServerBuilder {
+        register(&mut self.router);
+        self
+    }
+
+    /// Binds the listening socket and starts the worker threads.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the address cannot be bound, or the socket cannot
+    /// be restricted to `ServerConfig::bind_interface`.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the number of threads is zero.
+    pub fn build(self) -> io::Result<Server> {
+        let listener = bind(
+            &self.addr,
+            self.config.bind_backlog,
+            self.config.bind_interface.as_deref(),
+        )?;
+        let pool = ThreadPool::new(self.threads);
+        self.config.metrics.track_pool(pool.metrics_reader());
+        Ok(Server {
+            listener: BackpressureListener::new(listener, self.max_queued),
+            pool,
+            router: Arc::new(self.router),
+            config: Arc::new(self.config),
+        })
+    }
+}
+
+/// A running HTTP server, created by [`ServerBuilder::build`].
+pub struct Server {
+    listener: BackpressureListener, // The bound socket, paused while the pool is backed up
+    pool: ThreadPool,               // The workers connections are handed to
+    router: Arc<Router>,            // The routes, shared with every worker
+    config: Arc<ServerConfig>,      // The settings, shared with every worker
+}
+
+impl Server {
+    /// Returns the address the server is listening on.
+    pub fn local_addr(&self) -> io::Result<SocketAddr> {
+        self.listener.local_addr()
+    }
+
+    /// Returns the counter of in-flight requests, for draining them at shutdown.
+    pub fn requests(&self) -> RequestCounter {
+        self.config.requests.clone()
+    }
+
+    /// Returns how busy the worker threads are.
+    pub fn pool_metrics(&self) -> PoolMetrics {
+        self.pool.metrics()
+    }
+
+    /// Returns the counter of open connections.
+    pub fn conn_limit(&self) -> ConnLimit {
+        self.config.conn_limit.clone()
+    }
+
+    /// Accepts connections forever, handling each one on the thread pool.
+    ///
+    /// While `max_queued` connections are already waiting for a worker, no
+    /// new ones are accepted; see [`BackpressureListener`]. Connections past
+    /// `ServerConfig::max_connections` are answered with
+    /// `503 Service Unavailable` on the accepting thread and closed.
+    ///
+    /// A client that closes its connection while it waits for a worker has
+    /// its job cancelled, so the pool does not spend time on a response
+    /// nobody will read.
+    pub fn run(self) {
+        if cfg!(not(feature = "keepalive")) && self.config.tcp_keepalive.is_some() {
+            println!(
+                "TCP keepalive is configured but the `keepalive` feature is disabled; ignoring it"
+            );
+        }
+        if cfg!(not(feature = "crypto")) && self.config.validate_content_md5 {
+            println!(
+                "Content-MD5 validation is configured but the `crypto` feature is disabled; ignoring it"
+            );
+        }
+
+        let queued = Arc::new(QueuedConnections::default());
+        {
+            let queued = Arc::clone(&queued);
+            thread::spawn(move || loop {
+                queued.cancel_hung_up();
+                thread::sleep(HANG_UP_CHECK_INTERVAL);
+            });
+        }
+
+        loop {
+            let mut stream = match self.listener.accept(&self.pool) {
+                Ok(stream) => stream,
+                Err(err) => {
+                    println!("Failed to accept connection: {err}");
+                    continue;
+                }
+            };
+
+            let max_connections = self.config.max_connections.unwrap_or(usize::MAX);
+            let Some(conn_guard) = self.config.conn_limit.try_acquire(max_connections) else {
+                println!("Refusing connection: {max_connections} connections already open");
+                let mut response = Response::new(StatusCode::SERVICE_UNAVAILABLE);
+                add_error_page(&mut response, &self.config, "The server is at capacity.");
+                response.set_header("Connection", "close");
+                response.retry_after(RetryReason::Overloaded {
+                    estimated_seconds: OVERLOAD_RETRY_SECONDS,
+                });
+                let _ = response.write_to(&mut stream);
+                continue;
+            };
+
+            if let Some(keepalive) = &self.config.tcp_keepalive {
+                if let Err(err) = set_keepalive(&stream, keepalive) {
+                    println!("Failed to set TCP keepalive: {err}");
+                }
+            }
+            let recv_buf = self.config.socket_recv_buf.unwrap_or(0);
+            let send_buf = self.config.socket_send_buf.unwrap_or(0);
+            if let Err(err) = set_socket_buffer_sizes(&stream, recv_buf, send_buf) {
+                println!("Failed to set socket buffer sizes: {err}");
+            }
+
+            let router = Arc::clone(&self.router);
+            let config = Arc::clone(&self.config);
+            let Ok(watched) = stream.try_clone() else {
+                self.pool.execute(move || {
+                    let _conn_guard = conn_guard;
+                    handle_connection(stream, &router, &config);
+                });
+                continue;
+            };
+            let watch = Arc::new(Mutex::new(Some(watched)));
+            let cancel_token = {
+                let watch = Arc::clone(&watch);
+                self.pool.execute_cancellable(move || {
+                    // Stop the hang-up checks before reading from the stream
+                    drop(lock_watch(&watch).take());
+                    let _conn_guard = conn_guard;
+                    handle_connection(stream, &router, &config);
+                })
+            };
+            queued.watch(watch, cancel_token);
+        }
+    }
+}
+
+// The connections waiting in the pool queue, with the tokens that cancel their jobs
+#[derive(Default)]
+struct QueuedConnections {
+    waiting: Mutex<Vec<QueuedConnection>>, // Connections whose job has not started yet
+}
+
+// A connection waiting for a worker
+struct QueuedConnection {
+    watch: Arc<Mutex<Option<TcpStream>>>, // A clone of the stream, taken by the job when it starts
+    cancel_token: Arc<AtomicBool>,        // Set to drop the job instead of running it
+}
+
+impl QueuedConnections {
+    // Starts checking a queued connection for a client that hangs up
+    fn watch(&self, watch: Arc<Mutex<Option<TcpStream>>>, cancel_token: Arc<AtomicBool>) {
+        self.lock().push(QueuedConnection {
+            watch,
+            cancel_token,
+        });
+    }
+
+    // Cancels the jobs of clients that hung up, and forgets the jobs that have started
+    fn cancel_hung_up(&self) {
+        self.lock().retain(|queued| {
+            let mut watch = lock_watch(&queued.watch);
+            let Some(stream) = watch.as_ref() else {
+                return false;
+            };
+            if !hung_up(stream) {
+                return true;
+            }
+            match stream.peer_addr() {
+                Ok(addr) => println!("Client {addr} hung up while queued; cancelling its job"),
+                Err(_) => println!("Client hung up while queued; cancelling its job"),
+            }
+            queued.cancel_token.store(true, Ordering::SeqCst);
+            watch.take();
+            false
+        });
+    }
+
+    // Locks the list. Pushes and retains cannot leave it half changed, so poisoning is ignored
+    fn lock(&self) -> MutexGuard<'_, Vec<QueuedConnection>> {
+        self.waiting
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner())
+    }
+}
+
+// Locks the stream clone a queued job and the hang-up checks share
+fn lock_watch(watch: &Mutex<Option<TcpStream>>) -> MutexGuard<'_, Option<TcpStream>> {
+    watch
+        .lock()
+        .unwrap_or_else(|poisoned| poisoned.into_inner())
+}
+
+// Whether the client closed the connection: a peek that does not block
+// reads zero bytes. A client that sent its request first leaves data to
+// peek at, so only connections with nothing to serve are given up on.
+// The stream is blocking again before the job can take it, since both
+// sides hold the watch lock
+fn hung_up(stream: &TcpStream) -> bool {
+    if stream.set_nonblocking(true).is_err() {
+        return false;
+    }
+    let closed = matches!(stream.peek(&mut [0; 1]), Ok(0));
+    stream.set_nonblocking(false).is_ok() && closed
+}
+
+// Binds a listening socket to the first address `addr` resolves to that
+// works, with a listen backlog of `backlog`
+fn bind(addr: &str, backlog: i32, interface: Option<&str>) -> io::Result<TcpListener> {
+    let mut last_err = None;
+    for addr in addr.to_socket_addrs()? {
+        let socket = Socket::new(Domain::for_address(addr), Type::STREAM, Some(Protocol::TCP))?;
+        // Like TcpListener::bind, so a restarted server can rebind right away
+        #[cfg(not(windows))]
+        socket.set_reuse_address(true)?;
+        if let Some(interface) = interface {
+            bind_to_interface(&socket, addr, interface).map_err(|err| {
+                io::Error::new(
+                    err.kind(),
+                    format!("could not bind to interface {interface}: {err}"),
+                )
+            })?;
+        }
+        match socket
+            .bind(&addr.into())
+            .and_then(|()| socket.listen(backlog))
+        {
+            Ok(()) => return Ok(socket.into()),
+            Err(err) => last_err = Some(err),
+        }
+    }
+    Err(last_err.unwrap_or_else(|| {
+        io::Error::new(
+            io::ErrorKind::InvalidInput,
+            "could not resolve to any addresses",
+        )
+    }))
+}
+
+// Restricts `socket` to the network interface named `interface` (SO_BINDTODEVICE)
+#[cfg(any(target_os = "linux", target_os = "android"))]
+fn bind_to_interface(socket: &Socket, _addr: SocketAddr, interface: &str) -> io::Result<()> {
+    socket.bind_device(Some(interface.as_bytes()))
+}
+
+// Restricts `socket` to the network interface named `interface` (IP_BOUND_IF)
+#[cfg(target_os = "macos")]
+fn bind_to_interface(socket: &Socket, addr: SocketAddr, interface: &str) -> io::Result<()> {
+    let name = std::ffi::CString::new(interface)
+        .map_err(|_| io::Error::new(io::ErrorKind::InvalidInput, "interface name contains NUL"))?;
+    // SAFETY: `name` is a valid NUL-terminated string for the duration of the call
+    let index = unsafe { libc::if_nametoindex(name.as_ptr()) };
+    let Some(index) = std::num::NonZeroU32::new(index) else {
+        return Err(io::Error::last_os_error());
+    };
+    match addr {
+        SocketAddr::V4(_) => socket.bind_device_by_index_v4(Some(index)),
+        SocketAddr::V6(_) => socket.bind_device_by_index_v6(Some(index)),
+    }
+}
+
+// Elsewhere there is no way to bind to an interface, so refuse instead of listening on all of them
+#[cfg(not(any(target_os = "linux", target_os = "android", target_os = "macos")))]
+fn bind_to_interface(_socket: &Socket, _addr: SocketAddr, _interface: &str) -> io::Result<()> {
+    Err(io::Error::new(
+        io::ErrorKind::Unsupported,
+        "binding to an interface is only supported on Linux and macOS",
+    ))
+}
+
+/// Sets the kernel receive (`SO_RCVBUF`) and send (`SO_SNDBUF`) buffer sizes of `stream`.
+///
+/// Linux doubles the requested sizes to leave room for its bookkeeping,
+/// so reading them back gives twice the value set, and caps them at
+/// `net.core.rmem_max` and `net.core.wmem_max`. Setting a size also turns
+/// off the kernel's automatic tuning of that buffer.
+///
+/// # Arguments
+///
+/// * `stream` - The connection to configure.
+/// * `recv_buf` - The receive buffer size in bytes; `0` leaves it unchanged.
+/// * `send_buf` - The send buffer size in bytes; `0` leaves it unchanged.
+///
+/// # Errors
+///
+/// Returns `HttpError::Io` if the OS rejects either size.
+pub fn set_socket_buffer_sizes(
+    stream: &TcpStream,
+    recv_buf: usize,
+    send_buf: usize,
+) -> Result<(), HttpError> {
+    let socket = socket2::SockRef::from(stream);
+    if recv_buf > 0 {
+        socket.set_recv_buffer_size(recv_buf)?;
+    }
+    if send_buf > 0 {
+        socket.set_send_buffer_size(send_buf)?;
+    }
+    Ok(())
+}
+
+// Enables TCP keepalive on `stream` with the configured timings
+#[cfg(feature = "keepalive")]
+fn set_keepalive(stream: &TcpStream, keepalive: &TcpKeepalive) -> io::Result<()> {
+    let params = socket2::TcpKeepalive::new()
+        .with_time(keepalive.idle)
+        .with_interval(keepalive.interval);
+    #[cfg(not(windows))]
+    let params = params.with_retries(keepalive.retries);
+
+    socket2::SockRef::from(stream).set_tcp_keepalive(&params)
+}
+
+// Keepalive is only applied with the `keepalive` feature, so do nothing
+#[cfg(not(feature = "keepalive"))]
+fn set_keepalive(_stream: &TcpStream, _keepalive: &TcpKeepalive) -> io::Result<()> {
+    Ok(())
+}
+
+/// Serves the requests sent on `stream`, dispatching each one and writing its response.
+///
+/// The stream is consumed. After each response it is kept open for the
+/// client's next request if the client asked for that (see
+/// [`Request::is_keep_alive`]) and `ServerConfig::keep_alive_timeout` is
+/// set; otherwise the response carries `Connection: close` and the stream
+/// is closed once it is written. A tunnel, an upgrade or a
+/// [`Response::hijack`] response takes the stream over instead.
+/// Responses from the router get a `Date` header from a shared
+/// [`DateCache`](crate::DateCache) unless they set one.
+///
+/// # Arguments
+///
+/// * `stream` - The connection to serve, e.g. a `TcpStream` or `MockStream`.
+/// * `router` - The routes to dispatch the requests to.
+/// * `config` - The server settings applied while reading the requests.
+pub fn handle_connection<S>(mut stream: S, router: &Router, config: &ServerConfig)
+where
+    S: Connection,
+{
+    let connection = config.connections.register(stream.peer_addr());
+    // Lives as long as the connection, for every request read from it
+    let state = Arc::new(Mutex::new(ConnectionState::new()));
+
+    // Kept across requests, so bytes the client sent ahead are not lost
+    let mut reader = match stream.try_clone() {
+        Ok(reader) => BufReader::new(reader),
+        Err(err) => {
+            println!("Failed to clone stream: {err}");
+            return;
+        }
+    };
+
+    let mut first = true;
+    loop {
+        if !first && !wait_for_request(&stream, &mut reader, config.keep_alive_timeout) {
+            return;
+        }
+        first = false;
+
+        let started = Instant::now();
+        let _request_guard = config.requests.guard();
+        let mut request = match Request::next_from_connection(&mut reader, config) {
+            Ok(request) => request,
+            Err(err) => {
+                println!("Rejecting request: {err}");
+                let mut response = Response::new(err.status());
+                add_error_page(&mut response, config, &err.to_string());
+                response.set_header("Connection", "close");
+                let _ = response.write_to(&mut stream);
+                return;
+            }
+        };
+
+        request.remote_addr = stream.peer_addr();
+        request.set_connection_state(Arc::clone(&state));
+        let keep_alive = config.keep_alive_timeout.is_some() && request.is_keep_alive();
+        let version = request.version.clone();
+
+        #[cfg(feature = "crypto")]
+        if config.validate_content_md5 {
+            if let Err(err) = crate::validate_content_md5(&request.headers, &request.body) {
+                println!("Rejecting request to {}: {err}", request.path);
+                let mut response = Response::new(err.status());
+                add_error_page(&mut response, config, &err.to_string());
+                response.set_header("Connection", "close");
+                let _ = response.write_to(&mut stream);
+                return;
+            }
+        }
+
+        if let Some(mut response) = AdminHandler::handle(&request, config) {
+            let keep_alive = set_connection_header(&mut response, keep_alive, &version);
+            let written = response.write_to(&mut stream);
+            connection.request_served();
+            if let Err(err) = written {
+                println!("Failed to write response: {err}");
+                return;
+            }
+            if !keep_alive {
+                return;
+            }
+            continue;
+        }
+
+        if config.allow_trace && request.method == HttpMethod::Trace.as_str() {
+            let mut response = trace_echo(&request);
+            let keep_alive = set_connection_header(&mut response, keep_alive, &version);
+            let written = response.write_to(&mut stream);
+            connection.request_served();
+            if let Err(err) = written {
+                println!("Failed to write response: {err}");
+                return;
+            }
+            if !keep_alive {
+                return;
+            }
+            continue;
+        }
+
+        // The client may have sent more than the request, e.g. a TLS
+        // ClientHello or a first WebSocket frame; it goes to whoever takes over
+        if let Some(target) = router.connect_target(&request) {
+            open_tunnel(stream, reader.buffer().to_vec(), target);
+            return;
+        }
+
+        if let Some(handler) = router.upgrade_handler(&request) {
+            switch_protocols(stream, reader.buffer().to_vec(), request, handler);
+            return;
+        }
+
+        // Continue the caller's trace if it sent one, otherwise start a new one
+        let trace = TraceContext::from_request(&request)
+            .map(|parent| parent.new_child())
+            .unwrap_or_else(TraceContext::new_root);
+        let _trace_guard = trace.clone().enter();
+
+        let is_head = request.method == HttpMethod::Head.as_str();
+        let (method, path) = (request.method.clone(), request.path.clone());
+        let remote_ip = request.remote_addr.map(|addr| addr.ip());
+        let mut response = router.handle_with_cors(request, config.cors_config.as_ref());
+        if config.debug_mode {
+            if let Err(mismatch) = validate_content_type_matches_body(&response) {
+                println!("Response does not match its Content-Type: {mismatch}");
+                response = Response::new(StatusCode::INTERNAL_SERVER_ERROR);
+                let message = format!("The response does not match its Content-Type: {mismatch}");
+                add_error_page(&mut response, config, &message);
+            }
+        }
+        let bodiless = response.body.is_empty()
+            && !response.is_streamed()
+            && !response.is_hijacked()
+            && !response.headers.contains("Content-Type");
+        if response.status.as_u16() >= 400 && bodiless {
+            add_error_page(&mut response, config, "");
+        }
+        if !response.headers.contains("Date") {
+            response.set_header("Date", &current_http_date());
+        }
+        trace.inject(&mut response);
+        if response.is_hijacked() {
+            connection.request_served();
+            hand_over(stream, reader.buffer().to_vec(), response);
+            return;
+        }
+        let keep_alive = set_connection_header(&mut response, keep_alive, &version);
+        let status = response.status.as_u16();
+        let mut sent = 0;
+        let written = if let Some(rate) = config.max_bytes_per_sec_per_conn {
+            let mut throttled =
+                ThrottledWriter::new(CountingWriter::new(&mut stream, &mut sent), rate);
+            if is_head {
+                response.write_head_to(&mut throttled)
+            } else {
+                response.write_to(&mut throttled)
+            }
+        } else if is_head {
+            response.write_head_to(&mut CountingWriter::new(&mut stream, &mut sent))
+        } else if let Some(socket) = stream.as_tcp_stream() {
+            response.write_to_socket(socket).map(|count| sent = count)
+        } else {
+            response.write_to(&mut CountingWriter::new(&mut stream, &mut sent))
+        };
+        connection.request_served();
+
+        if let Some(log_path) = &config.json_access_log {
+            let record = RequestLog {
+                timestamp: format_rfc3339(SystemTime::now()),
+                method,
+                path,
+                status,
+                bytes_sent: sent as usize,
+                duration_ms: started.elapsed().as_millis() as u64,
+                remote_ip: remote_ip.map(|ip| ip.to_string()).unwrap_or_default(),
+                request_id: format!("{:032x}", trace.trace_id),
+            };
+            let logged = shared_access_log(log_path).and_then(|log| log.write(&record));
+            if let Err(err) = logged {
+                println!("Failed to write access log {}: {err}", log_path.display());
+            }
+        }
+
+        if let Err(err) = written {
+            println!("Failed to write response: {err}");
+            return;
+        }
+        if !keep_alive {
+            return;
+        }
+    }
+}
+
+// Decides whether the connection stays open after `response`, the answer
+// to a request of `version` whose client wants it kept open when
+// `keep_alive` is set, and says so in the response's `Connection` header.
+// A response that closes the connection itself, or one an HTTP/1.0 client
+// can only read to the end of the stream, closes it.
+fn set_connection_header(response: &mut Response, keep_alive: bool, version: &str) -> bool {
+    let closes = response
+        .headers
+        .connection_tokens()
+        .iter()
+        .any(|token| token == "close");
+    let http_1_0 = version == "HTTP/1.0";
+    let framed = !http_1_0
+        || response
+            .transfer_encoding()
+            .is_ok_and(|codings| codings.is_empty());
+    let keep_alive = keep_alive && !closes && framed;
+    if !keep_alive && !closes {
+        response.headers.append("Connection", "close");
+    } else if keep_alive && http_1_0 {
+        response.headers.append("Connection", "keep-alive");
+    }
+    keep_alive
+}
+
+// Waits for the client to start its next request on a kept-alive
+// connection, for at most `timeout` on a TCP socket. Returns `false` if
+// the client closed the connection or sent nothing in time.
+fn wait_for_request<S: Connection>(
+    stream: &S,
+    reader: &mut BufReader<S>,
+    timeout: Option<Duration>,
+) -> bool {
+    let Some(socket) = stream.as_tcp_stream() else {
+        return reader.fill_buf().is_ok_and(|buf| !buf.is_empty());
+    };
+    // The reader is a clone of the socket, so it shares the timeout
+    let previous = socket.read_timeout().ok().flatten();
+    if socket.set_read_timeout(timeout).is_err() {
+        return false;
+    }
+    let arrived = reader.fill_buf().is_ok_and(|buf| !buf.is_empty());
+    arrived && socket.set_read_timeout(previous).is_ok()
+}
+
+// Gives `response` the error page for its status as its body, describing the error with `message`
+fn add_error_page(response: &mut Response, config: &ServerConfig, message: &str) {
+    let template = config.error_page_template.as_deref();
+    let page = error_page_html(template, response.status, message);
+    response.set_header("Content-Type", "text/html; charset=utf-8");
+    response.body = page.into_bytes();
+}
+
+// Echoes the request line and headers of a TRACE request, minus credentials
+fn trace_echo(request: &Request) -> Response {
+    let mut echo = format!(
+        "{} {} {}\r\n",
+        request.method, request.path, request.version
+    );
+    for (name, value) in request.headers.iter() {
+        if !name.eq_ignore_ascii_case("Cookie") && !name.eq_ignore_ascii_case("Authorization") {
+            echo.push_str(&format!("{name}: {value}\r\n"));
+        }
+    }
+    echo.push_str("\r\n");
+    Response::with_body(StatusCode::OK, "message/http", echo.into_bytes())
+}
+
+// Sends the head of a hijacking response and hands the socket, with the
+// bytes the client sent past the request, to its closure
+fn hand_over<S: Connection>(stream: S, buffered: Vec<u8>, response: Response) {
+    match stream.into_tcp_stream() {
+        Ok(socket) => {
+            if let Err(err) = response.write_hijacked(socket, buffered) {
+                println!("Failed to write response: {err}");
+            }
+        }
+        Err(mut stream) => {
+            println!("Cannot hijack a connection that is not a TCP socket");
+            let _ = Response::new(StatusCode::NOT_IMPLEMENTED).write_to(&mut stream);
+        }
+    }
+}
+
+// Sends the handshake response of an upgrade and hands the socket, with
+// the bytes the client sent past the request, to the handler
+fn switch_protocols<S: Connection>(
+    stream: S,
+    buffered: Vec<u8>,
+    request: Request,
+    handler: &dyn UpgradeHandler,
+) {
+    let mut stream = match stream.into_tcp_stream() {
+        Ok(stream) => stream,
+        Err(mut stream) => {
+            println!("Cannot upgrade a connection that is not a TCP socket");
+            let _ = Response::new(StatusCode::NOT_IMPLEMENTED).write_to(&mut stream);
+            return;
+        }
+    };
+
+    let response = handler.handshake(&request);
+    let switching = response.status == StatusCode::SWITCHING_PROTOCOLS;
+    if let Err(err) = response.write_to(&mut stream) {
+        println!("Failed to write response: {err}");
+        return;
+    }
+    if switching {
+        handler.upgraded(request, hijack_buffered(stream, buffered));
+    }
+}
+
+// Answers a CONNECT request and relays bytes once the upstream connection
+// is open, starting with those the client sent past the request
+fn open_tunnel<S>(mut stream: S, buffered: Vec<u8>, target: io::Result<TcpStream>)
+where
+    S: Connection,
+{
+    let mut target = match target {
+        Ok(target) => target,
+        Err(err) => {
+            println!("Failed to open tunnel: {err}");
+            let _ = Response::new(StatusCode::BAD_GATEWAY).write_to(&mut stream);
+            return;
+        }
+    };
+
+    // Written by hand because the reason phrase differs from the usual "OK"
+    if let Err(err) = stream.write_all(b"HTTP/1.1 200 Connection established\r\n\r\n") {
+        println!("Failed to write response: {err}");
+        return;
+    }
+    if let Err(err) = target.write_all(&buffered) {
+        println!("Failed to write to tunnel: {err}");
+        return;
+    }
+    if let Err(err) = tunnel(stream, target) {
+        println!("Tunnel closed with an error: {err}");
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::net::IpAddr;
+
+    use std::io::Read;
+
+    use crate::{CorsConfig, HijackedStream, MockStream, UpgradeHandler};
+
+    // Serves `input` as one connection and returns everything written back
+    fn serve(input: &str, router: &Router, config: &ServerConfig) -> String {
+        let stream = MockStream::new(input);
+        handle_connection(stream.clone(), router, config);
+        String::from_utf8(stream.into_written_bytes()).unwrap()
+    }
+
+    fn hello_router() -> Router {
+        let mut router = Router::new();
+        router.get("/", |_| {
+            Response::with_body(StatusCode::OK, "text/plain", b"hello".to_vec())
+        });
+        router
+    }
+
+    #[test]
+    fn answers_options_star_with_every_method() {
+        let mut router = hello_router();
+        router.post("/items", |_| Response::new(StatusCode::CREATED));
+
+        let input = "OPTIONS * HTTP/1.1\r\nHost: a\r\nConnection: close\r\n\r\n";
+        let written = serve(input, &router, &ServerConfig::default());
+        assert!(written.starts_with("HTTP/1.1 204 No Content\r\n"));
+        assert!(written.contains("\r\nAllow: GET, POST, OPTIONS\r\n"));
+    }
+
+    #[test]
+    fn echoes_trace_requests_without_credentials() {
+        let input = "TRACE /debug?x=1 HTTP/1.1\r\nHost: a\r\nCookie: session=secret\r\n\
+                     Authorization: Bearer secret\r\nX-Probe: 1\r\nConnection: close\r\n\r\n";
+        let written = serve(input, &hello_router(), &ServerConfig::default());
+        let (head, body) = written.split_once("\r\n\r\n").unwrap();
+        assert!(head.starts_with("HTTP/1.1 200 OK\r\n"));
+        assert!(head.contains("\r\nContent-Type: message/http\r\n"));
+        assert_eq!(
+            body,
+            "TRACE /debug?x=1 HTTP/1.1\r\nHost: a\r\nX-Probe: 1\r\nConnection: close\r\n\r\n"
+        );
+    }
+
+    #[test]
+    fn routes_trace_when_disabled() {
+        let config = ServerConfig {
+            allow_trace: false,
+            ..ServerConfig::default()
+        };
+        let input = "TRACE / HTTP/1.1\r\nConnection: close\r\n\r\n";
+        let written = serve(input, &hello_router(), &config);
+        assert!(written.starts_with("HTTP/1.1 404 Not Found\r\n"));
+        assert!(!written.contains("message/http"));
+    }
+
+    #[test]
+    fn checks_acls_before_answering_preflights() {
+        let mut router = hello_router();
+        router.get("/admin/users", |_| Response::new(StatusCode::OK));
+        router.restrict("/admin", vec![IpAddr::from([127, 0, 0, 1])]);
+        let config = ServerConfig {
+            cors_config: Some(CorsConfig::default()),
+            ..ServerConfig::default()
+        };
+        let preflight = |path: &str| {
+            format!(
+                "OPTIONS {path} HTTP/1.1\r\nOrigin: https://app.example.com\r\n\
+                 Access-Control-Request-Method: GET\r\nConnection: close\r\n\r\n"
+            )
+        };
+
+        // The mock stream has no client address, so the ACL refuses it
+        let written = serve(&preflight("/admin/users"), &router, &config);
+        assert!(written.starts_with("HTTP/1.1 403 Forbidden\r\n"));
+        assert!(!written.contains("Access-Control-Allow-Origin"));
+
+        let written = serve(&preflight("/"), &router, &config);
+        assert!(written.starts_with("HTTP/1.1 204 No Content\r\n"));
+        assert!(written.contains("\r\nAccess-Control-Allow-Origin: "));
+    }
+
+    #[test]
+    fn relays_bytes_pipelined_behind_connect() {
+        let upstream = TcpListener::bind("127.0.0.1:0").unwrap();
+        let target = upstream.local_addr().unwrap().to_string();
+        let received = thread::spawn(move || {
+            let (mut socket, _) = upstream.accept().unwrap();
+            socket.write_all(b"server hello").unwrap();
+            let mut received = Vec::new();
+            socket.read_to_end(&mut received).unwrap();
+            received
+        });
+
+        let mut router = Router::new();
+        router.connect("*", |target| TcpStream::connect(target));
+        let input = format!("CONNECT {target} HTTP/1.1\r\nHost: {target}\r\n\r\nclient hello");
+        let written = serve(&input, &router, &ServerConfig::default());
+        assert_eq!(
+            written,
+            "HTTP/1.1 200 Connection established\r\n\r\nserver hello"
+        );
+        assert_eq!(received.join().unwrap(), b"client hello");
+    }
+
+    // Serves one connection on a loopback socket, sends `input` in one write
+    // and returns everything written back until the server closes it
+    fn serve_socket(input: &str, router: Router) -> String {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let mut client = TcpStream::connect(listener.local_addr().unwrap()).unwrap();
+        let server = thread::spawn(move || {
+            let (socket, _) = listener.accept().unwrap();
+            handle_connection(socket, &router, &ServerConfig::default());
+        });
+        client.write_all(input.as_bytes()).unwrap();
+        client.shutdown(std::net::Shutdown::Write).unwrap();
+        let mut written = String::new();
+        client.read_to_string(&mut written).unwrap();
+        server.join().unwrap();
+        written
+    }
+
+    // Answers an upgrade by echoing back what the client sent after it
+    struct Echo;
+
+    impl UpgradeHandler for Echo {
+        fn upgraded(&self, _req: Request, mut stream: HijackedStream) {
+            let mut received = Vec::new();
+            stream.read_to_end(&mut received).unwrap();
+            stream.write_all(&received).unwrap();
+        }
+    }
+
+    #[test]
+    fn hands_bytes_pipelined_behind_an_upgrade_to_the_handler() {
+        let mut router = Router::new();
+        router.upgrade("echo", Echo);
+        let input = "GET / HTTP/1.1\r\nConnection: Upgrade\r\nUpgrade: echo\r\n\r\nfirst frame";
+        let written = serve_socket(input, router);
+        assert!(written.starts_with("HTTP/1.1 101 Switching Protocols\r\n"));
+        assert!(written.ends_with("\r\n\r\nfirst frame"), "{written:?}");
+    }
+
+    #[test]
+    fn hands_bytes_pipelined_behind_a_hijacking_request_to_its_closure() {
+        let mut router = Router::new();
+        router.get("/raw", |_| {
+            Response::hijack(StatusCode::OK, |mut stream| {
+                let mut received = Vec::new();
+                stream.read_to_end(&mut received).unwrap();
+                stream.write_all(&received).unwrap();
+            })
+        });
+        let written = serve_socket("GET /raw HTTP/1.1\r\n\r\nraw bytes", router);
+        assert!(written.starts_with("HTTP/1.1 200 OK\r\n"));
+        assert!(written.ends_with("\r\n\r\nraw bytes"), "{written:?}");
+    }
+
+    #[test]
+    fn counts_admin_responses_as_served() {
+        let config = ServerConfig {
+            admin_path: Some("/admin".to_string()),
+            ..ServerConfig::default()
+        };
+        let request = "GET /admin/connections HTTP/1.1\r\n\r\n";
+        let stream =
+            MockStream::new(request.repeat(3)).with_peer_addr(([127, 0, 0, 1], 4000).into());
+        handle_connection(stream.clone(), &hello_router(), &config);
+        let written = String::from_utf8(stream.into_written_bytes()).unwrap();
+
+        for served in 0..3 {
+            assert!(
+                written.contains(&format!("\"requests_served\":{served}}}")),
+                "{written}"
+            );
+        }
+    }
+
+    #[test]
+    fn serves_pipelined_requests_on_one_connection() {
+        let input = "GET / HTTP/1.1\r\nHost: a\r\n\r\nGET / HTTP/1.1\r\nHost: a\r\n\r\n";
+        let written = serve(input, &hello_router(), &ServerConfig::default());
+        assert_eq!(written.matches("HTTP/1.1 200 OK\r\n").count(), 2);
+        assert!(!written.contains("Connection: close"));
+    }
+
+    #[test]
+    fn shares_connection_state_between_requests() {
+        // The number of requests seen so far on the connection
+        struct Seen(u32);
+
+        let mut router = Router::new();
+        router.get("/", |req| {
+            let mut state = req.connection_state();
+            let seen = match state.get_mut::<Seen>() {
+                Some(seen) => {
+                    seen.0 += 1;
+                    seen.0
+                }
+                None => {
+                    state.set(Seen(1));
+                    1
+                }
+            };
+            Response::with_body(StatusCode::OK, "text/plain", seen.to_string().into_bytes())
+        });
+
+        let input = "GET / HTTP/1.1\r\n\r\nGET / HTTP/1.1\r\n\r\n";
+        let written = serve(input, &router, &ServerConfig::default());
+        let bodies: Vec<&str> = written
+            .split("HTTP/1.1 ")
+            .skip(1)
+            .filter_map(|response| response.split("\r\n\r\n").nth(1))
+            .collect();
+        assert_eq!(bodies, ["1", "2"]);
+
+        // Every connection gets a store of its own
+        assert!(serve(input, &router, &ServerConfig::default()).ends_with("\r\n\r\n2"));
+    }
+
+    #[test]
+    fn closes_when_the_client_asks() {
+        let input = "GET / HTTP/1.1\r\nConnection: Close\r\n\r\nGET / HTTP/1.1\r\n\r\n";
+        let written = serve(input, &hello_router(), &ServerConfig::default());
+        assert_eq!(written.matches("HTTP/1.1 200 OK\r\n").count(), 1);
+        assert!(written.contains("Connection: close\r\n"));
+    }
+
+    #[test]
+    fn keeps_http_1_0_open_only_on_request() {
+        let router = hello_router();
+        let config = ServerConfig::default();
+
+        let written = serve(
+            "GET / HTTP/1.0\r\n\r\nGET / HTTP/1.0\r\n\r\n",
+            &router,
+            &config,
+        );
+        assert_eq!(written.matches("200 OK").count(), 1);
+        assert!(written.contains("Connection: close\r\n"));
+
+        let input = "GET / HTTP/1.0\r\nConnection: keep-alive\r\n\r\nGET / HTTP/1.0\r\n\r\n";
+        let written = serve(input, &router, &config);
+        assert_eq!(written.matches("200 OK").count(), 2);
+        assert!(written.contains("Connection: keep-alive\r\n"));
+    }
+
+    #[test]
+    fn closes_after_one_response_without_a_keep_alive_timeout() {
+        let config = ServerConfig {
+            keep_alive_timeout: None,
+            ..ServerConfig::default()
+        };
+        let input = "GET / HTTP/1.1\r\n\r\nGET / HTTP/1.1\r\n\r\n";
+        let written = serve(input, &hello_router(), &config);
+        assert_eq!(written.matches("200 OK").count(), 1);
+        assert!(written.contains("Connection: close\r\n"));
+    }
+}
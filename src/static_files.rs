@@ -0,0 +1,591 @@
+use std::collections::hash_map::DefaultHasher;
+use std::collections::HashMap;
+use std::fs::{self, File};
+use std::hash::{Hash, Hasher};
+use std::io;
+use std::path::{Path, PathBuf};
+use std::time::{Duration, SystemTime};
+
+use crate::range::range_response;
+use crate::{
+    detect_path_traversal, format_http_date, Request, Response, ServeRange, StatusCode, WeakCache,
+};
+
+// Long enough to count as "forever" for fingerprinted assets
+const ONE_YEAR: Duration = Duration::from_secs(31_536_000);
+
+// How much of a file `detect_binary_body` looks at
+const BINARY_SNIFF_LEN: usize = 512;
+
+// The signatures of binary formats that must never be served as text: PNG, PDF, ELF and ZIP
+const BINARY_SIGNATURES: [&[u8]; 4] = [b"\x89PNG\r\n\x1a\n", b"%PDF-", b"\x7fELF", b"PK\x03\x04"];
+
+/// How long clients and caches may reuse a response without revalidating it.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct CachePolicy {
+    /// How long the response stays fresh.
+    pub max_age: Duration,
+    /// Whether the response will never change while fresh, so clients can
+    /// skip revalidation even on reload.
+    pub immutable: bool,
+    /// Whether every use of a stored copy must be revalidated first.
+    pub no_cache: bool,
+}
+
+impl CachePolicy {
+    /// Fresh for `max_age`, then revalidated.
+    pub fn max_age(max_age: Duration) -> CachePolicy {
+        CachePolicy {
+            max_age,
+            immutable: false,
+            no_cache: false,
+        }
+    }
+
+    /// Fresh for `max_age` and never revalidated while fresh.
+    pub fn immutable(max_age: Duration) -> CachePolicy {
+        CachePolicy {
+            immutable: true,
+            ..CachePolicy::max_age(max_age)
+        }
+    }
+
+    /// Stored, but revalidated before every use.
+    pub fn no_cache() -> CachePolicy {
+        CachePolicy {
+            no_cache: true,
+            ..CachePolicy::max_age(Duration::ZERO)
+        }
+    }
+
+    /// Returns the `Cache-Control` header value for this policy.
+    pub fn header_value(&self) -> String {
+        if self.no_cache {
+            return "no-cache".to_string();
+        }
+        let mut value = format!("max-age={}", self.max_age.as_secs());
+        if self.immutable {
+            value.push_str(", immutable");
+        }
+        value
+    }
+}
+
+/// The cache policy for each file extension, built by [`CachePolicyBuilder`].
+#[derive(Debug, Clone)]
+pub struct CachePolicies {
+    by_extension: HashMap<String, CachePolicy>, // Keyed by lowercase extension
+    fallback: CachePolicy,                      // Used for unlisted extensions
+}
+
+impl CachePolicies {
+    /// Returns the policy for `extension` (without the leading dot).
+    pub fn get(&self, extension: &str) -> CachePolicy {
+        self.by_extension
+            .get(&extension.to_ascii_lowercase())
+            .copied()
+            .unwrap_or(self.fallback)
+    }
+}
+
+impl Default for CachePolicies {
+    fn default() -> CachePolicies {
+        CachePolicyBuilder::new().build()
+    }
+}
+
+/// Builds the per-extension [`CachePolicies`] of a [`StaticFileServer`].
+///
+/// Starts from the built-in defaults: scripts, stylesheets, fonts and images
+/// are cached for a year as `immutable`, while HTML and anything unlisted is
+/// `no-cache`.
+pub struct CachePolicyBuilder {
+    policies: CachePolicies,
+}
+
+impl CachePolicyBuilder {
+    /// Starts from the built-in defaults.
+    pub fn new() -> CachePolicyBuilder {
+        let mut by_extension = HashMap::new();
+        for extension in [
+            "js", "mjs", "css", "woff", "woff2", "ttf", "otf", "png", "jpg", "jpeg", "gif", "svg",
+            "webp", "ico",
+        ] {
+            by_extension.insert(extension.to_string(), CachePolicy::immutable(ONE_YEAR));
+        }
+        for extension in ["html", "htm"] {
+            by_extension.insert(extension.to_string(), CachePolicy::no_cache());
+        }
+
+        CachePolicyBuilder {
+            policies: CachePolicies {
+                by_extension,
+                fallback: CachePolicy::no_cache(),
+            },
+        }
+    }
+
+    /// Sets the policy for files with `extension` (without the leading dot).
+    pub fn extension(mut self, extension: &str, policy: CachePolicy) -> CachePolicyBuilder {
+        self.policies
+            .by_extension
+            .insert(extension.to_ascii_lowercase(), policy);
+        self
+    }
+
+    /// Sets the policy for extensions that have no policy of their own.
+    pub fn fallback(mut self, policy: CachePolicy) -> CachePolicyBuilder {
+        self.policies.fallback = policy;
+        self
+    }
+
+    /// Finishes building the policies.
+    pub fn build(self) -> CachePolicies {
+        self.policies
+    }
+}
+
+impl Default for CachePolicyBuilder {
+    fn default() -> CachePolicyBuilder {
+        CachePolicyBuilder::new()
+    }
+}
+
+/// Serves files from a directory on disk.
+///
+/// Text files, which are read into memory to sniff their charset, are
+/// shared between the responses in flight, so concurrent requests for the
+/// same file read it once; the bytes are freed when the last of those
+/// responses has been sent. `Range` requests for other files read only the
+/// requested ranges. A text file whose content turns out to be binary (see
+/// [`detect_binary_body`]) is served as `application/octet-stream` rather
+/// than garbled as text.
+pub struct StaticFileServer {
+    root: PathBuf,                    // The directory request paths are resolved against
+    cache_policies: CachePolicies,    // The Cache-Control policy of each extension
+    download_extensions: Vec<String>, // Lowercase extensions served as attachments
+    contents: WeakCache<PathBuf, Vec<u8>>, // The contents of the files being sent
+    sources: HashMap<String, (String, Box<dyn ServeRange>)>, // Content type and source by path
+}
+
+impl StaticFileServer {
+    /// Creates a server for the files under `root`, using the default cache policies.
+    pub fn new(root: impl Into<PathBuf>) -> StaticFileServer {
+        StaticFileServer {
+            root: root.into(),
+            cache_policies: CachePolicies::default(),
+            download_extensions: Vec::new(),
+            contents: WeakCache::new(),
+            sources: HashMap::new(),
+        }
+    }
+
+    /// Replaces the cache policies, e.g. with the result of a [`CachePolicyBuilder`].
+    pub fn with_cache_policies(mut self, cache_policies: CachePolicies) -> StaticFileServer {
+        self.cache_policies = cache_policies;
+        self
+    }
+
+    /// Serves files with these extensions (e.g. `["pdf", "zip"]`) as downloads.
+    ///
+    /// Matching files get `Content-Disposition: attachment` with their file
+    /// name, so browsers save them instead of displaying them. Extensions are
+    /// matched case-insensitively and without the leading dot.
+    pub fn with_download_extensions<I, S>(mut self, extensions: I) -> StaticFileServer
+    where
+        I: IntoIterator<Item = S>,
+        S: Into<String>,
+    {
+        self.download_extensions = extensions
+            .into_iter()
+            .map(|extension| {
+                extension
+                    .into()
+                    .trim_start_matches('.')
+                    .to_ascii_lowercase()
+            })
+            .collect();
+        self
+    }
+
+    /// Serves `source` at `path` instead of a file on disk.
+    ///
+    /// `Range` requests are answered from the source just like from a file,
+    /// so in-memory assets or a [`MmapFile`](crate::MmapFile) get the same
+    /// partial content handling. Cache policies and download extensions
+    /// apply to `path`'s extension as they would to a file.
+    ///
+    /// # Arguments
+    ///
+    /// * `path` - The path the source is served at, e.g. `/video.mp4`. It is
+    ///   matched exactly, without query string.
+    /// * `content_type` - The `Content-Type` to send the source with.
+    /// * `source` - Where the bytes come from.
+    pub fn with_source(
+        mut self,
+        path: &str,
+        content_type: &str,
+        source: Box<dyn ServeRange>,
+    ) -> StaticFileServer {
+        let path = path.trim_start_matches('/').to_string();
+        self.sources
+            .insert(path, (content_type.to_string(), source));
+        self
+    }
+
+    /// Returns the cache policy applied to files with `extension`.
+    pub fn cache_policy(&self, extension: &str) -> CachePolicy {
+        self.cache_policies.get(extension)
+    }
+
+    /// Serves the file named by the request path.
+    ///
+    /// A `Range` header is honoured: one range gets `206 Partial Content`,
+    /// several get a `multipart/byteranges` body, and ranges outside the file
+    /// get `416 Range Not Satisfiable`. Paths that could climb out of the
+    /// root directory get `400 Bad Request`; see [`detect_path_traversal`].
+    pub fn serve(&self, req: &Request) -> Response {
+        let path = req.path.split('?').next().unwrap_or_default();
+        self.serve_file(path, req.headers.get("Range"))
+    }
+
+    /// Serves the file at `path`, relative to the root directory.
+    ///
+    /// A path naming a directory serves its `index.html`. Missing files get
+    /// `404 Not Found`, and paths that could climb out of the root directory
+    /// `400 Bad Request`.
+    pub fn serve_path(&self, path: &str) -> Response {
+        self.serve_file(path, None)
+    }
+
+    // Serves the file at `path`, or part of it if a Range header was sent
+    fn serve_file(&self, path: &str, range: Option<&str>) -> Response {
+        // 400 rather than 403, so probing does not reveal which files exist
+        if detect_path_traversal(path) {
+            return Response::new(StatusCode::BAD_REQUEST);
+        }
+        let relative = path.trim_start_matches('/');
+        if let Some((content_type, source)) = self.sources.get(relative) {
+            let extension = extension_of(Path::new(relative));
+            let mut response = serve_source(source.as_ref(), content_type, range);
+            self.apply_cache_policy(&mut response, &extension);
+            self.apply_download(&mut response, Path::new(relative), &extension);
+            return response;
+        }
+        let mut file_path = self.root.join(relative);
+        if file_path.is_dir() {
+            file_path.push("index.html");
+        }
+
+        let extension = extension_of(&file_path);
+        let mime = mime_type(&extension);
+
+        // Non-text files need no sniffing, so they are sent without reading them in
+        let mut response = if !mime.starts_with("text/") {
+            let file = File::open(&file_path).and_then(|file| {
+                if file.metadata()?.is_file() {
+                    Ok(file)
+                } else {
+                    Err(io::Error::from(io::ErrorKind::NotFound))
+                }
+            });
+            let Ok(file) = file else {
+                return Response::new(StatusCode::NOT_FOUND);
+            };
+            match range {
+                Some(range) => range_response(&file, mime, range),
+                None => match Response::file(StatusCode::OK, mime, file) {
+                    Ok(response) => response,
+                    Err(_) => return Response::new(StatusCode::NOT_FOUND),
+                },
+            }
+        } else {
+            let Ok(content) = self
+                .contents
+                .get_or_load(&file_path, || fs::read(&file_path))
+            else {
+                return Response::new(StatusCode::NOT_FOUND);
+            };
+            let content_type = content_type_of(&extension, &content);
+            match range {
+                Some(range) => range_response(&*content, &content_type, range),
+                None => Response::shared(StatusCode::OK, &content_type, content),
+            }
+        };
+        self.apply_cache_policy(&mut response, &extension);
+        self.apply_download(&mut response, &file_path, &extension);
+        response
+    }
+
+    // Marks the response as a download if `extension` is one of the download extensions
+    fn apply_download(&self, response: &mut Response, file_path: &Path, extension: &str) {
+        if self
+            .download_extensions
+            .iter()
+            .any(|download| download == extension)
+        {
+            if let Some(name) = file_path.file_name().and_then(|name| name.to_str()) {
+                response.as_attachment(name);
+            }
+        }
+    }
+
+    // Sets Cache-Control, and Expires for policies with a freshness lifetime
+    fn apply_cache_policy(&self, response: &mut Response, extension: &str) {
+        let policy = self.cache_policy(extension);
+        response.set_header("Cache-Control", &policy.header_value());
+        if !policy.no_cache && !policy.max_age.is_zero() {
+            let expires = format_http_date(SystemTime::now() + policy.max_age);
+            response.set_header("Expires", &expires);
+        }
+    }
+}
+
+// Serves a whole source, or the ranges the Range header asks for
+fn serve_source(source: &dyn ServeRange, content_type: &str, range: Option<&str>) -> Response {
+    match range {
+        Some(range) => range_response(source, content_type, range),
+        None => match source.size() {
+            0 => Response::with_body(StatusCode::OK, content_type, Vec::new()),
+            len => match source.read_range(0, len - 1) {
+                Ok(body) => Response::with_body(StatusCode::OK, content_type, body),
+                Err(_) => Response::new(StatusCode::INTERNAL_SERVER_ERROR),
+            },
+        },
+    }
+}
+
+/// Creates a handler that serves assets embedded in the binary, e.g. with `include_bytes!`.
+///
+/// Request paths are looked up in `assets` as-is (e.g. `"/app.js"`) and
+/// misses get `404 Not Found`. Each asset's `ETag` is computed once, up
+/// front, so a matching `If-None-Match` is answered with `304 Not Modified`
+/// without touching the bytes. `Range` requests are answered as for files
+/// on disk.
+///
+/// # Arguments
+///
+/// * `assets` - Maps each path to the asset's bytes and MIME type.
+///
+/// # Returns
+///
+/// A handler that can be registered on a `Router`, e.g. under `/*`.
+pub fn serve_memory_assets(
+    assets: HashMap<&'static str, (&'static [u8], &'static str)>,
+) -> impl Fn(Request) -> Response + Send + Sync + 'static {
+    let assets: HashMap<&'static str, (&'static [u8], &'static str, String)> = assets
+        .into_iter()
+        .map(|(path, (bytes, mime))| (path, (bytes, mime, etag_of(bytes))))
+        .collect();
+
+    move |req: Request| {
+        let path = req.path.split('?').next().unwrap_or_default();
+        let Some((bytes, mime, etag)) = assets.get(path) else {
+            return Response::new(StatusCode::NOT_FOUND);
+        };
+
+        let mut response = match req.headers.get("If-None-Match") {
+            Some(tags)
+                if tags
+                    .split(',')
+                    .any(|tag| tag.trim() == etag || tag.trim() == "*") =>
+            {
+                Response::new(StatusCode::NOT_MODIFIED)
+            }
+            _ => match req.headers.get("Range") {
+                Some(range) => range_response(bytes, mime, range),
+                None => Response::with_body(StatusCode::OK, mime, bytes.to_vec()),
+            },
+        };
+        response.set_header("ETag", etag);
+        response
+    }
+}
+
+// Computes a strong ETag from the contents of an asset
+pub(crate) fn etag_of(bytes: &[u8]) -> String {
+    let mut hasher = DefaultHasher::new();
+    bytes.hash(&mut hasher);
+    format!("\"{:016x}\"", hasher.finish())
+}
+
+// Returns the lowercase extension of `path`, or an empty string
+fn extension_of(path: &Path) -> String {
+    path.extension()
+        .and_then(|extension| extension.to_str())
+        .unwrap_or_default()
+        .to_ascii_lowercase()
+}
+
+/// Guesses the character encoding of a text file.
+///
+/// Valid UTF-8 (which includes plain ASCII) is reported as `utf-8`. Anything
+/// else is taken for Latin-1 as long as it contains no control characters
+/// other than whitespace, C1 controls included, since those almost never
+/// appear in real Latin-1 text but are common in binary data.
+///
+/// # Returns
+///
+/// `utf-8`, `iso-8859-1`, or `None` if the bytes do not look like text.
+pub fn detect_charset(bytes: &[u8]) -> Option<&'static str> {
+    if std::str::from_utf8(bytes).is_ok() {
+        return Some("utf-8");
+    }
+
+    let is_latin1 = bytes.iter().all(|&byte| match byte {
+        b'\t' | b'\n' | b'\r' | 0x0c => true,
+        0x00..=0x1f | 0x7f..=0x9f => false,
+        _ => true,
+    });
+    is_latin1.then_some("iso-8859-1")
+}
+
+/// Guesses whether `body` is binary data rather than text.
+///
+/// Only the first 512 bytes are looked at. They count as binary if they
+/// start with the signature of a PNG, PDF, ELF or ZIP file, or contain a
+/// NUL byte or an ASCII control character that text does not use, i.e.
+/// anything below `0x20` other than tab, line feed, form feed, carriage
+/// return and escape, or `0x7f`. Bytes above `0x7f` are left to
+/// [`detect_charset`].
+pub fn detect_binary_body(body: &[u8]) -> bool {
+    let head = &body[..body.len().min(BINARY_SNIFF_LEN)];
+    if BINARY_SIGNATURES
+        .iter()
+        .any(|signature| head.starts_with(signature))
+    {
+        return true;
+    }
+    head.iter().any(|&byte| match byte {
+        b'\t' | b'\n' | b'\r' | 0x0c | 0x1b => false,
+        0x00..=0x1f | 0x7f => true,
+        _ => false,
+    })
+}
+
+// Returns the Content-Type of a file, with a charset for text types. Text
+// that is in no recognizable encoding, or that looks like binary data
+// despite its extension, is served as opaque bytes instead.
+fn content_type_of(extension: &str, content: &[u8]) -> String {
+    let mime = mime_type(extension);
+    if !mime.starts_with("text/") {
+        return mime.to_string();
+    }
+    if detect_binary_body(content) {
+        return "application/octet-stream".to_string();
+    }
+    match detect_charset(content) {
+        Some(charset) => format!("{mime}; charset={charset}"),
+        None => "application/octet-stream".to_string(),
+    }
+}
+
+/// Returns the MIME type conventionally used for files with `extension`.
+pub(crate) fn mime_type(extension: &str) -> &'static str {
+    match extension {
+        "html" | "htm" => "text/html",
+        "css" => "text/css",
+        "js" | "mjs" => "text/javascript",
+        "json" => "application/json",
+        "txt" => "text/plain",
+        "xml" => "application/xml",
+        "svg" => "image/svg+xml",
+        "png" => "image/png",
+        "jpg" | "jpeg" => "image/jpeg",
+        "gif" => "image/gif",
+        "webp" => "image/webp",
+        "ico" => "image/x-icon",
+        "woff" => "font/woff",
+        "woff2" => "font/woff2",
+        "ttf" => "font/ttf",
+        "otf" => "font/otf",
+        "pdf" => "application/pdf",
+        "zip" => "application/zip",
+        "wasm" => "application/wasm",
+        _ => "application/octet-stream",
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // A minimal PNG header: the signature and the start of the IHDR chunk
+    const PNG: &[u8] = b"\x89PNG\r\n\x1a\n\0\0\0\x0dIHDR";
+
+    #[test]
+    fn detects_binary_signatures() {
+        assert!(detect_binary_body(PNG));
+        assert!(detect_binary_body(b"\x7fELF\x02\x01\x01"));
+        assert!(detect_binary_body(b"%PDF-1.7\n%text after the signature"));
+        assert!(detect_binary_body(b"PK\x03\x04"));
+        assert!(!detect_binary_body(
+            b"PNG is not a signature without its lead byte"
+        ));
+    }
+
+    #[test]
+    fn detects_nul_and_control_bytes() {
+        assert!(detect_binary_body(b"text\0with a NUL"));
+        assert!(detect_binary_body(b"bell\x07"));
+        assert!(detect_binary_body(b"delete\x7f"));
+        assert!(!detect_binary_body(b""));
+        assert!(!detect_binary_body(
+            b"tab\tline\nfeed\x0c crlf\r\n \x1b[1mbold\x1b[0m"
+        ));
+        assert!(!detect_binary_body("utf-8 text: grüße".as_bytes()));
+        assert!(!detect_binary_body(b"latin-1 text: gr\xfc\xdfe"));
+    }
+
+    #[test]
+    fn looks_at_the_first_512_bytes_only() {
+        let mut body = vec![b'a'; BINARY_SNIFF_LEN];
+        body.push(0);
+        assert!(!detect_binary_body(&body));
+        body[BINARY_SNIFF_LEN - 1] = 0;
+        assert!(detect_binary_body(&body));
+    }
+
+    #[test]
+    fn serves_binary_text_files_as_opaque_bytes() {
+        assert_eq!(content_type_of("txt", PNG), "application/octet-stream");
+        assert_eq!(content_type_of("png", PNG), "image/png");
+        assert_eq!(
+            content_type_of("txt", b"hello"),
+            "text/plain; charset=utf-8"
+        );
+    }
+
+    #[test]
+    fn detects_utf8_and_latin1_text() {
+        assert_eq!(detect_charset(b""), Some("utf-8"));
+        assert_eq!(detect_charset(b"plain ascii\r\n"), Some("utf-8"));
+        assert_eq!(detect_charset("grüße, 日本 🎉".as_bytes()), Some("utf-8"));
+        assert_eq!(
+            detect_charset(b"gr\xfc\xdfe\tcaf\xe9\n"),
+            Some("iso-8859-1")
+        );
+        assert_eq!(detect_charset(b"\xa0\xff"), Some("iso-8859-1"));
+    }
+
+    #[test]
+    fn refuses_charsets_for_control_bytes_outside_utf8() {
+        assert_eq!(detect_charset(b"caf\xe9\0"), None);
+        assert_eq!(detect_charset(b"caf\xe9\x85"), None);
+        assert_eq!(detect_charset(b"caf\xe9\x7f"), None);
+        assert_eq!(detect_charset(b"\xc3"), Some("iso-8859-1"));
+        // The same controls are fine in UTF-8; binary sniffing catches those
+        assert_eq!(detect_charset(b"bell\x07"), Some("utf-8"));
+    }
+
+    #[test]
+    fn labels_text_files_with_their_charset() {
+        let latin1 = content_type_of("html", b"<p>caf\xe9</p>");
+        assert_eq!(latin1, "text/html; charset=iso-8859-1");
+        let utf8 = content_type_of("css", "p::after { content: \"→\" }".as_bytes());
+        assert_eq!(utf8, "text/css; charset=utf-8");
+        let unknown = content_type_of("txt", b"caf\xe9\x85");
+        assert_eq!(unknown, "application/octet-stream");
+        assert_eq!(content_type_of("json", b"\xff"), "application/json");
+    }
+}
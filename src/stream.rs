@@ -0,0 +1,139 @@
+use std::io::{Cursor, Read, Result, Write};
+use std::net::{Shutdown, SocketAddr, TcpStream};
+use std::sync::{Arc, Mutex};
+
+/// A stream that can be duplicated into a second handle to the same connection.
+///
+/// This mirrors `TcpStream::try_clone` so code that reads from one handle and
+/// writes to another can be written once for both real sockets and
+/// [`MockStream`].
+pub trait TryClone: Sized {
+    /// Creates a new handle that reads from and writes to the same underlying stream.
+    fn try_clone(&self) -> Result<Self>;
+}
+
+impl TryClone for TcpStream {
+    fn try_clone(&self) -> Result<TcpStream> {
+        TcpStream::try_clone(self)
+    }
+}
+
+/// A stream whose connection can be closed through any of its handles.
+///
+/// Dropping one handle of a `TcpStream` leaves the connection open while
+/// clones exist, so code that blocks on one handle, like [`tunnel`](crate::tunnel),
+/// uses this to wake it up.
+pub trait CloseStream {
+    /// Shuts down both directions of the connection.
+    fn close(&self) -> Result<()>;
+}
+
+impl CloseStream for TcpStream {
+    fn close(&self) -> Result<()> {
+        self.shutdown(Shutdown::Both)
+    }
+}
+
+/// A client connection the server can handle, e.g. a `TcpStream` or [`MockStream`].
+pub trait Connection: Read + Write + TryClone + CloseStream + Send + 'static {
+    /// Returns the address of the client, if the connection has one.
+    fn peer_addr(&self) -> Option<SocketAddr>;
+
+    /// Returns the underlying socket, or the connection itself if it is not a `TcpStream`.
+    fn into_tcp_stream(self) -> std::result::Result<TcpStream, Self>;
+
+    /// Borrows the underlying socket, if the connection is a `TcpStream`.
+    fn as_tcp_stream(&self) -> Option<&TcpStream>;
+}
+
+impl Connection for TcpStream {
+    fn peer_addr(&self) -> Option<SocketAddr> {
+        TcpStream::peer_addr(self).ok()
+    }
+
+    fn into_tcp_stream(self) -> std::result::Result<TcpStream, TcpStream> {
+        Ok(self)
+    }
+
+    fn as_tcp_stream(&self) -> Option<&TcpStream> {
+        Some(self)
+    }
+}
+
+/// An in-memory stream for exercising request parsing and connection handling
+/// without opening a socket.
+///
+/// Reads are served from a fixed input buffer and writes are collected so they
+/// can be inspected afterwards. Like a `TcpStream`, clones share the same
+/// buffers, so bytes written through one handle are visible through all of them.
+#[derive(Debug, Clone, Default)]
+pub struct MockStream {
+    read_buf: Arc<Mutex<Cursor<Vec<u8>>>>, // The bytes the "client" sent
+    write_buf: Arc<Mutex<Vec<u8>>>,        // The bytes the "server" wrote back
+    peer_addr: Option<SocketAddr>,         // The pretend client address, if any
+}
+
+impl MockStream {
+    /// Creates a stream whose reads return `input` and then end-of-stream.
+    pub fn new(input: impl Into<Vec<u8>>) -> MockStream {
+        MockStream {
+            read_buf: Arc::new(Mutex::new(Cursor::new(input.into()))),
+            write_buf: Arc::new(Mutex::new(Vec::new())),
+            peer_addr: None,
+        }
+    }
+
+    /// Makes the stream report `addr` as the client's address.
+    pub fn with_peer_addr(mut self, addr: SocketAddr) -> MockStream {
+        self.peer_addr = Some(addr);
+        self
+    }
+
+    /// Returns everything written to the stream (through any handle) so far.
+    pub fn into_written_bytes(self) -> Vec<u8> {
+        self.write_buf.lock().unwrap().clone()
+    }
+}
+
+impl Read for MockStream {
+    fn read(&mut self, buf: &mut [u8]) -> Result<usize> {
+        self.read_buf.lock().unwrap().read(buf)
+    }
+}
+
+impl Write for MockStream {
+    fn write(&mut self, buf: &[u8]) -> Result<usize> {
+        self.write_buf.lock().unwrap().write(buf)
+    }
+
+    fn flush(&mut self) -> Result<()> {
+        Ok(())
+    }
+}
+
+impl TryClone for MockStream {
+    fn try_clone(&self) -> Result<MockStream> {
+        Ok(self.clone())
+    }
+}
+
+impl CloseStream for MockStream {
+    // The input buffer simply runs out, so there is nothing to wake up
+    fn close(&self) -> Result<()> {
+        Ok(())
+    }
+}
+
+impl Connection for MockStream {
+    fn peer_addr(&self) -> Option<SocketAddr> {
+        self.peer_addr
+    }
+
+    fn into_tcp_stream(self) -> std::result::Result<TcpStream, MockStream> {
+        Err(self)
+    }
+
+    fn as_tcp_stream(&self) -> Option<&TcpStream> {
+        None
+    }
+}
@@ -0,0 +1,156 @@
+use std::env;
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, BufRead, Write};
+use std::path::{Path, PathBuf};
+
+use crate::body::stream_to_new_file;
+use crate::random::random_u64;
+use crate::HttpError;
+
+/// Uploaded data kept in a file in the system temp directory instead of memory.
+///
+/// The file is deleted when the `TempFile` is dropped, so an upload that the
+/// handler does not keep never outlives the request. Call
+/// [`TempFile::persist`] to move it somewhere permanent instead.
+#[derive(Debug)]
+pub struct TempFile {
+    path: PathBuf, // Where the data lives; removed on drop unless persisted
+    size: u64,     // The number of bytes in the file
+}
+
+impl TempFile {
+    /// Writes `data` to a new temp file.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the file cannot be created or written.
+    pub fn new(data: &[u8]) -> io::Result<TempFile> {
+        let path = temp_path();
+        let mut file = OpenOptions::new()
+            .write(true)
+            .create_new(true)
+            .open(&path)?;
+        let temp_file = TempFile {
+            path,
+            size: data.len() as u64,
+        };
+        file.write_all(data)?;
+        Ok(temp_file)
+    }
+
+    /// Streams `reader` to a new temp file without buffering it in memory.
+    ///
+    /// # Errors
+    ///
+    /// Returns `HttpError::PayloadTooLarge` if more than `max_bytes` are read,
+    /// or `HttpError::Io` if reading or writing fails. No file is left behind.
+    pub fn from_reader<R: BufRead>(reader: &mut R, max_bytes: u64) -> Result<TempFile, HttpError> {
+        let path = temp_path();
+        let size = stream_to_new_file(reader, &path, max_bytes)?;
+        Ok(TempFile { path, size })
+    }
+
+    /// Returns the path of the temp file.
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// Returns the size of the data in bytes.
+    pub fn size(&self) -> u64 {
+        self.size
+    }
+
+    /// Opens the temp file for reading.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the file cannot be opened.
+    pub fn open(&self) -> io::Result<File> {
+        File::open(&self.path)
+    }
+
+    /// Moves the file to `dest`, so it is kept after the `TempFile` is gone.
+    ///
+    /// The file is renamed when `dest` is on the same filesystem and copied
+    /// otherwise. On failure the temp file is still deleted on drop.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the file can be neither renamed nor copied to `dest`.
+    pub fn persist(self, dest: &Path) -> io::Result<()> {
+        if fs::rename(&self.path, dest).is_err() {
+            // Renaming fails across filesystems, e.g. from a tmpfs /tmp
+            fs::copy(&self.path, dest)?;
+        }
+        Ok(())
+    }
+}
+
+impl Drop for TempFile {
+    fn drop(&mut self) {
+        // Already gone if the file was persisted by renaming it
+        let _ = fs::remove_file(&self.path);
+    }
+}
+
+// Returns a fresh path in the system temp directory
+fn temp_path() -> PathBuf {
+    env::temp_dir().join(format!("upload-{:016x}.tmp", random_u64()))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Read;
+
+    use super::*;
+
+    fn contents(temp_file: &TempFile) -> Vec<u8> {
+        let mut data = Vec::new();
+        temp_file.open().unwrap().read_to_end(&mut data).unwrap();
+        data
+    }
+
+    #[test]
+    fn deletes_the_file_on_drop() {
+        let temp_file = TempFile::new(b"upload").unwrap();
+        let path = temp_file.path().to_path_buf();
+        assert_eq!(contents(&temp_file), b"upload");
+        assert_eq!(temp_file.size(), 6);
+        drop(temp_file);
+        assert!(!path.exists());
+
+        let temp_file = TempFile::from_reader(&mut &b"streamed"[..], 1024).unwrap();
+        let path = temp_file.path().to_path_buf();
+        assert_eq!(contents(&temp_file), b"streamed");
+        drop(temp_file);
+        assert!(!path.exists());
+    }
+
+    #[test]
+    fn persists_the_file_elsewhere() {
+        let temp_file = TempFile::new(b"keep me").unwrap();
+        let path = temp_file.path().to_path_buf();
+        let dest = temp_path().with_extension("kept");
+        temp_file.persist(&dest).unwrap();
+        assert!(!path.exists());
+        assert_eq!(fs::read(&dest).unwrap(), b"keep me");
+        fs::remove_file(&dest).unwrap();
+    }
+
+    #[test]
+    fn enforces_the_size_limit() {
+        let result = TempFile::from_reader(&mut &b"0123456789"[..], 9);
+        assert!(matches!(result, Err(HttpError::PayloadTooLarge)));
+        let temp_file = TempFile::from_reader(&mut &b"0123456789"[..], 10).unwrap();
+        assert_eq!(temp_file.size(), 10);
+    }
+
+    #[test]
+    fn leaves_existing_files_alone() {
+        let path = temp_path();
+        fs::write(&path, b"planted").unwrap();
+        assert!(stream_to_new_file(&mut &b"upload"[..], &path, 1024).is_err());
+        assert_eq!(fs::read(&path).unwrap(), b"planted");
+        fs::remove_file(&path).unwrap();
+    }
+}
@@ -0,0 +1,149 @@
+use std::collections::HashMap;
+
+use crate::{escape_html, HttpError, Response, StatusCode};
+
+// The page `render_error_page` fills in when no custom template is configured
+const ERROR_PAGE: &str = "<!DOCTYPE html>
+<html>
+<head><meta charset=\"utf-8\"><title>{{status}} {{reason}}</title></head>
+<body>
+<h1>{{status}} {{reason}}</h1>
+<p>{{message}}</p>
+</body>
+</html>
+";
+
+/// Replaces `{{key}}` placeholders in `template` with HTML-escaped values.
+///
+/// Whitespace inside the braces is ignored, so `{{ name }}` works too.
+/// Placeholders without a value in `vars` are left in the output unchanged;
+/// use [`render_template_strict`] to treat them as an error instead.
+///
+/// # Arguments
+///
+/// * `template` - The HTML with placeholders, e.g. `<h1>Hello, {{name}}</h1>`.
+/// * `vars` - The value of each placeholder. Values are escaped, so they cannot inject markup.
+pub fn render_template(template: &str, vars: &HashMap<&str, &str>) -> String {
+    match render(template, vars, false) {
+        Ok(rendered) => rendered,
+        Err(_) => unreachable!("non-strict rendering never fails"),
+    }
+}
+
+/// Like [`render_template`], but every placeholder must have a value.
+///
+/// # Errors
+///
+/// Returns `HttpError::MissingTemplateVariable` naming the first placeholder
+/// that has no value in `vars`.
+pub fn render_template_strict(
+    template: &str,
+    vars: &HashMap<&str, &str>,
+) -> Result<String, HttpError> {
+    render(template, vars, true)
+}
+
+/// Builds an HTML error page showing `status`, its reason phrase and `message`.
+///
+/// The page is served as `text/html; charset=utf-8` with `status` as the
+/// response status; `message` is escaped, so it may contain user input.
+/// The server uses this for the error responses it produces itself and for
+/// error responses without a body, with `ServerConfig::error_page_template`
+/// in place of the built-in page when it is set.
+pub fn render_error_page(status: StatusCode, message: &str) -> Response {
+    let page = error_page_html(None, status, message);
+    Response::with_body(status, "text/html; charset=utf-8", page.into_bytes())
+}
+
+// Fills in the `{{status}}`, `{{reason}}` and `{{message}}` placeholders of
+// `template`, or of the built-in page
+pub(crate) fn error_page_html(template: Option<&str>, status: StatusCode, message: &str) -> String {
+    let code = status.as_u16().to_string();
+    let vars = HashMap::from([
+        ("status", code.as_str()),
+        ("reason", status.reason_phrase()),
+        ("message", message),
+    ]);
+    render_template(template.unwrap_or(ERROR_PAGE), &vars)
+}
+
+// Renders the template, failing on unknown placeholders when `strict` is set
+fn render(template: &str, vars: &HashMap<&str, &str>, strict: bool) -> Result<String, HttpError> {
+    let mut output = String::with_capacity(template.len());
+    let mut rest = template;
+
+    while let Some(start) = rest.find("{{") {
+        let Some(end) = rest[start + 2..].find("}}") else {
+            break;
+        };
+        let placeholder = &rest[start..start + 2 + end + 2];
+        let key = placeholder[2..placeholder.len() - 2].trim();
+
+        output.push_str(&rest[..start]);
+        match vars.get(key) {
+            Some(value) => output.push_str(&escape_html(value)),
+            None if strict => return Err(HttpError::MissingTemplateVariable(key.to_string())),
+            None => output.push_str(placeholder),
+        }
+        rest = &rest[start + placeholder.len()..];
+    }
+
+    output.push_str(rest);
+    Ok(output)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn fills_in_and_escapes_placeholders() {
+        let vars = HashMap::from([("name", "<b>O'Neil & \"co\"</b>"), ("n", "3")]);
+        assert_eq!(
+            render_template("<h1>Hi, {{name}}</h1><p>{{ n }} {{n}}</p>", &vars),
+            "<h1>Hi, &lt;b&gt;O&#x27;Neil &amp; &quot;co&quot;&lt;/b&gt;</h1><p>3 3</p>"
+        );
+    }
+
+    #[test]
+    fn leaves_unknown_and_unterminated_placeholders_alone() {
+        let vars = HashMap::from([("name", "Ada")]);
+        assert_eq!(
+            render_template("{{missing}} {{ name }}", &vars),
+            "{{missing}} Ada"
+        );
+        assert_eq!(render_template("{{name}} {{name", &vars), "Ada {{name");
+        assert_eq!(render_template("{name}} }}{{", &vars), "{name}} }}{{");
+        let strict = render_template_strict("{{name}} {{name", &vars).unwrap();
+        assert_eq!(strict, "Ada {{name");
+    }
+
+    #[test]
+    fn does_not_expand_placeholders_inside_values() {
+        let vars = HashMap::from([("a", "{{b}}"), ("b", "no")]);
+        assert_eq!(render_template("{{a}}", &vars), "{{b}}");
+    }
+
+    #[test]
+    fn fails_strict_rendering_on_the_first_missing_placeholder() {
+        let vars = HashMap::from([("name", "Ada")]);
+        let err = render_template_strict("{{name}} {{ first }} {{second}}", &vars).err();
+        assert!(matches!(err, Some(HttpError::MissingTemplateVariable(key)) if key == "first"));
+        let rendered = render_template_strict("Hi {{name}}", &vars).unwrap();
+        assert_eq!(rendered, "Hi Ada");
+    }
+
+    #[test]
+    fn renders_escaped_error_pages() {
+        let response = render_error_page(StatusCode::NOT_FOUND, "no <script> here");
+        assert_eq!(response.status, StatusCode::NOT_FOUND);
+        let content_type = response.headers.get("Content-Type");
+        assert_eq!(content_type, Some("text/html; charset=utf-8"));
+        let page = String::from_utf8(response.body).unwrap();
+        assert!(page.contains("<title>404 Not Found</title>"));
+        assert!(page.contains("<p>no &lt;script&gt; here</p>"));
+
+        let custom = error_page_html(Some("{{status}}: {{message}}"), StatusCode::FORBIDDEN, "&");
+        assert_eq!(custom, "403: &amp;");
+    }
+}
@@ -0,0 +1,107 @@
+use std::io::{Result, Write};
+use std::thread;
+use std::time::{Duration, Instant};
+
+/// A writer that passes bytes on no faster than `bytes_per_sec`.
+///
+/// Useful for previewing how a site behaves on a slow connection, or for
+/// capping what one client can take on a metered tier; see
+/// `ServerConfig::max_bytes_per_sec_per_conn`. Each write adds its length
+/// to a debt that is paid off at `bytes_per_sec` as time passes, and a
+/// write that finds debt left first sleeps until it is paid. Writes are
+/// split into pieces of at most a tenth of a second's worth, so a large
+/// body trickles out instead of leaving in one burst followed by a long
+/// pause.
+#[derive(Debug)]
+pub struct ThrottledWriter<W: Write> {
+    inner: W,            // The writer the bytes go to
+    bytes_per_sec: u64,  // The rate the output is held to, at least 1
+    last_write: Instant, // When the debt was last updated
+    debt: u64,           // Bytes written ahead of the rate
+}
+
+impl<W: Write> ThrottledWriter<W> {
+    /// Wraps `inner`, holding it to `bytes_per_sec`. A rate of 0 is taken as 1.
+    pub fn new(inner: W, bytes_per_sec: u64) -> ThrottledWriter<W> {
+        ThrottledWriter {
+            inner,
+            bytes_per_sec: bytes_per_sec.max(1),
+            last_write: Instant::now(),
+            debt: 0,
+        }
+    }
+
+    /// Returns the wrapped writer.
+    pub fn into_inner(self) -> W {
+        self.inner
+    }
+
+    // Pays off the debt for the time since the last write, then sleeps off the rest
+    fn wait_for_debt(&mut self) {
+        let elapsed = self.last_write.elapsed();
+        let paid = elapsed.as_secs_f64() * self.bytes_per_sec as f64;
+        self.debt = self.debt.saturating_sub(paid as u64);
+        if self.debt > 0 {
+            thread::sleep(Duration::from_secs_f64(
+                self.debt as f64 / self.bytes_per_sec as f64,
+            ));
+            self.debt = 0;
+        }
+        self.last_write = Instant::now();
+    }
+}
+
+impl<W: Write> Write for ThrottledWriter<W> {
+    fn write(&mut self, buf: &[u8]) -> Result<usize> {
+        if buf.is_empty() {
+            return Ok(0);
+        }
+        self.wait_for_debt();
+
+        let piece = usize::try_from(self.bytes_per_sec / 10)
+            .unwrap_or(usize::MAX)
+            .max(1);
+        let written = self.inner.write(&buf[..buf.len().min(piece)])?;
+        self.debt += written as u64;
+        Ok(written)
+    }
+
+    fn flush(&mut self) -> Result<()> {
+        self.inner.flush()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn holds_writes_to_the_rate() {
+        let (bytes, rate) = (500, 1_000);
+        let body = vec![b'x'; bytes];
+        let start = Instant::now();
+        let mut writer = ThrottledWriter::new(Vec::new(), rate);
+        writer.write_all(&body).unwrap();
+        let elapsed = start.elapsed();
+
+        // The last piece leaves without waiting, so the minimum is N/R less
+        // a tenth of a second, with a little slack for the timer
+        let expected = Duration::from_secs_f64(bytes as f64 / rate as f64);
+        let tolerance = Duration::from_millis(100 + 20);
+        assert!(
+            elapsed >= expected - tolerance,
+            "wrote {bytes} bytes at {rate} B/s in {elapsed:?}"
+        );
+        assert_eq!(writer.into_inner(), body);
+    }
+
+    #[test]
+    fn splits_writes_into_tenths_of_a_second() {
+        let mut writer = ThrottledWriter::new(Vec::new(), 1_000);
+        assert_eq!(writer.write(&[0; 1_000]).unwrap(), 100);
+        assert_eq!(writer.write(&[]).unwrap(), 0);
+
+        let mut slowest = ThrottledWriter::new(Vec::new(), 0);
+        assert_eq!(slowest.write(&[0; 10]).unwrap(), 1);
+    }
+}
@@ -0,0 +1,68 @@
+use std::sync::{mpsc, Arc};
+use std::thread;
+use std::time::Duration;
+
+use crate::{Request, Response, StatusCode};
+
+/// Limits how long a handler may run before the client gets `504 Gateway Timeout`.
+///
+/// Each request runs the handler on a new thread while the worker waits for
+/// its response with a timeout. If the timeout fires, the worker answers with
+/// `504` right away and the handler thread is detached: Rust cannot stop a
+/// running thread, so it keeps going until the handler returns on its own and
+/// its response is discarded. The pool worker is freed, but a handler that
+/// never returns leaks its thread for good, so this protects the pool from
+/// slow handlers rather than replacing timeouts on the calls they make.
+///
+/// This wraps route handlers instead of implementing [`Middleware`](crate::Middleware),
+/// because a middleware's `next` borrows the router and cannot be moved onto
+/// a thread that may outlive the request.
+///
+/// ```ignore
+/// let timeout = TimeoutMiddleware::new(Duration::from_secs(2));
+/// router.get("/report", timeout.wrap(build_report));
+/// ```
+#[derive(Debug, Clone, Copy)]
+pub struct TimeoutMiddleware {
+    duration: Duration, // How long a handler may run
+}
+
+impl TimeoutMiddleware {
+    /// Creates a middleware that allows handlers to run for `duration`.
+    pub fn new(duration: Duration) -> TimeoutMiddleware {
+        TimeoutMiddleware { duration }
+    }
+
+    /// Wraps `handler` so it is answered with `504 Gateway Timeout` once it runs too long.
+    pub fn wrap<F>(&self, handler: F) -> impl Fn(Request) -> Response + Send + Sync + 'static
+    where
+        F: Fn(Request) -> Response + Send + Sync + 'static,
+    {
+        let handler = Arc::new(handler);
+        let duration = self.duration;
+
+        move |req: Request| {
+            let (sender, receiver) = mpsc::channel();
+            let handler = Arc::clone(&handler);
+            let path = req.path.clone();
+            thread::spawn(move || {
+                // The receiver is gone if the request already timed out
+                let _ = sender.send(handler(req));
+            });
+
+            match receiver.recv_timeout(duration) {
+                Ok(response) => response,
+                Err(mpsc::RecvTimeoutError::Timeout) => {
+                    println!(
+                        "Handler for {path} timed out after {duration:?}; detaching its thread"
+                    );
+                    Response::new(StatusCode::GATEWAY_TIMEOUT)
+                }
+                // The handler panicked before producing a response
+                Err(mpsc::RecvTimeoutError::Disconnected) => {
+                    Response::new(StatusCode::INTERNAL_SERVER_ERROR)
+                }
+            }
+        }
+    }
+}
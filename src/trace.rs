@@ -0,0 +1,173 @@
+use std::cell::RefCell;
+use std::fmt;
+
+use crate::random::{random_u128, random_u64};
+use crate::{Request, Response};
+
+// The only traceparent version this crate emits
+const VERSION: &str = "00";
+
+// Trace flag bit signalling that the caller may have recorded this trace
+const FLAG_SAMPLED: u8 = 0x01;
+
+thread_local! {
+    // The context of the request currently being handled on this thread
+    static CURRENT: RefCell<Option<TraceContext>> = const { RefCell::new(None) };
+}
+
+/// A W3C Trace Context (https://www.w3.org/TR/trace-context/) span identity.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TraceContext {
+    /// The ID of the whole trace, shared by every span in it.
+    pub trace_id: u128,
+    /// The ID of the span that sent or produced this context.
+    pub parent_id: u64,
+    /// The trace flags (bit 0 is the "sampled" flag).
+    pub flags: u8,
+    /// The opaque vendor-specific `tracestate` header, passed through untouched.
+    pub state: Option<String>,
+}
+
+impl TraceContext {
+    /// Starts a new, sampled trace with random IDs.
+    pub fn new_root() -> TraceContext {
+        TraceContext {
+            trace_id: non_zero(random_u128),
+            parent_id: non_zero(random_u64),
+            flags: FLAG_SAMPLED,
+            state: None,
+        }
+    }
+
+    /// Extracts the trace context sent by the client.
+    ///
+    /// # Arguments
+    ///
+    /// * `req` - The request whose `traceparent` and `tracestate` headers are read.
+    ///
+    /// # Returns
+    ///
+    /// The parsed context, or `None` if `traceparent` is missing or malformed.
+    pub fn from_request(req: &Request) -> Option<TraceContext> {
+        let mut context = parse_traceparent(req.headers.get("traceparent")?)?;
+        context.state = req
+            .headers
+            .get("tracestate")
+            .filter(|state| !state.trim().is_empty())
+            .map(str::to_string);
+        Some(context)
+    }
+
+    /// Creates a child span in the same trace with a fresh span ID.
+    pub fn new_child(&self) -> TraceContext {
+        TraceContext {
+            trace_id: self.trace_id,
+            parent_id: non_zero(random_u64),
+            flags: self.flags,
+            state: self.state.clone(),
+        }
+    }
+
+    /// Returns `true` if the sampled flag is set.
+    pub fn is_sampled(&self) -> bool {
+        self.flags & FLAG_SAMPLED != 0
+    }
+
+    /// Sets the `traceparent` (and `tracestate`, if any) headers on `response`.
+    pub fn inject(&self, response: &mut Response) {
+        response.set_header("traceparent", &self.to_string());
+        if let Some(state) = &self.state {
+            response.set_header("tracestate", state);
+        }
+    }
+
+    /// Makes this the active context of the current thread.
+    ///
+    /// The previously active context is restored when the returned guard is
+    /// dropped, so worker threads never leak a context into the next job.
+    pub fn enter(self) -> TraceGuard {
+        let previous = CURRENT.with(|current| current.replace(Some(self)));
+        TraceGuard { previous }
+    }
+
+    /// Returns a copy of the active context of the current thread, if any.
+    pub fn current() -> Option<TraceContext> {
+        CURRENT.with(|current| current.borrow().clone())
+    }
+}
+
+impl fmt::Display for TraceContext {
+    /// Formats the context as a `traceparent` header value.
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{VERSION}-{:032x}-{:016x}-{:02x}",
+            self.trace_id, self.parent_id, self.flags
+        )
+    }
+}
+
+/// Restores the previously active [`TraceContext`] when dropped.
+pub struct TraceGuard {
+    previous: Option<TraceContext>,
+}
+
+impl Drop for TraceGuard {
+    fn drop(&mut self) {
+        let previous = self.previous.take();
+        CURRENT.with(|current| *current.borrow_mut() = previous);
+    }
+}
+
+/// Parses a `traceparent` header of the form `00-<trace_id>-<parent_id>-<flags>`.
+///
+/// Versions newer than `00` are accepted as long as they start with the same
+/// four fields, as the specification requires.
+fn parse_traceparent(value: &str) -> Option<TraceContext> {
+    let mut parts = value.trim().split('-');
+    let version = parts.next()?;
+    let trace_id = parts.next()?;
+    let parent_id = parts.next()?;
+    let flags = parts.next()?;
+
+    if !is_lower_hex(version, 2) || version == "ff" {
+        return None;
+    }
+    if version == VERSION && parts.next().is_some() {
+        return None;
+    }
+    if !is_lower_hex(trace_id, 32) || !is_lower_hex(parent_id, 16) || !is_lower_hex(flags, 2) {
+        return None;
+    }
+
+    let trace_id = u128::from_str_radix(trace_id, 16).ok()?;
+    let parent_id = u64::from_str_radix(parent_id, 16).ok()?;
+    let flags = u8::from_str_radix(flags, 16).ok()?;
+
+    // All-zero IDs are explicitly invalid
+    if trace_id == 0 || parent_id == 0 {
+        return None;
+    }
+
+    Some(TraceContext {
+        trace_id,
+        parent_id,
+        flags,
+        state: None,
+    })
+}
+
+// Checks that `s` is exactly `len` lowercase hex digits
+fn is_lower_hex(s: &str, len: usize) -> bool {
+    s.len() == len && s.bytes().all(|b| matches!(b, b'0'..=b'9' | b'a'..=b'f'))
+}
+
+// Draws from `generate` until the value is non-zero
+fn non_zero<T: Default + PartialEq>(generate: fn() -> T) -> T {
+    loop {
+        let value = generate();
+        if value != T::default() {
+            return value;
+        }
+    }
+}
@@ -0,0 +1,284 @@
+use std::fmt;
+use std::io::{self, Write};
+
+use crate::{ChunkedWriter, HttpError};
+
+/// A transfer coding of a message body (RFC 7230, section 4).
+///
+/// Codings listed in a `Transfer-Encoding` header are applied to the body
+/// in the order they appear, so the last one is the outermost on the wire.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum TransferEncoding {
+    /// No transformation. Only allowed last, and dropped when the body is written.
+    Identity,
+    /// Chunked framing (RFC 7230, section 4.1). Only allowed last.
+    Chunked,
+    /// The gzip format (RFC 1952). Requires the `compress` feature to be written.
+    Gzip,
+    /// The zlib format (RFC 1950). Requires the `compress` feature to be written.
+    Deflate,
+}
+
+impl TransferEncoding {
+    /// Returns the coding name as it appears in the header (e.g. `"chunked"`).
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            TransferEncoding::Identity => "identity",
+            TransferEncoding::Chunked => "chunked",
+            TransferEncoding::Gzip => "gzip",
+            TransferEncoding::Deflate => "deflate",
+        }
+    }
+
+    /// Parses a `Transfer-Encoding` header value into its codings, in order.
+    ///
+    /// Coding names are case-insensitive and `x-gzip` counts as `gzip`.
+    ///
+    /// # Errors
+    ///
+    /// Returns `HttpError::InvalidTransferEncoding` if a coding is unknown,
+    /// if `identity` or `chunked` appears anywhere but last, or if `chunked`
+    /// is listed more than once. In particular `identity` and `chunked`
+    /// cannot both be listed.
+    pub fn parse_list(value: &str) -> Result<Vec<TransferEncoding>, HttpError> {
+        let codings = value
+            .split(',')
+            .map(str::trim)
+            .filter(|coding| !coding.is_empty())
+            .map(|coding| match coding.to_ascii_lowercase().as_str() {
+                "identity" => Ok(TransferEncoding::Identity),
+                "chunked" => Ok(TransferEncoding::Chunked),
+                "gzip" | "x-gzip" => Ok(TransferEncoding::Gzip),
+                "deflate" => Ok(TransferEncoding::Deflate),
+                _ => Err(HttpError::InvalidTransferEncoding(
+                    "unknown transfer coding",
+                )),
+            })
+            .collect::<Result<Vec<_>, _>>()?;
+
+        let applied_before_last = codings.split_last().map_or(&[][..], |(_, rest)| rest);
+        if applied_before_last.contains(&TransferEncoding::Identity) {
+            return Err(HttpError::InvalidTransferEncoding(
+                "identity must be the last coding",
+            ));
+        }
+        if applied_before_last.contains(&TransferEncoding::Chunked) {
+            return Err(HttpError::InvalidTransferEncoding(
+                "chunked must be the outermost coding",
+            ));
+        }
+        Ok(codings)
+    }
+}
+
+impl fmt::Display for TransferEncoding {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+// A writer in a chain of transfer codings, which must be told when the body ends
+trait Encoder: Write {
+    // Ends this coding, then the ones it writes into
+    fn finish_coding(self: Box<Self>) -> io::Result<()>;
+}
+
+impl<W: Write> Encoder for ChunkedWriter<W> {
+    fn finish_coding(self: Box<Self>) -> io::Result<()> {
+        self.finish().map(drop)
+    }
+}
+
+#[cfg(feature = "compress")]
+impl<'a> Encoder for flate2::write::GzEncoder<Box<dyn Encoder + 'a>> {
+    fn finish_coding(self: Box<Self>) -> io::Result<()> {
+        self.finish()?.finish_coding()
+    }
+}
+
+#[cfg(feature = "compress")]
+impl<'a> Encoder for flate2::write::ZlibEncoder<Box<dyn Encoder + 'a>> {
+    fn finish_coding(self: Box<Self>) -> io::Result<()> {
+        self.finish()?.finish_coding()
+    }
+}
+
+/// Writes a body onto `writer` with `codings` applied, ending with `chunked`.
+///
+/// `write_body` gets the writer the raw body goes into. Every coding is
+/// ended even if it fails, so the client sees a well-formed (if truncated)
+/// body, and its error is returned afterwards.
+///
+/// # Errors
+///
+/// Returns an error if `codings` does not end with `chunked` or contains
+/// `identity`, if a compressing coding is used without the `compress`
+/// feature, or if writing fails.
+pub(crate) fn write_encoded(
+    writer: &mut dyn Write,
+    codings: &[TransferEncoding],
+    write_body: impl FnOnce(&mut dyn Write) -> io::Result<()>,
+) -> io::Result<()> {
+    let Some((TransferEncoding::Chunked, inner)) = codings.split_last() else {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            "codings must end with chunked",
+        ));
+    };
+
+    // The first coding touches the body first, so it is the outermost writer
+    let mut encoder: Box<dyn Encoder + '_> = Box::new(ChunkedWriter::new(writer));
+    for coding in inner.iter().rev() {
+        encoder = wrap(*coding, encoder)?;
+    }
+
+    let written = write_body(&mut encoder);
+    encoder.finish_coding()?;
+    written
+}
+
+// Puts `coding` in front of `inner`
+#[cfg(feature = "compress")]
+fn wrap<'a>(
+    coding: TransferEncoding,
+    inner: Box<dyn Encoder + 'a>,
+) -> io::Result<Box<dyn Encoder + 'a>> {
+    use flate2::write::{GzEncoder, ZlibEncoder};
+    use flate2::Compression;
+
+    match coding {
+        TransferEncoding::Gzip => Ok(Box::new(GzEncoder::new(inner, Compression::default()))),
+        TransferEncoding::Deflate => Ok(Box::new(ZlibEncoder::new(inner, Compression::default()))),
+        TransferEncoding::Identity | TransferEncoding::Chunked => Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            "identity and chunked can only be the last coding",
+        )),
+    }
+}
+
+// Without flate2 there is nothing to compress with
+#[cfg(not(feature = "compress"))]
+fn wrap<'a>(
+    coding: TransferEncoding,
+    _inner: Box<dyn Encoder + 'a>,
+) -> io::Result<Box<dyn Encoder + 'a>> {
+    Err(io::Error::new(
+        io::ErrorKind::Unsupported,
+        format!("the {coding} transfer coding requires the `compress` feature"),
+    ))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Read;
+
+    use super::*;
+    use crate::ChunkedReader;
+
+    use TransferEncoding::{Chunked, Deflate, Gzip, Identity};
+
+    #[test]
+    fn parses_codings_in_order_ignoring_case() {
+        assert_eq!(TransferEncoding::parse_list("chunked").unwrap(), [Chunked]);
+        assert_eq!(
+            TransferEncoding::parse_list("Gzip, CHUNKED").unwrap(),
+            [Gzip, Chunked]
+        );
+        assert_eq!(
+            TransferEncoding::parse_list("x-gzip,,deflate ,chunked").unwrap(),
+            [Gzip, Deflate, Chunked]
+        );
+        assert_eq!(
+            TransferEncoding::parse_list("gzip, identity").unwrap(),
+            [Gzip, Identity]
+        );
+        assert_eq!(TransferEncoding::parse_list("").unwrap(), []);
+    }
+
+    #[test]
+    fn allows_identity_and_chunked_only_last() {
+        for value in [
+            "chunked, gzip",
+            "identity, gzip",
+            "identity, chunked",
+            "chunked, identity",
+        ] {
+            let error = TransferEncoding::parse_list(value).err().unwrap();
+            assert!(
+                matches!(error, HttpError::InvalidTransferEncoding(_)),
+                "{value}"
+            );
+        }
+    }
+
+    #[test]
+    fn rejects_repeated_chunked_and_unknown_codings() {
+        for value in [
+            "chunked, chunked",
+            "gzip, chunked, chunked",
+            "br, chunked",
+            "compress",
+            "gzip;q=1",
+        ] {
+            let error = TransferEncoding::parse_list(value).err().unwrap();
+            assert!(
+                matches!(error, HttpError::InvalidTransferEncoding(_)),
+                "{value}"
+            );
+        }
+    }
+
+    // Writes `body` with `codings` and returns the bytes sent
+    fn encode(codings: &[TransferEncoding], body: &[u8]) -> io::Result<Vec<u8>> {
+        let mut sent = Vec::new();
+        write_encoded(&mut sent, codings, |writer| writer.write_all(body))?;
+        Ok(sent)
+    }
+
+    #[test]
+    fn writes_chunked_bodies() {
+        let sent = encode(&[Chunked], b"hello").unwrap();
+        let mut decoded = Vec::new();
+        ChunkedReader::new(&sent[..])
+            .read_to_end(&mut decoded)
+            .unwrap();
+        assert_eq!(decoded, b"hello");
+        assert!(sent.ends_with(b"0\r\n\r\n"));
+    }
+
+    #[test]
+    fn requires_chunked_last_when_writing() {
+        for codings in [&[][..], &[Gzip], &[Chunked, Gzip], &[Identity]] {
+            let error = encode(codings, b"hello").err().unwrap();
+            assert_eq!(error.kind(), io::ErrorKind::InvalidInput, "{codings:?}");
+        }
+    }
+
+    #[cfg(feature = "compress")]
+    #[test]
+    fn chains_compression_inside_chunked() {
+        use flate2::read::{GzDecoder, ZlibDecoder};
+
+        let body = b"hello hello hello hello".repeat(100);
+        let sent = encode(&[Deflate, Gzip, Chunked], &body).unwrap();
+        let mut chunked = Vec::new();
+        ChunkedReader::new(&sent[..])
+            .read_to_end(&mut chunked)
+            .unwrap();
+
+        // Deflate was applied first, so it is the innermost layer
+        let mut decoded = Vec::new();
+        ZlibDecoder::new(GzDecoder::new(&chunked[..]))
+            .read_to_end(&mut decoded)
+            .unwrap();
+        assert_eq!(decoded, body);
+        assert!(chunked.len() < body.len());
+    }
+
+    #[cfg(not(feature = "compress"))]
+    #[test]
+    fn needs_the_compress_feature_to_compress() {
+        let error = encode(&[Gzip, Chunked], b"hello").err().unwrap();
+        assert_eq!(error.kind(), io::ErrorKind::Unsupported);
+    }
+}
@@ -0,0 +1,36 @@
+use std::io::{self, Read, Write};
+use std::net::{Shutdown, TcpStream};
+use std::thread;
+
+use crate::{CloseStream, TryClone};
+
+/// Relays bytes in both directions between `client` and `target` until both are done.
+///
+/// The client-to-target direction runs on a second thread. When the client
+/// stops sending, the target's write side is shut down so it sees the end of
+/// the request stream; when the target closes, the client connection is
+/// closed too, so neither side waits on a peer that has gone away.
+///
+/// # Errors
+///
+/// Returns an error if a stream cannot be cloned or relaying from the target
+/// to the client fails.
+pub fn tunnel<C>(mut client: C, mut target: TcpStream) -> io::Result<()>
+where
+    C: Read + Write + TryClone + CloseStream + Send + 'static,
+{
+    let mut client_reader = client.try_clone()?;
+    let mut target_writer = target.try_clone()?;
+
+    let upstream = thread::spawn(move || {
+        let _ = io::copy(&mut client_reader, &mut target_writer);
+        let _ = target_writer.shutdown(Shutdown::Write);
+    });
+
+    let downstream = io::copy(&mut target, &mut client).and_then(|_| client.flush());
+
+    // Wake the upstream thread if it is still blocked reading from the client
+    let _ = client.close();
+    let _ = upstream.join();
+    downstream.map(|_| ())
+}
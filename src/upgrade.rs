@@ -0,0 +1,36 @@
+use crate::{HijackedStream, Request, Response, StatusCode};
+
+/// Takes over a connection once it has switched to another protocol (RFC 9110, section 7.8).
+///
+/// Registered with [`Router::upgrade`](crate::Router::upgrade). When a
+/// request asks to upgrade to the handler's protocol, [`handshake`] builds
+/// the response; if it is `101 Switching Protocols` it is sent and the
+/// connection is handed to [`upgraded`]. A handler is shared by every
+/// connection, so it is borrowed rather than consumed.
+///
+/// [`handshake`]: UpgradeHandler::handshake
+/// [`upgraded`]: UpgradeHandler::upgraded
+pub trait UpgradeHandler: Send + Sync + 'static {
+    /// Builds the response to an upgrade request.
+    ///
+    /// The default accepts every request with `101 Switching Protocols` and
+    /// echoes the protocol in `Upgrade`. Override it to add headers the
+    /// protocol needs (e.g. `Sec-WebSocket-Accept`) or to refuse the upgrade
+    /// with any other status, which is then sent as a normal response.
+    fn handshake(&self, req: &Request) -> Response {
+        let mut response = Response::new(StatusCode::SWITCHING_PROTOCOLS);
+        response.set_header("Connection", "Upgrade");
+        if let Some(protocol) = req.headers.get("Upgrade") {
+            response.set_header("Upgrade", protocol);
+        }
+        response
+    }
+
+    /// Speaks the new protocol on `stream` after the `101` response has been sent.
+    ///
+    /// Reading `stream` first returns whatever the client sent right
+    /// behind the request, e.g. a first WebSocket frame. This runs on the
+    /// pool worker that accepted the connection, so a long-lived protocol
+    /// should move `stream` to its own thread to free it.
+    fn upgraded(&self, req: Request, stream: HijackedStream);
+}
@@ -0,0 +1,94 @@
+use std::collections::VecDeque;
+use std::fmt;
+use std::io;
+use std::net::{TcpListener, TcpStream};
+use std::os::unix::io::{AsRawFd, FromRawFd, RawFd};
+use std::ptr;
+
+use io_uring::{opcode, types, IoUring};
+
+// The size of the submission queue, and so the most accepts in flight at once
+const RING_ENTRIES: u32 = 32;
+
+/// Accepts connections through an `io_uring` instead of one `accept(2)` call each.
+///
+/// Several accept requests are kept in flight, so connections arriving
+/// together are picked up on a single `io_uring_enter` call and handed out
+/// one at a time. How many are in flight is left to the caller, so the
+/// backlog still fills up while the pool is busy.
+pub(crate) struct UringAcceptor {
+    ring: IoUring,                          // The submission and completion queues
+    listener: RawFd,                        // The listening socket, owned by the caller
+    in_flight: usize,                       // Accepts submitted but not yet completed
+    ready: VecDeque<io::Result<TcpStream>>, // Completed accepts not handed out yet
+}
+
+impl UringAcceptor {
+    /// Sets up a ring for accepting on `listener`, which must outlive the acceptor.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the kernel does not support `io_uring` or refuses to set one up.
+    pub(crate) fn new(listener: &TcpListener) -> io::Result<UringAcceptor> {
+        Ok(UringAcceptor {
+            ring: IoUring::new(RING_ENTRIES)?,
+            listener: listener.as_raw_fd(),
+            in_flight: 0,
+            ready: VecDeque::new(),
+        })
+    }
+
+    /// Returns the next accepted connection, keeping up to `wanted` accepts in flight.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the ring fails or the accept itself fails.
+    pub(crate) fn accept(&mut self, wanted: usize) -> io::Result<TcpStream> {
+        let wanted = wanted.clamp(1, RING_ENTRIES as usize);
+
+        loop {
+            if let Some(accepted) = self.ready.pop_front() {
+                return accepted;
+            }
+
+            while self.in_flight < wanted {
+                let entry =
+                    opcode::Accept::new(types::Fd(self.listener), ptr::null_mut(), ptr::null_mut())
+                        .flags(libc::SOCK_CLOEXEC)
+                        .build();
+                // SAFETY: the address pointers are null, and the listener outlives the ring
+                if unsafe { self.ring.submission().push(&entry) }.is_err() {
+                    break;
+                }
+                self.in_flight += 1;
+            }
+
+            match self.ring.submit_and_wait(1) {
+                Ok(_) => {}
+                Err(err) if err.kind() == io::ErrorKind::Interrupted => continue,
+                Err(err) => return Err(err),
+            }
+
+            for completion in self.ring.completion() {
+                self.in_flight -= 1;
+                let result = completion.result();
+                self.ready.push_back(if result < 0 {
+                    Err(io::Error::from_raw_os_error(-result))
+                } else {
+                    // SAFETY: a successful accept returns a new socket that nothing else owns
+                    Ok(unsafe { TcpStream::from_raw_fd(result) })
+                });
+            }
+        }
+    }
+}
+
+impl fmt::Debug for UringAcceptor {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("UringAcceptor")
+            .field("listener", &self.listener)
+            .field("in_flight", &self.in_flight)
+            .field("ready", &self.ready.len())
+            .finish_non_exhaustive()
+    }
+}
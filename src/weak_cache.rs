@@ -0,0 +1,69 @@
+use std::collections::HashMap;
+use std::hash::Hash;
+use std::sync::{Arc, Mutex, Weak};
+
+/// Shares values between their concurrent users without keeping them alive.
+///
+/// The cache holds only `Weak` references: a value stays in it while at
+/// least one `Arc` returned by [`WeakCache::get_or_load`] is alive, e.g.
+/// while a response sharing it is still being sent, and is freed as soon as
+/// the last one is dropped. [`StaticFileServer`](crate::StaticFileServer)
+/// uses it so that a burst of requests for the same file reads it once.
+#[derive(Debug)]
+pub struct WeakCache<K, V> {
+    entries: Mutex<HashMap<K, Weak<V>>>, // The values in use, by key
+}
+
+impl<K: Eq + Hash + Clone, V> WeakCache<K, V> {
+    /// Creates an empty cache.
+    pub fn new() -> WeakCache<K, V> {
+        WeakCache {
+            entries: Mutex::new(HashMap::new()),
+        }
+    }
+
+    /// Returns the value for `key` if one is still in use, or loads it with `load`.
+    ///
+    /// The lock is held while `load` runs, so concurrent callers wait for
+    /// the first one's value instead of loading it again. Entries whose
+    /// values have been freed are removed whenever a value is loaded.
+    ///
+    /// # Errors
+    ///
+    /// Returns the error of `load`; nothing is cached in that case.
+    pub fn get_or_load<E>(
+        &self,
+        key: &K,
+        load: impl FnOnce() -> Result<V, E>,
+    ) -> Result<Arc<V>, E> {
+        let mut entries = self.entries.lock().unwrap();
+        if let Some(value) = entries.get(key).and_then(Weak::upgrade) {
+            return Ok(value);
+        }
+
+        let value = Arc::new(load()?);
+        entries.retain(|_, value| value.strong_count() > 0);
+        entries.insert(key.clone(), Arc::downgrade(&value));
+        Ok(value)
+    }
+
+    /// Returns the number of values currently in use.
+    pub fn len(&self) -> usize {
+        let entries = self.entries.lock().unwrap();
+        entries
+            .values()
+            .filter(|value| value.strong_count() > 0)
+            .count()
+    }
+
+    /// Returns `true` if no value is currently in use.
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+}
+
+impl<K: Eq + Hash + Clone, V> Default for WeakCache<K, V> {
+    fn default() -> WeakCache<K, V> {
+        WeakCache::new()
+    }
+}
@@ -0,0 +1,192 @@
+use std::cell::Cell;
+use std::collections::VecDeque;
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::sync::{Arc, Condvar, Mutex, MutexGuard};
+use std::thread;
+
+use crate::random::random_u64;
+use crate::{Job, PoolMetrics};
+
+thread_local! {
+    // The pool and deque of the worker running on this thread, if any
+    static CURRENT_WORKER: Cell<Option<(usize, usize)>> = const { Cell::new(None) };
+}
+
+/// A thread pool where every worker has its own queue and idle workers steal.
+///
+/// [`ThreadPool`](crate::ThreadPool) keeps one queue behind one lock, which
+/// every submission and every worker contends on. Here each worker has a
+/// deque of its own: jobs submitted from outside the pool are spread over
+/// the deques in turn, jobs a worker submits while running one go onto its
+/// own deque, and a worker takes jobs from the front of its deque. A worker
+/// whose deque is empty steals from the back of its siblings' deques,
+/// starting at a random one, and only sleeps once every deque is empty.
+///
+/// Jobs run in no particular order: there are no priorities, and a job can
+/// overtake one submitted earlier to another deque.
+pub struct WorkStealingPool {
+    workers: Vec<thread::JoinHandle<()>>, // The worker threads, joined on drop
+    shared: Arc<Shared>,                  // The deques and counters the workers use
+    next: AtomicUsize,                    // The deque the next outside submission goes to
+}
+
+// The state shared between the pool and its workers
+struct Shared {
+    deques: Vec<Mutex<VecDeque<Job>>>, // One deque per worker
+    queued: AtomicUsize,               // Jobs in all the deques together
+    busy: AtomicUsize,                 // Workers currently running a job
+    closed: AtomicBool,                // Set on drop; workers exit once every deque is empty
+    sleeping: AtomicUsize,             // Workers waiting on `available`
+    sleep: Mutex<()>,                  // Guards the check-then-wait of idle workers
+    available: Condvar,                // Signalled when a job is queued or the pool shuts down
+}
+
+impl WorkStealingPool {
+    /// Creates a pool with `size` worker threads.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `size` is zero.
+    pub fn new(size: usize) -> WorkStealingPool {
+        assert!(size > 0);
+
+        let shared = Arc::new(Shared {
+            deques: (0..size).map(|_| Mutex::new(VecDeque::new())).collect(),
+            queued: AtomicUsize::new(0),
+            busy: AtomicUsize::new(0),
+            closed: AtomicBool::new(false),
+            sleeping: AtomicUsize::new(0),
+            sleep: Mutex::new(()),
+            available: Condvar::new(),
+        });
+
+        let workers = (0..size)
+            .map(|index| {
+                let shared = Arc::clone(&shared);
+                thread::spawn(move || run_worker(&shared, index))
+            })
+            .collect();
+
+        WorkStealingPool {
+            workers,
+            shared,
+            next: AtomicUsize::new(0),
+        }
+    }
+
+    /// Runs `f` on a worker thread.
+    ///
+    /// Called from one of this pool's jobs, `f` goes onto the deque of the
+    /// worker running it; otherwise the deques are filled in turn.
+    pub fn execute<F>(&self, f: F)
+    where
+        F: FnOnce() + Send + 'static,
+    {
+        let index = match CURRENT_WORKER.get() {
+            Some((pool, index)) if pool == self.shared.id() => index,
+            _ => self.next.fetch_add(1, Ordering::Relaxed) % self.shared.deques.len(),
+        };
+        // Counted first, so a worker taking it right away never sees the count below zero
+        self.shared.queued.fetch_add(1, Ordering::SeqCst);
+        self.shared.lock(index).push_back(Box::new(f));
+
+        // A worker counts itself as sleeping before checking for jobs, so either
+        // it sees this job or this sees it; notifying under the lock then
+        // reaches it even if it has not started waiting yet
+        if self.shared.sleeping.load(Ordering::SeqCst) > 0 {
+            let _sleep = self.shared.lock_sleep();
+            self.shared.available.notify_one();
+        }
+    }
+
+    /// Returns how many workers are busy and how many jobs are waiting for one.
+    pub fn metrics(&self) -> PoolMetrics {
+        PoolMetrics {
+            workers: self.workers.len(),
+            busy: self.shared.busy.load(Ordering::SeqCst),
+            queued: self.shared.queued.load(Ordering::SeqCst),
+        }
+    }
+}
+
+impl Drop for WorkStealingPool {
+    fn drop(&mut self) {
+        // Close the pool and wake every worker so they exit once the remaining jobs are done
+        {
+            let _sleep = self.shared.lock_sleep();
+            self.shared.closed.store(true, Ordering::SeqCst);
+            self.shared.available.notify_all();
+        }
+
+        for (index, worker) in self.workers.drain(..).enumerate() {
+            println!("Shutting down work-stealing worker {index}");
+            worker.join().unwrap();
+        }
+    }
+}
+
+impl Shared {
+    // Identifies the pool, so a job can tell whether it runs on this pool's workers
+    fn id(&self) -> usize {
+        self as *const Shared as usize
+    }
+
+    // Locks a deque. Jobs run outside the lock, so a poisoned deque is still consistent
+    fn lock(&self, index: usize) -> MutexGuard<'_, VecDeque<Job>> {
+        self.deques[index]
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner())
+    }
+
+    fn lock_sleep(&self) -> MutexGuard<'_, ()> {
+        self.sleep
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner())
+    }
+
+    // Takes the front of deque `index`, or else steals the back of a sibling's
+    fn take(&self, index: usize) -> Option<Job> {
+        if let Some(job) = self.lock(index).pop_front() {
+            return Some(job);
+        }
+
+        let len = self.deques.len();
+        let start = random_u64() as usize % len;
+        (0..len)
+            .map(|offset| (start + offset) % len)
+            .filter(|&sibling| sibling != index)
+            .find_map(|sibling| self.lock(sibling).pop_back())
+    }
+}
+
+// The loop of the worker owning deque `index`
+fn run_worker(shared: &Shared, index: usize) {
+    CURRENT_WORKER.set(Some((shared.id(), index)));
+    loop {
+        let Some(job) = shared.take(index) else {
+            // Check again as a sleeper, so a job queued meanwhile is not slept through
+            let sleep = shared.lock_sleep();
+            shared.sleeping.fetch_add(1, Ordering::SeqCst);
+            if shared.queued.load(Ordering::SeqCst) == 0 {
+                if shared.closed.load(Ordering::SeqCst) {
+                    shared.sleeping.fetch_sub(1, Ordering::SeqCst);
+                    println!("Work-stealing worker {index} disconnected; shutting down.");
+                    break;
+                }
+                drop(
+                    shared
+                        .available
+                        .wait(sleep)
+                        .unwrap_or_else(|poisoned| poisoned.into_inner()),
+                );
+            }
+            shared.sleeping.fetch_sub(1, Ordering::SeqCst);
+            continue;
+        };
+
+        shared.queued.fetch_sub(1, Ordering::SeqCst);
+        shared.busy.fetch_add(1, Ordering::SeqCst);
+        job();
+        shared.busy.fetch_sub(1, Ordering::SeqCst);
+    }
+}